@@ -0,0 +1,124 @@
+use crate::util::le::{read_u32, read_u64};
+use conquer_once::spin::OnceCell;
+use x86_64::{PhysAddr, VirtAddr};
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+// the RSDP lies on a 16 byte boundary in the first KiB of the EBDA or in
+// the BIOS area below 1 MiB
+const EBDA_POINTER: u64 = 0x40e;
+const BIOS_AREA_START: u64 = 0xe0000;
+const BIOS_AREA_END: u64 = 0x100000;
+// the ACPI 1.0 part of the RSDP, the only one the first checksum covers
+const RSDP_V1_LENGTH: usize = 20;
+const RSDP_V2_LENGTH: usize = 36;
+/// Size of the header every system description table starts with.
+pub const HEADER_LENGTH: usize = 36;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    /// No root system description pointer in the BIOS areas.
+    NoRsdp,
+    /// A table with this signature failed its checksum.
+    InvalidChecksum([u8; 4]),
+    /// The RSDP points to an address that is not a physical one.
+    InvalidAddress(u64),
+    AlreadyInitialized,
+}
+
+struct Tables {
+    physical_memory_offset: VirtAddr,
+    // the RSDT holds 32 bit table addresses, the XSDT 64 bit ones
+    root: PhysAddr,
+    entry_size: usize,
+}
+
+static TABLES: OnceCell<Tables> = OnceCell::uninit();
+
+// the bytes of a structure sum up to 0
+fn checksum_valid(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+unsafe fn physical_slice(offset: VirtAddr, address: PhysAddr, length: usize) -> &'static [u8] {
+    core::slice::from_raw_parts((offset + address.as_u64()).as_ptr(), length)
+}
+
+unsafe fn find_rsdp(offset: VirtAddr) -> Option<&'static [u8]> {
+    let ebda = (*(offset + EBDA_POINTER).as_ptr::<u16>() as u64) << 4;
+    let areas = [(ebda, ebda + 1024), (BIOS_AREA_START, BIOS_AREA_END)];
+    for (start, end) in areas {
+        if start == 0 {
+            continue;
+        }
+        for address in (start..end).step_by(16) {
+            let candidate = physical_slice(offset, PhysAddr::new(address), RSDP_V1_LENGTH);
+            if &candidate[..8] == RSDP_SIGNATURE && checksum_valid(candidate) {
+                let length = if candidate[15] >= 2 { RSDP_V2_LENGTH } else { RSDP_V1_LENGTH };
+                return Some(physical_slice(offset, PhysAddr::new(address), length));
+            }
+        }
+    }
+    None
+}
+
+// the table at `address` with its checksum verified
+unsafe fn table_at(offset: VirtAddr, address: PhysAddr) -> Result<&'static [u8], AcpiError> {
+    let header = physical_slice(offset, address, HEADER_LENGTH);
+    let length = read_u32(header, 4) as usize;
+    let table = physical_slice(offset, address, length.max(HEADER_LENGTH));
+    if checksum_valid(table) {
+        Ok(table)
+    } else {
+        Err(AcpiError::InvalidChecksum(header[..4].try_into().unwrap()))
+    }
+}
+
+/// Finds the ACPI tables. `physical_memory_offset` is where the bootloader
+/// mapped all of physical memory.
+pub fn init(physical_memory_offset: VirtAddr) -> Result<(), AcpiError> {
+    let rsdp = unsafe { find_rsdp(physical_memory_offset) }.ok_or(AcpiError::NoRsdp)?;
+    let (root, entry_size) = if rsdp.len() == RSDP_V2_LENGTH && checksum_valid(rsdp) {
+        (read_u64(rsdp, 24), 8)
+    } else {
+        (read_u32(rsdp, 16) as u64, 4)
+    };
+    let root = PhysAddr::try_new(root).map_err(|_| AcpiError::InvalidAddress(root))?;
+    unsafe { table_at(physical_memory_offset, root)? };
+    TABLES
+        .try_init_once(|| Tables { physical_memory_offset, root, entry_size })
+        .map_err(|_| AcpiError::AlreadyInitialized)
+}
+
+/// Returns the table with `signature`, like `b"HPET"`, header included.
+/// `None` if there is none, it is broken or `init` did not succeed.
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static [u8]> {
+    let tables = TABLES.try_get().ok()?;
+    let offset = tables.physical_memory_offset;
+    let root = unsafe { table_at(offset, tables.root) }.ok()?;
+    root[HEADER_LENGTH..].chunks_exact(tables.entry_size).find_map(|entry| {
+        let address = match tables.entry_size {
+            8 => read_u64(entry, 0),
+            _ => read_u32(entry, 0) as u64,
+        };
+        // a corrupt entry only hides its own table
+        let address = PhysAddr::try_new(address).ok()?;
+        let header = unsafe { physical_slice(offset, address, HEADER_LENGTH) };
+        if &header[..4] == signature {
+            unsafe { table_at(offset, address) }.ok()
+        } else {
+            None
+        }
+    })
+}
+
+#[test_case]
+fn test_checksum() {
+    let mut rsdp = [0u8; RSDP_V1_LENGTH];
+    rsdp[..8].copy_from_slice(RSDP_SIGNATURE);
+    rsdp[9..15].copy_from_slice(b"BOCHS ");
+    let sum = rsdp.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    rsdp[8] = 0u8.wrapping_sub(sum);
+    assert!(checksum_valid(&rsdp));
+    rsdp[16] = 1;
+    assert!(!checksum_valid(&rsdp));
+}
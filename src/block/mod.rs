@@ -0,0 +1,86 @@
+use alloc::{sync::Arc, vec};
+
+pub mod partition;
+pub mod ramdisk;
+
+/// Size in bytes of a logical block, every device in the kernel is
+/// addressed in 512 byte sectors.
+pub const SECTOR_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// The request touches sectors past the end of the device.
+    OutOfRange,
+    /// The buffer length is not a multiple of `SECTOR_SIZE`.
+    UnalignedBuffer,
+    /// The device reported a failure while transferring data.
+    Io,
+}
+
+/// A random access storage device addressed by logical block (LBA).
+///
+/// Methods take `&self` so a device can be shared between several
+/// filesystems or partitions; implementations synchronize internally.
+pub trait BlockDevice: Send + Sync {
+    /// Number of `SECTOR_SIZE` sectors on the device.
+    fn sector_count(&self) -> u64;
+
+    /// Reads `buf.len() / SECTOR_SIZE` sectors starting at `lba`.
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError>;
+
+    /// Writes `buf.len() / SECTOR_SIZE` sectors starting at `lba`.
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError>;
+
+    /// Reads `buf.len()` bytes starting at the byte `offset`, the range
+    /// does not need to be sector aligned.
+    fn read_bytes(&self, offset: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        let sector_size = SECTOR_SIZE as u64;
+        let first = offset / sector_size;
+        let last = (offset + buf.len() as u64).div_ceil(sector_size);
+        let mut tmp = vec![0u8; ((last - first) * sector_size) as usize];
+        self.read_sectors(first, &mut tmp)?;
+        let start = (offset - first * sector_size) as usize;
+        buf.copy_from_slice(&tmp[start..start + buf.len()]);
+        Ok(())
+    }
+
+    /// Writes `buf` at the byte `offset` with a read-modify-write of the
+    /// sectors it partially covers.
+    fn write_bytes(&self, offset: u64, buf: &[u8]) -> Result<(), BlockError> {
+        let sector_size = SECTOR_SIZE as u64;
+        let first = offset / sector_size;
+        let last = (offset + buf.len() as u64).div_ceil(sector_size);
+        let mut tmp = vec![0u8; ((last - first) * sector_size) as usize];
+        self.read_sectors(first, &mut tmp)?;
+        let start = (offset - first * sector_size) as usize;
+        tmp[start..start + buf.len()].copy_from_slice(buf);
+        self.write_sectors(first, &tmp)
+    }
+}
+
+// lets several owners (filesystems, partitions) share one device
+impl<T: BlockDevice + ?Sized> BlockDevice for Arc<T> {
+    fn sector_count(&self) -> u64 {
+        (**self).sector_count()
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        (**self).read_sectors(lba, buf)
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        (**self).write_sectors(lba, buf)
+    }
+}
+
+// checks a sector request against the size of a device
+pub(crate) fn check_range(sector_count: u64, lba: u64, len: usize) -> Result<(), BlockError> {
+    if !len.is_multiple_of(SECTOR_SIZE) {
+        return Err(BlockError::UnalignedBuffer);
+    }
+    let sectors = (len / SECTOR_SIZE) as u64;
+    match lba.checked_add(sectors) {
+        Some(end) if end <= sector_count => Ok(()),
+        _ => Err(BlockError::OutOfRange),
+    }
+}
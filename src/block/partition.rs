@@ -0,0 +1,371 @@
+use super::{check_range, BlockDevice, BlockError, SECTOR_SIZE};
+use crate::util::le::{read_u16, read_u32, read_u64};
+use alloc::{string::String, vec, vec::Vec};
+use core::fmt;
+
+const MBR_SIGNATURE: u16 = 0xaa55;
+const MBR_TABLE_OFFSET: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+const MBR_TYPE_EMPTY: u8 = 0x00;
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xee;
+// CHS, LBA and Linux flavours of the extended partition container
+const MBR_EXTENDED_TYPES: [u8; 3] = [0x05, 0x0f, 0x85];
+// Linux numbers logical partitions from 5 whatever the primaries are
+const FIRST_LOGICAL_INDEX: usize = 5;
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_HEADER_MIN_SIZE: usize = 92;
+const GPT_ENTRY_MIN_SIZE: u32 = 128;
+const GPT_ENTRY_MAX_SIZE: u32 = 4096;
+// the usual 128 entries of 128 bytes, the array is read onto the heap
+const GPT_MAX_ENTRY_ARRAY: usize = 128 * 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionError {
+    Block(BlockError),
+    /// Sector 0 carries no MBR boot signature.
+    NoTable,
+    /// The MBR extended partition chain is broken.
+    InvalidMbr(&'static str),
+    /// Neither the primary nor the backup GPT header is valid.
+    InvalidGpt(&'static str),
+}
+
+impl From<BlockError> for PartitionError {
+    fn from(error: BlockError) -> Self {
+        PartitionError::Block(error)
+    }
+}
+
+/// A GPT GUID, printed in the usual mixed endian form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub const UNUSED: Guid = Guid([0; 16]);
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-",
+            b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6], b[8], b[9]
+        )?;
+        for byte in &b[10..] {
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartitionKind {
+    Mbr { system_id: u8, bootable: bool },
+    Gpt { type_guid: Guid, unique_guid: Guid, name: String },
+}
+
+/// One entry of a partition table, addresses are absolute LBAs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionInfo {
+    /// 1 based number as Linux shows it, logical MBR partitions start at 5.
+    pub index: usize,
+    pub start_lba: u64,
+    pub sector_count: u64,
+    pub kind: PartitionKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableKind {
+    Mbr,
+    Gpt { disk_guid: Guid, used_backup_header: bool },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionTable {
+    pub kind: TableKind,
+    pub partitions: Vec<PartitionInfo>,
+}
+
+impl PartitionTable {
+    /// Reads the MBR of `device`, following a protective MBR to the GPT.
+    pub fn read<D: BlockDevice + ?Sized>(device: &D) -> Result<PartitionTable, PartitionError> {
+        let mut mbr = [0u8; SECTOR_SIZE];
+        device.read_sectors(0, &mut mbr)?;
+        if read_u16(&mbr, 510) != MBR_SIGNATURE {
+            return Err(PartitionError::NoTable);
+        }
+        let entries = mbr_entries(&mbr);
+        if entries.iter().any(|entry| entry.system_id == MBR_TYPE_GPT_PROTECTIVE) {
+            return read_gpt(device);
+        }
+
+        let mut partitions = Vec::new();
+        for (i, entry) in entries.iter().enumerate() {
+            if entry.system_id == MBR_TYPE_EMPTY {
+                continue;
+            }
+            if MBR_EXTENDED_TYPES.contains(&entry.system_id) {
+                read_logical_partitions(device, entry.start as u64, &mut partitions)?;
+                continue;
+            }
+            partitions.push(entry.to_info(i + 1, 0));
+        }
+        Ok(PartitionTable {
+            kind: TableKind::Mbr,
+            partitions,
+        })
+    }
+}
+
+/// A window of `sector_count` sectors starting at `start_lba` on another
+/// device, every access is checked against the partition bounds.
+pub struct Partition<D: BlockDevice> {
+    device: D,
+    start_lba: u64,
+    sector_count: u64,
+}
+
+impl<D: BlockDevice> Partition<D> {
+    pub fn new(device: D, start_lba: u64, sector_count: u64) -> Result<Self, BlockError> {
+        match start_lba.checked_add(sector_count) {
+            Some(end) if end <= device.sector_count() => Ok(Partition {
+                device,
+                start_lba,
+                sector_count,
+            }),
+            _ => Err(BlockError::OutOfRange),
+        }
+    }
+
+    pub fn from_info(device: D, info: &PartitionInfo) -> Result<Self, BlockError> {
+        Partition::new(device, info.start_lba, info.sector_count)
+    }
+
+    pub fn start_lba(&self) -> u64 {
+        self.start_lba
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for Partition<D> {
+    fn sector_count(&self) -> u64 {
+        self.sector_count
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        check_range(self.sector_count, lba, buf.len())?;
+        self.device.read_sectors(self.start_lba + lba, buf)
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        check_range(self.sector_count, lba, buf.len())?;
+        self.device.write_sectors(self.start_lba + lba, buf)
+    }
+}
+
+/// Reads the partition table of `device` and opens every partition that
+/// fits on it.
+pub fn open_partitions<D: BlockDevice + Clone>(device: &D) -> Result<Vec<Partition<D>>, PartitionError> {
+    let table = PartitionTable::read(device)?;
+    Ok(table
+        .partitions
+        .iter()
+        .filter_map(|info| Partition::from_info(device.clone(), info).ok())
+        .collect())
+}
+
+struct MbrEntry {
+    bootable: bool,
+    system_id: u8,
+    start: u32,
+    sectors: u32,
+}
+
+impl MbrEntry {
+    fn to_info(&self, index: usize, base: u64) -> PartitionInfo {
+        PartitionInfo {
+            index,
+            start_lba: base + self.start as u64,
+            sector_count: self.sectors as u64,
+            kind: PartitionKind::Mbr {
+                system_id: self.system_id,
+                bootable: self.bootable,
+            },
+        }
+    }
+}
+
+fn mbr_entries(sector: &[u8]) -> [MbrEntry; 4] {
+    let entry = |i: usize| {
+        let raw = &sector[MBR_TABLE_OFFSET + i * MBR_ENTRY_SIZE..];
+        MbrEntry {
+            bootable: raw[0] & 0x80 != 0,
+            system_id: raw[4],
+            start: read_u32(raw, 8),
+            sectors: read_u32(raw, 12),
+        }
+    };
+    [entry(0), entry(1), entry(2), entry(3)]
+}
+
+// walks the chain of extended boot records inside an extended partition:
+// the first entry of each EBR is relative to the EBR itself, the second
+// points at the next EBR relative to the start of the extended partition
+fn read_logical_partitions<D: BlockDevice + ?Sized>(
+    device: &D,
+    extended_start: u64,
+    partitions: &mut Vec<PartitionInfo>,
+) -> Result<(), PartitionError> {
+    let mut ebr_lba = extended_start;
+    let mut sector = [0u8; SECTOR_SIZE];
+    for index in FIRST_LOGICAL_INDEX..FIRST_LOGICAL_INDEX + MAX_LOGICAL_PARTITIONS {
+        device.read_sectors(ebr_lba, &mut sector)?;
+        if read_u16(&sector, 510) != MBR_SIGNATURE {
+            return Err(PartitionError::InvalidMbr("extended boot record without signature"));
+        }
+        let entries = mbr_entries(&sector);
+        if entries[0].system_id != MBR_TYPE_EMPTY {
+            partitions.push(entries[0].to_info(index, ebr_lba));
+        }
+        if !MBR_EXTENDED_TYPES.contains(&entries[1].system_id) {
+            return Ok(());
+        }
+        let next = extended_start + entries[1].start as u64;
+        if next <= ebr_lba {
+            return Err(PartitionError::InvalidMbr("extended boot record chain loops"));
+        }
+        ebr_lba = next;
+    }
+    Err(PartitionError::InvalidMbr("too many logical partitions"))
+}
+
+// tries the primary header at LBA 1, then the backup in the last sector
+fn read_gpt<D: BlockDevice + ?Sized>(device: &D) -> Result<PartitionTable, PartitionError> {
+    let last_lba = device.sector_count() - 1;
+    let ((disk_guid, partitions), used_backup_header) = match read_gpt_at(device, 1) {
+        Ok(table) => (table, false),
+        Err(_) => (read_gpt_at(device, last_lba)?, true),
+    };
+    Ok(PartitionTable {
+        kind: TableKind::Gpt {
+            disk_guid,
+            used_backup_header,
+        },
+        partitions,
+    })
+}
+
+fn read_gpt_at<D: BlockDevice + ?Sized>(device: &D, lba: u64) -> Result<(Guid, Vec<PartitionInfo>), PartitionError> {
+    let header = read_gpt_header(device, lba)?;
+    let len = header.entry_count as usize * header.entry_size as usize;
+    let mut entries = vec![0u8; len.div_ceil(SECTOR_SIZE) * SECTOR_SIZE];
+    device.read_sectors(header.entries_lba, &mut entries)?;
+    entries.truncate(len);
+    if crc32(&entries) != header.entries_crc {
+        return Err(PartitionError::InvalidGpt("partition entry array checksum mismatch"));
+    }
+
+    let mut partitions = Vec::new();
+    for (i, raw) in entries.chunks_exact(header.entry_size as usize).enumerate() {
+        let type_guid = guid(&raw[0..16]);
+        if type_guid == Guid::UNUSED {
+            continue;
+        }
+        let first = read_u64(raw, 32);
+        let last = read_u64(raw, 40);
+        if first < header.first_usable || last > header.last_usable || last < first {
+            return Err(PartitionError::InvalidGpt("partition outside the usable area"));
+        }
+        let name_units: Vec<u16> = raw[56..128]
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .take_while(|&unit| unit != 0)
+            .collect();
+        partitions.push(PartitionInfo {
+            index: i + 1,
+            start_lba: first,
+            sector_count: last - first + 1,
+            kind: PartitionKind::Gpt {
+                type_guid,
+                unique_guid: guid(&raw[16..32]),
+                name: char::decode_utf16(name_units)
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect(),
+            },
+        });
+    }
+    Ok((header.disk_guid, partitions))
+}
+
+struct GptHeader {
+    first_usable: u64,
+    last_usable: u64,
+    disk_guid: Guid,
+    entries_lba: u64,
+    entry_count: u32,
+    entry_size: u32,
+    entries_crc: u32,
+}
+
+fn read_gpt_header<D: BlockDevice + ?Sized>(device: &D, lba: u64) -> Result<GptHeader, PartitionError> {
+    let mut sector = [0u8; SECTOR_SIZE];
+    device.read_sectors(lba, &mut sector)?;
+    if &sector[0..8] != GPT_SIGNATURE {
+        return Err(PartitionError::InvalidGpt("missing EFI PART signature"));
+    }
+    let header_size = read_u32(&sector, 12) as usize;
+    if !(GPT_HEADER_MIN_SIZE..=SECTOR_SIZE).contains(&header_size) {
+        return Err(PartitionError::InvalidGpt("invalid header size"));
+    }
+    // the checksum covers the header with its own checksum field zeroed
+    let stored_crc = read_u32(&sector, 16);
+    sector[16..20].fill(0);
+    if crc32(&sector[..header_size]) != stored_crc {
+        return Err(PartitionError::InvalidGpt("header checksum mismatch"));
+    }
+    if read_u64(&sector, 24) != lba {
+        return Err(PartitionError::InvalidGpt("header is not at its own LBA"));
+    }
+
+    let header = GptHeader {
+        first_usable: read_u64(&sector, 40),
+        last_usable: read_u64(&sector, 48),
+        disk_guid: guid(&sector[56..72]),
+        entries_lba: read_u64(&sector, 72),
+        entry_count: read_u32(&sector, 80),
+        entry_size: read_u32(&sector, 84),
+        entries_crc: read_u32(&sector, 88),
+    };
+    if !(GPT_ENTRY_MIN_SIZE..=GPT_ENTRY_MAX_SIZE).contains(&header.entry_size) || !header.entry_size.is_power_of_two() {
+        return Err(PartitionError::InvalidGpt("invalid partition entry size"));
+    }
+    let array_len = (header.entry_count as usize).checked_mul(header.entry_size as usize);
+    if array_len.is_none_or(|len| len > GPT_MAX_ENTRY_ARRAY) {
+        return Err(PartitionError::InvalidGpt("partition entry array too large"));
+    }
+    if header.last_usable >= device.sector_count() || header.first_usable > header.last_usable {
+        return Err(PartitionError::InvalidGpt("usable area outside the device"));
+    }
+    Ok(header)
+}
+
+fn guid(bytes: &[u8]) -> Guid {
+    let mut guid = [0u8; 16];
+    guid.copy_from_slice(&bytes[..16]);
+    Guid(guid)
+}
+
+/// CRC-32 (IEEE 802.3, reflected) as used by GPT headers and entry arrays.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
@@ -0,0 +1,49 @@
+use super::{check_range, BlockDevice, BlockError, SECTOR_SIZE};
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// A block device backed by a heap buffer, used to build disk images
+/// in tests without a disk controller.
+pub struct RamDisk {
+    data: Mutex<Vec<u8>>,
+}
+
+impl RamDisk {
+    /// Creates a zero filled disk with `sectors` sectors.
+    pub fn new(sectors: usize) -> Self {
+        RamDisk {
+            data: Mutex::new(vec![0; sectors * SECTOR_SIZE]),
+        }
+    }
+
+    /// Wraps an existing image, its length must be a multiple of `SECTOR_SIZE`.
+    pub fn from_vec(data: Vec<u8>) -> Self {
+        assert_eq!(data.len() % SECTOR_SIZE, 0, "image is not sector aligned");
+        RamDisk {
+            data: Mutex::new(data),
+        }
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn sector_count(&self) -> u64 {
+        (self.data.lock().len() / SECTOR_SIZE) as u64
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        let data = self.data.lock();
+        check_range((data.len() / SECTOR_SIZE) as u64, lba, buf.len())?;
+        let start = lba as usize * SECTOR_SIZE;
+        buf.copy_from_slice(&data[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        let mut data = self.data.lock();
+        check_range((data.len() / SECTOR_SIZE) as u64, lba, buf.len())?;
+        let start = lba as usize * SECTOR_SIZE;
+        data[start..start + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+}
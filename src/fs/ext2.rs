@@ -0,0 +1,1062 @@
+use super::{components, split_parent, DirEntry, FileSystem, FileType, FsError};
+use crate::block::{BlockDevice, SECTOR_SIZE};
+use crate::time::{self, DateTime};
+use crate::util::le::{read_u16, read_u32};
+use alloc::{string::String, vec, vec::Vec};
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const EXT2_MAGIC: u16 = 0xef53;
+const ROOT_INODE: u32 = 2;
+
+const GOOD_OLD_REV: u32 = 0;
+const GOOD_OLD_FIRST_INO: u32 = 11;
+const GOOD_OLD_INODE_SIZE: u16 = 128;
+
+const STATE_VALID: u16 = 1;
+const STATE_ERROR: u16 = 2;
+
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+const SUPPORTED_INCOMPAT: u32 = INCOMPAT_FILETYPE;
+const RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+const RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+const SUPPORTED_RO_COMPAT: u32 = RO_COMPAT_SPARSE_SUPER | RO_COMPAT_LARGE_FILE;
+
+const S_IFMT: u16 = 0xf000;
+const S_IFREG: u16 = 0x8000;
+const S_IFDIR: u16 = 0x4000;
+const S_IFLNK: u16 = 0xa000;
+// hashed directory index, stale as soon as we add or remove an entry
+const INDEX_FL: u32 = 0x1000;
+
+const FT_REG_FILE: u8 = 1;
+const FT_DIR: u8 = 2;
+const FT_SYMLINK: u8 = 7;
+
+const GROUP_DESCRIPTOR_SIZE: usize = 32;
+const DIRECT_BLOCKS: usize = 12;
+const INDIRECT_BLOCK: usize = 12;
+const DOUBLE_INDIRECT_BLOCK: usize = 13;
+const TRIPLE_INDIRECT_BLOCK: usize = 14;
+const DIR_ENTRY_HEADER: usize = 8;
+const MAX_NAME_LEN: usize = 255;
+// symlink targets shorter than this are stored in i_block itself
+const FAST_SYMLINK_MAX: usize = 60;
+const MAX_SYMLINK_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Superblock {
+    pub inodes_count: u32,
+    pub blocks_count: u32,
+    pub free_blocks_count: u32,
+    pub free_inodes_count: u32,
+    pub first_data_block: u32,
+    pub log_block_size: u32,
+    pub blocks_per_group: u32,
+    pub inodes_per_group: u32,
+    pub write_time: u32,
+    pub state: u16,
+    pub rev_level: u32,
+    pub first_ino: u32,
+    pub inode_size: u16,
+    pub feature_incompat: u32,
+    pub feature_ro_compat: u32,
+}
+
+impl Superblock {
+    pub fn parse(raw: &[u8]) -> Result<Superblock, FsError> {
+        if read_u16(raw, 56) != EXT2_MAGIC {
+            return Err(FsError::Corrupted("bad ext2 magic"));
+        }
+        let rev_level = read_u32(raw, 76);
+        let superblock = Superblock {
+            inodes_count: read_u32(raw, 0),
+            blocks_count: read_u32(raw, 4),
+            free_blocks_count: read_u32(raw, 12),
+            free_inodes_count: read_u32(raw, 16),
+            first_data_block: read_u32(raw, 20),
+            log_block_size: read_u32(raw, 24),
+            blocks_per_group: read_u32(raw, 32),
+            inodes_per_group: read_u32(raw, 40),
+            write_time: read_u32(raw, 48),
+            state: read_u16(raw, 58),
+            rev_level,
+            first_ino: if rev_level == GOOD_OLD_REV { GOOD_OLD_FIRST_INO } else { read_u32(raw, 84) },
+            inode_size: if rev_level == GOOD_OLD_REV { GOOD_OLD_INODE_SIZE } else { read_u16(raw, 88) },
+            feature_incompat: if rev_level == GOOD_OLD_REV { 0 } else { read_u32(raw, 96) },
+            feature_ro_compat: if rev_level == GOOD_OLD_REV { 0 } else { read_u32(raw, 100) },
+        };
+        if superblock.log_block_size > 2 {
+            return Err(FsError::Corrupted("unsupported block size"));
+        }
+        if superblock.blocks_per_group == 0
+            || superblock.inodes_per_group == 0
+            || superblock.blocks_per_group as usize > superblock.block_size() * 8
+            || superblock.inodes_per_group as usize > superblock.block_size() * 8
+        {
+            return Err(FsError::Corrupted("invalid group size"));
+        }
+        let inode_size = superblock.inode_size as usize;
+        if inode_size < GOOD_OLD_INODE_SIZE as usize || !inode_size.is_power_of_two() || inode_size > superblock.block_size() {
+            return Err(FsError::Corrupted("invalid inode size"));
+        }
+        if superblock.feature_incompat & !SUPPORTED_INCOMPAT != 0 {
+            return Err(FsError::Corrupted("unsupported incompatible features"));
+        }
+        if superblock.first_data_block >= superblock.blocks_count || superblock.first_ino <= ROOT_INODE {
+            return Err(FsError::Corrupted("invalid superblock layout"));
+        }
+        Ok(superblock)
+    }
+
+    pub fn block_size(&self) -> usize {
+        1024 << self.log_block_size
+    }
+
+    pub fn group_count(&self) -> u32 {
+        (self.blocks_count - self.first_data_block).div_ceil(self.blocks_per_group)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct GroupDescriptor {
+    block_bitmap: u32,
+    inode_bitmap: u32,
+    inode_table: u32,
+    free_blocks_count: u16,
+    free_inodes_count: u16,
+    used_dirs_count: u16,
+}
+
+/// A problem found by the consistency check run on mount.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// The volume was not cleanly unmounted or has its error flag set.
+    NotClean,
+    GroupFreeBlocks { group: u32, recorded: u32, counted: u32 },
+    GroupFreeInodes { group: u32, recorded: u32, counted: u32 },
+    FreeBlocks { recorded: u32, counted: u32 },
+    FreeInodes { recorded: u32, counted: u32 },
+}
+
+#[derive(Debug, Clone)]
+struct Inode {
+    mode: u16,
+    size: u64,
+    links_count: u16,
+    // allocated space in 512 byte units, indirect blocks included
+    sectors: u32,
+    flags: u32,
+    block: [u32; 15],
+    file_acl: u32,
+    atime: u32,
+    ctime: u32,
+    mtime: u32,
+    dtime: u32,
+}
+
+// inode times are 32 bit seconds since the epoch
+fn inode_time() -> u32 {
+    time::now().unix_seconds() as u32
+}
+
+impl Inode {
+    fn new(mode: u16) -> Self {
+        let now = inode_time();
+        Inode {
+            mode,
+            size: 0,
+            links_count: 1,
+            sectors: 0,
+            flags: 0,
+            block: [0; 15],
+            file_acl: 0,
+            atime: now,
+            ctime: now,
+            mtime: now,
+            dtime: 0,
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.mode & S_IFMT == S_IFLNK
+    }
+
+    fn file_type(&self) -> FileType {
+        match self.mode & S_IFMT {
+            S_IFDIR => FileType::Directory,
+            S_IFLNK => FileType::Symlink,
+            _ => FileType::File,
+        }
+    }
+}
+
+// a live directory entry and where it sits inside the directory
+struct RawDirEntry {
+    inode: u32,
+    name: String,
+    block_index: u64,
+    offset: usize,
+}
+
+/// A mounted ext2 volume.
+pub struct Ext2<D: BlockDevice> {
+    device: D,
+    superblock: Superblock,
+    groups: Vec<GroupDescriptor>,
+    read_only: bool,
+    report: Vec<Inconsistency>,
+}
+
+impl<D: BlockDevice> Ext2<D> {
+    /// Mounts the volume, checking the group layout and repairing free
+    /// block and inode counts that disagree with the bitmaps.
+    pub fn mount(device: D) -> Result<Self, FsError> {
+        let mut raw = [0u8; 1024];
+        device.read_bytes(SUPERBLOCK_OFFSET, &mut raw)?;
+        let superblock = Superblock::parse(&raw)?;
+        if superblock.blocks_count as u64 * superblock.block_size() as u64 > device.sector_count() * SECTOR_SIZE as u64 {
+            return Err(FsError::Corrupted("volume larger than device"));
+        }
+        let group_count = superblock.group_count();
+        // every group but the last one is full of inodes
+        let inode_capacity = group_count as u64 * superblock.inodes_per_group as u64;
+        if inode_capacity < superblock.inodes_count as u64
+            || inode_capacity - (superblock.inodes_count as u64) >= superblock.inodes_per_group as u64
+        {
+            return Err(FsError::Corrupted("inode count does not fit the groups"));
+        }
+
+        let table_offset = (superblock.first_data_block as u64 + 1) * superblock.block_size() as u64;
+        let mut table = vec![0u8; group_count as usize * GROUP_DESCRIPTOR_SIZE];
+        device.read_bytes(table_offset, &mut table)?;
+        let groups = table
+            .chunks_exact(GROUP_DESCRIPTOR_SIZE)
+            .map(|raw| GroupDescriptor {
+                block_bitmap: read_u32(raw, 0),
+                inode_bitmap: read_u32(raw, 4),
+                inode_table: read_u32(raw, 8),
+                free_blocks_count: read_u16(raw, 12),
+                free_inodes_count: read_u16(raw, 14),
+                used_dirs_count: read_u16(raw, 16),
+            })
+            .collect();
+
+        let mut fs = Ext2 {
+            device,
+            superblock,
+            groups,
+            read_only: superblock.feature_ro_compat & !SUPPORTED_RO_COMPAT != 0,
+            report: Vec::new(),
+        };
+        fs.report = fs.check()?;
+        if !fs.read_only {
+            fs.repair_counts()?;
+        }
+        Ok(fs)
+    }
+
+    pub fn superblock(&self) -> &Superblock {
+        &self.superblock
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Problems found when the volume was mounted.
+    pub fn check_report(&self) -> &[Inconsistency] {
+        &self.report
+    }
+
+    /// Runs the consistency checks, structural damage is returned as an
+    /// error while wrong summary counts are reported.
+    pub fn check(&self) -> Result<Vec<Inconsistency>, FsError> {
+        let sb = &self.superblock;
+        let mut report = Vec::new();
+        if sb.state & STATE_VALID == 0 || sb.state & STATE_ERROR != 0 {
+            report.push(Inconsistency::NotClean);
+        }
+
+        let table_blocks = (sb.inodes_per_group as usize * sb.inode_size as usize).div_ceil(self.block_size()) as u32;
+        let (mut free_blocks, mut free_inodes) = (0, 0);
+        for (index, group) in self.groups.iter().enumerate() {
+            let index = index as u32;
+            let in_volume = |block: u32, len: u32| block >= sb.first_data_block && block as u64 + len as u64 <= sb.blocks_count as u64;
+            if !in_volume(group.block_bitmap, 1) || !in_volume(group.inode_bitmap, 1) || !in_volume(group.inode_table, table_blocks) {
+                return Err(FsError::Corrupted("group metadata outside the volume"));
+            }
+
+            let bitmap = self.read_block(group.block_bitmap)?;
+            let counted = count_zero_bits(&bitmap, self.blocks_in_group(index));
+            if counted != group.free_blocks_count as u32 {
+                report.push(Inconsistency::GroupFreeBlocks { group: index, recorded: group.free_blocks_count as u32, counted });
+            }
+            free_blocks += counted;
+
+            let bitmap = self.read_block(group.inode_bitmap)?;
+            let counted = count_zero_bits(&bitmap, self.inodes_in_group(index));
+            if counted != group.free_inodes_count as u32 {
+                report.push(Inconsistency::GroupFreeInodes { group: index, recorded: group.free_inodes_count as u32, counted });
+            }
+            free_inodes += counted;
+        }
+        if free_blocks != sb.free_blocks_count {
+            report.push(Inconsistency::FreeBlocks { recorded: sb.free_blocks_count, counted: free_blocks });
+        }
+        if free_inodes != sb.free_inodes_count {
+            report.push(Inconsistency::FreeInodes { recorded: sb.free_inodes_count, counted: free_inodes });
+        }
+
+        if !self.read_inode(ROOT_INODE)?.is_dir() {
+            return Err(FsError::Corrupted("root inode is not a directory"));
+        }
+        Ok(report)
+    }
+
+    // rewrites the summary counts found wrong by `check`
+    fn repair_counts(&mut self) -> Result<(), FsError> {
+        for issue in self.report.clone() {
+            match issue {
+                Inconsistency::GroupFreeBlocks { group, counted, .. } => {
+                    self.groups[group as usize].free_blocks_count = counted as u16;
+                    self.write_group(group)?;
+                }
+                Inconsistency::GroupFreeInodes { group, counted, .. } => {
+                    self.groups[group as usize].free_inodes_count = counted as u16;
+                    self.write_group(group)?;
+                }
+                Inconsistency::FreeBlocks { counted, .. } => self.superblock.free_blocks_count = counted,
+                Inconsistency::FreeInodes { counted, .. } => self.superblock.free_inodes_count = counted,
+                Inconsistency::NotClean => {}
+            }
+        }
+        self.write_superblock_counts()
+    }
+
+    fn block_size(&self) -> usize {
+        self.superblock.block_size()
+    }
+
+    fn blocks_in_group(&self, group: u32) -> u32 {
+        let sb = &self.superblock;
+        let start = sb.first_data_block + group * sb.blocks_per_group;
+        sb.blocks_per_group.min(sb.blocks_count - start)
+    }
+
+    fn inodes_in_group(&self, group: u32) -> u32 {
+        let sb = &self.superblock;
+        sb.inodes_per_group.min(sb.inodes_count.saturating_sub(group.saturating_mul(sb.inodes_per_group)))
+    }
+
+    fn read_block(&self, block: u32) -> Result<Vec<u8>, FsError> {
+        let mut data = vec![0u8; self.block_size()];
+        self.device.read_bytes(block as u64 * self.block_size() as u64, &mut data)?;
+        Ok(data)
+    }
+
+    fn write_block(&self, block: u32, data: &[u8]) -> Result<(), FsError> {
+        self.device.write_bytes(block as u64 * self.block_size() as u64, data)?;
+        Ok(())
+    }
+
+    fn write_superblock_counts(&self) -> Result<(), FsError> {
+        let mut counts = [0u8; 8];
+        counts[0..4].copy_from_slice(&self.superblock.free_blocks_count.to_le_bytes());
+        counts[4..8].copy_from_slice(&self.superblock.free_inodes_count.to_le_bytes());
+        self.device.write_bytes(SUPERBLOCK_OFFSET + 12, &counts)?;
+        Ok(())
+    }
+
+    fn write_group(&self, group: u32) -> Result<(), FsError> {
+        let descriptor = &self.groups[group as usize];
+        let mut counts = [0u8; 6];
+        counts[0..2].copy_from_slice(&descriptor.free_blocks_count.to_le_bytes());
+        counts[2..4].copy_from_slice(&descriptor.free_inodes_count.to_le_bytes());
+        counts[4..6].copy_from_slice(&descriptor.used_dirs_count.to_le_bytes());
+        let table_offset = (self.superblock.first_data_block as u64 + 1) * self.block_size() as u64;
+        self.device
+            .write_bytes(table_offset + group as u64 * GROUP_DESCRIPTOR_SIZE as u64 + 12, &counts)?;
+        Ok(())
+    }
+
+    fn inode_offset(&self, inode: u32) -> Result<u64, FsError> {
+        if inode == 0 || inode > self.superblock.inodes_count {
+            return Err(FsError::Corrupted("inode number out of range"));
+        }
+        let group = (inode - 1) / self.superblock.inodes_per_group;
+        let index = (inode - 1) % self.superblock.inodes_per_group;
+        let table = self.groups[group as usize].inode_table as u64 * self.block_size() as u64;
+        Ok(table + index as u64 * self.superblock.inode_size as u64)
+    }
+
+    fn read_inode(&self, inode: u32) -> Result<Inode, FsError> {
+        let mut raw = [0u8; 128];
+        self.device.read_bytes(self.inode_offset(inode)?, &mut raw)?;
+        let mode = read_u16(&raw, 0);
+        let mut block = [0u32; 15];
+        for (i, pointer) in block.iter_mut().enumerate() {
+            *pointer = read_u32(&raw, 40 + i * 4);
+        }
+        // the high half of the size is only defined for regular files
+        let size_high = if mode & S_IFMT == S_IFREG { read_u32(&raw, 108) as u64 } else { 0 };
+        Ok(Inode {
+            mode,
+            size: size_high << 32 | read_u32(&raw, 4) as u64,
+            links_count: read_u16(&raw, 26),
+            sectors: read_u32(&raw, 28),
+            flags: read_u32(&raw, 32),
+            block,
+            file_acl: read_u32(&raw, 104),
+            atime: read_u32(&raw, 8),
+            ctime: read_u32(&raw, 12),
+            mtime: read_u32(&raw, 16),
+            dtime: read_u32(&raw, 20),
+        })
+    }
+
+    // updates the fields the driver manages and keeps everything else
+    fn write_inode(&self, number: u32, inode: &Inode) -> Result<(), FsError> {
+        let offset = self.inode_offset(number)?;
+        let mut raw = [0u8; 128];
+        self.device.read_bytes(offset, &mut raw)?;
+        raw[0..2].copy_from_slice(&inode.mode.to_le_bytes());
+        raw[4..8].copy_from_slice(&(inode.size as u32).to_le_bytes());
+        raw[8..12].copy_from_slice(&inode.atime.to_le_bytes());
+        raw[12..16].copy_from_slice(&inode.ctime.to_le_bytes());
+        raw[16..20].copy_from_slice(&inode.mtime.to_le_bytes());
+        raw[20..24].copy_from_slice(&inode.dtime.to_le_bytes());
+        raw[26..28].copy_from_slice(&inode.links_count.to_le_bytes());
+        raw[28..32].copy_from_slice(&inode.sectors.to_le_bytes());
+        raw[32..36].copy_from_slice(&inode.flags.to_le_bytes());
+        for (i, pointer) in inode.block.iter().enumerate() {
+            raw[40 + i * 4..44 + i * 4].copy_from_slice(&pointer.to_le_bytes());
+        }
+        if inode.mode & S_IFMT == S_IFREG {
+            raw[108..112].copy_from_slice(&((inode.size >> 32) as u32).to_le_bytes());
+        }
+        self.device.write_bytes(offset, &raw)?;
+        Ok(())
+    }
+
+    fn check_writable(&self) -> Result<(), FsError> {
+        if self.read_only {
+            return Err(FsError::ReadOnly);
+        }
+        Ok(())
+    }
+
+    // finds and sets a clear bit in a group bitmap, starting at `goal`
+    fn allocate_bit(&mut self, goal_group: u32, inodes: bool) -> Result<Option<(u32, u32)>, FsError> {
+        let group_count = self.groups.len() as u32;
+        for step in 0..group_count {
+            let group = (goal_group + step) % group_count;
+            let descriptor = self.groups[group as usize];
+            let (free, bitmap_block, bits) = if inodes {
+                (descriptor.free_inodes_count, descriptor.inode_bitmap, self.inodes_in_group(group))
+            } else {
+                (descriptor.free_blocks_count, descriptor.block_bitmap, self.blocks_in_group(group))
+            };
+            if free == 0 {
+                continue;
+            }
+            let mut bitmap = self.read_block(bitmap_block)?;
+            if let Some(bit) = (0..bits).find(|&bit| bitmap[bit as usize / 8] & (1 << (bit % 8)) == 0) {
+                bitmap[bit as usize / 8] |= 1 << (bit % 8);
+                self.write_block(bitmap_block, &bitmap)?;
+                return Ok(Some((group, bit)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn clear_bit(&self, bitmap_block: u32, bit: u32) -> Result<(), FsError> {
+        let mut bitmap = self.read_block(bitmap_block)?;
+        if bitmap[bit as usize / 8] & (1 << (bit % 8)) == 0 {
+            return Err(FsError::Corrupted("freeing an unused block or inode"));
+        }
+        bitmap[bit as usize / 8] &= !(1 << (bit % 8));
+        self.write_block(bitmap_block, &bitmap)
+    }
+
+    // allocates a zeroed block, preferably in `goal_group`
+    fn allocate_block(&mut self, goal_group: u32) -> Result<u32, FsError> {
+        let (group, bit) = self.allocate_bit(goal_group, false)?.ok_or(FsError::NoSpace)?;
+        self.groups[group as usize].free_blocks_count -= 1;
+        self.superblock.free_blocks_count -= 1;
+        self.write_group(group)?;
+        self.write_superblock_counts()?;
+        let block = self.superblock.first_data_block + group * self.superblock.blocks_per_group + bit;
+        self.write_block(block, &vec![0u8; self.block_size()])?;
+        Ok(block)
+    }
+
+    fn free_block(&mut self, block: u32) -> Result<(), FsError> {
+        let sb = &self.superblock;
+        if block < sb.first_data_block || block >= sb.blocks_count {
+            return Err(FsError::Corrupted("block number out of range"));
+        }
+        let group = (block - sb.first_data_block) / sb.blocks_per_group;
+        let bit = (block - sb.first_data_block) % sb.blocks_per_group;
+        self.clear_bit(self.groups[group as usize].block_bitmap, bit)?;
+        self.groups[group as usize].free_blocks_count += 1;
+        self.superblock.free_blocks_count += 1;
+        self.write_group(group)?;
+        self.write_superblock_counts()
+    }
+
+    fn allocate_inode(&mut self, goal_group: u32, inode: &Inode) -> Result<u32, FsError> {
+        let (group, bit) = self.allocate_bit(goal_group, true)?.ok_or(FsError::NoSpace)?;
+        let number = group * self.superblock.inodes_per_group + bit + 1;
+        if number < self.superblock.first_ino {
+            let _ = self.clear_bit(self.groups[group as usize].inode_bitmap, bit);
+            return Err(FsError::Corrupted("reserved inode marked free"));
+        }
+        let descriptor = &mut self.groups[group as usize];
+        descriptor.free_inodes_count -= 1;
+        if inode.is_dir() {
+            descriptor.used_dirs_count += 1;
+        }
+        self.superblock.free_inodes_count -= 1;
+        if let Err(error) = self.initialize_inode(group, number, inode) {
+            // give the inode back, the first error is the one to report
+            let descriptor = &mut self.groups[group as usize];
+            descriptor.free_inodes_count += 1;
+            if inode.is_dir() {
+                descriptor.used_dirs_count -= 1;
+            }
+            self.superblock.free_inodes_count += 1;
+            let _ = self.clear_bit(self.groups[group as usize].inode_bitmap, bit);
+            let _ = self.write_group(group).and_then(|_| self.write_superblock_counts());
+            return Err(error);
+        }
+        Ok(number)
+    }
+
+    fn initialize_inode(&mut self, group: u32, number: u32, inode: &Inode) -> Result<(), FsError> {
+        self.write_group(group)?;
+        self.write_superblock_counts()?;
+        // start from a clean on-disk inode, dropping whatever the last
+        // owner left in the fields this driver does not manage
+        let offset = self.inode_offset(number)?;
+        self.device
+            .write_bytes(offset, &vec![0u8; self.superblock.inode_size as usize])?;
+        self.write_inode(number, inode)
+    }
+
+    fn free_inode(&mut self, number: u32, inode: &mut Inode) -> Result<(), FsError> {
+        self.free_inode_blocks(inode)?;
+        inode.links_count = 0;
+        // fsck reads a dtime below the inode count as an orphan list link,
+        // which a clock that was never set would produce
+        inode.dtime = inode_time().max(self.superblock.inodes_count);
+        self.write_inode(number, inode)?;
+
+        let group = (number - 1) / self.superblock.inodes_per_group;
+        let bit = (number - 1) % self.superblock.inodes_per_group;
+        self.clear_bit(self.groups[group as usize].inode_bitmap, bit)?;
+        let descriptor = &mut self.groups[group as usize];
+        descriptor.free_inodes_count += 1;
+        if inode.is_dir() {
+            descriptor.used_dirs_count -= 1;
+        }
+        self.superblock.free_inodes_count += 1;
+        self.write_group(group)?;
+        self.write_superblock_counts()
+    }
+
+    fn inode_group(&self, number: u32) -> u32 {
+        (number - 1) / self.superblock.inodes_per_group
+    }
+
+    // the slot in i_block and the offsets inside each indirect level
+    // leading to logical block `index`
+    fn block_path(&self, index: u64) -> Result<(usize, Vec<usize>), FsError> {
+        let per_block = (self.block_size() / 4) as u64;
+        if index < DIRECT_BLOCKS as u64 {
+            return Ok((index as usize, Vec::new()));
+        }
+        let index = index - DIRECT_BLOCKS as u64;
+        if index < per_block {
+            return Ok((INDIRECT_BLOCK, vec![index as usize]));
+        }
+        let index = index - per_block;
+        if index < per_block * per_block {
+            return Ok((DOUBLE_INDIRECT_BLOCK, vec![(index / per_block) as usize, (index % per_block) as usize]));
+        }
+        let index = index - per_block * per_block;
+        if index < per_block * per_block * per_block {
+            let path = vec![
+                (index / (per_block * per_block)) as usize,
+                (index / per_block % per_block) as usize,
+                (index % per_block) as usize,
+            ];
+            return Ok((TRIPLE_INDIRECT_BLOCK, path));
+        }
+        Err(FsError::NoSpace)
+    }
+
+    fn read_pointer(&self, table: u32, index: usize) -> Result<u32, FsError> {
+        let mut pointer = [0u8; 4];
+        self.device
+            .read_bytes(table as u64 * self.block_size() as u64 + index as u64 * 4, &mut pointer)?;
+        Ok(u32::from_le_bytes(pointer))
+    }
+
+    fn write_pointer(&self, table: u32, index: usize, block: u32) -> Result<(), FsError> {
+        self.device
+            .write_bytes(table as u64 * self.block_size() as u64 + index as u64 * 4, &block.to_le_bytes())?;
+        Ok(())
+    }
+
+    // physical block of logical block `index`, 0 for a hole
+    fn map_block(&self, inode: &Inode, index: u64) -> Result<u32, FsError> {
+        let (slot, path) = self.block_path(index)?;
+        let mut block = inode.block[slot];
+        for offset in path {
+            if block == 0 {
+                return Ok(0);
+            }
+            block = self.read_pointer(block, offset)?;
+        }
+        Ok(block)
+    }
+
+    // points logical block `index` at `block`, allocating indirect blocks
+    fn set_block(&mut self, inode: &mut Inode, index: u64, block: u32, goal_group: u32) -> Result<(), FsError> {
+        let (slot, path) = self.block_path(index)?;
+        if path.is_empty() {
+            inode.block[slot] = block;
+            return Ok(());
+        }
+        let sectors_per_block = (self.block_size() / 512) as u32;
+        if inode.block[slot] == 0 {
+            inode.block[slot] = self.allocate_block(goal_group)?;
+            inode.sectors += sectors_per_block;
+        }
+        let mut table = inode.block[slot];
+        for (depth, &offset) in path.iter().enumerate() {
+            if depth == path.len() - 1 {
+                return self.write_pointer(table, offset, block);
+            }
+            let mut next = self.read_pointer(table, offset)?;
+            if next == 0 {
+                next = self.allocate_block(goal_group)?;
+                inode.sectors += sectors_per_block;
+                self.write_pointer(table, offset, next)?;
+            }
+            table = next;
+        }
+        Ok(())
+    }
+
+    fn free_tree(&mut self, block: u32, depth: usize) -> Result<(), FsError> {
+        if block == 0 {
+            return Ok(());
+        }
+        if depth > 0 {
+            let table = self.read_block(block)?;
+            for pointer in table.chunks_exact(4).map(|raw| read_u32(raw, 0)) {
+                self.free_tree(pointer, depth - 1)?;
+            }
+        }
+        self.free_block(block)
+    }
+
+    // releases every data and indirect block, leaving an empty inode
+    fn free_inode_blocks(&mut self, inode: &mut Inode) -> Result<(), FsError> {
+        if !self.is_fast_symlink(inode) {
+            for slot in 0..inode.block.len() {
+                let depth = slot.saturating_sub(DIRECT_BLOCKS - 1);
+                self.free_tree(inode.block[slot], depth)?;
+            }
+            inode.block = [0; 15];
+        }
+        inode.sectors = if inode.file_acl != 0 { (self.block_size() / 512) as u32 } else { 0 };
+        inode.size = 0;
+        Ok(())
+    }
+
+    fn is_fast_symlink(&self, inode: &Inode) -> bool {
+        let acl_sectors = if inode.file_acl != 0 { (self.block_size() / 512) as u32 } else { 0 };
+        inode.is_symlink() && inode.sectors == acl_sectors
+    }
+
+    fn read_data(&self, inode: &Inode) -> Result<Vec<u8>, FsError> {
+        let block_size = self.block_size();
+        let mut data = vec![0u8; inode.size as usize];
+        for (index, chunk) in data.chunks_mut(block_size).enumerate() {
+            let block = self.map_block(inode, index as u64)?;
+            // holes read back as zeros
+            if block != 0 {
+                let content = self.read_block(block)?;
+                chunk.copy_from_slice(&content[..chunk.len()]);
+            }
+        }
+        Ok(data)
+    }
+
+    fn write_data(&mut self, number: u32, inode: &mut Inode, data: &[u8]) -> Result<(), FsError> {
+        let goal_group = self.inode_group(number);
+        let sectors_per_block = (self.block_size() / 512) as u32;
+        for (index, chunk) in data.chunks(self.block_size()).enumerate() {
+            let block = self.allocate_block(goal_group)?;
+            inode.sectors += sectors_per_block;
+            self.set_block(inode, index as u64, block, goal_group)?;
+            self.write_block(block, chunk)?;
+        }
+        inode.size = data.len() as u64;
+        Ok(())
+    }
+
+    fn dir_entries(&self, dir: &Inode) -> Result<Vec<RawDirEntry>, FsError> {
+        let block_size = self.block_size();
+        let mut entries = Vec::new();
+        for block_index in 0..dir.size / block_size as u64 {
+            let block = self.map_block(dir, block_index)?;
+            if block == 0 {
+                return Err(FsError::Corrupted("hole in directory"));
+            }
+            let data = self.read_block(block)?;
+            let mut offset = 0;
+            while offset < block_size {
+                let (inode, rec_len, name_len) = parse_dir_header(&data, offset)?;
+                if inode != 0 {
+                    entries.push(RawDirEntry {
+                        inode,
+                        name: String::from_utf8_lossy(&data[offset + DIR_ENTRY_HEADER..offset + DIR_ENTRY_HEADER + name_len]).into(),
+                        block_index,
+                        offset,
+                    });
+                }
+                offset += rec_len;
+            }
+        }
+        Ok(entries)
+    }
+
+    fn find_entry(&self, dir: &Inode, name: &str) -> Result<Option<u32>, FsError> {
+        Ok(self.dir_entries(dir)?.into_iter().find(|entry| entry.name == name).map(|entry| entry.inode))
+    }
+
+    fn add_dir_entry(&mut self, dir_number: u32, dir: &mut Inode, name: &str, inode: u32, file_type: u8) -> Result<(), FsError> {
+        let block_size = self.block_size();
+        let needed = dir_record_len(name.len());
+        for block_index in 0..dir.size / block_size as u64 {
+            let block = self.map_block(dir, block_index)?;
+            let mut data = self.read_block(block)?;
+            let mut offset = 0;
+            while offset < block_size {
+                let (current, rec_len, name_len) = parse_dir_header(&data, offset)?;
+                let used = if current == 0 { 0 } else { dir_record_len(name_len) };
+                if rec_len - used >= needed {
+                    // split the record, the new entry takes the slack at its end
+                    let new_offset = offset + used;
+                    if used != 0 {
+                        data[offset + 4..offset + 6].copy_from_slice(&(used as u16).to_le_bytes());
+                    }
+                    self.write_dir_entry(&mut data, new_offset, rec_len - used, inode, name, file_type);
+                    self.write_block(block, &data)?;
+                    return self.finish_dir_update(dir_number, dir);
+                }
+                offset += rec_len;
+            }
+        }
+
+        // no slack anywhere, append a fresh block to the directory
+        let goal_group = self.inode_group(dir_number);
+        let block = self.allocate_block(goal_group)?;
+        dir.sectors += (block_size / 512) as u32;
+        self.set_block(dir, dir.size / block_size as u64, block, goal_group)?;
+        let mut data = vec![0u8; block_size];
+        self.write_dir_entry(&mut data, 0, block_size, inode, name, file_type);
+        self.write_block(block, &data)?;
+        dir.size += block_size as u64;
+        self.finish_dir_update(dir_number, dir)
+    }
+
+    fn write_dir_entry(&self, data: &mut [u8], offset: usize, rec_len: usize, inode: u32, name: &str, file_type: u8) {
+        data[offset..offset + 4].copy_from_slice(&inode.to_le_bytes());
+        data[offset + 4..offset + 6].copy_from_slice(&(rec_len as u16).to_le_bytes());
+        data[offset + 6] = name.len() as u8;
+        data[offset + 7] = if self.superblock.feature_incompat & INCOMPAT_FILETYPE != 0 { file_type } else { 0 };
+        data[offset + DIR_ENTRY_HEADER..offset + DIR_ENTRY_HEADER + name.len()].copy_from_slice(name.as_bytes());
+    }
+
+    fn remove_dir_entry(&mut self, dir_number: u32, dir: &mut Inode, entry: &RawDirEntry) -> Result<(), FsError> {
+        let block = self.map_block(dir, entry.block_index)?;
+        let mut data = self.read_block(block)?;
+        // merge the record into its predecessor, or clear it when first
+        let mut previous = None;
+        let mut offset = 0;
+        while offset < entry.offset {
+            previous = Some(offset);
+            offset += parse_dir_header(&data, offset)?.1;
+        }
+        match previous {
+            Some(previous) => {
+                let merged = read_u16(&data, previous + 4) + read_u16(&data, entry.offset + 4);
+                data[previous + 4..previous + 6].copy_from_slice(&merged.to_le_bytes());
+            }
+            None => data[entry.offset..entry.offset + 4].copy_from_slice(&0u32.to_le_bytes()),
+        }
+        self.write_block(block, &data)?;
+        self.finish_dir_update(dir_number, dir)
+    }
+
+    // a linear edit invalidates any hashed index, as Linux does
+    fn finish_dir_update(&mut self, dir_number: u32, dir: &mut Inode) -> Result<(), FsError> {
+        dir.flags &= !INDEX_FL;
+        dir.mtime = inode_time();
+        dir.ctime = dir.mtime;
+        self.write_inode(dir_number, dir)
+    }
+
+    fn read_symlink(&self, inode: &Inode) -> Result<String, FsError> {
+        let target = if self.is_fast_symlink(inode) {
+            let mut raw = Vec::with_capacity(FAST_SYMLINK_MAX);
+            for pointer in inode.block.iter() {
+                raw.extend_from_slice(&pointer.to_le_bytes());
+            }
+            raw.truncate(inode.size as usize);
+            raw
+        } else {
+            self.read_data(inode)?
+        };
+        String::from_utf8(target).map_err(|_| FsError::Corrupted("symlink target is not UTF-8"))
+    }
+
+    // walks `path` from the root, following symlinks in every component
+    // and in the last one as well when `follow_last` is set
+    fn lookup(&self, path: &str, follow_last: bool) -> Result<(u32, Inode), FsError> {
+        let mut pending: Vec<String> = components(path).rev().map(String::from).collect();
+        let mut current = ROOT_INODE;
+        let mut inode = self.read_inode(ROOT_INODE)?;
+        let mut links_followed = 0;
+        while let Some(name) = pending.pop() {
+            if !inode.is_dir() {
+                return Err(FsError::NotADirectory);
+            }
+            let child = self.find_entry(&inode, &name)?.ok_or(FsError::NotFound)?;
+            let child_inode = self.read_inode(child)?;
+            if child_inode.is_symlink() && (follow_last || !pending.is_empty()) {
+                links_followed += 1;
+                if links_followed > MAX_SYMLINK_DEPTH {
+                    return Err(FsError::SymlinkLoop);
+                }
+                let target = self.read_symlink(&child_inode)?;
+                if target.starts_with('/') {
+                    current = ROOT_INODE;
+                    inode = self.read_inode(ROOT_INODE)?;
+                }
+                pending.extend(components(&target).rev().map(String::from));
+                continue;
+            }
+            current = child;
+            inode = child_inode;
+        }
+        Ok((current, inode))
+    }
+
+    fn lookup_dir(&self, path: &str) -> Result<(u32, Inode), FsError> {
+        let (number, inode) = self.lookup(path, true)?;
+        if !inode.is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+        Ok((number, inode))
+    }
+
+    // creates a new inode named `name` in the parent of `path`, `init`
+    // fills in its contents before it becomes visible in the directory
+    fn create<F>(&mut self, path: &str, mut inode: Inode, file_type: u8, init: F) -> Result<(u32, u32), FsError>
+    where
+        F: FnOnce(&mut Self, u32, u32, &mut Inode) -> Result<(), FsError>,
+    {
+        self.check_writable()?;
+        let (parent, name) = split_parent(path)?;
+        validate_name(name)?;
+        let (dir_number, mut dir) = self.lookup_dir(parent)?;
+        if self.find_entry(&dir, name)?.is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+        let number = self.allocate_inode(self.inode_group(dir_number), &inode)?;
+        let result = init(self, dir_number, number, &mut inode)
+            .and_then(|_| self.write_inode(number, &inode))
+            .and_then(|_| self.add_dir_entry(dir_number, &mut dir, name, number, file_type));
+        if let Err(error) = result {
+            self.free_inode(number, &mut inode)?;
+            return Err(error);
+        }
+        Ok((dir_number, number))
+    }
+
+    /// Creates a symbolic link at `path` pointing to `target`.
+    pub fn symlink(&mut self, path: &str, target: &str) -> Result<(), FsError> {
+        if target.is_empty() || target.len() >= self.block_size() {
+            return Err(FsError::InvalidName);
+        }
+        self.create(path, Inode::new(S_IFLNK | 0o777), FT_SYMLINK, |fs, _, number, inode| {
+            if target.len() < FAST_SYMLINK_MAX {
+                let mut raw = [0u8; FAST_SYMLINK_MAX];
+                raw[..target.len()].copy_from_slice(target.as_bytes());
+                for (pointer, bytes) in inode.block.iter_mut().zip(raw.chunks_exact(4)) {
+                    *pointer = read_u32(bytes, 0);
+                }
+                inode.size = target.len() as u64;
+                Ok(())
+            } else {
+                fs.write_data(number, inode, target.as_bytes())
+            }
+        })?;
+        Ok(())
+    }
+
+    /// Returns the target of the symbolic link at `path`.
+    pub fn read_link(&self, path: &str) -> Result<String, FsError> {
+        let (_, inode) = self.lookup(path, false)?;
+        if !inode.is_symlink() {
+            return Err(FsError::InvalidName);
+        }
+        self.read_symlink(&inode)
+    }
+}
+
+impl<D: BlockDevice> FileSystem for Ext2<D> {
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, FsError> {
+        let (_, dir) = self.lookup_dir(path)?;
+        let mut entries = Vec::new();
+        for entry in self.dir_entries(&dir)? {
+            if entry.name == "." || entry.name == ".." {
+                continue;
+            }
+            let inode = self.read_inode(entry.inode)?;
+            entries.push(DirEntry {
+                name: entry.name,
+                file_type: inode.file_type(),
+                size: inode.size,
+                modified: Some(DateTime::from_unix(inode.mtime as u64)),
+            });
+        }
+        Ok(entries)
+    }
+
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, FsError> {
+        let (_, inode) = self.lookup(path, true)?;
+        if inode.is_dir() {
+            return Err(FsError::IsADirectory);
+        }
+        self.read_data(&inode)
+    }
+
+    fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), FsError> {
+        self.check_writable()?;
+        let (number, mut inode) = match self.lookup(path, true) {
+            Ok((_, inode)) if inode.is_dir() => return Err(FsError::IsADirectory),
+            Ok(found) => found,
+            Err(FsError::NotFound) => {
+                let (_, number) = self.create(path, Inode::new(S_IFREG | 0o644), FT_REG_FILE, |_, _, _, _| Ok(()))?;
+                (number, self.read_inode(number)?)
+            }
+            Err(error) => return Err(error),
+        };
+        // write the new contents into blocks of their own, the old ones
+        // stay in place until the inode points at the new ones
+        let mut rewritten = inode.clone();
+        rewritten.block = [0; 15];
+        rewritten.sectors = if inode.file_acl != 0 { (self.block_size() / 512) as u32 } else { 0 };
+        rewritten.size = 0;
+        if let Err(error) = self.write_data(number, &mut rewritten, data) {
+            self.free_inode_blocks(&mut rewritten)?;
+            return Err(error);
+        }
+        rewritten.mtime = inode_time();
+        rewritten.ctime = rewritten.mtime;
+        self.write_inode(number, &rewritten)?;
+        self.free_inode_blocks(&mut inode)
+    }
+
+    fn create_dir(&mut self, path: &str) -> Result<(), FsError> {
+        let (parent, _) = self.create(path, Inode::new(S_IFDIR | 0o755), FT_DIR, |fs, parent, number, inode| {
+            let block_size = fs.block_size();
+            let block = fs.allocate_block(fs.inode_group(number))?;
+            inode.block[0] = block;
+            inode.sectors = (block_size / 512) as u32;
+            inode.size = block_size as u64;
+            // one link from the parent entry and one from "."
+            inode.links_count = 2;
+            let mut data = vec![0u8; block_size];
+            fs.write_dir_entry(&mut data, 0, dir_record_len(1), number, ".", FT_DIR);
+            fs.write_dir_entry(&mut data, dir_record_len(1), block_size - dir_record_len(1), parent, "..", FT_DIR);
+            fs.write_block(block, &data)
+        })?;
+
+        // the new ".." entry links back to the parent
+        let mut parent_inode = self.read_inode(parent)?;
+        parent_inode.links_count += 1;
+        parent_inode.ctime = inode_time();
+        self.write_inode(parent, &parent_inode)
+    }
+
+    fn remove(&mut self, path: &str) -> Result<(), FsError> {
+        self.check_writable()?;
+        let (parent, name) = split_parent(path)?;
+        if name == "." || name == ".." {
+            return Err(FsError::InvalidName);
+        }
+        let (dir_number, mut dir) = self.lookup_dir(parent)?;
+        let entry = self
+            .dir_entries(&dir)?
+            .into_iter()
+            .find(|entry| entry.name == name)
+            .ok_or(FsError::NotFound)?;
+        let mut inode = self.read_inode(entry.inode)?;
+        if inode.is_dir() {
+            if self.dir_entries(&inode)?.iter().any(|e| e.name != "." && e.name != "..") {
+                return Err(FsError::DirectoryNotEmpty);
+            }
+            // the child's ".." no longer links to the parent
+            dir.links_count -= 1;
+        }
+        self.remove_dir_entry(dir_number, &mut dir, &entry)?;
+
+        inode.links_count = inode.links_count.saturating_sub(if inode.is_dir() { 2 } else { 1 });
+        if inode.links_count == 0 {
+            self.free_inode(entry.inode, &mut inode)
+        } else {
+            inode.ctime = inode_time();
+            self.write_inode(entry.inode, &inode)
+        }
+    }
+}
+
+fn parse_dir_header(data: &[u8], offset: usize) -> Result<(u32, usize, usize), FsError> {
+    if offset + DIR_ENTRY_HEADER > data.len() {
+        return Err(FsError::Corrupted("directory entry crosses the block"));
+    }
+    let inode = read_u32(data, offset);
+    let rec_len = read_u16(data, offset + 4) as usize;
+    let name_len = data[offset + 6] as usize;
+    if rec_len < DIR_ENTRY_HEADER || !rec_len.is_multiple_of(4) || offset + rec_len > data.len() || DIR_ENTRY_HEADER + name_len > rec_len {
+        return Err(FsError::Corrupted("bad directory entry"));
+    }
+    Ok((inode, rec_len, name_len))
+}
+
+// space a record with a name of `name_len` bytes needs, 4 byte aligned
+fn dir_record_len(name_len: usize) -> usize {
+    (DIR_ENTRY_HEADER + name_len + 3) & !3
+}
+
+fn validate_name(name: &str) -> Result<(), FsError> {
+    if name.is_empty() || name == "." || name == ".." || name.len() > MAX_NAME_LEN || name.contains('\0') {
+        return Err(FsError::InvalidName);
+    }
+    Ok(())
+}
+
+fn count_zero_bits(bitmap: &[u8], bits: u32) -> u32 {
+    (0..bits).filter(|&bit| bitmap[bit as usize / 8] & (1 << (bit % 8)) == 0).count() as u32
+}
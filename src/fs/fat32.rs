@@ -0,0 +1,836 @@
+use super::{components, split_parent, DirEntry, FileSystem, FileType, FsError};
+use crate::block::{BlockDevice, SECTOR_SIZE};
+use crate::time::{self, DateTime};
+use crate::util::le::{read_u16, read_u32};
+use alloc::{format, string::String, vec, vec::Vec};
+
+const BOOT_SIGNATURE: u16 = 0xaa55;
+const FSINFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FSINFO_TRAIL_SIGNATURE: u32 = 0xaa55_0000;
+const FSINFO_UNKNOWN: u32 = 0xffff_ffff;
+
+// only the low 28 bits of a FAT32 entry are used
+const FAT_ENTRY_MASK: u32 = 0x0fff_ffff;
+const FAT_FREE: u32 = 0;
+const FAT_BAD: u32 = 0x0fff_fff7;
+const FAT_END_OF_CHAIN: u32 = 0x0fff_fff8;
+const FAT_END_MARK: u32 = 0x0fff_ffff;
+
+const DIR_ENTRY_SIZE: usize = 32;
+const ENTRY_END: u8 = 0x00;
+const ENTRY_DELETED: u8 = 0xe5;
+// a short name really starting with 0xe5 is stored as 0x05
+const ENTRY_KANJI_E5: u8 = 0x05;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_HIDDEN: u8 = 0x02;
+const ATTR_SYSTEM: u8 = 0x04;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+// NT case flags in byte 12, used for all lowercase 8.3 names
+const CASE_LOWER_BASE: u8 = 0x08;
+const CASE_LOWER_EXT: u8 = 0x10;
+
+const LFN_LAST_ENTRY: u8 = 0x40;
+const LFN_CHARS_PER_ENTRY: usize = 13;
+const LFN_MAX_CHARS: usize = 255;
+// byte offsets of the UCS-2 characters inside a long name entry
+const LFN_CHAR_OFFSETS: [usize; LFN_CHARS_PER_ENTRY] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+/// BIOS parameter block of a FAT32 volume.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bpb {
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    pub fat_count: u8,
+    pub total_sectors: u32,
+    pub fat_size: u32,
+    pub root_cluster: u32,
+    pub fs_info_sector: u16,
+}
+
+impl Bpb {
+    /// Parses the boot sector, rejecting FAT12/16 volumes.
+    pub fn parse(sector: &[u8]) -> Result<Bpb, FsError> {
+        if sector.len() < 512 || read_u16(sector, 510) != BOOT_SIGNATURE {
+            return Err(FsError::Corrupted("missing boot sector signature"));
+        }
+        let bpb = Bpb {
+            bytes_per_sector: read_u16(sector, 11),
+            sectors_per_cluster: sector[13],
+            reserved_sectors: read_u16(sector, 14),
+            fat_count: sector[16],
+            total_sectors: match read_u16(sector, 19) {
+                0 => read_u32(sector, 32),
+                small => small as u32,
+            },
+            fat_size: read_u32(sector, 36),
+            root_cluster: read_u32(sector, 44),
+            fs_info_sector: read_u16(sector, 48),
+        };
+        // FAT32 is recognised by the zero root entry count and 16-bit FAT
+        // size rather than by cluster count, so the tiny images built for
+        // tests (and `mkfs.vfat -F 32` on small disks) still mount
+        if read_u16(sector, 17) != 0 || read_u16(sector, 22) != 0 {
+            return Err(FsError::Corrupted("not a FAT32 volume"));
+        }
+        if !bpb.bytes_per_sector.is_power_of_two() || !(512..=4096).contains(&bpb.bytes_per_sector) {
+            return Err(FsError::Corrupted("invalid bytes per sector"));
+        }
+        if !bpb.sectors_per_cluster.is_power_of_two() {
+            return Err(FsError::Corrupted("invalid sectors per cluster"));
+        }
+        if bpb.reserved_sectors == 0 || bpb.fat_count == 0 || bpb.fat_size == 0 {
+            return Err(FsError::Corrupted("invalid FAT layout"));
+        }
+        Ok(bpb)
+    }
+
+    fn data_start_sector(&self) -> u32 {
+        self.reserved_sectors as u32 + self.fat_count as u32 * self.fat_size
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.bytes_per_sector as usize * self.sectors_per_cluster as usize
+    }
+}
+
+// a directory entry as found on disk together with its location
+#[derive(Debug, Clone)]
+struct RawEntry {
+    name: String,
+    short_name: [u8; 11],
+    attr: u8,
+    cluster: u32,
+    size: u32,
+    modified: Option<DateTime>,
+    // byte offset of the short entry inside the directory
+    offset: usize,
+    // byte offset of the first long name entry, equals `offset` without one
+    lfn_offset: usize,
+}
+
+impl RawEntry {
+    fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+}
+
+// a resolved path: the root directory has no parent entry
+struct Node {
+    cluster: u32,
+    is_dir: bool,
+    size: u32,
+    location: Option<(u32, RawEntry)>,
+}
+
+/// A mounted FAT32 volume.
+pub struct Fat32<D: BlockDevice> {
+    device: D,
+    bpb: Bpb,
+    cluster_count: u32,
+    free_count: u32,
+    next_free: u32,
+    // byte offset of the FSInfo sector, only set when its signatures match
+    fs_info: Option<u64>,
+}
+
+impl<D: BlockDevice> Fat32<D> {
+    /// Mounts the volume on `device`, the free cluster count is taken from
+    /// the FSInfo sector or recounted from the FAT when it is not valid.
+    pub fn mount(device: D) -> Result<Self, FsError> {
+        let mut boot = [0u8; 512];
+        device.read_bytes(0, &mut boot)?;
+        let bpb = Bpb::parse(&boot)?;
+
+        let data_sectors = bpb
+            .total_sectors
+            .checked_sub(bpb.data_start_sector())
+            .ok_or(FsError::Corrupted("FAT larger than volume"))?;
+        let cluster_count = data_sectors / bpb.sectors_per_cluster as u32;
+        let fat_entries = bpb.fat_size as u64 * bpb.bytes_per_sector as u64 / 4;
+        if cluster_count == 0 || cluster_count as u64 + 2 > fat_entries {
+            return Err(FsError::Corrupted("cluster count does not fit the FAT"));
+        }
+        if bpb.total_sectors as u64 * bpb.bytes_per_sector as u64 > device.sector_count() * SECTOR_SIZE as u64 {
+            return Err(FsError::Corrupted("volume larger than device"));
+        }
+
+        let mut fs = Fat32 {
+            device,
+            bpb,
+            cluster_count,
+            free_count: FSINFO_UNKNOWN,
+            next_free: 2,
+            fs_info: None,
+        };
+        if !fs.is_valid_cluster(bpb.root_cluster) {
+            return Err(FsError::Corrupted("invalid root cluster"));
+        }
+        fs.read_fs_info()?;
+        Ok(fs)
+    }
+
+    pub fn bpb(&self) -> &Bpb {
+        &self.bpb
+    }
+
+    /// Number of unallocated data clusters.
+    pub fn free_clusters(&self) -> u32 {
+        self.free_count
+    }
+
+    pub fn cluster_size(&self) -> usize {
+        self.bpb.cluster_size()
+    }
+
+    fn read_fs_info(&mut self) -> Result<(), FsError> {
+        let mut info = vec![0u8; 512];
+        let offset = match self.bpb.fs_info_sector {
+            0 | 0xffff => None,
+            sector => Some(sector as u64 * self.bpb.bytes_per_sector as u64),
+        };
+        let valid = match offset {
+            Some(offset) => {
+                self.device.read_bytes(offset, &mut info)?;
+                read_u32(&info, 0) == FSINFO_LEAD_SIGNATURE
+                    && read_u32(&info, 484) == FSINFO_STRUCT_SIGNATURE
+                    && read_u32(&info, 508) == FSINFO_TRAIL_SIGNATURE
+            }
+            None => false,
+        };
+        if valid {
+            self.fs_info = offset;
+        }
+        let free_count = read_u32(&info, 488);
+        let next_free = read_u32(&info, 492);
+        if valid && free_count <= self.cluster_count {
+            self.free_count = free_count;
+        } else {
+            self.free_count = self.count_free_clusters()?;
+        }
+        if valid && self.is_valid_cluster(next_free) {
+            self.next_free = next_free;
+        }
+        Ok(())
+    }
+
+    // writes the free cluster hints back to the FSInfo sector
+    fn flush_fs_info(&self) -> Result<(), FsError> {
+        if let Some(offset) = self.fs_info {
+            let mut info = [0u8; 8];
+            info[0..4].copy_from_slice(&self.free_count.to_le_bytes());
+            info[4..8].copy_from_slice(&self.next_free.to_le_bytes());
+            self.device.write_bytes(offset + 488, &info)?;
+        }
+        Ok(())
+    }
+
+    fn count_free_clusters(&self) -> Result<u32, FsError> {
+        let mut free = 0;
+        for cluster in 2..self.cluster_count + 2 {
+            if self.read_fat(cluster)? == FAT_FREE {
+                free += 1;
+            }
+        }
+        Ok(free)
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.cluster_count + 2
+    }
+
+    fn fat_entry_offset(&self, fat: u32, cluster: u32) -> u64 {
+        let fat_start = self.bpb.reserved_sectors as u64 + fat as u64 * self.bpb.fat_size as u64;
+        fat_start * self.bpb.bytes_per_sector as u64 + cluster as u64 * 4
+    }
+
+    fn read_fat(&self, cluster: u32) -> Result<u32, FsError> {
+        let mut entry = [0u8; 4];
+        self.device.read_bytes(self.fat_entry_offset(0, cluster), &mut entry)?;
+        Ok(u32::from_le_bytes(entry) & FAT_ENTRY_MASK)
+    }
+
+    // updates the entry in every FAT copy, keeping the reserved high bits
+    fn write_fat(&self, cluster: u32, value: u32) -> Result<(), FsError> {
+        for fat in 0..self.bpb.fat_count as u32 {
+            let offset = self.fat_entry_offset(fat, cluster);
+            let mut entry = [0u8; 4];
+            self.device.read_bytes(offset, &mut entry)?;
+            let old = u32::from_le_bytes(entry);
+            let new = (old & !FAT_ENTRY_MASK) | (value & FAT_ENTRY_MASK);
+            self.device.write_bytes(offset, &new.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        let sector = self.bpb.data_start_sector() as u64
+            + (cluster as u64 - 2) * self.bpb.sectors_per_cluster as u64;
+        sector * self.bpb.bytes_per_sector as u64
+    }
+
+    /// Follows the cluster chain starting at `start`.
+    pub fn cluster_chain(&self, start: u32) -> Result<Vec<u32>, FsError> {
+        let mut chain = Vec::new();
+        let mut cluster = start;
+        while self.is_valid_cluster(cluster) {
+            // a chain longer than the volume must contain a loop
+            if chain.len() as u32 >= self.cluster_count {
+                return Err(FsError::Corrupted("cluster chain loops"));
+            }
+            chain.push(cluster);
+            cluster = self.read_fat(cluster)?;
+        }
+        match cluster {
+            _ if cluster >= FAT_END_OF_CHAIN => Ok(chain),
+            // an empty file has no clusters at all
+            FAT_FREE if chain.is_empty() => Ok(chain),
+            FAT_BAD => Err(FsError::Corrupted("bad cluster in chain")),
+            _ => Err(FsError::Corrupted("cluster chain points outside the volume")),
+        }
+    }
+
+    // takes a free cluster, zeroes it and links it after `previous`
+    fn allocate_cluster(&mut self, previous: Option<u32>) -> Result<u32, FsError> {
+        if self.free_count == 0 {
+            return Err(FsError::NoSpace);
+        }
+        let first = self.cluster_count + 2;
+        let mut cluster = if self.is_valid_cluster(self.next_free) { self.next_free } else { 2 };
+        for _ in 0..self.cluster_count {
+            if self.read_fat(cluster)? == FAT_FREE {
+                self.write_fat(cluster, FAT_END_MARK)?;
+                if let Some(previous) = previous {
+                    self.write_fat(previous, cluster)?;
+                }
+                self.device
+                    .write_bytes(self.cluster_offset(cluster), &vec![0u8; self.cluster_size()])?;
+                self.free_count -= 1;
+                self.next_free = if cluster + 1 < first { cluster + 1 } else { 2 };
+                return Ok(cluster);
+            }
+            cluster = if cluster + 1 < first { cluster + 1 } else { 2 };
+        }
+        // the FSInfo count was wrong
+        self.free_count = 0;
+        Err(FsError::NoSpace)
+    }
+
+    // allocates a chain of `count` clusters and returns its first cluster
+    fn allocate_chain(&mut self, count: usize) -> Result<u32, FsError> {
+        let mut first = 0;
+        let mut previous = None;
+        for _ in 0..count {
+            match self.allocate_cluster(previous) {
+                Ok(cluster) => {
+                    if previous.is_none() {
+                        first = cluster;
+                    }
+                    previous = Some(cluster);
+                }
+                Err(error) => {
+                    // give back what was taken before running out
+                    self.free_chain(first)?;
+                    return Err(error);
+                }
+            }
+        }
+        Ok(first)
+    }
+
+    fn free_chain(&mut self, start: u32) -> Result<(), FsError> {
+        for cluster in self.cluster_chain(start)? {
+            self.write_fat(cluster, FAT_FREE)?;
+            self.free_count += 1;
+            if cluster < self.next_free {
+                self.next_free = cluster;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_chain(&self, start: u32) -> Result<Vec<u8>, FsError> {
+        let chain = self.cluster_chain(start)?;
+        let cluster_size = self.cluster_size();
+        let mut data = vec![0u8; chain.len() * cluster_size];
+        for (chunk, &cluster) in data.chunks_mut(cluster_size).zip(chain.iter()) {
+            self.device.read_bytes(self.cluster_offset(cluster), chunk)?;
+        }
+        Ok(data)
+    }
+
+    // writes `data` over the chain, which must already be long enough
+    fn write_chain(&self, start: u32, data: &[u8]) -> Result<(), FsError> {
+        let chain = self.cluster_chain(start)?;
+        for (chunk, &cluster) in data.chunks(self.cluster_size()).zip(chain.iter()) {
+            self.device.write_bytes(self.cluster_offset(cluster), chunk)?;
+        }
+        Ok(())
+    }
+
+    fn resolve(&self, path: &str) -> Result<Node, FsError> {
+        let mut node = Node {
+            cluster: self.bpb.root_cluster,
+            is_dir: true,
+            size: 0,
+            location: None,
+        };
+        for name in components(path) {
+            if !node.is_dir {
+                return Err(FsError::NotADirectory);
+            }
+            let entry = self.find_entry(node.cluster, name)?.ok_or(FsError::NotFound)?;
+            node = Node {
+                // ".." entries pointing at the root store cluster 0
+                cluster: if entry.is_dir() && entry.cluster == 0 { self.bpb.root_cluster } else { entry.cluster },
+                is_dir: entry.is_dir(),
+                size: entry.size,
+                location: Some((node.cluster, entry)),
+            };
+        }
+        Ok(node)
+    }
+
+    fn resolve_dir(&self, path: &str) -> Result<u32, FsError> {
+        let node = self.resolve(path)?;
+        if !node.is_dir {
+            return Err(FsError::NotADirectory);
+        }
+        Ok(node.cluster)
+    }
+
+    fn find_entry(&self, dir: u32, name: &str) -> Result<Option<RawEntry>, FsError> {
+        let data = self.read_chain(dir)?;
+        Ok(parse_dir(&data).into_iter().find(|entry| {
+            entry.name.eq_ignore_ascii_case(name) || short_display_name(&entry.short_name, 0).eq_ignore_ascii_case(name)
+        }))
+    }
+
+    // adds an entry (plus long name entries when needed) to a directory
+    fn insert_entry(&mut self, dir: u32, name: &str, attr: u8, cluster: u32, size: u32) -> Result<(), FsError> {
+        let mut data = self.read_chain(dir)?;
+        let existing = parse_dir(&data);
+        if existing.iter().any(|entry| entry.name.eq_ignore_ascii_case(name)) {
+            return Err(FsError::AlreadyExists);
+        }
+        let (short_name, case, long_name) = match fits_short_name(name) {
+            Some((short_name, case)) if !existing.iter().any(|entry| entry.short_name == short_name) => {
+                (short_name, case, None)
+            }
+            _ => {
+                let long_name: Vec<u16> = name.encode_utf16().collect();
+                (unique_short_name(name, &existing)?, 0, Some(long_name))
+            }
+        };
+
+        let mut entries = Vec::new();
+        if let Some(long_name) = long_name {
+            let checksum = lfn_checksum(&short_name);
+            let count = long_name.len().div_ceil(LFN_CHARS_PER_ENTRY);
+            for seq in (1..=count).rev() {
+                let mut entry = [0u8; DIR_ENTRY_SIZE];
+                entry[0] = seq as u8 | if seq == count { LFN_LAST_ENTRY } else { 0 };
+                entry[11] = ATTR_LONG_NAME;
+                entry[13] = checksum;
+                for (i, &offset) in LFN_CHAR_OFFSETS.iter().enumerate() {
+                    let index = (seq - 1) * LFN_CHARS_PER_ENTRY + i;
+                    // the name is NUL terminated, then padded with 0xffff
+                    let unit = match index {
+                        _ if index < long_name.len() => long_name[index],
+                        _ if index == long_name.len() => 0,
+                        _ => 0xffff,
+                    };
+                    entry[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+                }
+                entries.extend_from_slice(&entry);
+            }
+        }
+        entries.extend_from_slice(&short_entry(&short_name, attr, case, cluster, size));
+
+        let slot = loop {
+            if let Some(slot) = find_free_slots(&data, entries.len() / DIR_ENTRY_SIZE) {
+                break slot;
+            }
+            // grow the directory one cluster at a time until the entries fit
+            let last = *self.cluster_chain(dir)?.last().ok_or(FsError::Corrupted("empty directory"))?;
+            self.allocate_cluster(Some(last))?;
+            data.resize(data.len() + self.cluster_size(), 0);
+        };
+        data[slot..slot + entries.len()].copy_from_slice(&entries);
+        self.write_chain(dir, &data)?;
+        self.flush_fs_info()
+    }
+
+    fn update_entry(&self, dir: u32, entry: &RawEntry, cluster: u32, size: u32) -> Result<(), FsError> {
+        let mut data = self.read_chain(dir)?;
+        let raw = &mut data[entry.offset..entry.offset + DIR_ENTRY_SIZE];
+        raw[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+        raw[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+        raw[28..32].copy_from_slice(&size.to_le_bytes());
+        stamp(raw, false);
+        self.write_chain(dir, &data)
+    }
+
+    fn delete_entry(&self, dir: u32, entry: &RawEntry) -> Result<(), FsError> {
+        let mut data = self.read_chain(dir)?;
+        for offset in (entry.lfn_offset..=entry.offset).step_by(DIR_ENTRY_SIZE) {
+            data[offset] = ENTRY_DELETED;
+        }
+        self.write_chain(dir, &data)
+    }
+}
+
+impl<D: BlockDevice> FileSystem for Fat32<D> {
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, FsError> {
+        let dir = self.resolve_dir(path)?;
+        let data = self.read_chain(dir)?;
+        Ok(parse_dir(&data)
+            .into_iter()
+            .filter(|entry| entry.name != "." && entry.name != "..")
+            .map(|entry| DirEntry {
+                file_type: if entry.is_dir() { FileType::Directory } else { FileType::File },
+                size: entry.size as u64,
+                modified: entry.modified,
+                name: entry.name,
+            })
+            .collect())
+    }
+
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, FsError> {
+        let node = self.resolve(path)?;
+        if node.is_dir {
+            return Err(FsError::IsADirectory);
+        }
+        let mut data = self.read_chain(node.cluster)?;
+        if data.len() < node.size as usize {
+            return Err(FsError::Corrupted("file shorter than its size"));
+        }
+        data.truncate(node.size as usize);
+        Ok(data)
+    }
+
+    fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), FsError> {
+        if data.len() > u32::MAX as usize {
+            return Err(FsError::NoSpace);
+        }
+        let (parent, name) = split_parent(path)?;
+        let dir = self.resolve_dir(parent)?;
+        let existing = self.find_entry(dir, name)?;
+        if let Some(entry) = &existing {
+            if entry.is_dir() {
+                return Err(FsError::IsADirectory);
+            }
+        } else {
+            validate_name(name)?;
+        }
+
+        let cluster_count = data.len().div_ceil(self.cluster_size());
+        let first = if cluster_count == 0 { 0 } else { self.allocate_chain(cluster_count)? };
+        if first != 0 {
+            self.write_chain(first, data)?;
+        }
+        // the old contents stay until the new ones are written, running
+        // out of space leaves the file as it was
+        match existing {
+            Some(entry) => {
+                self.update_entry(dir, &entry, first, data.len() as u32)?;
+                if entry.cluster != 0 {
+                    self.free_chain(entry.cluster)?;
+                }
+            }
+            None => {
+                if let Err(error) = self.insert_entry(dir, name, ATTR_ARCHIVE, first, data.len() as u32) {
+                    if first != 0 {
+                        self.free_chain(first)?;
+                    }
+                    return Err(error);
+                }
+            }
+        }
+        self.flush_fs_info()
+    }
+
+    fn create_dir(&mut self, path: &str) -> Result<(), FsError> {
+        let (parent, name) = split_parent(path)?;
+        let dir = self.resolve_dir(parent)?;
+        validate_name(name)?;
+        if self.find_entry(dir, name)?.is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let cluster = self.allocate_cluster(None)?;
+        let mut entries = [0u8; 2 * DIR_ENTRY_SIZE];
+        let parent_cluster = if dir == self.bpb.root_cluster { 0 } else { dir };
+        entries[..DIR_ENTRY_SIZE].copy_from_slice(&short_entry(b".          ", ATTR_DIRECTORY, 0, cluster, 0));
+        entries[DIR_ENTRY_SIZE..].copy_from_slice(&short_entry(b"..         ", ATTR_DIRECTORY, 0, parent_cluster, 0));
+        self.device.write_bytes(self.cluster_offset(cluster), &entries)?;
+
+        if let Err(error) = self.insert_entry(dir, name, ATTR_DIRECTORY, cluster, 0) {
+            self.free_chain(cluster)?;
+            return Err(error);
+        }
+        self.flush_fs_info()
+    }
+
+    fn remove(&mut self, path: &str) -> Result<(), FsError> {
+        // "." and ".." are links to directories with entries elsewhere
+        let (_, name) = split_parent(path)?;
+        if name == "." || name == ".." {
+            return Err(FsError::InvalidName);
+        }
+        let node = self.resolve(path)?;
+        let (dir, entry) = node.location.ok_or(FsError::InvalidName)?;
+        if node.is_dir {
+            let data = self.read_chain(node.cluster)?;
+            if parse_dir(&data).iter().any(|e| e.name != "." && e.name != "..") {
+                return Err(FsError::DirectoryNotEmpty);
+            }
+        }
+        self.delete_entry(dir, &entry)?;
+        if entry.cluster != 0 {
+            self.free_chain(entry.cluster)?;
+        }
+        self.flush_fs_info()
+    }
+}
+
+// decodes all live entries of a directory, joining long name fragments
+fn parse_dir(data: &[u8]) -> Vec<RawEntry> {
+    let mut entries = Vec::new();
+    let mut long_name: Vec<u16> = Vec::new();
+    let mut lfn_checksum_expected = 0;
+    let mut lfn_next_seq = 0;
+    let mut lfn_offset = 0;
+
+    for (index, raw) in data.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+        let offset = index * DIR_ENTRY_SIZE;
+        match raw[0] {
+            ENTRY_END => break,
+            ENTRY_DELETED => {
+                long_name.clear();
+                continue;
+            }
+            _ => {}
+        }
+        let attr = raw[11];
+        if attr & 0x3f == ATTR_LONG_NAME {
+            let seq = raw[0] & 0x1f;
+            if raw[0] & LFN_LAST_ENTRY != 0 && seq != 0 {
+                // long names are stored back to front, the last part first
+                long_name = vec![0xffff; seq as usize * LFN_CHARS_PER_ENTRY];
+                lfn_checksum_expected = raw[13];
+                lfn_offset = offset;
+            } else if long_name.is_empty() || seq == 0 || seq != lfn_next_seq || raw[13] != lfn_checksum_expected {
+                long_name.clear();
+                continue;
+            }
+            let start = (seq as usize - 1) * LFN_CHARS_PER_ENTRY;
+            for (i, &char_offset) in LFN_CHAR_OFFSETS.iter().enumerate() {
+                long_name[start + i] = read_u16(raw, char_offset);
+            }
+            lfn_next_seq = seq - 1;
+            continue;
+        }
+        if attr & ATTR_VOLUME_ID != 0 {
+            long_name.clear();
+            continue;
+        }
+
+        let mut short_name = [0u8; 11];
+        short_name.copy_from_slice(&raw[..11]);
+        if short_name[0] == ENTRY_KANJI_E5 {
+            short_name[0] = ENTRY_DELETED;
+        }
+        let has_long_name = lfn_next_seq == 0 && !long_name.is_empty() && lfn_checksum(&raw[..11]) == lfn_checksum_expected;
+        let name = if has_long_name {
+            let end = long_name.iter().position(|&c| c == 0 || c == 0xffff).unwrap_or(long_name.len());
+            char::decode_utf16(long_name[..end].iter().cloned())
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect()
+        } else {
+            short_display_name(&short_name, raw[12])
+        };
+        entries.push(RawEntry {
+            name,
+            short_name,
+            attr,
+            cluster: (read_u16(raw, 20) as u32) << 16 | read_u16(raw, 26) as u32,
+            size: read_u32(raw, 28),
+            modified: from_fat_time(read_u16(raw, 24), read_u16(raw, 22)),
+            offset,
+            lfn_offset: if has_long_name { lfn_offset } else { offset },
+        });
+        long_name.clear();
+    }
+    entries
+}
+
+// formats "NAME    EXT" as "NAME.EXT", honouring the NT lowercase flags
+fn short_display_name(short_name: &[u8; 11], case: u8) -> String {
+    let mut name = String::new();
+    for &byte in short_name[..8].iter().take_while(|&&b| b != b' ') {
+        name.push(if case & CASE_LOWER_BASE != 0 { byte.to_ascii_lowercase() } else { byte } as char);
+    }
+    if short_name[8] != b' ' {
+        name.push('.');
+        for &byte in short_name[8..].iter().take_while(|&&b| b != b' ') {
+            name.push(if case & CASE_LOWER_EXT != 0 { byte.to_ascii_lowercase() } else { byte } as char);
+        }
+    }
+    name
+}
+
+fn short_entry(short_name: &[u8; 11], attr: u8, case: u8, cluster: u32, size: u32) -> [u8; DIR_ENTRY_SIZE] {
+    let mut entry = [0u8; DIR_ENTRY_SIZE];
+    entry[..11].copy_from_slice(short_name);
+    entry[11] = attr;
+    entry[12] = case;
+    entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    entry[28..32].copy_from_slice(&size.to_le_bytes());
+    stamp(&mut entry, true);
+    entry
+}
+
+// FAT dates count years from 1980, times go in two second steps
+fn to_fat_time(time: &DateTime) -> (u16, u16) {
+    if time.year() < 1980 {
+        return (1 << 5 | 1, 0);
+    }
+    let date = (time.year().min(2107) - 1980) << 9 | (time.month() as u16) << 5 | time.day() as u16;
+    let clock = (time.hour() as u16) << 11 | (time.minute() as u16) << 5 | (time.second() as u16 / 2);
+    (date, clock)
+}
+
+fn from_fat_time(date: u16, clock: u16) -> Option<DateTime> {
+    DateTime::new(
+        1980 + (date >> 9),
+        (date >> 5 & 0x0f) as u8,
+        (date & 0x1f) as u8,
+        (clock >> 11) as u8,
+        (clock >> 5 & 0x3f) as u8,
+        (clock & 0x1f) as u8 * 2,
+    )
+}
+
+// sets the write and access times of a short entry, and the creation time
+// of a new one
+fn stamp(entry: &mut [u8], created: bool) {
+    let now = time::now();
+    let (date, clock) = to_fat_time(&now.date_time());
+    if created {
+        // 10 ms units covering the odd second the time field can not hold
+        entry[13] = (now.unix_seconds() % 2 * 100 + now.subsec_nanos() as u64 / 10_000_000) as u8;
+        entry[14..16].copy_from_slice(&clock.to_le_bytes());
+        entry[16..18].copy_from_slice(&date.to_le_bytes());
+    }
+    entry[18..20].copy_from_slice(&date.to_le_bytes());
+    entry[22..24].copy_from_slice(&clock.to_le_bytes());
+    entry[24..26].copy_from_slice(&date.to_le_bytes());
+}
+
+fn lfn_checksum(short_name: &[u8]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, &byte| ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(byte))
+}
+
+// first byte offset of `count` consecutive unused entries
+fn find_free_slots(data: &[u8], count: usize) -> Option<usize> {
+    let mut run = 0;
+    for (index, raw) in data.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+        if raw[0] == ENTRY_END || raw[0] == ENTRY_DELETED {
+            run += 1;
+            if run == count {
+                return Some((index + 1 - count) * DIR_ENTRY_SIZE);
+            }
+        } else {
+            run = 0;
+        }
+    }
+    None
+}
+
+fn validate_name(name: &str) -> Result<(), FsError> {
+    let invalid = |c: char| c < ' ' || "\"*/:<>?\\|".contains(c);
+    if name.is_empty()
+        || name == "."
+        || name == ".."
+        || name.encode_utf16().count() > LFN_MAX_CHARS
+        || name.chars().any(invalid)
+    {
+        return Err(FsError::InvalidName);
+    }
+    Ok(())
+}
+
+fn is_short_char(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"$%'-_@~`!(){}^#&".contains(&byte)
+}
+
+// the 8.3 form of `name` if it can be stored without a long name entry
+fn fits_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = match name.rfind('.') {
+        Some(index) => (&name[..index], &name[index + 1..]),
+        None => (name, ""),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || (ext.is_empty() && name.ends_with('.')) {
+        return None;
+    }
+    let mut short_name = [b' '; 11];
+    let mut case = 0;
+    for (part, field, flag) in [(base, 0, CASE_LOWER_BASE), (ext, 8, CASE_LOWER_EXT)] {
+        if !part.bytes().all(is_short_char) {
+            return None;
+        }
+        let has_lower = part.bytes().any(|b| b.is_ascii_lowercase());
+        let has_upper = part.bytes().any(|b| b.is_ascii_uppercase());
+        match (has_lower, has_upper) {
+            (true, true) => return None,
+            (true, false) => case |= flag,
+            _ => {}
+        }
+        for (i, byte) in part.bytes().enumerate() {
+            short_name[field + i] = byte.to_ascii_uppercase();
+        }
+    }
+    Some((short_name, case))
+}
+
+// builds a "BASE~N.EXT" alias that does not clash with `existing`
+fn unique_short_name(name: &str, existing: &[RawEntry]) -> Result<[u8; 11], FsError> {
+    let to_short = |c: char| match c {
+        c if c.is_ascii() && is_short_char(c as u8) => Some(c.to_ascii_uppercase() as u8),
+        ' ' | '.' => None,
+        _ => Some(b'_'),
+    };
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = match trimmed.rfind('.') {
+        Some(index) => (&trimmed[..index], &trimmed[index + 1..]),
+        None => (trimmed, ""),
+    };
+    let mut base: Vec<u8> = base.chars().filter_map(to_short).collect();
+    let ext: Vec<u8> = ext.chars().filter_map(to_short).take(3).collect();
+    if base.is_empty() {
+        base.push(b'_');
+    }
+
+    for n in 1..1_000_000u32 {
+        let tail = format!("~{}", n);
+        let keep = base.len().min(8 - tail.len());
+        let mut short_name = [b' '; 11];
+        short_name[..keep].copy_from_slice(&base[..keep]);
+        short_name[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        short_name[8..8 + ext.len()].copy_from_slice(&ext);
+        if !existing.iter().any(|entry| entry.short_name == short_name) {
+            return Ok(short_name);
+        }
+    }
+    Err(FsError::AlreadyExists)
+}
@@ -0,0 +1,76 @@
+use crate::block::BlockError;
+use crate::time::DateTime;
+use alloc::{string::String, vec::Vec};
+
+pub mod ext2;
+pub mod fat32;
+pub mod mount;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    /// The underlying block device failed.
+    Block(BlockError),
+    /// The on-disk structures are not a valid filesystem of this type.
+    Corrupted(&'static str),
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    DirectoryNotEmpty,
+    InvalidName,
+    NoSpace,
+    /// Path resolution followed too many symbolic links.
+    SymlinkLoop,
+    /// The volume uses features that only allow mounting it read-only.
+    ReadOnly,
+}
+
+impl From<BlockError> for FsError {
+    fn from(error: BlockError) -> Self {
+        FsError::Block(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Directory,
+    Symlink,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub file_type: FileType,
+    pub size: u64,
+    /// Time of the last change to the contents, `None` when the
+    /// filesystem did not record one.
+    pub modified: Option<DateTime>,
+}
+
+/// Operations every filesystem driver offers, paths are absolute and
+/// use `/` as separator.
+pub trait FileSystem {
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, FsError>;
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, FsError>;
+    /// Creates the file or replaces its contents.
+    fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), FsError>;
+    fn create_dir(&mut self, path: &str) -> Result<(), FsError>;
+    /// Removes a file or an empty directory.
+    fn remove(&mut self, path: &str) -> Result<(), FsError>;
+}
+
+// iterates over the non empty components of a path
+pub(crate) fn components(path: &str) -> impl DoubleEndedIterator<Item = &str> {
+    path.split('/').filter(|c| !c.is_empty())
+}
+
+// splits a path into its parent directory and final component
+pub(crate) fn split_parent(path: &str) -> Result<(&str, &str), FsError> {
+    let path = path.trim_end_matches('/');
+    match path.rfind('/') {
+        Some(index) if !path[index + 1..].is_empty() => Ok((&path[..index], &path[index + 1..])),
+        None if !path.is_empty() => Ok(("", path)),
+        _ => Err(FsError::InvalidName),
+    }
+}
@@ -0,0 +1,98 @@
+use super::{components, FileSystem, FsError};
+use crate::sync::Rcu;
+use alloc::{string::String, sync::Arc, vec::Vec};
+use spin::Mutex;
+
+/// A mounted filesystem. Every path resolved into it shares the same
+/// driver, the lock serializes their operations.
+pub type SharedFs = Arc<Mutex<dyn FileSystem + Send>>;
+
+#[derive(Clone)]
+struct Mount {
+    // normalized, "/" or "/a/b" without a trailing slash
+    path: String,
+    fs: SharedFs,
+}
+
+// looked up on every path, changed only by mount and unmount
+static MOUNTS: Rcu<Vec<Mount>> = Rcu::new(Vec::new());
+
+// "/a//b/" and "a/b" both become "/a/b"
+fn normalize(path: &str) -> String {
+    let mut normalized = String::new();
+    for component in components(path) {
+        normalized.push('/');
+        normalized.push_str(component);
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
+
+// the rest of `path` below `mount_point`, `None` if it lies elsewhere
+fn strip_mount_point<'a>(path: &'a str, mount_point: &str) -> Option<&'a str> {
+    if mount_point == "/" {
+        return Some(path);
+    }
+    let rest = path.strip_prefix(mount_point)?;
+    if rest.is_empty() || rest.starts_with('/') {
+        Some(rest)
+    } else {
+        None
+    }
+}
+
+/// Makes `fs` visible at `path`. Paths below it go to `fs` until it is
+/// unmounted, even if an earlier mount also covers them.
+pub fn mount(path: &str, fs: impl FileSystem + Send + 'static) -> Result<(), FsError> {
+    let path = normalize(path);
+    let fs: SharedFs = Arc::new(Mutex::new(fs));
+    MOUNTS.update(|mounts| {
+        if mounts.iter().any(|mount| mount.path == path) {
+            return Err(FsError::AlreadyExists);
+        }
+        mounts.push(Mount { path, fs });
+        Ok(())
+    })
+}
+
+/// Removes the filesystem mounted at `path` and hands it back. Paths
+/// resolved before keep using it.
+pub fn unmount(path: &str) -> Result<SharedFs, FsError> {
+    let path = normalize(path);
+    MOUNTS.update(|mounts| {
+        let index = mounts.iter().position(|mount| mount.path == path).ok_or(FsError::NotFound)?;
+        Ok(mounts.remove(index).fs)
+    })
+}
+
+/// Finds the filesystem with the longest mount point above `path` and
+/// the path inside it.
+pub fn resolve(path: &str) -> Result<(SharedFs, String), FsError> {
+    let path = normalize(path);
+    let (fs, prefix) = {
+        let mounts = MOUNTS.read();
+        let mount = mounts
+            .iter()
+            .filter(|mount| strip_mount_point(&path, &mount.path).is_some())
+            .max_by_key(|mount| mount.path.len())
+            .ok_or(FsError::NotFound)?;
+        (mount.fs.clone(), mount.path.len())
+    };
+    let rest = if prefix == 1 { &path[..] } else { &path[prefix..] };
+    Ok((fs, normalize(rest)))
+}
+
+/// Mount points, in the order they were mounted.
+pub fn mount_points() -> Vec<String> {
+    MOUNTS.read().iter().map(|mount| mount.path.clone()).collect()
+}
+
+#[test_case]
+fn test_strip_mount_point() {
+    assert_eq!(strip_mount_point("/data/x", "/data"), Some("/x"));
+    assert_eq!(strip_mount_point("/data", "/data"), Some(""));
+    assert_eq!(strip_mount_point("/database", "/data"), None);
+    assert_eq!(strip_mount_point("/database", "/"), Some("/database"));
+}
@@ -0,0 +1,344 @@
+use core::fmt;
+use spin::Mutex;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::VirtAddr;
+use crate::{gdt, log, println};
+
+/// The architectural exceptions of x86_64.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    DivideError,
+    Debug,
+    NonMaskableInterrupt,
+    Breakpoint,
+    Overflow,
+    BoundRangeExceeded,
+    InvalidOpcode,
+    DeviceNotAvailable,
+    DoubleFault,
+    InvalidTss,
+    SegmentNotPresent,
+    StackSegmentFault,
+    GeneralProtectionFault,
+    PageFault,
+    X87FloatingPoint,
+    AlignmentCheck,
+    MachineCheck,
+    SimdFloatingPoint,
+    Virtualization,
+    Security,
+}
+
+impl Exception {
+    pub fn vector(self) -> u8 {
+        match self {
+            Exception::DivideError => 0,
+            Exception::Debug => 1,
+            Exception::NonMaskableInterrupt => 2,
+            Exception::Breakpoint => 3,
+            Exception::Overflow => 4,
+            Exception::BoundRangeExceeded => 5,
+            Exception::InvalidOpcode => 6,
+            Exception::DeviceNotAvailable => 7,
+            Exception::DoubleFault => 8,
+            Exception::InvalidTss => 10,
+            Exception::SegmentNotPresent => 11,
+            Exception::StackSegmentFault => 12,
+            Exception::GeneralProtectionFault => 13,
+            Exception::PageFault => 14,
+            Exception::X87FloatingPoint => 16,
+            Exception::AlignmentCheck => 17,
+            Exception::MachineCheck => 18,
+            Exception::SimdFloatingPoint => 19,
+            Exception::Virtualization => 20,
+            Exception::Security => 30,
+        }
+    }
+
+    /// The name in the manuals, like "GENERAL PROTECTION FAULT".
+    pub fn name(self) -> &'static str {
+        match self {
+            Exception::DivideError => "DIVIDE ERROR",
+            Exception::Debug => "DEBUG",
+            Exception::NonMaskableInterrupt => "NON-MASKABLE INTERRUPT",
+            Exception::Breakpoint => "BREAKPOINT",
+            Exception::Overflow => "OVERFLOW",
+            Exception::BoundRangeExceeded => "BOUND RANGE EXCEEDED",
+            Exception::InvalidOpcode => "INVALID OPCODE",
+            Exception::DeviceNotAvailable => "DEVICE NOT AVAILABLE",
+            Exception::DoubleFault => "DOUBLE FAULT",
+            Exception::InvalidTss => "INVALID TSS",
+            Exception::SegmentNotPresent => "SEGMENT NOT PRESENT",
+            Exception::StackSegmentFault => "STACK-SEGMENT FAULT",
+            Exception::GeneralProtectionFault => "GENERAL PROTECTION FAULT",
+            Exception::PageFault => "PAGE FAULT",
+            Exception::X87FloatingPoint => "X87 FLOATING-POINT EXCEPTION",
+            Exception::AlignmentCheck => "ALIGNMENT CHECK",
+            Exception::MachineCheck => "MACHINE CHECK",
+            Exception::SimdFloatingPoint => "SIMD FLOATING-POINT EXCEPTION",
+            Exception::Virtualization => "VIRTUALIZATION EXCEPTION",
+            Exception::Security => "SECURITY EXCEPTION",
+        }
+    }
+
+    /// The mnemonic, like "#GP".
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Exception::DivideError => "#DE",
+            Exception::Debug => "#DB",
+            Exception::NonMaskableInterrupt => "NMI",
+            Exception::Breakpoint => "#BP",
+            Exception::Overflow => "#OF",
+            Exception::BoundRangeExceeded => "#BR",
+            Exception::InvalidOpcode => "#UD",
+            Exception::DeviceNotAvailable => "#NM",
+            Exception::DoubleFault => "#DF",
+            Exception::InvalidTss => "#TS",
+            Exception::SegmentNotPresent => "#NP",
+            Exception::StackSegmentFault => "#SS",
+            Exception::GeneralProtectionFault => "#GP",
+            Exception::PageFault => "#PF",
+            Exception::X87FloatingPoint => "#MF",
+            Exception::AlignmentCheck => "#AC",
+            Exception::MachineCheck => "#MC",
+            Exception::SimdFloatingPoint => "#XM",
+            Exception::Virtualization => "#VE",
+            Exception::Security => "#SX",
+        }
+    }
+
+    // whether the error code names the segment selector that caused it
+    fn has_selector_error(self) -> bool {
+        matches!(
+            self,
+            Exception::InvalidTss
+                | Exception::SegmentNotPresent
+                | Exception::StackSegmentFault
+                | Exception::GeneralProtectionFault
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorTable {
+    Gdt,
+    Idt,
+    Ldt,
+}
+
+/// The error code of #TS, #NP, #SS and #GP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelectorError {
+    /// The exception happened while delivering an external interrupt.
+    pub external: bool,
+    pub table: DescriptorTable,
+    pub index: u16,
+}
+
+impl SelectorError {
+    pub fn from_error_code(error_code: u64) -> SelectorError {
+        let table = match (error_code >> 1) & 0b11 {
+            0b00 => DescriptorTable::Gdt,
+            0b10 => DescriptorTable::Ldt,
+            // both 01 and 11 mean the IDT
+            _ => DescriptorTable::Idt,
+        };
+        SelectorError {
+            external: error_code & 1 != 0,
+            table,
+            index: ((error_code >> 3) & 0x1fff) as u16,
+        }
+    }
+}
+
+/// What the CPU told about an exception.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaultReport {
+    pub exception: Exception,
+    /// Address of the faulting instruction, for traps like #BP the one after it.
+    pub instruction_pointer: VirtAddr,
+    pub stack_pointer: VirtAddr,
+    pub code_segment: u64,
+    pub cpu_flags: u64,
+    pub error_code: Option<u64>,
+    /// The address a page fault tried to access.
+    pub fault_address: Option<VirtAddr>,
+}
+
+impl FaultReport {
+    fn new(exception: Exception, stack_frame: &InterruptStackFrame, error_code: Option<u64>) -> FaultReport {
+        let fault_address = if exception == Exception::PageFault {
+            Some(x86_64::registers::control::Cr2::read())
+        } else {
+            None
+        };
+        FaultReport {
+            exception,
+            instruction_pointer: stack_frame.instruction_pointer,
+            stack_pointer: stack_frame.stack_pointer,
+            code_segment: stack_frame.code_segment,
+            cpu_flags: stack_frame.cpu_flags,
+            error_code,
+            fault_address,
+        }
+    }
+
+    /// The decoded error code of #TS, #NP, #SS and #GP. An error code of
+    /// 0 means the fault had nothing to do with a selector.
+    pub fn selector_error(&self) -> Option<SelectorError> {
+        match self.error_code {
+            Some(error_code) if error_code != 0 && self.exception.has_selector_error() => {
+                Some(SelectorError::from_error_code(error_code))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FaultReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "EXCEPTION: {} ({}, vector {})", self.exception.name(), self.exception.mnemonic(), self.exception.vector())?;
+        writeln!(f, "  instruction: {:#x}", self.instruction_pointer.as_u64())?;
+        writeln!(f, "  stack:       {:#x}", self.stack_pointer.as_u64())?;
+        writeln!(f, "  code segment: {:#x}  flags: {:#x}", self.code_segment, self.cpu_flags)?;
+        if let Some(address) = self.fault_address {
+            writeln!(f, "  accessed address: {:#x}", address.as_u64())?;
+        }
+        match (self.error_code, self.exception) {
+            (None, _) => Ok(()),
+            (Some(error_code), Exception::PageFault) => {
+                writeln!(f, "  error code: {:?}", PageFaultErrorCode::from_bits_truncate(error_code))
+            }
+            (Some(error_code), _) => match self.selector_error() {
+                Some(selector) => writeln!(
+                    f,
+                    "  error code: {:#x} ({:?} index {}{})",
+                    error_code,
+                    selector.table,
+                    selector.index,
+                    if selector.external { ", external event" } else { "" }
+                ),
+                None => writeln!(f, "  error code: {:#x}", error_code),
+            },
+        }
+    }
+}
+
+static LAST_FAULT: Mutex<Option<FaultReport>> = Mutex::new(None);
+
+/// The report of the last fatal exception, for panic handlers.
+pub fn last_fault() -> Option<FaultReport> {
+    LAST_FAULT.try_lock().and_then(|fault| *fault)
+}
+
+// kernel tasks share the kernel stack and cannot be unwound, so every
+// fault in them takes the kernel down with a report of what happened
+fn fault(report: FaultReport) -> ! {
+    // the fault may have hit while the report was being written
+    if let Some(mut last) = LAST_FAULT.try_lock() {
+        *last = Some(report);
+    }
+    panic!("{}", report);
+}
+
+macro_rules! fault_handler {
+    ($name:ident, $exception:expr) => {
+        extern "x86-interrupt" fn $name(stack_frame: InterruptStackFrame) {
+            fault(FaultReport::new($exception, &stack_frame, None));
+        }
+    };
+    ($name:ident, $exception:expr, error_code) => {
+        extern "x86-interrupt" fn $name(stack_frame: InterruptStackFrame, error_code: u64) {
+            fault(FaultReport::new($exception, &stack_frame, Some(error_code)));
+        }
+    };
+}
+
+fault_handler!(divide_error_handler, Exception::DivideError);
+fault_handler!(overflow_handler, Exception::Overflow);
+fault_handler!(bound_range_exceeded_handler, Exception::BoundRangeExceeded);
+fault_handler!(invalid_opcode_handler, Exception::InvalidOpcode);
+fault_handler!(device_not_available_handler, Exception::DeviceNotAvailable);
+fault_handler!(invalid_tss_handler, Exception::InvalidTss, error_code);
+fault_handler!(segment_not_present_handler, Exception::SegmentNotPresent, error_code);
+fault_handler!(stack_segment_fault_handler, Exception::StackSegmentFault, error_code);
+fault_handler!(general_protection_fault_handler, Exception::GeneralProtectionFault, error_code);
+fault_handler!(x87_floating_point_handler, Exception::X87FloatingPoint);
+fault_handler!(alignment_check_handler, Exception::AlignmentCheck, error_code);
+fault_handler!(simd_floating_point_handler, Exception::SimdFloatingPoint);
+fault_handler!(virtualization_handler, Exception::Virtualization);
+fault_handler!(security_exception_handler, Exception::Security, error_code);
+
+extern "x86-interrupt" fn page_fault_handler(stack_frame: InterruptStackFrame, error_code: PageFaultErrorCode) {
+    fault(FaultReport::new(Exception::PageFault, &stack_frame, Some(error_code.bits())));
+}
+
+extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame, error_code: u64) -> ! {
+    fault(FaultReport::new(Exception::DoubleFault, &stack_frame, Some(error_code)));
+}
+
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    // the handler can not return, so even a recoverable machine check is
+    // fatal, the banks tell what went wrong
+    super::mca::log_banks();
+    fault(FaultReport::new(Exception::MachineCheck, &stack_frame, None));
+}
+
+// the traps below do not stop the kernel
+
+extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn debug_handler(stack_frame: InterruptStackFrame) {
+    println!("EXCEPTION: DEBUG at {:#x}", stack_frame.instruction_pointer.as_u64());
+}
+
+extern "x86-interrupt" fn non_maskable_interrupt_handler(stack_frame: InterruptStackFrame) {
+    log!("WARNING: non-maskable interrupt at {:#x}", stack_frame.instruction_pointer.as_u64());
+}
+
+/// Points the exception entries of `idt` to the handlers above.
+pub(super) fn install(idt: &mut InterruptDescriptorTable) {
+    idt.divide_error.set_handler_fn(divide_error_handler);
+    idt.debug.set_handler_fn(debug_handler);
+    unsafe {
+        idt.non_maskable_interrupt.set_handler_fn(non_maskable_interrupt_handler)
+            .set_stack_index(gdt::NMI_IST_INDEX);
+    }
+    idt.breakpoint.set_handler_fn(breakpoint_handler);
+    idt.overflow.set_handler_fn(overflow_handler);
+    idt.bound_range_exceeded.set_handler_fn(bound_range_exceeded_handler);
+    idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+    idt.device_not_available.set_handler_fn(device_not_available_handler);
+    unsafe {
+        idt.double_fault.set_handler_fn(double_fault_handler)
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+    }
+    idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+    idt.segment_not_present.set_handler_fn(segment_not_present_handler);
+    idt.stack_segment_fault.set_handler_fn(stack_segment_fault_handler);
+    idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+    unsafe {
+        idt.page_fault.set_handler_fn(page_fault_handler)
+            .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
+    }
+    idt.x87_floating_point.set_handler_fn(x87_floating_point_handler);
+    idt.alignment_check.set_handler_fn(alignment_check_handler);
+    unsafe {
+        idt.machine_check.set_handler_fn(machine_check_handler)
+            .set_stack_index(gdt::MACHINE_CHECK_IST_INDEX);
+    }
+    idt.simd_floating_point.set_handler_fn(simd_floating_point_handler);
+    idt.virtualization.set_handler_fn(virtualization_handler);
+    idt.security_exception.set_handler_fn(security_exception_handler);
+}
+
+#[test_case]
+fn test_selector_error() {
+    let selector = SelectorError::from_error_code(0x68);
+    assert_eq!(selector, SelectorError { external: false, table: DescriptorTable::Gdt, index: 13 });
+    let selector = SelectorError::from_error_code(14 * 8 + 0b011);
+    assert_eq!(selector, SelectorError { external: true, table: DescriptorTable::Idt, index: 14 });
+    assert_eq!(SelectorError::from_error_code(0b100).table, DescriptorTable::Ldt);
+}
@@ -1,10 +1,15 @@
 use x86_64::VirtAddr;
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::structures::gdt::{GlobalDescriptorTable,Descriptor,SegmentSelector};
+use x86_64::structures::paging::{mapper::UnmapError, Mapper, Page, Size4KiB};
 use lazy_static::lazy_static;
 
 
 pub const DOUBLE_FAULT_IST_INDEX:u16 = 0;
+pub const NMI_IST_INDEX:u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX:u16 = 2;
+pub const PAGE_FAULT_IST_INDEX:u16 = 3;
+const IST_STACKS:usize = 4;
 
 pub fn init(){
     use x86_64::instructions::tables::load_tss;
@@ -16,6 +21,18 @@ pub fn init(){
     }
 }
 
+const STACK_SIZE:usize = 4096 * 5;
+
+// an interrupt stack with a page below it that is unmapped by
+// `protect_ist_stacks`, so an overflow faults instead of corrupting memory
+#[repr(C, align(4096))]
+struct IstStack{
+    guard:[u8;4096],
+    stack:[u8;STACK_SIZE],
+}
+
+static mut STACKS:[IstStack;IST_STACKS] = [const { IstStack{guard:[0;4096], stack:[0;STACK_SIZE]} }; IST_STACKS];
+
 lazy_static!{
     static ref GDT:(GlobalDescriptorTable,Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
@@ -29,18 +46,40 @@ lazy_static!{
 lazy_static!{
     static ref TSS:TaskStateSegment = {
         let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE:usize = 4096 * 5;
-            static mut STACK:[u8;STACK_SIZE] = [0;STACK_SIZE];
-            let stack_start = VirtAddr::from_ptr(unsafe{ &STACK});
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
+        // the double fault, NMI, machine check and page fault handlers get
+        // stacks of their own, the kernel stack may be what is broken
+        for index in 0..IST_STACKS{
+            let stack_start = VirtAddr::from_ptr(unsafe{ core::ptr::addr_of!(STACKS[index].stack) });
+            tss.interrupt_stack_table[index] = stack_start + STACK_SIZE;
+        }
         tss
     };
 }
 
+/// Unmaps the guard pages below the interrupt stacks, call once the
+/// page tables are accessible.
+pub fn protect_ist_stacks(mapper: &mut impl Mapper<Size4KiB>) -> Result<(), UnmapError>{
+    for index in 0..IST_STACKS{
+        let guard = VirtAddr::from_ptr(unsafe{ core::ptr::addr_of!(STACKS[index].guard) });
+        let (_frame, flush) = mapper.unmap(Page::containing_address(guard))?;
+        flush.flush();
+    }
+    Ok(())
+}
+
 struct Selectors{
     code_selector: SegmentSelector,
     tss_selector: SegmentSelector,
-}
\ No newline at end of file
+}
+
+#[test_case]
+fn test_ist_stacks(){
+    let tops: [VirtAddr; IST_STACKS] = core::array::from_fn(|index| TSS.interrupt_stack_table[index]);
+    for (index, top) in tops.iter().enumerate(){
+        assert!(top.is_aligned(4096u64));
+        // stacks are apart by their size plus the guard page
+        if index > 0 {
+            assert_eq!(*top - tops[index - 1], (STACK_SIZE + 4096) as u64);
+        }
+    }
+}
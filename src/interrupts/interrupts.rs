@@ -1,8 +1,7 @@
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame,PageFaultErrorCode};
+use x86_64::structures::idt::InterruptDescriptorTable;
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
 use spin;
-use crate::{println,gdt, hlt_loop};
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
@@ -10,84 +9,100 @@ pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 pub static PICS: spin::Mutex<ChainedPics> = 
     spin::Mutex::new(unsafe{ChainedPics::new(PIC_1_OFFSET,PIC_2_OFFSET)});
 
-#[derive(Debug, Clone, Copy)]
-#[repr(u8)]
-pub enum InterruptIndex{
-    Timer = PIC_1_OFFSET,
-    Keyboard,
+#[path = "exceptions.rs"]
+pub mod exceptions;
+#[path = "irq.rs"]
+pub mod irq;
+#[path = "mca.rs"]
+pub mod mca;
+
+use irq::IrqReturn;
+
+// the PIC lines of the devices the kernel drives itself
+const TIMER_IRQ: u8 = 0;
+const KEYBOARD_IRQ: u8 = 1;
+const MOUSE_IRQ: u8 = 12;
+
+pub fn init_idt(){
+    IDT.load();
 }
 
-impl InterruptIndex {
-    fn as_u8(self) -> u8{
-        self as u8
-    }
+// IRQ 2 of the master PIC is where the slave PIC is chained in
+const CASCADE_IRQ: u8 = 2;
 
-    fn as_usize(self) -> usize{
-        usize::from(self.as_u8())
-    }
+// the interrupt mask register of the PIC serving `irq`
+fn mask_port(irq: u8) -> x86_64::instructions::port::Port<u8> {
+    x86_64::instructions::port::Port::new(if irq < 8 { 0x21 } else { 0xa1 })
 }
 
+/// Unmasks an interrupt line of the PICs, `irq` counts from 0 to 15.
+pub fn enable_irq(irq: u8){
+    if irq >= 8 {
+        enable_irq(CASCADE_IRQ);
+    }
+    let mut port = mask_port(irq);
+    unsafe {
+        let mask = port.read();
+        port.write(mask & !(1 << (irq % 8)));
+    }
+}
 
+/// Masks an interrupt line of the PICs.
+pub fn disable_irq(irq: u8){
+    let mut port = mask_port(irq);
+    unsafe {
+        let mask = port.read();
+        port.write(mask | 1 << (irq % 8));
+    }
+}
 
-pub fn init_idt(){
-    IDT.load();
+/// Registers the handlers of the timer, the keyboard and, if `ps2::init`
+/// found one, the mouse.
+pub fn register_handlers(){
+    irq::register_irq(TIMER_IRQ, "timer", 0, timer_interrupt_handler)
+        .expect("timer interrupt handler");
+    irq::register_irq(KEYBOARD_IRQ, "keyboard", 0, keyboard_interrupt_handler)
+        .expect("keyboard interrupt handler");
+    if crate::ps2::mouse_present() {
+        irq::register_irq(MOUSE_IRQ, "mouse", 0, mouse_interrupt_handler)
+            .expect("mouse interrupt handler");
+    }
 }
 
 lazy_static!{
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
-        unsafe{
-            idt.double_fault.set_handler_fn(double_fault_handler)
-                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        exceptions::install(&mut idt);
+        // everything past the exceptions goes to the registered handlers
+        for (index, stub) in irq::STUBS.iter().enumerate() {
+            idt[usize::from(irq::FIRST_VECTOR) + index].set_handler_fn(*stub);
         }
-        idt[InterruptIndex::Timer.as_usize()]
-            .set_handler_fn(timer_interrupt_handler);
-        idt[InterruptIndex::Keyboard.as_usize()]
-            .set_handler_fn(keyboard_interrupt_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
         idt
     };
 }
 
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame:InterruptStackFrame){
-    println!("EXCEPTION: BREAKPOINT\n{:#?}",stack_frame);
-}
-
-extern "x86-interrupt" fn double_fault_handler(stack_frame:InterruptStackFrame, _error_code: u64)-> !{
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}",stack_frame);
-}
-
-extern  "x86-interrupt" fn timer_interrupt_handler(
-    _stack_frame:InterruptStackFrame
-){
-    unsafe{
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
-    }
+fn timer_interrupt_handler(_vector: u8) -> IrqReturn {
+    crate::time::tick();
+    IrqReturn::Handled
 }
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame:InterruptStackFrame){
+fn keyboard_interrupt_handler(_vector: u8) -> IrqReturn {
     use x86_64::instructions::port::Port;
 
     let mut port = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
     crate::task::keyboard::add_scancode(scancode);
-
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
-    }
+    IrqReturn::Handled
 }
 
-extern "x86-interrupt" fn page_fault_handler(stack_frame: InterruptStackFrame, error_code:PageFaultErrorCode,){
-    use x86_64::registers::control::Cr2;
-    println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
-    println!("Error Code: {:?}", error_code);
-    println!("{:#?}", stack_frame);
-    hlt_loop();
+fn mouse_interrupt_handler(_vector: u8) -> IrqReturn {
+    use x86_64::instructions::port::Port;
+
+    let mut port = Port::new(0x60);
+    let byte: u8 = unsafe { port.read() };
+    crate::task::mouse::add_byte(byte);
+    IrqReturn::Handled
 }
 
 #[test_case]
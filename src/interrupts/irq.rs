@@ -0,0 +1,322 @@
+use core::{fmt, pin::Pin, task::{Poll, Context}};
+use core::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use futures_util::stream::Stream;
+use futures_util::task::AtomicWaker;
+use crate::sync::Rcu;
+use x86_64::structures::idt::{HandlerFunc, InterruptStackFrame};
+use super::{PICS, PIC_1_OFFSET};
+
+/// The first vector handlers can be registered for, the ones below are
+/// CPU exceptions.
+pub const FIRST_VECTOR: u8 = 32;
+const VECTORS: usize = 256 - FIRST_VECTOR as usize;
+/// Handlers one vector can be shared between.
+pub const MAX_SHARED: usize = 4;
+/// Interrupt lines of the two chained PICs.
+pub const IRQ_LINES: u8 = 16;
+
+/// What a handler did about an interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqReturn {
+    /// The interrupt was not for this handler, another one sharing the
+    /// vector has to deal with it.
+    None,
+    Handled,
+    /// Handled, and the handler's bottom half has work to do.
+    WakeBottomHalf,
+}
+
+/// The top half of an interrupt handler, called with interrupts disabled
+/// and the number of the vector that fired. It must not block or
+/// allocate, slow work belongs in the bottom half.
+pub type Handler = fn(u8) -> IrqReturn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterError {
+    /// CPU exceptions have their own handlers.
+    ReservedVector(u8),
+    InvalidIrq(u8),
+    /// The vector already has `MAX_SHARED` handlers.
+    Full,
+}
+
+/// Identifies a registered handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandlerId {
+    vector: u8,
+    slot: usize,
+    serial: u64,
+}
+
+impl HandlerId {
+    pub fn vector(&self) -> u8 {
+        self.vector
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Registration {
+    name: &'static str,
+    priority: u8,
+    handler: Handler,
+    serial: u64,
+}
+
+// bottom half state of a handler slot
+struct Deferred {
+    pending: AtomicUsize,
+    waker: AtomicWaker,
+}
+
+// read by every interrupt, changed on (un)registration only
+static TABLE: [Rcu<[Option<Registration>; MAX_SHARED]>; VECTORS] =
+    [const { Rcu::new([None; MAX_SHARED]) }; VECTORS];
+static DEFERRED: [[Deferred; MAX_SHARED]; VECTORS] =
+    [const { [const { Deferred { pending: AtomicUsize::new(0), waker: AtomicWaker::new() } }; MAX_SHARED] }; VECTORS];
+static COUNTS: [AtomicU64; VECTORS] = [const { AtomicU64::new(0) }; VECTORS];
+// interrupts no handler claimed
+static UNHANDLED: [AtomicU64; VECTORS] = [const { AtomicU64::new(0) }; VECTORS];
+static NEXT_SERIAL: AtomicU64 = AtomicU64::new(1);
+// the vector dispatched last, 0 after `take_last_vector`
+static LAST_VECTOR: AtomicU8 = AtomicU8::new(0);
+// the vector being dispatched, 0 outside of interrupt handlers
+static CURRENT_VECTOR: AtomicU8 = AtomicU8::new(0);
+
+fn index(vector: u8) -> usize {
+    (vector - FIRST_VECTOR) as usize
+}
+
+fn is_pic_vector(vector: u8) -> bool {
+    (PIC_1_OFFSET..PIC_1_OFFSET + IRQ_LINES).contains(&vector)
+}
+
+/// Registers a handler for `vector`. Handlers sharing a vector run by
+/// descending `priority` until all had a look.
+pub fn register(vector: u8, name: &'static str, priority: u8, handler: Handler) -> Result<HandlerId, RegisterError> {
+    if vector < FIRST_VECTOR {
+        return Err(RegisterError::ReservedVector(vector));
+    }
+    let serial = NEXT_SERIAL.fetch_add(1, Ordering::Relaxed);
+    TABLE[index(vector)].update(|slots| {
+        let slot = slots.iter().position(Option::is_none).ok_or(RegisterError::Full)?;
+        DEFERRED[index(vector)][slot].pending.store(0, Ordering::Relaxed);
+        slots[slot] = Some(Registration { name, priority, handler, serial });
+        Ok(HandlerId { vector, slot, serial })
+    })
+}
+
+/// Registers a handler for a PIC interrupt line and unmasks the line.
+pub fn register_irq(irq: u8, name: &'static str, priority: u8, handler: Handler) -> Result<HandlerId, RegisterError> {
+    if irq >= IRQ_LINES {
+        return Err(RegisterError::InvalidIrq(irq));
+    }
+    let id = register(PIC_1_OFFSET + irq, name, priority, handler)?;
+    super::enable_irq(irq);
+    Ok(id)
+}
+
+/// Removes a handler, a PIC line without handlers left is masked again.
+/// Its bottom half stream ends.
+pub fn unregister(id: HandlerId) {
+    let empty = TABLE[index(id.vector)].update(|slots| {
+        if matches!(slots[id.slot], Some(registration) if registration.serial == id.serial) {
+            slots[id.slot] = None;
+        }
+        slots.iter().all(Option::is_none)
+    });
+    DEFERRED[index(id.vector)][id.slot].waker.wake();
+    if empty && is_pic_vector(id.vector) {
+        super::disable_irq(id.vector - PIC_1_OFFSET);
+    }
+}
+
+/// Called by the IDT entries of all vectors from `FIRST_VECTOR` on.
+fn dispatch(vector: u8) {
+    let index = index(vector);
+    COUNTS[index].fetch_add(1, Ordering::Relaxed);
+    LAST_VECTOR.store(vector, Ordering::Relaxed);
+    let interrupted = CURRENT_VECTOR.swap(vector, Ordering::Relaxed);
+    // handlers may register others, so they run outside the read section
+    let slots = *TABLE[index].read();
+    let mut order: [usize; MAX_SHARED] = core::array::from_fn(|slot| slot);
+    let priority = |slot: &usize| slots[*slot].map_or(0, |registration| registration.priority);
+    order.sort_unstable_by_key(|slot| core::cmp::Reverse(priority(slot)));
+
+    let mut handled = false;
+    for slot in order {
+        let registration = match slots[slot] {
+            Some(registration) => registration,
+            None => continue,
+        };
+        match (registration.handler)(vector) {
+            IrqReturn::None => {}
+            IrqReturn::Handled => handled = true,
+            IrqReturn::WakeBottomHalf => {
+                handled = true;
+                let deferred = &DEFERRED[index][slot];
+                deferred.pending.fetch_add(1, Ordering::Relaxed);
+                deferred.waker.wake();
+            }
+        }
+    }
+    if !handled {
+        UNHANDLED[index].fetch_add(1, Ordering::Relaxed);
+    }
+    if is_pic_vector(vector) {
+        unsafe { PICS.lock().notify_end_of_interrupt(vector) };
+    }
+    CURRENT_VECTOR.store(interrupted, Ordering::Relaxed);
+}
+
+extern "x86-interrupt" fn stub<const VECTOR: u8>(_stack_frame: InterruptStackFrame) {
+    dispatch(VECTOR);
+}
+
+// one entry point per vector, since the CPU does not say which one fired
+macro_rules! stubs {
+    ($($high:literal)*) => {
+        [$(
+            stub::<{ $high * 16 }>, stub::<{ $high * 16 + 1 }>, stub::<{ $high * 16 + 2 }>,
+            stub::<{ $high * 16 + 3 }>, stub::<{ $high * 16 + 4 }>, stub::<{ $high * 16 + 5 }>,
+            stub::<{ $high * 16 + 6 }>, stub::<{ $high * 16 + 7 }>, stub::<{ $high * 16 + 8 }>,
+            stub::<{ $high * 16 + 9 }>, stub::<{ $high * 16 + 10 }>, stub::<{ $high * 16 + 11 }>,
+            stub::<{ $high * 16 + 12 }>, stub::<{ $high * 16 + 13 }>, stub::<{ $high * 16 + 14 }>,
+            stub::<{ $high * 16 + 15 }>,
+        )*]
+    };
+}
+
+/// The IDT entries for vectors `FIRST_VECTOR` to 255.
+pub(super) static STUBS: [HandlerFunc; VECTORS] = stubs!(2 3 4 5 6 7 8 9 10 11 12 13 14 15);
+
+/// How often `vector` fired since boot.
+pub fn count(vector: u8) -> u64 {
+    if vector < FIRST_VECTOR {
+        return 0;
+    }
+    COUNTS[index(vector)].load(Ordering::Relaxed)
+}
+
+/// The vector of the last interrupt since the previous call, to tell what
+/// woke the CPU.
+pub fn take_last_vector() -> Option<u8> {
+    match LAST_VECTOR.swap(0, Ordering::Relaxed) {
+        0 => None,
+        vector => Some(vector),
+    }
+}
+
+/// The vector whose handlers are running, `None` outside of interrupt
+/// handlers.
+pub fn current_vector() -> Option<u8> {
+    match CURRENT_VECTOR.load(Ordering::Relaxed) {
+        0 => None,
+        vector => Some(vector),
+    }
+}
+
+/// Writes a table of the vectors with handlers or interrupts, with their
+/// counts and handler names, for the `interrupts` shell command.
+pub fn write_statistics(out: &mut impl fmt::Write) -> fmt::Result {
+    writeln!(out, "vector  irq       count  unhandled  handlers")?;
+    for vector in FIRST_VECTOR..=255 {
+        let index = index(vector);
+        let slots = *TABLE[index].read();
+        let count = COUNTS[index].load(Ordering::Relaxed);
+        if count == 0 && slots.iter().all(Option::is_none) {
+            continue;
+        }
+        write!(out, "{:>6}  ", vector)?;
+        if is_pic_vector(vector) {
+            write!(out, "{:>3}", vector - PIC_1_OFFSET)?;
+        } else {
+            write!(out, "  -")?;
+        }
+        write!(out, "  {:>10}  {:>9} ", count, UNHANDLED[index].load(Ordering::Relaxed))?;
+        for registration in slots.iter().flatten() {
+            write!(out, " {}", registration.name)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// The bottom half of a handler: yields how often its top half returned
+/// `IrqReturn::WakeBottomHalf` since the last item. Ends once the handler
+/// is unregistered. Spawn a task draining it to run the deferred work.
+pub struct BottomHalf {
+    id: HandlerId,
+}
+
+impl BottomHalf {
+    pub fn new(id: HandlerId) -> Self {
+        BottomHalf { id }
+    }
+
+    fn registered(&self) -> bool {
+        let id = self.id;
+        matches!(TABLE[index(id.vector)].read()[id.slot], Some(registration) if registration.serial == id.serial)
+    }
+}
+
+impl Stream for BottomHalf {
+    type Item = usize;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<usize>> {
+        let deferred = &DEFERRED[index(self.id.vector)][self.id.slot];
+        if !self.registered() {
+            return Poll::Ready(None);
+        }
+        let pending = deferred.pending.swap(0, Ordering::Relaxed);
+        if pending > 0 {
+            return Poll::Ready(Some(pending));
+        }
+
+        deferred.waker.register(cx.waker());
+
+        match deferred.pending.swap(0, Ordering::Relaxed) {
+            0 if self.registered() => Poll::Pending,
+            0 => Poll::Ready(None),
+            pending => {
+                deferred.waker.take();
+                Poll::Ready(Some(pending))
+            }
+        }
+    }
+}
+
+#[test_case]
+fn test_software_interrupt() {
+    use core::sync::atomic::AtomicBool;
+    use futures_util::{stream::StreamExt, task::noop_waker_ref};
+    static LOW_RAN: AtomicBool = AtomicBool::new(false);
+    static HIGH_RAN_FIRST: AtomicBool = AtomicBool::new(false);
+
+    let low = register(200, "low", 1, |_| {
+        LOW_RAN.store(true, Ordering::Relaxed);
+        IrqReturn::WakeBottomHalf
+    })
+    .unwrap();
+    let high = register(200, "high", 9, |vector| {
+        if vector == 200 && !LOW_RAN.load(Ordering::Relaxed) {
+            HIGH_RAN_FIRST.store(true, Ordering::Relaxed);
+        }
+        IrqReturn::None
+    })
+    .unwrap();
+    let mut bottom_half = BottomHalf::new(low);
+    let mut cx = Context::from_waker(noop_waker_ref());
+    assert_eq!(bottom_half.poll_next_unpin(&mut cx), Poll::Pending);
+
+    let before = count(200);
+    unsafe { core::arch::asm!("int 200") };
+    unsafe { core::arch::asm!("int 200") };
+    assert_eq!(count(200), before + 2);
+    assert!(LOW_RAN.load(Ordering::Relaxed) && HIGH_RAN_FIRST.load(Ordering::Relaxed));
+    assert_eq!(bottom_half.poll_next_unpin(&mut cx), Poll::Ready(Some(2)));
+
+    unregister(high);
+    unregister(low);
+    assert_eq!(bottom_half.poll_next_unpin(&mut cx), Poll::Ready(None));
+    assert_eq!(register(3, "exception", 0, |_| IrqReturn::Handled), Err(RegisterError::ReservedVector(3)));
+}
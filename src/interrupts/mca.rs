@@ -0,0 +1,145 @@
+use core::fmt;
+use x86_64::registers::control::{Cr4, Cr4Flags};
+use x86_64::registers::model_specific::Msr;
+use crate::println;
+use crate::time::clocksource::cpuid;
+
+// machine check architecture registers
+const MCG_CAP: u32 = 0x179;
+const MCG_STATUS: u32 = 0x17a;
+// each bank has 4 registers starting here, control, status, address and misc
+const MC0_CTL: u32 = 0x400;
+const MCG_CAP_COUNT: u64 = 0xff;
+const MCG_STATUS_MCIP: u64 = 1 << 2;
+
+const CPUID_FEATURES: u32 = 1;
+// feature bits of CPUID leaf 1 in EDX
+const CPUID_MCE: u32 = 1 << 7;
+const CPUID_MCA: u32 = 1 << 14;
+
+/// The decoded status register of a machine check bank.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BankStatus {
+    /// The register holds an error.
+    pub valid: bool,
+    /// Another error came in before this one was cleared.
+    pub overflow: bool,
+    /// The hardware could not correct the error.
+    pub uncorrected: bool,
+    pub enabled: bool,
+    pub misc_valid: bool,
+    pub address_valid: bool,
+    /// The processor state is corrupt, execution can not go on.
+    pub context_corrupt: bool,
+    /// The architectural error code.
+    pub mca_error: u16,
+    pub model_error: u16,
+}
+
+impl BankStatus {
+    pub fn from_bits(status: u64) -> BankStatus {
+        BankStatus {
+            valid: status & 1 << 63 != 0,
+            overflow: status & 1 << 62 != 0,
+            uncorrected: status & 1 << 61 != 0,
+            enabled: status & 1 << 60 != 0,
+            misc_valid: status & 1 << 59 != 0,
+            address_valid: status & 1 << 58 != 0,
+            context_corrupt: status & 1 << 57 != 0,
+            mca_error: status as u16,
+            model_error: (status >> 16) as u16,
+        }
+    }
+}
+
+/// An error found in a machine check bank.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BankError {
+    pub bank: u8,
+    pub status: BankStatus,
+    pub address: Option<u64>,
+    pub misc: Option<u64>,
+}
+
+impl fmt::Display for BankError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = &self.status;
+        write!(f, "MCA bank {}: error {:#06x} model {:#06x}", self.bank, status.mca_error, status.model_error)?;
+        write!(f, ", {}", if status.uncorrected { "uncorrected" } else { "corrected" })?;
+        if status.context_corrupt {
+            write!(f, ", processor context corrupt")?;
+        }
+        if status.overflow {
+            write!(f, ", overflow")?;
+        }
+        if let Some(address) = self.address {
+            write!(f, ", address {:#x}", address)?;
+        }
+        if let Some(misc) = self.misc {
+            write!(f, ", misc {:#x}", misc)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether the processor has machine check banks.
+pub fn supported() -> bool {
+    cpuid(CPUID_FEATURES).2 & (CPUID_MCE | CPUID_MCA) == CPUID_MCE | CPUID_MCA
+}
+
+fn bank_count() -> u8 {
+    (unsafe { Msr::new(MCG_CAP).read() } & MCG_CAP_COUNT) as u8
+}
+
+/// Reads the error of a bank and clears it, `None` if it has none.
+pub fn take_bank_error(bank: u8) -> Option<BankError> {
+    let base = MC0_CTL + 4 * bank as u32;
+    let mut status_register = Msr::new(base + 1);
+    let status = BankStatus::from_bits(unsafe { status_register.read() });
+    if !status.valid {
+        return None;
+    }
+    let address = if status.address_valid { Some(unsafe { Msr::new(base + 2).read() }) } else { None };
+    let misc = if status.misc_valid { Some(unsafe { Msr::new(base + 3).read() }) } else { None };
+    unsafe { status_register.write(0) };
+    Some(BankError { bank, status, address, misc })
+}
+
+/// Prints and clears the errors of all banks.
+pub fn log_banks() {
+    if !supported() {
+        println!("MCA: no machine check banks");
+        return;
+    }
+    for bank in 0..bank_count() {
+        if let Some(error) = take_bank_error(bank) {
+            println!("{}", error);
+        }
+    }
+    let mut global = Msr::new(MCG_STATUS);
+    let status = unsafe { global.read() };
+    unsafe { global.write(status & !MCG_STATUS_MCIP) };
+}
+
+/// Logs the errors left over from before the boot and turns machine check
+/// exceptions on.
+pub fn init() {
+    if !supported() {
+        return;
+    }
+    for bank in 0..bank_count() {
+        if let Some(error) = take_bank_error(bank) {
+            println!("{} (from before boot)", error);
+        }
+    }
+    unsafe { Cr4::update(|flags| flags.insert(Cr4Flags::MACHINE_CHECK_EXCEPTION)) };
+}
+
+#[test_case]
+fn test_bank_status() {
+    let status = BankStatus::from_bits(1 << 63 | 1 << 61 | 1 << 58 | 0x0012_0151);
+    assert!(status.valid && status.uncorrected && status.address_valid);
+    assert!(!status.overflow && !status.context_corrupt && !status.misc_valid);
+    assert_eq!((status.mca_error, status.model_error), (0x0151, 0x0012));
+    assert!(!BankStatus::from_bits(0x0151).valid);
+}
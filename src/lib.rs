@@ -10,18 +10,35 @@ use core::panic::PanicInfo;
 
 #[path ="modules/uart/serial.rs"]pub mod serial;
 #[path ="modules/vga/vga_buffer.rs"]pub mod vga_buffer;
+#[path = "modules/framebuffer/framebuffer.rs"] pub mod framebuffer;
 #[path = "interrupts/interrupts.rs"] pub mod interrupts;
+#[path = "modules/ps2/ps2.rs"] pub mod ps2;
 #[path = "interrupts/gdt.rs"] pub mod gdt;
 #[path = "memory/memory.rs"] pub mod memory;
 #[path = "memory/allocator.rs"] pub mod allocator;
 #[path = "task/mod.rs"] pub mod task;
+#[path = "block/mod.rs"] pub mod block;
+#[path = "fs/mod.rs"] pub mod fs;
+#[path = "time/mod.rs"] pub mod time;
+#[path = "acpi/acpi.rs"] pub mod acpi;
+#[path = "sync/mod.rs"] pub mod sync;
+#[path = "process/mod.rs"] pub mod process;
+#[path = "shell/mod.rs"] pub mod shell;
+#[path = "util/mod.rs"] pub mod util;
 extern crate alloc;
 
 
 pub fn init(){
     gdt::init();
     interrupts::init_idt();
+    interrupts::mca::init();
     unsafe{interrupts::PICS.lock().initialize()};
+    time::init();
+    if let Err(error) = ps2::init(){
+        log!("WARNING: PS/2 controller initialization failed: {:?}", error);
+    }
+    interrupts::register_handlers();
+    serial::init_interrupts();
     x86_64::instructions::interrupts::enable();
 }
 
@@ -60,6 +77,19 @@ pub fn test_panic_handler(info: &PanicInfo) -> ! {
     hlt_loop();
 }
 
+/// Panic handler of the exception tests, they pass when the kernel
+/// panicked because of `expected`.
+pub fn test_exception_panic_handler(expected: interrupts::exceptions::Exception, info: &PanicInfo) -> ! {
+    match interrupts::exceptions::last_fault() {
+        Some(report) if report.exception == expected => {
+            serial_println!("[ok]");
+            exit_qemu(QemuExitCode::Success);
+            hlt_loop();
+        }
+        _ => test_panic_handler(info),
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
 pub enum QemuExitCode {
@@ -71,6 +101,8 @@ pub enum QemuExitCode {
 pub fn exit_qemu(exit_code: QemuExitCode) {
     use x86_64::instructions::port::Port;
 
+    // the test output must not stay in the transmit buffer
+    serial::flush();
     unsafe {
         let mut port = Port::new(0xf4);
         port.write(exit_code as u32);
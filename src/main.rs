@@ -4,7 +4,7 @@
 #![test_runner(os::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 extern crate alloc;
-use os::{println, task::{Task,executor::Executor},task::keyboard};
+use os::{println, shell::Shell, task::{Task,Priority,executor::Executor},task::{keyboard, workqueue}, vga_buffer::terminal};
 use core::panic::PanicInfo;
 use bootloader::{BootInfo,entry_point};
 
@@ -21,11 +21,30 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     //mapper + frame allocator
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    if let Err(error) = os::gdt::protect_ist_stacks(&mut mapper) {
+        os::log!("WARNING: interrupt stacks have no guard pages: {:?}", error);
+    }
     let mut frame_allocator = unsafe{
         BootInfoFrameAllocator::init(&boot_info.memory_map)
     };
+    if let Err(error) = os::acpi::init(phys_mem_offset) {
+        os::log!("WARNING: no ACPI tables: {:?}", error);
+    }
+    os::time::clocksource::init(&mut mapper, &mut frame_allocator);
+    #[cfg(feature = "tickless")]
+    match os::time::tickless::init(&mut mapper, &mut frame_allocator) {
+        Ok(timer) => os::log!("tickless idle with the {}", timer.name()),
+        Err(error) => os::log!("WARNING: keeping the periodic tick: {:?}", error),
+    }
     //initialize heap mem
     allocator::init_heap(&mut mapper,&mut frame_allocator).expect("heap initialization failed");
+    os::vga_buffer::enable_scrollback();
+    #[cfg(feature = "graphics-console")]
+    if let Err(error) = os::framebuffer::init(&mut mapper, &mut frame_allocator, 800, 600) {
+        println!("graphics console unavailable, staying in text mode: {:?}", error);
+    }
+    // processes get page tables of their own from the frames left
+    unsafe { os::process::address_space::init(phys_mem_offset, frame_allocator) };
     
     //tests
     #[cfg(test)]
@@ -34,8 +53,18 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     //keyboard
 
     let mut executor = Executor::new();
-    executor.spawn(Task::new(example_task()));
-    executor.spawn(Task::new(keyboard::print_keypresses()));
+    // interrupt handlers queue their slow work for this task
+    executor.spawn(Task::new(workqueue::run_worker(0).expect("no work queue for the boot CPU")).named("work").priority(Priority::High));
+    executor.spawn(Task::new(example_task()).named("example"));
+    executor.spawn(Task::new(keyboard::dispatch_keypresses()).named("keyboard"));
+    executor.spawn(Task::new(os::serial::forward_input()).named("serial input"));
+    // the first virtual terminal runs the shell, the others echo what is
+    // typed on them
+    let shell = Shell::new(executor.monitor());
+    executor.spawn(Task::with_terminal(shell.run(), 0).named("shell"));
+    for index in 1..terminal::COUNT{
+        executor.spawn(Task::with_terminal(keyboard::print_keypresses(), index).named("echo"));
+    }
     executor.run();
 }
 
@@ -52,7 +81,10 @@ async fn example_task() {
 #[cfg(not(test))] //use in cargo run 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{}",info);
+    match os::task::current_name() {
+        Some(name) => println!("{} in task {}", info, name),
+        None => println!("{}",info),
+    }
     os::hlt_loop();
 }
 
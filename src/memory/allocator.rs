@@ -12,6 +12,7 @@ use x86_64::{
 //use bump::BumpAllocator;
 //use linked_list::LinkedListAllocator;
 use fixed_size_block::FixedSizeBlockAllocator;
+use crate::sync::{TicketLock, TicketLockGuard};
 pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 100*1024; // 100Kib
 
@@ -44,19 +45,20 @@ pub fn init_heap(
     Ok(())
 }
 
-// A wrappaer around spin::Mutex to permit trait implementations
+// A wrappaer around a ticket lock to permit trait implementations,
+// contending CPUs get the heap in the order they asked for it
 
 pub struct Locked<A>{
-    inner: spin::Mutex<A>,
+    inner: TicketLock<A>,
 }
 
 impl <A> Locked<A>{
     pub const fn new(inner:A) -> Self{
         Locked{
-            inner: spin::Mutex::new(inner),
+            inner: TicketLock::new(inner),
         }
     } 
-    pub fn lock(&self) -> spin::MutexGuard<A>{
+    pub fn lock(&self) -> TicketLockGuard<A>{
         self.inner.lock()
     }
 }
@@ -0,0 +1,102 @@
+use x86_64::instructions::port::Port;
+use x86_64::PhysAddr;
+
+// the Bochs graphics adapter (QEMU's standard VGA) is programmed through
+// an index and a data port
+const DISPI_INDEX: u16 = 0x01ce;
+const DISPI_DATA: u16 = 0x01cf;
+
+const INDEX_ID: u16 = 0;
+const INDEX_XRES: u16 = 1;
+const INDEX_YRES: u16 = 2;
+const INDEX_BPP: u16 = 3;
+const INDEX_ENABLE: u16 = 4;
+const INDEX_VIRT_WIDTH: u16 = 6;
+const INDEX_X_OFFSET: u16 = 8;
+const INDEX_Y_OFFSET: u16 = 9;
+
+// ids from 0xb0c0 on, every version since 0xb0c2 supports 32 bits per pixel
+const ID_MIN: u16 = 0xb0c2;
+const ID_MAX: u16 = 0xb0cf;
+
+const ENABLED: u16 = 0x01;
+const LFB_ENABLED: u16 = 0x40;
+
+/// Largest resolution the adapter accepts.
+pub const MAX_WIDTH: usize = 2560;
+pub const MAX_HEIGHT: usize = 1600;
+
+// PCI ids of the adapter
+const VENDOR_ID: u16 = 0x1234;
+const DEVICE_ID: u16 = 0x1111;
+// where QEMU places the linear framebuffer if PCI does not tell otherwise
+const DEFAULT_FRAMEBUFFER: u64 = 0xfd00_0000;
+
+fn read_register(index: u16) -> u16 {
+    unsafe {
+        Port::<u16>::new(DISPI_INDEX).write(index);
+        Port::<u16>::new(DISPI_DATA).read()
+    }
+}
+
+fn write_register(index: u16, value: u16) {
+    unsafe {
+        Port::<u16>::new(DISPI_INDEX).write(index);
+        Port::<u16>::new(DISPI_DATA).write(value);
+    }
+}
+
+/// Whether a Bochs graphics adapter with 32 bit color support is present.
+pub fn is_present() -> bool {
+    (ID_MIN..=ID_MAX).contains(&read_register(INDEX_ID))
+}
+
+/// Switches to a linear framebuffer mode with 32 bits per pixel, returns
+/// false if the adapter did not accept the resolution.
+pub fn set_mode(width: usize, height: usize) -> bool {
+    if width == 0 || height == 0 || width > MAX_WIDTH || height > MAX_HEIGHT {
+        return false;
+    }
+    write_register(INDEX_ENABLE, 0);
+    write_register(INDEX_XRES, width as u16);
+    write_register(INDEX_YRES, height as u16);
+    write_register(INDEX_BPP, 32);
+    write_register(INDEX_VIRT_WIDTH, width as u16);
+    write_register(INDEX_X_OFFSET, 0);
+    write_register(INDEX_Y_OFFSET, 0);
+    write_register(INDEX_ENABLE, ENABLED | LFB_ENABLED);
+    read_register(INDEX_XRES) == width as u16 && read_register(INDEX_YRES) == height as u16
+}
+
+/// Returns to VGA text mode.
+pub fn disable() {
+    write_register(INDEX_ENABLE, 0);
+}
+
+fn pci_config_read(bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+    let address = 0x8000_0000
+        | (bus as u32) << 16
+        | (device as u32) << 11
+        | (function as u32) << 8
+        | (offset as u32 & 0xfc);
+    unsafe {
+        Port::<u32>::new(0xcf8).write(address);
+        Port::<u32>::new(0xcfc).read()
+    }
+}
+
+/// Physical address of the linear framebuffer, taken from BAR 0 of the
+/// adapter's PCI function.
+pub fn framebuffer_address() -> PhysAddr {
+    for device in 0..32 {
+        let id = pci_config_read(0, device, 0, 0);
+        if id == (DEVICE_ID as u32) << 16 | VENDOR_ID as u32 {
+            // the low bits of a memory BAR are flags
+            let bar = pci_config_read(0, device, 0, 0x10) & !0xf;
+            if bar != 0 {
+                return PhysAddr::new(bar as u64);
+            }
+        }
+    }
+    PhysAddr::new(DEFAULT_FRAMEBUFFER)
+}
@@ -0,0 +1,196 @@
+use super::{font::Font, Framebuffer, FramebufferError, Rgb};
+use crate::vga_buffer::{ansi, Rendition};
+use alloc::{vec, vec::Vec};
+use crate::sync::IrqSpinlock;
+use core::fmt;
+
+// the 16 colors of the VGA text mode palette in ANSI order
+const PALETTE: [Rgb; 16] = [
+    Rgb::new(0x00, 0x00, 0x00),
+    Rgb::new(0xaa, 0x00, 0x00),
+    Rgb::new(0x00, 0xaa, 0x00),
+    Rgb::new(0xaa, 0x55, 0x00),
+    Rgb::new(0x00, 0x00, 0xaa),
+    Rgb::new(0xaa, 0x00, 0xaa),
+    Rgb::new(0x00, 0xaa, 0xaa),
+    Rgb::new(0xaa, 0xaa, 0xaa),
+    Rgb::new(0x55, 0x55, 0x55),
+    Rgb::new(0xff, 0x55, 0x55),
+    Rgb::new(0x55, 0xff, 0x55),
+    Rgb::new(0xff, 0xff, 0x55),
+    Rgb::new(0x55, 0x55, 0xff),
+    Rgb::new(0xff, 0x55, 0xff),
+    Rgb::new(0x55, 0xff, 0xff),
+    Rgb::new(0xff, 0xff, 0xff),
+];
+
+/// The graphics console once `framebuffer::init` set it up.
+pub static CONSOLE: IrqSpinlock<Option<Console>> = IrqSpinlock::new("console", None);
+
+// a character on the screen and its palette colors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    c: char,
+    colors: (usize, usize),
+}
+
+/// A text console drawing with a bitmap font, it understands the same
+/// escape sequences as the VGA text mode writer.
+pub struct Console {
+    screen: Screen,
+    emulator: ansi::Emulator,
+}
+
+// the framebuffer and the cells drawn on it
+struct Screen {
+    framebuffer: Framebuffer,
+    font: Font,
+    columns: usize,
+    rows: usize,
+    // the text on the screen, redrawn from here when scrolling since
+    // reading the framebuffer back is slow
+    cells: Vec<Cell>,
+}
+
+impl Console {
+    /// Fails with `InvalidMode` if not even one glyph fits on the
+    /// framebuffer.
+    pub fn new(framebuffer: Framebuffer, font: Font) -> Result<Console, FramebufferError> {
+        let columns = framebuffer.width() / font.width();
+        let rows = framebuffer.height() / font.height();
+        if columns == 0 || rows == 0 {
+            return Err(FramebufferError::InvalidMode);
+        }
+        let blank = Cell {
+            c: ' ',
+            colors: Rendition::DEFAULT.colors(),
+        };
+        let mut console = Console {
+            screen: Screen {
+                framebuffer,
+                font,
+                columns,
+                rows,
+                cells: vec![blank; columns * rows],
+            },
+            emulator: ansi::Emulator::new(rows, 0),
+        };
+        console.screen.framebuffer.clear(PALETTE[blank.colors.1]);
+        console.draw_cursor();
+        Ok(console)
+    }
+
+    /// Size of the console in characters, (columns, rows).
+    pub fn size(&self) -> (usize, usize) {
+        (self.screen.columns, self.screen.rows)
+    }
+
+    /// The (row, column) the next character is written to.
+    pub fn position(&self) -> (usize, usize) {
+        self.emulator.position()
+    }
+
+    /// The framebuffer the console draws on, for graphics next to the text.
+    pub fn framebuffer(&mut self) -> &mut Framebuffer {
+        &mut self.screen.framebuffer
+    }
+
+    /// Writes UTF-8 text, interpreting the VT100/ANSI escape sequences in it.
+    pub fn write_string(&mut self, s: &str) {
+        // the cell under the cursor loses its underline
+        let (row, col) = self.emulator.position();
+        self.screen.draw_cell(row, col);
+        self.emulator.write_str(&mut self.screen, s);
+        self.draw_cursor();
+    }
+
+    /// Blanks the whole screen and moves the cursor to the top left corner.
+    pub fn clear_screen(&mut self) {
+        self.emulator.clear(&mut self.screen);
+        self.draw_cursor();
+    }
+
+    // an underline in the bottom two pixel rows of the cursor's cell
+    fn draw_cursor(&mut self) {
+        if !self.emulator.cursor_visible() {
+            return;
+        }
+        let screen = &mut self.screen;
+        let (row, col) = self.emulator.position();
+        let col = col.min(screen.columns - 1);
+        let (foreground, _) = self.emulator.rendition().colors();
+        let x = col * screen.font.width();
+        let y = (row + 1) * screen.font.height() - 2;
+        screen.framebuffer.fill_rect(x, y, screen.font.width(), 2, PALETTE[foreground]);
+    }
+}
+
+impl Screen {
+    fn draw_cell(&mut self, row: usize, col: usize) {
+        if row >= self.rows || col >= self.columns {
+            return;
+        }
+        let cell = self.cells[row * self.columns + col];
+        let glyph = self.font.glyph_or_replacement(cell.c);
+        let (x, y) = (col * self.font.width(), row * self.font.height());
+        self.framebuffer
+            .draw_glyph(x, y, &self.font, glyph, PALETTE[cell.colors.0], PALETTE[cell.colors.1]);
+    }
+}
+
+impl ansi::Grid for Screen {
+    fn size(&self) -> (usize, usize) {
+        (self.columns, self.rows)
+    }
+
+    fn put(&mut self, row: usize, col: usize, c: char, rendition: Rendition) {
+        self.cells[row * self.columns + col] = Cell {
+            c,
+            colors: rendition.colors(),
+        };
+        self.draw_cell(row, col);
+    }
+
+    fn erase(&mut self, start: usize, end: usize, rendition: Rendition) {
+        let blank = Cell {
+            c: ' ',
+            colors: rendition.colors(),
+        };
+        for index in start..end {
+            self.cells[index] = blank;
+            self.draw_cell(index / self.columns, index % self.columns);
+        }
+    }
+
+    fn scroll_up(&mut self, top: usize, bottom: usize, rendition: Rendition) {
+        let (first, last) = (top * self.columns, bottom * self.columns);
+        self.cells.copy_within(first + self.columns..last + self.columns, first);
+        let blank = Cell {
+            c: ' ',
+            colors: rendition.colors(),
+        };
+        self.cells[last..last + self.columns].fill(blank);
+        for row in top..=bottom {
+            for col in 0..self.columns {
+                self.draw_cell(row, col);
+            }
+        }
+    }
+}
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_string(s);
+        Ok(())
+    }
+}
+
+/// Makes `console` the target of print!/println!.
+pub fn enable(console: Console) {
+    *CONSOLE.lock() = Some(console);
+}
+
+/// Sends print!/println! output back to the VGA text mode terminals.
+pub fn disable() -> Option<Console> {
+    CONSOLE.lock().take()
+}
@@ -0,0 +1,195 @@
+use crate::util::le::read_u32;
+use alloc::collections::BTreeMap;
+use core::str;
+
+// an 8x16 font with the code page 437 glyphs in their usual places and
+// more of Latin-1 and Latin Extended-A after them, generated from the
+// public domain misc-fixed 8x13 font
+static BUILTIN: &[u8] = include_bytes!("fixed8x16.psf");
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TABLE: u8 = 0x02;
+const PSF1_SEPARATOR: u16 = 0xffff;
+const PSF1_START_SEQUENCE: u16 = 0xfffe;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xff;
+const PSF2_START_SEQUENCE: u8 = 0xfe;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontError {
+    /// Neither the PSF1 nor the PSF2 magic.
+    UnknownFormat,
+    /// The header describes more data than there is.
+    Truncated,
+    InvalidHeader(&'static str),
+}
+
+/// A PC Screen Font (PSF1 or PSF2) bitmap font.
+pub struct Font {
+    glyphs: &'static [u8],
+    glyph_count: usize,
+    glyph_size: usize,
+    width: usize,
+    height: usize,
+    // glyph index for every character of the unicode table, fonts
+    // without one map code points below the glyph count to themselves
+    unicode: Option<BTreeMap<char, usize>>,
+}
+
+impl Font {
+    /// The font compiled into the kernel.
+    pub fn builtin() -> Font {
+        Font::parse(BUILTIN).expect("built in font is invalid")
+    }
+
+    pub fn parse(data: &'static [u8]) -> Result<Font, FontError> {
+        if data.starts_with(&PSF2_MAGIC) {
+            Font::parse_psf2(data)
+        } else if data.starts_with(&PSF1_MAGIC) {
+            Font::parse_psf1(data)
+        } else {
+            Err(FontError::UnknownFormat)
+        }
+    }
+
+    fn parse_psf1(data: &'static [u8]) -> Result<Font, FontError> {
+        if data.len() < 4 {
+            return Err(FontError::Truncated);
+        }
+        let mode = data[2];
+        let height = data[3] as usize;
+        let glyph_count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+        let end = 4 + glyph_count * height;
+        if height == 0 {
+            return Err(FontError::InvalidHeader("glyph height of 0"));
+        }
+        if data.len() < end {
+            return Err(FontError::Truncated);
+        }
+        let unicode = if mode & PSF1_MODE_HAS_TABLE != 0 {
+            // one list of UCS-2 code points per glyph, each ended by 0xffff
+            let mut unicode = BTreeMap::new();
+            let mut glyph = 0;
+            let mut in_sequence = false;
+            for unit in data[end..].chunks_exact(2).map(|unit| u16::from_le_bytes([unit[0], unit[1]])) {
+                match unit {
+                    PSF1_SEPARATOR => {
+                        glyph += 1;
+                        in_sequence = false;
+                    }
+                    // combining sequences are not supported
+                    PSF1_START_SEQUENCE => in_sequence = true,
+                    _ if in_sequence => {}
+                    unit => {
+                        if let Some(c) = char::from_u32(unit as u32) {
+                            unicode.entry(c).or_insert(glyph);
+                        }
+                    }
+                }
+                if glyph == glyph_count {
+                    break;
+                }
+            }
+            Some(unicode)
+        } else {
+            None
+        };
+        Ok(Font {
+            glyphs: &data[4..end],
+            glyph_count,
+            glyph_size: height,
+            width: 8,
+            height,
+            unicode,
+        })
+    }
+
+    fn parse_psf2(data: &'static [u8]) -> Result<Font, FontError> {
+        if data.len() < 32 {
+            return Err(FontError::Truncated);
+        }
+        let header_size = read_u32(data, 8) as usize;
+        let flags = read_u32(data, 12);
+        let glyph_count = read_u32(data, 16) as usize;
+        let glyph_size = read_u32(data, 20) as usize;
+        let height = read_u32(data, 24) as usize;
+        let width = read_u32(data, 28) as usize;
+        if width == 0 || height == 0 {
+            return Err(FontError::InvalidHeader("glyph of zero size"));
+        }
+        if glyph_size < width.div_ceil(8) * height {
+            return Err(FontError::InvalidHeader("glyph size too small for its dimensions"));
+        }
+        let end = glyph_count
+            .checked_mul(glyph_size)
+            .and_then(|size| size.checked_add(header_size))
+            .ok_or(FontError::Truncated)?;
+        if header_size < 32 || data.len() < end {
+            return Err(FontError::Truncated);
+        }
+        let unicode = if flags & PSF2_HAS_UNICODE_TABLE != 0 {
+            // one list of UTF-8 characters per glyph, each ended by 0xff
+            let mut unicode = BTreeMap::new();
+            for (glyph, entry) in data[end..].split(|&byte| byte == PSF2_SEPARATOR).take(glyph_count).enumerate() {
+                // combining sequences follow 0xfe and are not supported
+                let single = entry.split(|&byte| byte == PSF2_START_SEQUENCE).next().unwrap_or(&[]);
+                let text = str::from_utf8(single).map_err(|_| FontError::InvalidHeader("unicode table is not UTF-8"))?;
+                for c in text.chars() {
+                    unicode.entry(c).or_insert(glyph);
+                }
+            }
+            Some(unicode)
+        } else {
+            None
+        };
+        Ok(Font {
+            glyphs: &data[header_size..end],
+            glyph_count,
+            glyph_size,
+            width,
+            height,
+            unicode,
+        })
+    }
+
+    /// Width of a glyph in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Height of a glyph in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Bytes per row of a glyph bitmap, the leftmost pixel is the most
+    /// significant bit of the first byte.
+    pub fn bytes_per_row(&self) -> usize {
+        self.width.div_ceil(8)
+    }
+
+    /// The bitmap for `c`, `None` if the font has no glyph for it.
+    pub fn glyph(&self, c: char) -> Option<&'static [u8]> {
+        let index = match &self.unicode {
+            Some(unicode) => *unicode.get(&c)?,
+            None => c as usize,
+        };
+        if index >= self.glyph_count {
+            return None;
+        }
+        let start = index * self.glyph_size;
+        Some(&self.glyphs[start..start + self.bytes_per_row() * self.height])
+    }
+
+    /// The bitmap for `c`, or a replacement when the font has none.
+    pub fn glyph_or_replacement(&self, c: char) -> &'static [u8] {
+        self.glyph(c)
+            .or_else(|| self.glyph('\u{fffd}'))
+            .or_else(|| self.glyph('■'))
+            .or_else(|| self.glyph('?'))
+            .unwrap_or(&self.glyphs[..self.bytes_per_row() * self.height])
+    }
+}
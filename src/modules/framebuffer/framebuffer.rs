@@ -0,0 +1,226 @@
+use x86_64::{
+    structures::paging::{
+        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB,
+    },
+    VirtAddr,
+};
+
+#[path = "bga.rs"]
+pub mod bga;
+#[path = "console.rs"]
+pub mod console;
+#[path = "font.rs"]
+pub mod font;
+
+/// Where the linear framebuffer is mapped into the kernel's address space.
+pub const FRAMEBUFFER_START: usize = 0x_5555_5555_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Rgb {
+        Rgb { r, g, b }
+    }
+
+    // 32 bit pixels are stored as 0x00RRGGBB
+    fn to_pixel(self) -> u32 {
+        (self.r as u32) << 16 | (self.g as u32) << 8 | self.b as u32
+    }
+
+    fn from_pixel(pixel: u32) -> Rgb {
+        Rgb::new((pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8)
+    }
+}
+
+/// A linear framebuffer with 32 bits per pixel. Drawing is clipped to its
+/// bounds, anything outside is silently dropped.
+pub struct Framebuffer {
+    pixels: *mut u32,
+    width: usize,
+    height: usize,
+    // pixels from the start of one row to the start of the next
+    stride: usize,
+}
+
+// the framebuffer is only reached through the console's lock
+unsafe impl Send for Framebuffer {}
+
+impl Framebuffer {
+    /// # Safety
+    ///
+    /// `pixels` must point to `stride * height` writable pixels that
+    /// nothing else accesses while the framebuffer exists.
+    pub unsafe fn new(pixels: *mut u32, width: usize, height: usize, stride: usize) -> Framebuffer {
+        assert!(width <= stride, "rows overlap");
+        Framebuffer {
+            pixels,
+            width,
+            height,
+            stride,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Rgb) {
+        if x < self.width && y < self.height {
+            unsafe { self.pixels.add(y * self.stride + x).write_volatile(color.to_pixel()) };
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Option<Rgb> {
+        if x < self.width && y < self.height {
+            Some(Rgb::from_pixel(unsafe { self.pixels.add(y * self.stride + x).read_volatile() }))
+        } else {
+            None
+        }
+    }
+
+    pub fn clear(&mut self, color: Rgb) {
+        self.fill_rect(0, 0, self.width, self.height, color);
+    }
+
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Rgb) {
+        let pixel = color.to_pixel();
+        let right = x.saturating_add(width).min(self.width);
+        let bottom = y.saturating_add(height).min(self.height);
+        for row in y..bottom {
+            for col in x..right {
+                unsafe { self.pixels.add(row * self.stride + col).write_volatile(pixel) };
+            }
+        }
+    }
+
+    /// Draws the outline of a rectangle.
+    pub fn draw_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Rgb) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.fill_rect(x, y, width, 1, color);
+        self.fill_rect(x, y + height - 1, width, 1, color);
+        self.fill_rect(x, y, 1, height, color);
+        self.fill_rect(x + width - 1, y, 1, height, color);
+    }
+
+    /// Draws a line between two points, both included, which may lie
+    /// outside the framebuffer.
+    pub fn draw_line(&mut self, from: (isize, isize), to: (isize, isize), color: Rgb) {
+        // Bresenham's algorithm for all octants
+        let (mut x, mut y) = from;
+        let dx = (to.0 - x).abs();
+        let dy = -(to.1 - y).abs();
+        let step_x = if x < to.0 { 1 } else { -1 };
+        let step_y = if y < to.1 { 1 } else { -1 };
+        let mut error = dx + dy;
+        loop {
+            if x >= 0 && y >= 0 {
+                self.set_pixel(x as usize, y as usize, color);
+            }
+            if (x, y) == to {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// Copies an image of `width` pixels per row to (x, y).
+    pub fn blit(&mut self, x: usize, y: usize, width: usize, image: &[Rgb]) {
+        if width == 0 {
+            return;
+        }
+        for (row, line) in image.chunks(width).enumerate() {
+            for (col, &color) in line.iter().enumerate() {
+                self.set_pixel(x + col, y + row, color);
+            }
+        }
+    }
+
+    /// Draws a font bitmap with its top left corner at (x, y).
+    pub fn draw_glyph(&mut self, x: usize, y: usize, font: &font::Font, glyph: &[u8], foreground: Rgb, background: Rgb) {
+        let bytes_per_row = font.bytes_per_row();
+        for (row, bits) in glyph.chunks(bytes_per_row).enumerate() {
+            for col in 0..font.width() {
+                let set = bits[col / 8] & (0x80 >> (col % 8)) != 0;
+                self.set_pixel(x + col, y + row, if set { foreground } else { background });
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FramebufferError {
+    /// There is no Bochs/QEMU graphics adapter.
+    Unsupported,
+    /// The adapter rejected the resolution.
+    InvalidMode,
+    Map(MapToError<Size4KiB>),
+}
+
+impl From<MapToError<Size4KiB>> for FramebufferError {
+    fn from(error: MapToError<Size4KiB>) -> Self {
+        FramebufferError::Map(error)
+    }
+}
+
+/// Switches the display to a `width` x `height` framebuffer and sends
+/// print!/println! output to a text console on it. Must only be called
+/// once, after the heap is initialized.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    width: usize,
+    height: usize,
+) -> Result<(), FramebufferError> {
+    if !bga::is_present() {
+        return Err(FramebufferError::Unsupported);
+    }
+    let physical = bga::framebuffer_address();
+    if !bga::set_mode(width, height) {
+        bga::disable();
+        return Err(FramebufferError::InvalidMode);
+    }
+
+    // the framebuffer is device memory and must not be cached
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+    let size = (width * height * 4) as u64;
+    for offset in (0..size).step_by(4096) {
+        let page = Page::containing_address(VirtAddr::new(FRAMEBUFFER_START as u64 + offset));
+        let frame = PhysFrame::containing_address(physical + offset);
+        match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+            Ok(flush) => flush.flush(),
+            Err(error) => {
+                bga::disable();
+                return Err(error.into());
+            }
+        }
+    }
+
+    let framebuffer = unsafe { Framebuffer::new(FRAMEBUFFER_START as *mut u32, width, height, width) };
+    match console::Console::new(framebuffer, font::Font::builtin()) {
+        Ok(console) => console::enable(console),
+        Err(error) => {
+            bga::disable();
+            return Err(error);
+        }
+    }
+    Ok(())
+}
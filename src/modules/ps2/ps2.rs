@@ -0,0 +1,254 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::instructions::port::Port;
+
+// the 8042 PS/2 controller
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const COMMAND_PORT: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 0x01;
+const STATUS_INPUT_FULL: u8 = 0x02;
+
+const COMMAND_READ_CONFIG: u8 = 0x20;
+const COMMAND_WRITE_CONFIG: u8 = 0x60;
+const COMMAND_DISABLE_SECOND: u8 = 0xa7;
+const COMMAND_ENABLE_SECOND: u8 = 0xa8;
+const COMMAND_TEST_SECOND: u8 = 0xa9;
+const COMMAND_SELF_TEST: u8 = 0xaa;
+const COMMAND_TEST_FIRST: u8 = 0xab;
+const COMMAND_DISABLE_FIRST: u8 = 0xad;
+const COMMAND_ENABLE_FIRST: u8 = 0xae;
+// the next data byte goes to the second port
+const COMMAND_WRITE_SECOND: u8 = 0xd4;
+
+const CONFIG_FIRST_IRQ: u8 = 0x01;
+const CONFIG_SECOND_IRQ: u8 = 0x02;
+const CONFIG_SECOND_CLOCK_DISABLED: u8 = 0x20;
+// the controller translates scancode set 2 into set 1
+const CONFIG_TRANSLATION: u8 = 0x40;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+// commands both devices understand
+const DEVICE_RESET: u8 = 0xff;
+const DEVICE_SET_DEFAULTS: u8 = 0xf6;
+const DEVICE_ENABLE_REPORTING: u8 = 0xf4;
+const DEVICE_SET_SAMPLE_RATE: u8 = 0xf3;
+const DEVICE_GET_ID: u8 = 0xf2;
+const KEYBOARD_SCANCODE_SET: u8 = 0xf0;
+
+const REPLY_ACK: u8 = 0xfa;
+const REPLY_RESEND: u8 = 0xfe;
+const REPLY_RESET_PASSED: u8 = 0xaa;
+
+// a mouse with a scroll wheel reports this id and sends 4 byte packets
+const MOUSE_ID_WHEEL: u8 = 0x03;
+
+// polls of the status register before giving up on the hardware
+const TIMEOUT: usize = 100_000;
+
+static MOUSE_PRESENT: AtomicBool = AtomicBool::new(false);
+static MOUSE_WHEEL: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Error {
+    /// The controller or a device did not respond in time.
+    Timeout,
+    /// The controller self test returned this instead of 0x55.
+    SelfTestFailed(u8),
+    /// The interface test of the first (keyboard) port failed.
+    KeyboardPortFailed(u8),
+    /// A device answered a command with this instead of an ACK.
+    NoAck(u8),
+    /// The keyboard supports neither scancode set 1 nor set 2.
+    ScancodeSet,
+}
+
+fn status() -> u8 {
+    unsafe { Port::<u8>::new(STATUS_PORT).read() }
+}
+
+fn wait_input_empty() -> Result<(), Ps2Error> {
+    for _ in 0..TIMEOUT {
+        if status() & STATUS_INPUT_FULL == 0 {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err(Ps2Error::Timeout)
+}
+
+fn read_data() -> Result<u8, Ps2Error> {
+    for _ in 0..TIMEOUT {
+        if status() & STATUS_OUTPUT_FULL != 0 {
+            return Ok(unsafe { Port::<u8>::new(DATA_PORT).read() });
+        }
+        core::hint::spin_loop();
+    }
+    Err(Ps2Error::Timeout)
+}
+
+fn write_command(command: u8) -> Result<(), Ps2Error> {
+    wait_input_empty()?;
+    unsafe { Port::<u8>::new(COMMAND_PORT).write(command) };
+    Ok(())
+}
+
+fn write_data(byte: u8) -> Result<(), Ps2Error> {
+    wait_input_empty()?;
+    unsafe { Port::<u8>::new(DATA_PORT).write(byte) };
+    Ok(())
+}
+
+fn read_config() -> Result<u8, Ps2Error> {
+    write_command(COMMAND_READ_CONFIG)?;
+    read_data()
+}
+
+fn write_config(config: u8) -> Result<(), Ps2Error> {
+    write_command(COMMAND_WRITE_CONFIG)?;
+    write_data(config)
+}
+
+/// Sends a byte to the keyboard without waiting for its reply, which
+/// arrives through IRQ 1 like a scancode.
+pub fn send_keyboard(byte: u8) -> Result<(), Ps2Error> {
+    write_data(byte)
+}
+
+/// Sends a byte to the mouse without waiting for its reply.
+pub fn send_mouse(byte: u8) -> Result<(), Ps2Error> {
+    write_command(COMMAND_WRITE_SECOND)?;
+    write_data(byte)
+}
+
+// sends a command to a device and polls for the ACK, only usable while
+// the device interrupts are off
+fn command(second: bool, byte: u8) -> Result<(), Ps2Error> {
+    for _ in 0..3 {
+        if second {
+            send_mouse(byte)?;
+        } else {
+            send_keyboard(byte)?;
+        }
+        match read_data()? {
+            REPLY_ACK => return Ok(()),
+            REPLY_RESEND => continue,
+            reply => return Err(Ps2Error::NoAck(reply)),
+        }
+    }
+    Err(Ps2Error::NoAck(REPLY_RESEND))
+}
+
+fn reset(second: bool) -> Result<(), Ps2Error> {
+    command(second, DEVICE_RESET)?;
+    match read_data()? {
+        REPLY_RESET_PASSED => {}
+        reply => return Err(Ps2Error::NoAck(reply)),
+    }
+    // a mouse sends its id after the self test, keyboards usually nothing
+    while status() & STATUS_OUTPUT_FULL != 0 {
+        unsafe { Port::<u8>::new(DATA_PORT).read() };
+    }
+    Ok(())
+}
+
+// asks the keyboard for scancode set 1 and returns whether it uses it now
+fn keyboard_uses_set_1() -> Result<bool, Ps2Error> {
+    command(false, KEYBOARD_SCANCODE_SET)?;
+    command(false, 1)?;
+    command(false, KEYBOARD_SCANCODE_SET)?;
+    command(false, 0)?;
+    Ok(read_data()? == 1)
+}
+
+// the keyboard service expects scancode set 1, which keyboards either
+// send themselves or the controller translates from set 2
+fn negotiate_scancode_set(config: &mut u8) -> Result<(), Ps2Error> {
+    write_config(*config & !CONFIG_TRANSLATION)?;
+    if keyboard_uses_set_1().unwrap_or(false) {
+        *config &= !CONFIG_TRANSLATION;
+        return Ok(());
+    }
+    *config |= CONFIG_TRANSLATION;
+    write_config(*config)?;
+    command(false, KEYBOARD_SCANCODE_SET)?;
+    command(false, 2).map_err(|_| Ps2Error::ScancodeSet)
+}
+
+// turns the wheel of an IntelliMouse on with its magic sample rate sequence
+fn enable_wheel() -> Result<bool, Ps2Error> {
+    for rate in [200, 100, 80] {
+        command(true, DEVICE_SET_SAMPLE_RATE)?;
+        command(true, rate)?;
+    }
+    command(true, DEVICE_GET_ID)?;
+    Ok(read_data()? == MOUSE_ID_WHEEL)
+}
+
+fn init_mouse() -> Result<(), Ps2Error> {
+    if write_command(COMMAND_TEST_SECOND).and_then(|_| read_data())? != PORT_TEST_PASSED {
+        return Ok(());
+    }
+    write_command(COMMAND_ENABLE_SECOND)?;
+    reset(true)?;
+    command(true, DEVICE_SET_DEFAULTS)?;
+    MOUSE_WHEEL.store(enable_wheel()?, Ordering::Relaxed);
+    command(true, DEVICE_ENABLE_REPORTING)?;
+    MOUSE_PRESENT.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Tests and configures the PS/2 controller, resets the keyboard and
+/// the mouse if there is one, then turns on their interrupts. Must run
+/// with interrupts disabled.
+pub fn init() -> Result<(), Ps2Error> {
+    write_command(COMMAND_DISABLE_FIRST)?;
+    write_command(COMMAND_DISABLE_SECOND)?;
+    // throw away whatever is still in the output buffer
+    while status() & STATUS_OUTPUT_FULL != 0 {
+        unsafe { Port::<u8>::new(DATA_PORT).read() };
+    }
+
+    let mut config = read_config()?;
+    // a second port is there if disabling it shows in the configuration
+    let dual = config & CONFIG_SECOND_CLOCK_DISABLED != 0;
+    config &= !(CONFIG_FIRST_IRQ | CONFIG_SECOND_IRQ);
+    write_config(config)?;
+
+    write_command(COMMAND_SELF_TEST)?;
+    match read_data()? {
+        SELF_TEST_PASSED => {}
+        result => return Err(Ps2Error::SelfTestFailed(result)),
+    }
+    // the self test may reset the controller
+    write_config(config)?;
+
+    write_command(COMMAND_TEST_FIRST)?;
+    match read_data()? {
+        PORT_TEST_PASSED => {}
+        result => return Err(Ps2Error::KeyboardPortFailed(result)),
+    }
+    write_command(COMMAND_ENABLE_FIRST)?;
+    reset(false)?;
+    negotiate_scancode_set(&mut config)?;
+    command(false, DEVICE_ENABLE_REPORTING)?;
+
+    // a missing or broken mouse leaves the keyboard working
+    if dual && init_mouse().is_ok() && MOUSE_PRESENT.load(Ordering::Relaxed) {
+        config |= CONFIG_SECOND_IRQ;
+        config &= !CONFIG_SECOND_CLOCK_DISABLED;
+    }
+    write_config(config | CONFIG_FIRST_IRQ)
+}
+
+/// Whether `init` found a mouse.
+pub fn mouse_present() -> bool {
+    MOUSE_PRESENT.load(Ordering::Relaxed)
+}
+
+/// Whether the mouse sends 4 byte packets with wheel movement.
+pub fn mouse_has_wheel() -> bool {
+    MOUSE_WHEEL.load(Ordering::Relaxed)
+}
@@ -1,26 +1,503 @@
-use uart_16550::SerialPort;
-use spin::Mutex;
+use core::{fmt, pin::Pin, task::{Poll, Context}};
+use futures_util::stream::Stream;
+use futures_util::task::AtomicWaker;
+use crate::sync::IrqSpinlock;
 use lazy_static::lazy_static;
+use x86_64::instructions::port::Port;
 
+// registers of a 16550 UART, relative to its base port
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+// with the divisor latch bit of the line control register set, the
+// first two registers hold the baud rate divisor instead
+const DIVISOR_LOW: u16 = 0;
+const DIVISOR_HIGH: u16 = 1;
+const INTERRUPT_ID: u16 = 2;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+const MODEM_STATUS: u16 = 6;
+const SCRATCH: u16 = 7;
+
+const INTERRUPT_RX: u8 = 0x01;
+const INTERRUPT_TX_EMPTY: u8 = 0x02;
+const LINE_DIVISOR_LATCH: u8 = 0x80;
+// enable and clear both FIFOs, interrupt once 14 bytes arrived
+const FIFO_ENABLE: u8 = 0xc7;
+const FIFO_SIZE: usize = 16;
+// DTR, RTS and OUT2, which connects the interrupt line to the PIC
+const MODEM_READY: u8 = 0x0b;
+const MODEM_LOOPBACK: u8 = 0x1e;
+const STATUS_DATA_READY: u8 = 0x01;
+const STATUS_TX_EMPTY: u8 = 0x20;
+const STATUS_IDLE: u8 = 0x40;
+const ID_NONE_PENDING: u8 = 0x01;
+
+// the UART clock divided by the baud rate is the divisor
+const CLOCK: u32 = 115_200;
+// polls of the line status before deciding the port is stuck
+const TIMEOUT: usize = 100_000;
+
+const TX_CAPACITY: usize = 1024;
+const RX_CAPACITY: usize = 256;
+
+/// The four standard serial ports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComPort {
+    Com1,
+    Com2,
+    Com3,
+    Com4,
+}
+
+impl ComPort {
+    pub const ALL: [ComPort; 4] = [ComPort::Com1, ComPort::Com2, ComPort::Com3, ComPort::Com4];
+
+    pub const fn base(self) -> u16 {
+        match self {
+            ComPort::Com1 => 0x3f8,
+            ComPort::Com2 => 0x2f8,
+            ComPort::Com3 => 0x3e8,
+            ComPort::Com4 => 0x2e8,
+        }
+    }
+
+    /// The PIC line the port interrupts on, COM1 and COM3 share IRQ 4,
+    /// COM2 and COM4 IRQ 3.
+    pub fn irq(self) -> u8 {
+        match self {
+            ComPort::Com1 | ComPort::Com3 => 4,
+            ComPort::Com2 | ComPort::Com4 => 3,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    /// The parity bit is always 1.
+    Mark,
+    /// The parity bit is always 0.
+    Space,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+/// Line settings of a serial port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialConfig {
+    /// Bits per second, must divide 115200.
+    pub baud: u32,
+    /// 5 to 8 bits per character.
+    pub data_bits: u8,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl Default for SerialConfig {
+    /// 38400 baud 8N1.
+    fn default() -> Self {
+        SerialConfig {
+            baud: 38400,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+        }
+    }
+}
+
+impl SerialConfig {
+    fn divisor(&self) -> Result<u16, SerialError> {
+        if !CLOCK.is_multiple_of(self.baud) {
+            return Err(SerialError::InvalidBaud(self.baud));
+        }
+        // the divisor latch is 16 bits wide, too slow rates do not fit
+        u16::try_from(CLOCK / self.baud).map_err(|_| SerialError::InvalidBaud(self.baud))
+    }
+
+    fn line_control(&self) -> Result<u8, SerialError> {
+        if !(5..=8).contains(&self.data_bits) {
+            return Err(SerialError::InvalidDataBits(self.data_bits));
+        }
+        let stop = match self.stop_bits {
+            StopBits::One => 0,
+            StopBits::Two => 0x04,
+        };
+        let parity = match self.parity {
+            Parity::None => 0b000,
+            Parity::Odd => 0b001,
+            Parity::Even => 0b011,
+            Parity::Mark => 0b101,
+            Parity::Space => 0b111,
+        };
+        Ok((self.data_bits - 5) | stop | parity << 3)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialError {
+    /// No UART answers at the port's address.
+    NotPresent,
+    /// The UART failed its loopback test.
+    Faulty,
+    InvalidBaud(u32),
+    InvalidDataBits(u8),
+}
+
+// a fixed size byte queue, usable before the heap exists and from
+// interrupt handlers
+struct Ring<const N: usize> {
+    data: [u8; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> Ring<N> {
+    const fn new() -> Self {
+        Ring { data: [0; N], head: 0, len: 0 }
+    }
+
+    fn push(&mut self, byte: u8) -> bool {
+        if self.len == N {
+            return false;
+        }
+        self.data[(self.head + self.len) % N] = byte;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.data[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == N
+    }
+}
+
+/// A 16550 UART. Received bytes are queued by its interrupt handler,
+/// written bytes are buffered and sent as the transmitter empties.
+pub struct SerialPort {
+    base: u16,
+    config: Option<SerialConfig>,
+    tx: Ring<TX_CAPACITY>,
+    rx: Ring<RX_CAPACITY>,
+    /// Bytes lost because nobody read them in time.
+    pub rx_dropped: usize,
+}
+
+impl SerialPort {
+    const fn new(base: u16) -> SerialPort {
+        SerialPort {
+            base,
+            config: None,
+            tx: Ring::new(),
+            rx: Ring::new(),
+            rx_dropped: 0,
+        }
+    }
+
+    fn port(&self, register: u16) -> Port<u8> {
+        Port::new(self.base + register)
+    }
+
+    fn read(&self, register: u16) -> u8 {
+        unsafe { self.port(register).read() }
+    }
+
+    fn write(&self, register: u16, value: u8) {
+        unsafe { self.port(register).write(value) }
+    }
+
+    /// Programs the line settings and turns on the FIFOs and interrupts.
+    pub fn init(&mut self, config: SerialConfig) -> Result<(), SerialError> {
+        let divisor = config.divisor()?;
+        let line_control = config.line_control()?;
+        // a missing UART reads back all ones
+        self.write(SCRATCH, 0xae);
+        if self.read(SCRATCH) != 0xae {
+            return Err(SerialError::NotPresent);
+        }
+
+        self.write(INTERRUPT_ENABLE, 0);
+        self.write(LINE_CONTROL, LINE_DIVISOR_LATCH);
+        self.write(DIVISOR_LOW, divisor as u8);
+        self.write(DIVISOR_HIGH, (divisor >> 8) as u8);
+        self.write(LINE_CONTROL, line_control);
+        self.write(FIFO_CONTROL, FIFO_ENABLE);
+
+        // a byte sent in loopback mode must come back unchanged
+        let modem_control = self.read(MODEM_CONTROL);
+        self.write(MODEM_CONTROL, MODEM_LOOPBACK);
+        self.write(DATA, 0xae);
+        if self.read(DATA) != 0xae {
+            self.write(MODEM_CONTROL, modem_control);
+            return Err(SerialError::Faulty);
+        }
+        self.write(MODEM_CONTROL, MODEM_READY);
+        self.write(INTERRUPT_ENABLE, INTERRUPT_RX | INTERRUPT_TX_EMPTY);
+        self.config = Some(config);
+        Ok(())
+    }
+
+    /// Routes sent bytes straight back to the receiver, to test the port
+    /// and whatever reads from it without a cable.
+    pub fn set_loopback(&mut self, enabled: bool) {
+        self.write(MODEM_CONTROL, if enabled { MODEM_LOOPBACK } else { MODEM_READY });
+    }
+
+    /// The line settings, `None` until the port is initialized.
+    pub fn config(&self) -> Option<SerialConfig> {
+        self.config
+    }
+
+    pub fn send(&mut self, byte: u8) {
+        if self.config.is_none() {
+            return;
+        }
+        if self.tx.is_full() {
+            self.flush();
+        }
+        self.tx.push(byte);
+        self.transmit();
+    }
+
+    /// Waits until every buffered byte is sent.
+    pub fn flush(&mut self) {
+        if self.config.is_none() {
+            return;
+        }
+        // only give up when nothing moved for a while, slow lines take long
+        let mut stalled = 0;
+        while stalled < TIMEOUT {
+            let pending = self.tx.len;
+            self.transmit();
+            if self.tx.is_empty() && self.read(LINE_STATUS) & STATUS_IDLE != 0 {
+                return;
+            }
+            stalled = if self.tx.len == pending { stalled + 1 } else { 0 };
+            core::hint::spin_loop();
+        }
+        // the other end is not listening, the bytes are gone
+        self.tx = Ring::new();
+    }
+
+    /// A received byte, if there is one.
+    pub fn receive(&mut self) -> Option<u8> {
+        self.rx.pop()
+    }
+
+    // refills the transmit FIFO once it ran empty
+    fn transmit(&mut self) {
+        if self.read(LINE_STATUS) & STATUS_TX_EMPTY == 0 {
+            return;
+        }
+        for _ in 0..FIFO_SIZE {
+            match self.tx.pop() {
+                Some(byte) => self.write(DATA, byte),
+                None => break,
+            }
+        }
+    }
+
+    // handles every pending interrupt, returns whether there was one and
+    // whether bytes arrived
+    fn handle_interrupt(&mut self) -> (bool, bool) {
+        let mut pending = false;
+        let mut received = false;
+        while self.read(INTERRUPT_ID) & ID_NONE_PENDING == 0 {
+            pending = true;
+            // reading the status registers clears line and modem status
+            // interrupts, the data register data ones
+            while self.read(LINE_STATUS) & STATUS_DATA_READY != 0 {
+                let byte = self.read(DATA);
+                if !self.rx.push(byte) {
+                    self.rx_dropped += 1;
+                }
+                received = true;
+            }
+            self.read(MODEM_STATUS);
+            self.transmit();
+        }
+        (pending, received)
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
 
 lazy_static!{
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8)};
-        serial_port.init();
-        Mutex::new(serial_port)
+    pub static ref SERIAL1: IrqSpinlock<SerialPort> = {
+        let mut serial_port = SerialPort::new(ComPort::Com1.base());
+        // without COM1 the output goes nowhere, there is nobody to tell
+        let _ = serial_port.init(SerialConfig::default());
+        IrqSpinlock::new("COM1", serial_port)
     };
 }
 
+static SERIAL2: IrqSpinlock<SerialPort> = IrqSpinlock::new("COM2", SerialPort::new(ComPort::Com2.base()));
+static SERIAL3: IrqSpinlock<SerialPort> = IrqSpinlock::new("COM3", SerialPort::new(ComPort::Com3.base()));
+static SERIAL4: IrqSpinlock<SerialPort> = IrqSpinlock::new("COM4", SerialPort::new(ComPort::Com4.base()));
+
+static WAKERS: [AtomicWaker; 4] = [const { AtomicWaker::new() }; 4];
+
+/// The driver of a serial port.
+pub fn port(com: ComPort) -> &'static IrqSpinlock<SerialPort> {
+    match com {
+        ComPort::Com1 => &SERIAL1,
+        ComPort::Com2 => &SERIAL2,
+        ComPort::Com3 => &SERIAL3,
+        ComPort::Com4 => &SERIAL4,
+    }
+}
+
+/// (Re)initializes a serial port with the given line settings.
+pub fn open(com: ComPort, config: SerialConfig) -> Result<(), SerialError> {
+    let mut port = port(com).lock();
+    port.flush();
+    port.init(config)
+}
+
+/// Registers the serial interrupt handlers, called by `os::init` once
+/// the PICs are set up.
+pub fn init_interrupts() {
+    use crate::interrupts::{irq, PIC_1_OFFSET};
+    // make sure COM1 is initialized
+    lazy_static::initialize(&SERIAL1);
+    irq::register_irq(ComPort::Com1.irq(), "serial", 0, |vector| handle_interrupt(vector - PIC_1_OFFSET))
+        .expect("serial interrupt handler");
+    irq::register_irq(ComPort::Com2.irq(), "serial", 0, |vector| handle_interrupt(vector - PIC_1_OFFSET))
+        .expect("serial interrupt handler");
+}
+
+// handles the interrupts of the ports sharing `irq`
+fn handle_interrupt(irq: u8) -> crate::interrupts::irq::IrqReturn {
+    use crate::interrupts::irq::IrqReturn;
+    let mut result = IrqReturn::None;
+    for com in ComPort::ALL.iter().filter(|com| com.irq() == irq) {
+        let mut port = port(*com).lock();
+        if port.config().is_none() {
+            continue;
+        }
+        // the line is shared, only a port with an interrupt pending raised it
+        let (pending, received) = port.handle_interrupt();
+        if pending {
+            result = IrqReturn::Handled;
+        }
+        if received {
+            WAKERS[com.index()].wake();
+        }
+    }
+    result
+}
+
+/// Sends everything buffered for COM1, for when the machine is about to stop.
+pub fn flush() {
+    SERIAL1.lock().flush();
+}
+
+/// Bytes received on a serial port.
+pub struct SerialStream {
+    com: ComPort,
+}
+
+impl SerialStream {
+    pub fn new(com: ComPort) -> Self {
+        SerialStream { com }
+    }
+}
+
+impl Stream for SerialStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        let port = port(self.com);
+        if let Some(byte) = port.lock().receive() {
+            return Poll::Ready(Some(byte));
+        }
+
+        WAKERS[self.com.index()].register(cx.waker());
+
+        let received = port.lock().receive();
+        match received {
+            Some(byte) => {
+                WAKERS[self.com.index()].take();
+                Poll::Ready(Some(byte))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Feeds what arrives on COM1 to the terminal on the screen as if it was
+/// typed, so the kernel can be driven over `-serial stdio`.
+pub async fn forward_input() {
+    use crate::vga_buffer::terminal;
+    use futures_util::stream::StreamExt;
+    use pc_keyboard::DecodedKey;
+
+    let mut bytes = SerialStream::new(ComPort::Com1);
+    let mut utf8 = [0u8; 4];
+    let mut len = 0;
+    while let Some(byte) = bytes.next().await {
+        utf8[len] = byte;
+        len += 1;
+        let c = match core::str::from_utf8(&utf8[..len]) {
+            Ok(text) => text.chars().next(),
+            // wait for the rest of a multi byte character
+            Err(error) if error.error_len().is_none() && len < utf8.len() => continue,
+            Err(_) => Some(char::REPLACEMENT_CHARACTER),
+        };
+        len = 0;
+        // terminals send a carriage return for Enter and DEL for Backspace
+        let key = match c {
+            Some('\r') => '\n',
+            Some('\x7f') => '\x08',
+            Some(c) => c,
+            None => continue,
+        };
+        terminal::push_input(DecodedKey::Unicode(key));
+    }
+}
+
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments){
     use core::fmt::Write;
     use x86_64::instructions::interrupts;
-    interrupts::without_interrupts(||{
-        SERIAL1
-            .lock()
-            .write_fmt(args)
-            .expect("Printing to serial failed");
-    })
+    // without interrupts the transmitter interrupt cannot send the rest
+    let wait = !interrupts::are_enabled();
+    let mut serial = SERIAL1.lock();
+    let _ = serial.write_fmt(args);
+    if wait {
+        serial.flush();
+    }
 }
 
 #[macro_export]
@@ -38,4 +515,33 @@ macro_rules! serial_println {
     ($fmt:expr) => ($crate::serial_print!(concat!($fmt, "\n")));
     ($fmt:expr, $($arg:tt)*) => ($crate::serial_print!(
         concat!($fmt, "\n"),$($arg)*));
-}
\ No newline at end of file
+}
+
+#[test_case]
+fn test_ring() {
+    let mut ring = Ring::<4>::new();
+    assert_eq!(ring.pop(), None);
+    for byte in 0..4 {
+        assert!(ring.push(byte));
+    }
+    assert!(ring.is_full() && !ring.push(4));
+    assert_eq!(ring.pop(), Some(0));
+    assert!(ring.push(4));
+    assert_eq!((1..5).map(|_| ring.pop().unwrap()).sum::<u8>(), 1 + 2 + 3 + 4);
+    assert!(ring.is_empty());
+}
+
+#[test_case]
+fn test_config() {
+    let config = SerialConfig::default();
+    assert_eq!(config.divisor(), Ok(3));
+    assert_eq!(config.line_control(), Ok(0x03));
+    let config = SerialConfig { baud: 9600, data_bits: 7, parity: Parity::Even, stop_bits: StopBits::Two };
+    assert_eq!(config.divisor(), Ok(12));
+    assert_eq!(config.line_control(), Ok(0x02 | 0x04 | 0x18));
+    assert_eq!(SerialConfig { baud: 7, ..config }.divisor(), Err(SerialError::InvalidBaud(7)));
+    assert_eq!(SerialConfig { baud: 1, ..config }.divisor(), Err(SerialError::InvalidBaud(1)));
+    assert_eq!(SerialConfig { data_bits: 9, ..config }.line_control(), Err(SerialError::InvalidDataBits(9)));
+    let config = SERIAL1.lock().config();
+    assert_eq!(config, Some(SerialConfig::default()));
+}
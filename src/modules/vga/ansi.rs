@@ -0,0 +1,368 @@
+use super::Rendition;
+
+const MAX_PARAMS: usize = 8;
+
+/// A control sequence, `ESC [` followed by parameters and a final character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Csi {
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    /// Set for private sequences such as `ESC [ ? 25 l`.
+    pub private: bool,
+    pub action: char,
+}
+
+impl Csi {
+    /// The parameters as written, a missing parameter reads as 0.
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.len]
+    }
+
+    /// The parameter at `index`, missing or 0 parameters take `default`.
+    pub fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(&value) if value != 0 => value,
+            _ => default,
+        }
+    }
+}
+
+/// What a terminal should do with the next piece of its input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Print(char),
+    /// A C0 control character such as `\n`, `\r` or backspace.
+    Execute(char),
+    /// `ESC` followed by a single final character, e.g. `ESC 7`.
+    Escape(char),
+    Csi(Csi),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    // ESC followed by intermediates, e.g. the character set selection `ESC ( B`
+    EscapeIntermediate,
+    Csi,
+    // the payload of OSC, DCS, PM and APC strings, which are not supported
+    String,
+}
+
+/// VT100/ANSI escape sequence parser, turns a stream of characters into
+/// the actions they stand for.
+pub struct Parser {
+    state: State,
+    csi: Csi,
+    // more parameters than fit into `csi` were given
+    overflow: bool,
+}
+
+impl Parser {
+    pub const fn new() -> Parser {
+        Parser {
+            state: State::Ground,
+            csi: Csi {
+                params: [0; MAX_PARAMS],
+                len: 0,
+                private: false,
+                action: '\0',
+            },
+            overflow: false,
+        }
+    }
+
+    /// Feeds the next character, returns an action once one is complete.
+    pub fn advance(&mut self, c: char) -> Option<Action> {
+        match (self.state, c) {
+            // ESC aborts whatever sequence was in progress
+            (_, '\x1b') => {
+                self.state = State::Escape;
+                None
+            }
+            // CAN and SUB cancel a sequence
+            (_, '\x18') | (_, '\x1a') => {
+                self.state = State::Ground;
+                None
+            }
+            (State::String, '\x07') => {
+                self.state = State::Ground;
+                None
+            }
+            (State::String, _) => None,
+            // DEL is ignored everywhere
+            (_, '\x7f') => None,
+            // control characters are executed even in the middle of a sequence
+            (_, '\0'..='\x1f') => Some(Action::Execute(c)),
+            (State::Ground, _) => Some(Action::Print(c)),
+            (State::Escape, '[') => {
+                self.state = State::Csi;
+                self.csi.len = 0;
+                self.csi.private = false;
+                self.overflow = false;
+                None
+            }
+            (State::Escape, ']') | (State::Escape, 'P') | (State::Escape, '^') | (State::Escape, '_') => {
+                self.state = State::String;
+                None
+            }
+            (State::Escape, ' '..='/') => {
+                self.state = State::EscapeIntermediate;
+                None
+            }
+            (State::Escape, '0'..='~') => {
+                self.state = State::Ground;
+                Some(Action::Escape(c))
+            }
+            (State::EscapeIntermediate, ' '..='/') => None,
+            (State::Csi, '0'..='9') if self.overflow => None,
+            (State::Csi, '0'..='9') => {
+                if self.csi.len == 0 {
+                    self.csi.params[0] = 0;
+                    self.csi.len = 1;
+                }
+                let param = &mut self.csi.params[self.csi.len - 1];
+                *param = param.saturating_mul(10).saturating_add(c as u16 - '0' as u16);
+                None
+            }
+            (State::Csi, ';') => {
+                if self.csi.len == 0 {
+                    self.csi.params[0] = 0;
+                    self.csi.len = 1;
+                }
+                // parameters past the limit are dropped
+                if self.csi.len < MAX_PARAMS {
+                    self.csi.params[self.csi.len] = 0;
+                    self.csi.len += 1;
+                } else {
+                    self.overflow = true;
+                }
+                None
+            }
+            (State::Csi, '<'..='?') => {
+                self.csi.private = true;
+                None
+            }
+            // intermediates are not used by any supported sequence
+            (State::Csi, ' '..='/') => None,
+            (State::Csi, '@'..='~') => {
+                self.state = State::Ground;
+                self.csi.action = c;
+                Some(Action::Csi(self.csi))
+            }
+            // anything else is malformed and dropped
+            _ => {
+                self.state = State::Ground;
+                None
+            }
+        }
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Parser::new()
+    }
+}
+
+/// A grid of character cells, what an `Emulator` draws on.
+pub(crate) trait Grid {
+    /// Size in cells, (columns, rows).
+    fn size(&self) -> (usize, usize);
+    /// Shows `c` in the cell at `row`, `col`.
+    fn put(&mut self, row: usize, col: usize, c: char, rendition: Rendition);
+    /// Blanks the cells from `start` up to `end`, counted row by row.
+    fn erase(&mut self, start: usize, end: usize, rendition: Rendition);
+    /// Moves the rows `top + 1..=bottom` up by one and blanks `bottom`.
+    fn scroll_up(&mut self, top: usize, bottom: usize, rendition: Rendition);
+}
+
+/// The cursor, attributes and modes of a VT100/ANSI terminal. It runs
+/// the actions of its parser on a `Grid`, which only stores and shows
+/// the cells.
+pub(crate) struct Emulator {
+    row: usize,
+    column: usize,
+    rendition: Rendition,
+    // cursor and attributes stored by ESC 7 / CSI s
+    saved: (usize, usize, Rendition),
+    // rows a new line at the bottom scrolls, both inclusive, set by CSI r
+    scroll_top: usize,
+    scroll_bottom: usize,
+    // set by CSI ? 25 h/l
+    cursor_visible: bool,
+    parser: Parser,
+}
+
+impl Emulator {
+    /// An emulator for a grid of `rows` rows with the cursor at the start
+    /// of `row`.
+    pub(crate) const fn new(rows: usize, row: usize) -> Emulator {
+        Emulator {
+            row,
+            column: 0,
+            rendition: Rendition::DEFAULT,
+            saved: (row, 0, Rendition::DEFAULT),
+            scroll_top: 0,
+            scroll_bottom: rows - 1,
+            cursor_visible: true,
+            parser: Parser::new(),
+        }
+    }
+
+    /// The (row, column) the next character is written to. The column
+    /// is one past the last after a full row, until the next character.
+    pub(crate) fn position(&self) -> (usize, usize) {
+        (self.row, self.column)
+    }
+
+    /// Moves the cursor, clamped to `grid`.
+    pub(crate) fn set_position(&mut self, grid: &impl Grid, row: usize, column: usize) {
+        let (columns, rows) = grid.size();
+        self.row = row.min(rows - 1);
+        self.column = column.min(columns - 1);
+    }
+
+    pub(crate) fn rendition(&self) -> Rendition {
+        self.rendition
+    }
+
+    pub(crate) fn cursor_visible(&self) -> bool {
+        self.cursor_visible
+    }
+
+    /// Writes text, interpreting the escape sequences in it.
+    pub(crate) fn write_str(&mut self, grid: &mut impl Grid, s: &str) {
+        for c in s.chars() {
+            match self.parser.advance(c) {
+                Some(Action::Print(c)) => self.print(grid, c),
+                Some(Action::Execute(c)) => self.execute(grid, c),
+                Some(Action::Escape(c)) => self.escape(grid, c),
+                Some(Action::Csi(csi)) => self.control_sequence(grid, &csi),
+                None => {}
+            }
+        }
+    }
+
+    /// Shows `c` at the cursor and moves it on, control characters
+    /// included.
+    pub(crate) fn print(&mut self, grid: &mut impl Grid, c: char) {
+        if self.column >= grid.size().0 {
+            self.new_line(grid);
+        }
+        grid.put(self.row, self.column, c, self.rendition);
+        self.column += 1;
+    }
+
+    /// Runs the C0 control character `c`.
+    pub(crate) fn execute(&mut self, grid: &mut impl Grid, c: char) {
+        let columns = grid.size().0;
+        match c {
+            '\n' => self.new_line(grid),
+            '\r' => self.column = 0,
+            '\x08' => {
+                if self.column > 0 {
+                    self.column -= 1;
+                    let cursor = self.row * columns + self.column;
+                    grid.erase(cursor, cursor + 1, self.rendition);
+                }
+            }
+            // tab stops every 8 columns
+            '\t' => self.column = ((self.column / 8 + 1) * 8).min(columns - 1),
+            _ => {}
+        }
+    }
+
+    /// Blanks the whole grid and moves the cursor to the top left corner.
+    pub(crate) fn clear(&mut self, grid: &mut impl Grid) {
+        let (columns, rows) = grid.size();
+        grid.erase(0, columns * rows, self.rendition);
+        self.row = 0;
+        self.column = 0;
+    }
+
+    fn escape(&mut self, grid: &mut impl Grid, c: char) {
+        match c {
+            '7' => self.save_cursor(),
+            '8' => self.restore_cursor(),
+            // full reset
+            'c' => {
+                self.rendition = Rendition::DEFAULT;
+                self.scroll_top = 0;
+                self.scroll_bottom = grid.size().1 - 1;
+                self.cursor_visible = true;
+                self.clear(grid);
+            }
+            _ => {}
+        }
+    }
+
+    fn control_sequence(&mut self, grid: &mut impl Grid, csi: &Csi) {
+        if csi.private {
+            // only cursor visibility of the private modes is supported
+            match (csi.action, csi.param(0, 0)) {
+                ('h', 25) => self.cursor_visible = true,
+                ('l', 25) => self.cursor_visible = false,
+                _ => {}
+            }
+            return;
+        }
+        let (columns, rows) = grid.size();
+        let n = csi.param(0, 1) as usize;
+        let cursor = self.row * columns + self.column.min(columns - 1);
+        match csi.action {
+            'A' => self.row = self.row.saturating_sub(n),
+            'B' => self.row = (self.row + n).min(rows - 1),
+            'C' => self.column = (self.column + n).min(columns - 1),
+            'D' => self.column = self.column.min(columns - 1).saturating_sub(n),
+            // positions are 1 based
+            'H' | 'f' => self.set_position(grid, n - 1, csi.param(1, 1) as usize - 1),
+            'J' => match csi.param(0, 0) {
+                0 => grid.erase(cursor, columns * rows, self.rendition),
+                1 => grid.erase(0, cursor + 1, self.rendition),
+                _ => grid.erase(0, columns * rows, self.rendition),
+            },
+            'K' => {
+                let start = self.row * columns;
+                match csi.param(0, 0) {
+                    0 => grid.erase(cursor, start + columns, self.rendition),
+                    1 => grid.erase(start, cursor + 1, self.rendition),
+                    _ => grid.erase(start, start + columns, self.rendition),
+                }
+            }
+            'm' => self.rendition.select_graphic_rendition(csi.params()),
+            'r' => {
+                let top = n - 1;
+                let bottom = (csi.param(1, rows as u16) as usize - 1).min(rows - 1);
+                if top < bottom {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom;
+                    self.row = 0;
+                    self.column = 0;
+                }
+            }
+            's' => self.save_cursor(),
+            'u' => self.restore_cursor(),
+            _ => {}
+        }
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved = (self.row, self.column, self.rendition);
+    }
+
+    fn restore_cursor(&mut self) {
+        (self.row, self.column, self.rendition) = self.saved;
+    }
+
+    fn new_line(&mut self, grid: &mut impl Grid) {
+        // only the bottom of the scroll region scrolls, elsewhere the
+        // cursor just moves down
+        if self.row == self.scroll_bottom {
+            grid.scroll_up(self.scroll_top, self.scroll_bottom, self.rendition);
+        } else if self.row + 1 < grid.size().1 {
+            self.row += 1;
+        }
+        self.column = 0;
+    }
+}
@@ -0,0 +1,49 @@
+// glyphs the VGA font shows for the control range 0x01..=0x1f
+const LOW: [char; 31] = [
+    '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼', '►',
+    '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+// glyphs of 0x80..=0xff
+const HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+// characters that share a glyph with a different code point
+const ALIASES: [(char, u8); 3] = [('β', 0xe1), ('μ', 0xe6), ('∑', 0xe4)];
+
+/// Glyph shown for characters code page 437 has no equivalent for.
+pub const REPLACEMENT: u8 = 0xfe;
+
+/// Translates a character to its code page 437 byte, `None` if the VGA
+/// font has no glyph for it.
+pub fn encode(c: char) -> Option<u8> {
+    match c {
+        ' '..='~' => Some(c as u8),
+        '⌂' => Some(0x7f),
+        _ => LOW
+            .iter()
+            .position(|&glyph| glyph == c)
+            .map(|index| index as u8 + 0x01)
+            .or_else(|| HIGH.iter().position(|&glyph| glyph == c).map(|index| index as u8 + 0x80))
+            .or_else(|| ALIASES.iter().find(|&&(alias, _)| alias == c).map(|&(_, byte)| byte)),
+    }
+}
+
+/// The character the VGA font shows for a code page 437 byte.
+pub fn decode(byte: u8) -> char {
+    match byte {
+        0x00 => ' ',
+        0x01..=0x1f => LOW[byte as usize - 0x01],
+        0x7f => '⌂',
+        0x80..=0xff => HIGH[byte as usize - 0x80],
+        _ => byte as char,
+    }
+}
@@ -0,0 +1,202 @@
+use super::{ansi, Buffer, Rendition, TextGrid, Writer, BUFFER_HEIGHT, BUFFER_WIDTH};
+use conquer_once::spin::OnceCell;
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::stream::Stream;
+use futures_util::task::AtomicWaker;
+use lazy_static::lazy_static;
+use pc_keyboard::DecodedKey;
+use crate::sync::IrqSpinlock;
+
+/// Number of virtual terminals, switched with Alt+F1..F6.
+pub const COUNT: usize = 6;
+
+// keys buffered per terminal before input is dropped
+const INPUT_CAPACITY: usize = 64;
+
+// off-screen contents of every terminal, kept outside the heap so the
+// terminals work before it is initialized
+static mut SCREENS: [[[u16; BUFFER_WIDTH]; BUFFER_HEIGHT]; COUNT] = [[[0; BUFFER_WIDTH]; BUFFER_HEIGHT]; COUNT];
+
+// the terminal on the screen
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+// the terminal of the task being polled, print! writes there
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+
+static INPUT: [OnceCell<ArrayQueue<DecodedKey>>; COUNT] = [const { OnceCell::uninit() }; COUNT];
+static INPUT_WAKERS: [AtomicWaker; COUNT] = [const { AtomicWaker::new() }; COUNT];
+
+lazy_static! {
+    static ref TERMINALS: [IrqSpinlock<Writer>; COUNT] =
+        core::array::from_fn(|index| IrqSpinlock::new("terminal", new_writer(index)));
+}
+
+fn new_writer(index: usize) -> Writer {
+    let buffer = unsafe { &mut *(core::ptr::addr_of_mut!(SCREENS[index]) as *mut Buffer) };
+    // terminal 0 starts on the screen and takes over what the bootloader
+    // left there, the others start out blank at the top
+    let (screen, row) = if index == 0 {
+        let screen = unsafe { &mut *(0xb8000 as *mut Buffer) };
+        for row in 0..BUFFER_HEIGHT {
+            for col in 0..BUFFER_WIDTH {
+                buffer.characters[row][col].write(screen.characters[row][col].read());
+            }
+        }
+        (Some(screen), BUFFER_HEIGHT - 1)
+    } else {
+        (None, 0)
+    };
+    let mut writer = Writer {
+        emulator: ansi::Emulator::new(BUFFER_HEIGHT, row),
+        grid: TextGrid {
+            history: None,
+            history_limit: 0,
+            view_offset: 0,
+            buffer,
+            screen,
+        },
+    };
+    if index != 0 {
+        ansi::Grid::erase(&mut writer.grid, 0, BUFFER_HEIGHT * BUFFER_WIDTH, Rendition::DEFAULT);
+    }
+    writer
+}
+
+/// The writer of terminal `index`.
+pub fn writer(index: usize) -> &'static IrqSpinlock<Writer> {
+    &TERMINALS[index]
+}
+
+/// The terminal shown on the screen.
+pub fn active() -> usize {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// The terminal output of the running task goes to.
+pub fn current() -> usize {
+    CURRENT.load(Ordering::Relaxed)
+}
+
+// called by the executor around polling a task
+pub(crate) fn set_current(index: usize) {
+    CURRENT.store(index, Ordering::Relaxed);
+}
+
+/// Shows terminal `index` on the screen.
+pub fn switch_to(index: usize) {
+    use x86_64::instructions::interrupts;
+
+    assert!(index < COUNT, "there is no terminal {}", index);
+    interrupts::without_interrupts(|| {
+        let previous = active();
+        if previous == index {
+            return;
+        }
+        // always lock the lower terminal first
+        let (mut from, mut to) = if previous < index {
+            let from = TERMINALS[previous].lock();
+            (from, TERMINALS[index].lock())
+        } else {
+            let to = TERMINALS[index].lock();
+            (TERMINALS[previous].lock(), to)
+        };
+        // the VGA memory moves over, the new terminal's copy is drawn into it
+        from.grid.view_offset = 0;
+        to.grid.screen = from.grid.screen.take();
+        to.grid.view_offset = 0;
+        to.redraw();
+        ACTIVE.store(index, Ordering::Relaxed);
+    });
+}
+
+// the input queue of a terminal, created on first use since it lives on the heap
+fn input(index: usize) -> &'static ArrayQueue<DecodedKey> {
+    let _ = INPUT[index].try_init_once(|| ArrayQueue::new(INPUT_CAPACITY));
+    INPUT[index].try_get().expect("terminal input queue not initialized")
+}
+
+/// Queues a key for the terminal on the screen.
+pub fn push_input(key: DecodedKey) {
+    let index = active();
+    let queue = input(index);
+    // a terminal nobody reads from just loses its oldest keys
+    if queue.push(key).is_err() {
+        let _ = queue.pop();
+        let _ = queue.push(key);
+    }
+    INPUT_WAKERS[index].wake();
+}
+
+/// Keys typed while a terminal was on the screen.
+pub struct InputStream {
+    terminal: usize,
+}
+
+impl InputStream {
+    pub fn new(terminal: usize) -> Self {
+        assert!(terminal < COUNT, "there is no terminal {}", terminal);
+        InputStream { terminal }
+    }
+}
+
+impl Stream for InputStream {
+    type Item = DecodedKey;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<DecodedKey>> {
+        let queue = input(self.terminal);
+        if let Ok(key) = queue.pop() {
+            return Poll::Ready(Some(key));
+        }
+
+        INPUT_WAKERS[self.terminal].register(cx.waker());
+
+        match queue.pop() {
+            Ok(key) => {
+                INPUT_WAKERS[self.terminal].take();
+                Poll::Ready(Some(key))
+            }
+            Err(crossbeam_queue::PopError) => Poll::Pending,
+        }
+    }
+}
+
+#[test_case]
+fn test_print_goes_to_current_terminal() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+    interrupts::without_interrupts(|| write!(writer(0).lock(), "\n0").unwrap());
+    set_current(2);
+    crate::print!("tty3");
+    set_current(0);
+    let read = |index: usize, row: usize, col: usize| writer(index).lock().grid.buffer.characters[row][col].read().ascii_char;
+    assert_eq!([read(2, 0, 0), read(2, 0, 3)], *b"t3");
+    assert_eq!(read(0, BUFFER_HEIGHT - 1, 0), b'0');
+}
+
+#[test_case]
+fn test_switch_terminals() {
+    use x86_64::instructions::interrupts;
+    let on_screen = |index: usize, row: usize| {
+        interrupts::without_interrupts(|| {
+            let writer = writer(index).lock();
+            writer.grid.screen.as_ref().map(|screen| screen.characters[row][0].read().ascii_char)
+        })
+    };
+    interrupts::without_interrupts(|| writer(4).lock().write_string("\x1b[Hfive"));
+    assert_eq!(on_screen(4, 0), None);
+
+    switch_to(4);
+    assert_eq!(active(), 4);
+    assert_eq!(on_screen(4, 0), Some(b'f'));
+    assert_eq!(on_screen(0, 0), None);
+
+    // terminals off the screen keep taking output
+    interrupts::without_interrupts(|| writer(0).lock().write_string("\nX"));
+    switch_to(0);
+    assert_eq!(on_screen(0, BUFFER_HEIGHT - 1), Some(b'X'));
+    assert_eq!(on_screen(4, 0), None);
+}
@@ -1,8 +1,14 @@
 
 use volatile::Volatile;
 use core::fmt;
-use lazy_static::lazy_static;
-use spin::Mutex;
+use alloc::collections::VecDeque;
+
+#[path = "ansi.rs"]
+pub mod ansi;
+#[path = "cp437.rs"]
+pub mod cp437;
+#[path = "terminal.rs"]
+pub mod terminal;
 // (color_name,number)
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -46,6 +52,11 @@ struct ScreenChar{
     color_code: ColorCode,
 }
 
+const BLANK: ScreenChar = ScreenChar{
+    ascii_char: b' ',
+    color_code: ColorCode((Color::Black as u8) << 4 | (Color::Green as u8)),
+};
+
 // creating Buffer
 const BUFFER_HEIGHT: usize = 25;
 const BUFFER_WIDTH: usize = 80;
@@ -55,83 +66,290 @@ struct Buffer{
     characters: [[Volatile<ScreenChar>;BUFFER_WIDTH];BUFFER_HEIGHT],
 }
 
+// a row of the screen as kept in the scrollback history
+type Row = [ScreenChar; BUFFER_WIDTH];
+
+// CRT controller index and data ports, the cursor registers are
+// reached through them
+const CRTC_ADDRESS: u16 = 0x3d4;
+const CRTC_DATA: u16 = 0x3d5;
+const CURSOR_START: u8 = 0x0a;
+const CURSOR_END: u8 = 0x0b;
+const CURSOR_LOCATION_HIGH: u8 = 0x0e;
+const CURSOR_LOCATION_LOW: u8 = 0x0f;
+// bit 5 of the cursor start register hides the cursor
+const CURSOR_DISABLE: u8 = 0x20;
+
+fn write_crtc(register:u8, value:u8){
+    use x86_64::instructions::port::Port;
+    unsafe{
+        Port::<u8>::new(CRTC_ADDRESS).write(register);
+        Port::<u8>::new(CRTC_DATA).write(value);
+    }
+}
+
+// VGA colors in ANSI order, the first 8 are selected by SGR 30..=37 and
+// 40..=47, the bright ones by 90..=97 and 100..=107
+const ANSI_COLORS: [Color; 16] = [
+    Color::Black, Color::Red, Color::Green, Color::Brown,
+    Color::Blue, Color::Magenta, Color::Cyan, Color::LightGray,
+    Color::DarkGray, Color::LightRed, Color::LightGreen, Color::Yellow,
+    Color::LightBlue, Color::Pink, Color::LightCyan, Color::White,
+];
+
+// green on black, as indices into ANSI_COLORS
+const DEFAULT_FOREGROUND: usize = 2;
+const DEFAULT_BACKGROUND: usize = 0;
+
+// the attributes set by SGR sequences
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Rendition{
+    foreground: usize,
+    background: usize,
+    bold: bool,
+    reverse: bool,
+}
+
+impl Rendition{
+    pub(crate) const DEFAULT: Rendition = Rendition{
+        foreground: DEFAULT_FOREGROUND,
+        background: DEFAULT_BACKGROUND,
+        bold: false,
+        reverse: false,
+    };
+
+    // foreground and background as indices into the 16 ANSI colors
+    pub(crate) fn colors(&self) -> (usize, usize){
+        // bold is shown as the bright variant of the color
+        let foreground = if self.bold { self.foreground | 0x8 } else { self.foreground };
+        if self.reverse{
+            (self.background, foreground)
+        }else{
+            (foreground, self.background)
+        }
+    }
+
+    fn color_code(&self) -> ColorCode{
+        let (foreground, background) = self.colors();
+        ColorCode::new(ANSI_COLORS[foreground], ANSI_COLORS[background])
+    }
+
+    pub(crate) fn select_graphic_rendition(&mut self, params:&[u16]){
+        // ESC [ m is the same as ESC [ 0 m
+        if params.is_empty(){
+            *self = Rendition::DEFAULT;
+        }
+        for &param in params{
+            match param{
+                0 => *self = Rendition::DEFAULT,
+                1 => self.bold = true,
+                22 => self.bold = false,
+                7 => self.reverse = true,
+                27 => self.reverse = false,
+                30..=37 => self.foreground = param as usize - 30,
+                39 => self.foreground = DEFAULT_FOREGROUND,
+                40..=47 => self.background = param as usize - 40,
+                49 => self.background = DEFAULT_BACKGROUND,
+                90..=97 => self.foreground = param as usize - 90 + 8,
+                100..=107 => self.background = param as usize - 100 + 8,
+                _ => {}
+            }
+        }
+    }
+}
+
 pub struct Writer{
-    column_position: usize,
-    color_code: ColorCode,
+    emulator: ansi::Emulator,
+    grid: TextGrid,
+}
+
+// the cells of a terminal and the VGA memory it shows them in
+struct TextGrid{
+    // rows that scrolled off the top, oldest first, None until
+    // enable_scrollback is called since the heap is not up before
+    history: Option<VecDeque<Row>>,
+    history_limit: usize,
+    // how many history rows are shown above the screen
+    view_offset: usize,
+    // the terminal's own off-screen copy of its contents, always up to date
     buffer: &'static mut Buffer,
+    // VGA memory while this terminal is the one on the screen
+    screen: Option<&'static mut Buffer>,
 }
 
-impl Writer{
-    pub fn write_byte(&mut self, byte:u8){
-        match byte{
-            b'\n' => self.new_line(),
-            0x8 =>self.clear_byte(),
-            byte =>{
-                // new line at the end of "terminal"
-                if self.column_position >= BUFFER_WIDTH{
-                    self.new_line()
-                }
-                // set the pointer at the needed position
-                // save nessesary data
-                let row:usize = BUFFER_HEIGHT - 1;
-                let col = self.column_position;
-                let color_code:ColorCode = self.color_code;
-                // write char to screen
-                self.buffer.characters[row][col].write(ScreenChar{
-                    ascii_char:byte,
-                    color_code
-                });
-                //move pointer after write
-                self.column_position +=1;
+impl TextGrid{
+    fn write_cell(&mut self, row:usize, col:usize, character:ScreenChar){
+        self.buffer.characters[row][col].write(character);
+        // while scrolled back the screen shows history, redraw catches up later
+        if self.view_offset == 0{
+            if let Some(screen) = self.screen.as_mut(){
+                screen.characters[row][col].write(character);
             }
         }
     }
 
-    pub fn write_string(&mut self,s:&str){
-        for byte in s.bytes(){
-            match byte{
-                // printable ascii byte or new line 
-                // is going to be written
-                0x20..=0x7e | b'\n' | 0x08 => self.write_byte(byte),
-                // not part of printable ASCII range
-                _=> self.write_byte(0xfe),
+    // copies what the terminal shows into VGA memory
+    fn redraw(&mut self){
+        let screen = match self.screen.as_mut(){
+            Some(screen) => screen,
+            None => return,
+        };
+        for row in 0..BUFFER_HEIGHT{
+            for col in 0..BUFFER_WIDTH{
+                let character = match self.history.as_ref(){
+                    Some(history) if row < self.view_offset => history[history.len() - self.view_offset + row][col],
+                    _ => self.buffer.characters[row - self.view_offset][col].read(),
+                };
+                screen.characters[row][col].write(character);
             }
         }
     }
+}
+
+impl ansi::Grid for TextGrid{
+    fn size(&self) -> (usize, usize){
+        (BUFFER_WIDTH, BUFFER_HEIGHT)
+    }
+
+    fn put(&mut self, row:usize, col:usize, c:char, rendition:Rendition){
+        // anything the VGA font has a glyph for is translated to code
+        // page 437, the rest shows as a box; glyphs in the control range
+        // like ◘ are drawn, not executed
+        self.write_cell(row, col, ScreenChar{
+            ascii_char: cp437::encode(c).unwrap_or(cp437::REPLACEMENT),
+            color_code: rendition.color_code(),
+        });
+    }
 
-    fn clear_byte(&mut self){
+    fn erase(&mut self, start:usize, end:usize, rendition:Rendition){
         let blank = ScreenChar{
             ascii_char:b' ',
-            color_code:self.color_code,
+            color_code:rendition.color_code(),
         };
-        let col = self.column_position;
-        if col == 0{
-            return;
-        }else{
-            self.buffer.characters[BUFFER_HEIGHT-1][col-1].write(blank);
-            self.column_position -= 1;
+        for cell in start..end{
+            self.write_cell(cell / BUFFER_WIDTH, cell % BUFFER_WIDTH, blank);
         }
     }
 
-    fn new_line(&mut self){
-        for row in 1..BUFFER_HEIGHT{
+    fn scroll_up(&mut self, top:usize, bottom:usize, rendition:Rendition){
+        // the top row of the screen goes to the scrollback history
+        if let (0, Some(history)) = (top, self.history.as_mut()){
+            if history.len() == self.history_limit{
+                history.pop_front();
+            }
+            if self.history_limit > 0{
+                let mut line = [BLANK; BUFFER_WIDTH];
+                for (col, character) in line.iter_mut().enumerate(){
+                    *character = self.buffer.characters[0][col].read();
+                }
+                history.push_back(line);
+            }
+        }
+        for row in top + 1..=bottom{
             for col in 0..BUFFER_WIDTH{
                 let character = self.buffer.characters[row][col].read();
-                self.buffer.characters[row-1][col].write(character);
+                self.write_cell(row-1, col, character);
             }
         }
-        self.clear_row(BUFFER_HEIGHT - 1);
-        self.column_position = 0;
+        self.erase(bottom * BUFFER_WIDTH, (bottom + 1) * BUFFER_WIDTH, rendition);
     }
+}
 
-    fn clear_row(&mut self,row:usize){
-        let blank = ScreenChar{
-            ascii_char:b' ',
-            color_code:self.color_code,
-        };
-        for col in 0..BUFFER_WIDTH{
-            self.buffer.characters[row][col].write(blank);
+impl Writer{
+    /// Writes a code page 437 byte, `\n` and backspace move the cursor,
+    /// every other byte is drawn.
+    pub fn write_byte(&mut self, byte:u8){
+        self.show_live();
+        match byte{
+            b'\n' => self.emulator.execute(&mut self.grid, '\n'),
+            0x8 => self.emulator.execute(&mut self.grid, '\x08'),
+            byte => self.emulator.print(&mut self.grid, cp437::decode(byte)),
         }
+        self.update_cursor();
+    }
+
+    /// Writes UTF-8 text, interpreting the VT100/ANSI escape sequences in it.
+    pub fn write_string(&mut self,s:&str){
+        // output always goes to the live screen
+        self.show_live();
+        self.emulator.write_str(&mut self.grid, s);
+        self.update_cursor();
+    }
+
+    /// Moves the cursor, both are counted from 0 and clamped to the screen.
+    pub fn set_position(&mut self, row:usize, col:usize){
+        self.emulator.set_position(&self.grid, row, col);
+        self.update_cursor();
+    }
+
+    /// The (row, column) the next character is written to.
+    pub fn position(&self) -> (usize, usize){
+        self.emulator.position()
+    }
+
+    /// Blanks the whole screen and moves the cursor to the top left corner.
+    pub fn clear_screen(&mut self){
+        self.show_live();
+        self.emulator.clear(&mut self.grid);
+        self.update_cursor();
+    }
+
+    /// Starts keeping up to `rows` rows that scroll off the top, needs the heap.
+    pub fn enable_scrollback(&mut self, rows:usize){
+        self.grid.history = Some(VecDeque::with_capacity(rows));
+        self.grid.history_limit = rows;
+    }
+
+    /// Shows `rows` more rows of the scrollback history.
+    pub fn scroll_back(&mut self, rows:usize){
+        let available = self.grid.history.as_ref().map_or(0, |history| history.len());
+        self.show_history((self.grid.view_offset + rows).min(available));
+    }
+
+    /// Moves the view `rows` rows back towards the live screen.
+    pub fn scroll_forward(&mut self, rows:usize){
+        self.show_history(self.grid.view_offset.saturating_sub(rows));
+    }
+
+    /// How many rows the view is scrolled back, 0 while showing the live screen.
+    pub fn view_offset(&self) -> usize{
+        self.grid.view_offset
+    }
+
+    fn show_live(&mut self){
+        self.show_history(0);
+    }
+
+    fn show_history(&mut self, offset:usize){
+        if offset != self.grid.view_offset{
+            self.grid.view_offset = offset;
+            self.redraw();
+        }
+    }
+
+    fn redraw(&mut self){
+        self.grid.redraw();
+        self.update_cursor();
+    }
+
+    // moves the blinking hardware cursor to where the next character goes
+    fn update_cursor(&self){
+        // only the terminal on the screen owns the cursor
+        if self.grid.screen.is_none(){
+            return;
+        }
+        if !self.emulator.cursor_visible() || self.grid.view_offset != 0{
+            write_crtc(CURSOR_START, CURSOR_DISABLE);
+            return;
+        }
+        // a full row leaves the cursor past the last column until the next character
+        let (row, col) = self.emulator.position();
+        let position = row * BUFFER_WIDTH + col.min(BUFFER_WIDTH - 1);
+        // an underline in the last two scanlines of the 16 line font
+        write_crtc(CURSOR_START, 14);
+        write_crtc(CURSOR_END, 15);
+        write_crtc(CURSOR_LOCATION_HIGH, (position >> 8) as u8);
+        write_crtc(CURSOR_LOCATION_LOW, position as u8);
     }
 }
 
@@ -144,14 +362,6 @@ impl fmt::Write for Writer{
 }
 
 
-// Global Interface
-lazy_static!{
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        column_position: 0,
-        color_code: ColorCode::new(Color::Green, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
-    });
-}
 
 
 // for making them available over the whole crate
@@ -173,10 +383,34 @@ macro_rules! println {
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments){
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
-    interrupts::without_interrupts(|| {
-        WRITER.lock().write_fmt(args).unwrap();
-    });
+    use crate::framebuffer::console::CONSOLE;
+    // the graphics console replaces the text mode terminals once enabled
+    if let Some(console) = CONSOLE.lock().as_mut() {
+        console.write_fmt(args).unwrap();
+        return;
+    }
+    terminal::writer(terminal::current()).lock().write_fmt(args).unwrap();
+}
+
+/// Clears the screen of the calling task's terminal.
+pub fn clear_screen(){
+    use crate::framebuffer::console::CONSOLE;
+    if let Some(console) = CONSOLE.lock().as_mut() {
+        console.clear_screen();
+        return;
+    }
+    terminal::writer(terminal::current()).lock().clear_screen();
+}
+
+/// Rows of scrollback each terminal keeps once the heap is initialized.
+pub const SCROLLBACK_ROWS: usize = 50;
+
+/// Enables the scrollback history of every terminal, must only be
+/// called once the heap is initialized.
+pub fn enable_scrollback(){
+    for index in 0..terminal::COUNT{
+        terminal::writer(index).lock().enable_scrollback(SCROLLBACK_ROWS);
+    }
 }
 
 
@@ -200,10 +434,10 @@ fn test_println_output() {
     use x86_64::instructions::interrupts;
     let s = "Some test string that fits on a single line";
     interrupts::without_interrupts(||{
-        let mut writer = WRITER.lock();
+        let mut writer = terminal::writer(0).lock();
         writeln!(writer ,"\n{}", s).expect("writeln failed");
         for (i, c) in s.chars().enumerate() {
-            let screen_char = writer.buffer.characters[BUFFER_HEIGHT - 2][i].read();
+            let screen_char = writer.grid.buffer.characters[BUFFER_HEIGHT - 2][i].read();
             assert_eq!(char::from(screen_char.ascii_char), c);
         }
     });
@@ -221,7 +455,7 @@ fn test_really_long_line(){
         nlin = len / BUFFER_WIDTH + 1;
     }
     interrupts::without_interrupts(||{
-        let mut writer = WRITER.lock();
+        let mut writer = terminal::writer(0).lock();
         writeln!(writer,"\n{}",s).expect("writeln failed");
         let mut rw_start:usize= BUFFER_HEIGHT- 2 - nlin;
         let mut current_char:usize = 0;
@@ -230,10 +464,109 @@ fn test_really_long_line(){
                 current_char = 0;
                 rw_start += 1;
             }
-            let screen_char:ScreenChar = writer.buffer.characters[rw_start][current_char].read();
+            let screen_char:ScreenChar = writer.grid.buffer.characters[rw_start][current_char].read();
             assert_eq!(char::from(screen_char.ascii_char),c);  
             current_char += 1; 
         }
     });
 }
 
+#[test_case]
+fn test_cp437_output(){
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+    let s = "Grüße, π ≈ 3.14 ╔═╗ ☺ €";
+    interrupts::without_interrupts(||{
+        let mut writer = terminal::writer(0).lock();
+        writeln!(writer ,"\n{}", s).expect("writeln failed");
+        for (i, c) in s.chars().enumerate() {
+            let screen_char = writer.grid.buffer.characters[BUFFER_HEIGHT - 2][i].read();
+            match c {
+                // there is no euro sign in code page 437
+                '€' => assert_eq!(screen_char.ascii_char, cp437::REPLACEMENT),
+                c => assert_eq!(cp437::decode(screen_char.ascii_char), c),
+            }
+        }
+    });
+}
+
+#[test_case]
+fn test_cp437_control_glyphs(){
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+    interrupts::without_interrupts(||{
+        let mut writer = terminal::writer(0).lock();
+        write!(writer, "\n").expect("write failed");
+        let (row, col) = writer.position();
+        write!(writer, "◘◙").expect("write failed");
+        assert_eq!(writer.position(), (row, col + 2));
+        assert_eq!(writer.grid.buffer.characters[row][col].read().ascii_char, 0x08);
+        assert_eq!(writer.grid.buffer.characters[row][col + 1].read().ascii_char, 0x0a);
+    });
+}
+
+#[test_case]
+fn test_ansi_colors(){
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+    interrupts::without_interrupts(||{
+        let mut writer = terminal::writer(0).lock();
+        write!(writer, "\n\x1b[1;31mA\x1b[44mB\x1b[0mC\x1b[7mD\x1b[m").expect("write failed");
+        let row = BUFFER_HEIGHT - 1;
+        let colors = [
+            ColorCode::new(Color::LightRed, Color::Black),
+            ColorCode::new(Color::LightRed, Color::Blue),
+            ColorCode::new(Color::Green, Color::Black),
+            ColorCode::new(Color::Black, Color::Green),
+        ];
+        for (col, color_code) in colors.iter().enumerate(){
+            assert_eq!(writer.grid.buffer.characters[row][col].read().color_code, *color_code);
+        }
+        assert_eq!(writer.emulator.rendition(), Rendition::DEFAULT);
+    });
+}
+
+#[test_case]
+fn test_ansi_cursor_and_erase(){
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+    interrupts::without_interrupts(||{
+        let mut writer = terminal::writer(0).lock();
+        // clear the screen, write two rows and erase the end of the first one
+        write!(writer, "\x1b[2J\x1b[3;5Hhello\x1b7\x1b[4;1Hworld\x1b8\x1b[3D\x1b[K").expect("write failed");
+        let read = |row:usize, col:usize| writer.grid.buffer.characters[row][col].read().ascii_char;
+        assert_eq!([read(2, 4), read(2, 5), read(2, 6)], *b"he ");
+        assert_eq!(read(3, 0), b'w');
+        assert_eq!(read(0, 0), b' ');
+
+        // a scroll region of rows 2..=3 leaves the rows around it alone
+        write!(writer, "\x1b[1;1Htop\x1b[2;3r\x1b[2;1Ha\nb\nc").expect("write failed");
+        let read = |row:usize, col:usize| writer.grid.buffer.characters[row][col].read().ascii_char;
+        assert_eq!([read(0, 0), read(1, 0), read(2, 0), read(3, 0)], *b"tbcw");
+
+        // put the writer back the way the other tests expect it
+        write!(writer, "\x1bc\x1b[25H").expect("write failed");
+        assert_eq!(writer.position(), (BUFFER_HEIGHT - 1, 0));
+    });
+}
+
+#[test_case]
+fn test_position_and_clear_screen(){
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+    interrupts::without_interrupts(||{
+        let mut writer = terminal::writer(0).lock();
+        writer.clear_screen();
+        assert_eq!(writer.position(), (0, 0));
+        write!(writer, "ab").expect("write failed");
+        writer.set_position(5, 10);
+        write!(writer, "c").expect("write failed");
+        assert_eq!(writer.position(), (5, 11));
+        assert_eq!(writer.grid.buffer.characters[0][0].read().ascii_char, b'a');
+        assert_eq!(writer.grid.buffer.characters[5][10].read().ascii_char, b'c');
+
+        writer.set_position(100, 100);
+        assert_eq!(writer.position(), (BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1));
+        writer.set_position(BUFFER_HEIGHT - 1, 0);
+    });
+}
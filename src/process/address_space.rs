@@ -0,0 +1,95 @@
+use super::ProcessError;
+use alloc::{boxed::Box, vec::Vec};
+use spin::Mutex;
+use x86_64::{
+    registers::control::Cr3,
+    structures::paging::{FrameAllocator, PageTable, PhysFrame, Size4KiB},
+    VirtAddr,
+};
+
+/// The level 4 page table of a process, loaded while its task is polled.
+/// It starts as a copy of the kernel's, so the kernel stays mapped and
+/// shares its lower tables with every process; the slots the kernel
+/// leaves empty are the process' own.
+#[derive(Debug, PartialEq, Eq)]
+pub struct AddressSpace {
+    level_4_table: PhysFrame,
+}
+
+// where the tables of processes come from, set by `init`
+struct Tables {
+    physical_memory_offset: VirtAddr,
+    kernel: PhysFrame,
+    frames: Box<dyn FrameAllocator<Size4KiB> + Send>,
+    // tables of ended processes, the frame allocator can not take them back
+    free: Vec<PhysFrame>,
+}
+
+impl Tables {
+    fn table(&self, frame: PhysFrame) -> *mut PageTable {
+        (self.physical_memory_offset + frame.start_address().as_u64()).as_mut_ptr()
+    }
+}
+
+static TABLES: Mutex<Option<Tables>> = Mutex::new(None);
+
+/// Gives processes started from now on a level 4 table of their own,
+/// allocated from `frames`, with the mappings of the table loaded now.
+/// Before, they run on the kernel's table. Needs the heap.
+///
+/// # Safety
+///
+/// All physical memory must be mapped at `physical_memory_offset`, and
+/// the kernel must not add level 4 entries once processes run: they would
+/// not see them.
+pub unsafe fn init(physical_memory_offset: VirtAddr, frames: impl FrameAllocator<Size4KiB> + Send + 'static) {
+    *TABLES.lock() = Some(Tables {
+        physical_memory_offset,
+        kernel: Cr3::read().0,
+        frames: Box::new(frames),
+        free: Vec::new(),
+    });
+}
+
+impl AddressSpace {
+    // a copy of the kernel's table, None before `init`
+    pub(super) fn new() -> Result<Option<AddressSpace>, ProcessError> {
+        let mut tables = TABLES.lock();
+        let tables = match tables.as_mut() {
+            Some(tables) => tables,
+            None => return Ok(None),
+        };
+        let frame = match tables.free.pop() {
+            Some(frame) => frame,
+            None => tables.frames.allocate_frame().ok_or(ProcessError::NoMemory)?,
+        };
+        // the frame is no table in use, the kernel's is only read
+        let (table, kernel) = unsafe { (&mut *tables.table(frame), &*tables.table(tables.kernel)) };
+        for (entry, kernel) in table.iter_mut().zip(kernel.iter()) {
+            *entry = kernel.clone();
+        }
+        Ok(Some(AddressSpace { level_4_table: frame }))
+    }
+
+    pub fn level_4_table(&self) -> PhysFrame {
+        self.level_4_table
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        if let Some(tables) = TABLES.lock().as_mut() {
+            tables.free.push(self.level_4_table);
+        }
+    }
+}
+
+// loads `table`, which must map the kernel like the loaded one, into CR3
+// and returns the table it replaced
+pub(super) unsafe fn load(table: PhysFrame) -> PhysFrame {
+    let (loaded, flags) = Cr3::read();
+    if loaded != table {
+        unsafe { Cr3::write(table, flags) };
+    }
+    loaded
+}
@@ -0,0 +1,120 @@
+use super::ProcessError;
+use crate::fs::{self, mount::{self, SharedFs}, FileType, FsError};
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+/// Files one process can have open at the same time.
+pub const MAX_FILES: usize = 16;
+
+/// A file descriptor, an index into the file table of a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fd(pub usize);
+
+impl fmt::Display for Fd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// An entry of the file table, a file on a mounted filesystem. Reads and
+/// writes go through `fs`, which stays usable after it is unmounted.
+#[derive(Clone)]
+pub struct OpenFile {
+    pub fs: SharedFs,
+    /// The path of the file inside `fs`.
+    pub path: String,
+    pub writable: bool,
+    /// Where the next read or write starts.
+    pub offset: u64,
+}
+
+impl OpenFile {
+    /// Finds the file at `path` on the mounted filesystems. Directories
+    /// can not be opened.
+    pub fn open(path: &str, writable: bool) -> Result<OpenFile, FsError> {
+        let (fs, path) = mount::resolve(path)?;
+        if path == "/" {
+            return Err(FsError::IsADirectory);
+        }
+        let (parent, name) = fs::split_parent(&path)?;
+        let parent = if parent.is_empty() { "/" } else { parent };
+        let entry = fs
+            .lock()
+            .read_dir(parent)?
+            .into_iter()
+            .find(|entry| entry.name == name)
+            .ok_or(FsError::NotFound)?;
+        if entry.file_type == FileType::Directory {
+            return Err(FsError::IsADirectory);
+        }
+        Ok(OpenFile { fs, path, writable, offset: 0 })
+    }
+}
+
+impl fmt::Debug for OpenFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OpenFile")
+            .field("path", &self.path)
+            .field("writable", &self.writable)
+            .field("offset", &self.offset)
+            .finish()
+    }
+}
+
+/// The open files of a process, copied into the children it spawns.
+#[derive(Debug, Clone, Default)]
+pub struct FileTable {
+    files: Vec<Option<OpenFile>>,
+}
+
+impl FileTable {
+    pub const fn new() -> FileTable {
+        FileTable { files: Vec::new() }
+    }
+
+    /// Adds `file` under the lowest free descriptor.
+    pub fn open(&mut self, file: OpenFile) -> Result<Fd, ProcessError> {
+        if let Some(index) = self.files.iter().position(Option::is_none) {
+            self.files[index] = Some(file);
+            return Ok(Fd(index));
+        }
+        if self.files.len() == MAX_FILES {
+            return Err(ProcessError::TooManyFiles);
+        }
+        self.files.push(Some(file));
+        Ok(Fd(self.files.len() - 1))
+    }
+
+    pub fn close(&mut self, fd: Fd) -> Result<OpenFile, ProcessError> {
+        let file = self.files.get_mut(fd.0).and_then(Option::take).ok_or(ProcessError::BadFd(fd))?;
+        while let Some(None) = self.files.last() {
+            self.files.pop();
+        }
+        Ok(file)
+    }
+
+    pub fn get(&self, fd: Fd) -> Option<&OpenFile> {
+        self.files.get(fd.0).and_then(Option::as_ref)
+    }
+
+    pub fn get_mut(&mut self, fd: Fd) -> Option<&mut OpenFile> {
+        self.files.get_mut(fd.0).and_then(Option::as_mut)
+    }
+
+    /// The open files with their descriptors.
+    pub fn iter(&self) -> impl Iterator<Item = (Fd, &OpenFile)> {
+        self.files.iter().enumerate().filter_map(|(index, file)| Some((Fd(index), file.as_ref()?)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+}
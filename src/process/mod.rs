@@ -0,0 +1,422 @@
+use crate::fs::FsError;
+use crate::task::{executor::Spawner, Task};
+use alloc::{boxed::Box, collections::BTreeMap, format, vec::Vec};
+use core::{
+    convert::Infallible,
+    fmt,
+    future::Future,
+    mem,
+    pin::Pin,
+    sync::atomic::{AtomicU32, Ordering},
+    task::{Context, Poll, Waker},
+};
+use spin::{Mutex, MutexGuard};
+use x86_64::{structures::paging::PhysFrame, PhysAddr};
+
+pub mod address_space;
+pub mod files;
+
+pub use address_space::AddressSpace;
+pub use files::{Fd, FileTable, OpenFile, MAX_FILES};
+
+/// A process id. Pids are not reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pid(pub u32);
+
+impl Pid {
+    /// The kernel itself, the parent of the processes it starts and of
+    /// the orphans of others. It reaps them as soon as they end.
+    pub const KERNEL: Pid = Pid(0);
+}
+
+impl fmt::Display for Pid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// How a process ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// Its main future returned, or it called `exit`, with this code.
+    Exited(i32),
+    Killed,
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExitStatus::Exited(code) => write!(f, "exited {}", code),
+            ExitStatus::Killed => write!(f, "killed"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Running,
+    /// Ended, kept until its parent collects the status with `wait`.
+    Zombie(ExitStatus),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessError {
+    NoSuchProcess(Pid),
+    /// `wait` found no child it could wait for.
+    NoChildren,
+    /// The kernel process can not be killed.
+    NotKillable,
+    /// `MAX_FILES` files are open already.
+    TooManyFiles,
+    BadFd(Fd),
+    /// Called outside of a process.
+    NotInProcess,
+    /// Opening a file failed on its filesystem.
+    Fs(FsError),
+    /// No frame was left for the page table of a new process.
+    NoMemory,
+}
+
+impl From<FsError> for ProcessError {
+    fn from(error: FsError) -> Self {
+        ProcessError::Fs(error)
+    }
+}
+
+struct Process {
+    name: &'static str,
+    parent: Pid,
+    state: State,
+    // None for the kernel and processes started before `address_space::init`
+    address_space: Option<AddressSpace>,
+    files: FileTable,
+    // set by `exit` or `kill`, the process ends at its next poll
+    ending: Option<ExitStatus>,
+    // the task running the process, woken by `kill`
+    task: Option<Waker>,
+    // tasks of the process waiting for a child to end
+    waiters: Vec<Waker>,
+}
+
+impl Process {
+    fn new(name: &'static str, parent: Pid, address_space: Option<AddressSpace>, files: FileTable) -> Process {
+        Process {
+            name,
+            parent,
+            state: State::Running,
+            address_space,
+            files,
+            ending: None,
+            task: None,
+            waiters: Vec::new(),
+        }
+    }
+}
+
+// only used from tasks, not from interrupt handlers, and only one CPU runs
+// tasks; no function of this module calls out while holding it, so it is
+// never locked twice
+static TABLE: Mutex<BTreeMap<Pid, Process>> = Mutex::new(BTreeMap::new());
+static NEXT_PID: AtomicU32 = AtomicU32::new(1);
+// the pid of the process being polled, 0 outside of processes
+static CURRENT: AtomicU32 = AtomicU32::new(0);
+
+// the kernel process is added on first use
+fn table() -> MutexGuard<'static, BTreeMap<Pid, Process>> {
+    let mut table = TABLE.lock();
+    table
+        .entry(Pid::KERNEL)
+        .or_insert_with(|| Process::new("kernel", Pid::KERNEL, None, FileTable::new()));
+    table
+}
+
+/// The process being polled, `Pid::KERNEL` outside of processes.
+pub fn current() -> Pid {
+    Pid(CURRENT.load(Ordering::Relaxed))
+}
+
+/// Starts a process running `main` as a child of the current one, in a
+/// task spawned with `spawner`. It gets a copy of its parent's open files
+/// and ends with the code `main` returns, unless it calls `exit` or is
+/// killed first. Once `address_space::init` ran it gets an `AddressSpace`
+/// too, loaded while `main` is polled.
+///
+/// There is no `fork`: the state of a task is a future, which can not be
+/// copied, so a child always starts from a `main` of its own.
+pub fn spawn<F>(spawner: &Spawner, name: &'static str, main: F) -> Result<Pid, ProcessError>
+where
+    F: Future<Output = i32> + 'static,
+{
+    let address_space = AddressSpace::new()?;
+    let level_4_table = address_space.as_ref().map(AddressSpace::level_4_table);
+    let pid = Pid(NEXT_PID.fetch_add(1, Ordering::Relaxed));
+    let parent = current();
+    {
+        let mut table = table();
+        let files = table.get(&parent).map(|parent| parent.files.clone()).unwrap_or_default();
+        table.insert(pid, Process::new(name, parent, address_space, files));
+    }
+    spawner.spawn(Task::new(Main { pid, level_4_table, main: Some(Box::pin(main)) }).named(name));
+    Ok(pid)
+}
+
+// the task of a process, polls its main future with `current` set to it
+// and its page table loaded
+struct Main {
+    pid: Pid,
+    level_4_table: Option<PhysFrame>,
+    main: Option<Pin<Box<dyn Future<Output = i32>>>>,
+}
+
+impl Future for Main {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        let main = match this.main.as_mut() {
+            Some(main) => main,
+            None => return Poll::Ready(()),
+        };
+        let status = match ending(this.pid, cx.waker()) {
+            Some(status) => status,
+            None => {
+                let interrupted = CURRENT.swap(this.pid.0, Ordering::Relaxed);
+                // the process' table maps the kernel like every other one
+                let loaded = this.level_4_table.map(|table| unsafe { address_space::load(table) });
+                let result = main.as_mut().poll(cx);
+                if let Some(table) = loaded {
+                    unsafe { address_space::load(table) };
+                }
+                CURRENT.store(interrupted, Ordering::Relaxed);
+                match result {
+                    Poll::Ready(code) => ExitStatus::Exited(code),
+                    Poll::Pending => match ending(this.pid, cx.waker()) {
+                        Some(status) => status,
+                        None => return Poll::Pending,
+                    },
+                }
+            }
+        };
+        this.main = None;
+        finish(this.pid, status);
+        Poll::Ready(())
+    }
+}
+
+impl Drop for Main {
+    fn drop(&mut self) {
+        // the task was aborted or its executor dropped
+        if self.main.take().is_some() {
+            finish(self.pid, ExitStatus::Killed);
+        }
+    }
+}
+
+// whether `exit` or `kill` ended the process, remembers its task for `kill`
+fn ending(pid: Pid, waker: &Waker) -> Option<ExitStatus> {
+    let mut table = table();
+    let process = table.get_mut(&pid)?;
+    if !process.task.as_ref().is_some_and(|task| task.will_wake(waker)) {
+        process.task = Some(waker.clone());
+    }
+    process.ending
+}
+
+// turns the process into a zombie for its parent to wait for and closes
+// its files, hands its children to the kernel. Like init, the kernel reaps
+// its zombies right away instead.
+fn finish(pid: Pid, status: ExitStatus) {
+    let waiters = {
+        let mut table = table();
+        // its zombie children have no one left to wait for them
+        table.retain(|_, process| process.parent != pid || process.state == State::Running);
+        for process in table.values_mut().filter(|process| process.parent == pid) {
+            process.parent = Pid::KERNEL;
+        }
+        let process = match table.get_mut(&pid) {
+            Some(process) => process,
+            None => return,
+        };
+        let parent = process.parent;
+        if parent == Pid::KERNEL {
+            table.remove(&pid);
+            return;
+        }
+        process.state = State::Zombie(status);
+        // the task is done with the table, it is not loaded any more
+        process.address_space = None;
+        process.files.clear();
+        process.task = None;
+        process.waiters.clear();
+        table.get_mut(&parent).map(|parent| mem::take(&mut parent.waiters)).unwrap_or_default()
+    };
+    for waker in waiters {
+        waker.wake();
+    }
+}
+
+/// Ends the current process with exit status `code`. The future never
+/// completes, the process stops at the `.await`.
+pub fn exit(code: i32) -> Result<Exit, ProcessError> {
+    match current() {
+        Pid::KERNEL => Err(ProcessError::NotInProcess),
+        pid => Ok(Exit { pid, code }),
+    }
+}
+
+/// Ends a process, see `exit`.
+pub struct Exit {
+    pid: Pid,
+    code: i32,
+}
+
+impl Future for Exit {
+    type Output = Infallible;
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Infallible> {
+        if let Some(process) = table().get_mut(&self.pid) {
+            // a kill that came first wins
+            process.ending.get_or_insert(ExitStatus::Exited(self.code));
+        }
+        Poll::Pending
+    }
+}
+
+/// Ends process `pid` with `ExitStatus::Killed` the next time its task is
+/// polled, dropping its main future. Killing a zombie does nothing.
+pub fn kill(pid: Pid) -> Result<(), ProcessError> {
+    if pid == Pid::KERNEL {
+        return Err(ProcessError::NotKillable);
+    }
+    let task = {
+        let mut table = table();
+        let process = table.get_mut(&pid).ok_or(ProcessError::NoSuchProcess(pid))?;
+        if let State::Zombie(_) = process.state {
+            return Ok(());
+        }
+        process.ending.get_or_insert(ExitStatus::Killed);
+        process.task.take()
+    };
+    if let Some(task) = task {
+        task.wake();
+    }
+    Ok(())
+}
+
+/// Waits for child `pid` of the current process, or any child for
+/// `None`, to end and removes it from the process table. The kernel reaps
+/// its children itself, outside of a process there are none to wait for.
+pub fn wait(pid: Option<Pid>) -> Wait {
+    Wait { parent: current(), pid }
+}
+
+pub struct Wait {
+    parent: Pid,
+    pid: Option<Pid>,
+}
+
+impl Future for Wait {
+    type Output = Result<(Pid, ExitStatus), ProcessError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let (parent, wanted) = (self.parent, self.pid);
+        if parent == Pid::KERNEL {
+            return Poll::Ready(Err(ProcessError::NoChildren));
+        }
+        let mut table = table();
+        let mut children = table
+            .iter()
+            .filter(|(&child, process)| child != Pid::KERNEL && process.parent == parent)
+            .filter(|(&child, _)| wanted.is_none_or(|pid| pid == child))
+            .peekable();
+        if children.peek().is_none() {
+            return Poll::Ready(Err(ProcessError::NoChildren));
+        }
+        let zombie = children.find_map(|(&child, process)| match process.state {
+            State::Zombie(status) => Some((child, status)),
+            State::Running => None,
+        });
+        if let Some((child, status)) = zombie {
+            table.remove(&child);
+            return Poll::Ready(Ok((child, status)));
+        }
+        if let Some(parent) = table.get_mut(&parent) {
+            if !parent.waiters.iter().any(|waiter| waiter.will_wake(cx.waker())) {
+                parent.waiters.push(cx.waker().clone());
+            }
+        }
+        Poll::Pending
+    }
+}
+
+/// Opens the file at `path` on the mounted filesystems in the file table
+/// of the current process.
+pub fn open(path: &str, writable: bool) -> Result<Fd, ProcessError> {
+    let file = OpenFile::open(path, writable)?;
+    with_files(|files| files.open(file))
+}
+
+pub fn close(fd: Fd) -> Result<(), ProcessError> {
+    with_files(|files| files.close(fd).map(|_| ()))
+}
+
+/// Runs `f` on the file table of the current process. The table is taken
+/// out of the process meanwhile, so `f` can use the rest of this module,
+/// but files it opens or closes with `open` and `close` are lost.
+pub fn with_files<R>(f: impl FnOnce(&mut FileTable) -> R) -> R {
+    let pid = current();
+    let mut files = table().get_mut(&pid).map(|process| mem::take(&mut process.files)).unwrap_or_default();
+    let result = f(&mut files);
+    // a process that ended meanwhile has its files closed
+    if let Some(process) = table().get_mut(&pid).filter(|process| process.state == State::Running) {
+        process.files = files;
+    }
+    result
+}
+
+/// What `ps` shows of a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessInfo {
+    pub pid: Pid,
+    pub parent: Pid,
+    pub name: &'static str,
+    pub state: State,
+    /// The level 4 table of its `AddressSpace`, None for the kernel's.
+    pub page_table: Option<PhysAddr>,
+    pub open_files: usize,
+}
+
+/// The processes in the table by pid, zombies included.
+pub fn processes() -> Vec<ProcessInfo> {
+    table()
+        .iter()
+        .map(|(&pid, process)| ProcessInfo {
+            pid,
+            parent: process.parent,
+            name: process.name,
+            state: process.state,
+            page_table: process.address_space.as_ref().map(|space| space.level_4_table().start_address()),
+            open_files: process.files.len(),
+        })
+        .collect()
+}
+
+/// Writes the pid, parent, state, open files and page table of every
+/// process, for the `ps` shell command.
+pub fn write_ps(out: &mut impl fmt::Write) -> fmt::Result {
+    writeln!(out, "  pid   ppid  state      files  page table  name")?;
+    for process in processes() {
+        write!(out, "{:>5}  {:>5}  ", process.pid, process.parent)?;
+        match process.state {
+            State::Running => write!(out, "{:<9}", "running")?,
+            State::Zombie(status) => write!(out, "{:<9}", format!("{}", status))?,
+        }
+        write!(out, "  {:>5}  ", process.open_files)?;
+        match process.page_table {
+            Some(table) => write!(out, "{:>#10x}", table.as_u64())?,
+            None => write!(out, "{:>10}", "kernel")?,
+        }
+        writeln!(out, "  {}", process.name)?;
+    }
+    Ok(())
+}
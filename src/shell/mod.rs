@@ -0,0 +1,109 @@
+use crate::interrupts::irq;
+use crate::process;
+#[cfg(feature = "lock-debug")]
+use crate::sync::lockdep;
+use crate::task::executor::{self, Monitor};
+use crate::task::workqueue;
+use crate::time::idle;
+use crate::{print, println};
+use crate::vga_buffer::terminal::{self, InputStream};
+use alloc::string::String;
+use core::fmt;
+use futures_util::stream::StreamExt;
+use pc_keyboard::DecodedKey;
+
+const PROMPT: &str = "> ";
+
+// the commands and what they show, in the order `help` lists them
+const COMMANDS: &[(&str, &str)] = &[
+    ("help", "list the commands"),
+    ("interrupts", "interrupt counts and handlers per vector"),
+    ("idle", "time spent halted and what woke the CPU"),
+    ("tasks", "the tasks of the executor and their terminals"),
+    ("top", "the tasks by CPU time with their wake reasons"),
+    ("workqueues", "work queued, run and dropped per CPU"),
+    ("ps", "the processes with their parents and open files"),
+    #[cfg(feature = "lock-debug")]
+    ("lockdep", "lock order inversions and the locks held"),
+];
+
+// print!/println! as a `fmt::Write`, so commands show up on the terminal
+// of the shell task
+struct TerminalOutput;
+
+impl fmt::Write for TerminalOutput {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        print!("{}", s);
+        Ok(())
+    }
+}
+
+/// Runs command lines, looking at the executor it runs on through a
+/// `Monitor`.
+pub struct Shell {
+    monitor: Monitor,
+}
+
+impl Shell {
+    pub fn new(monitor: Monitor) -> Self {
+        Shell { monitor }
+    }
+
+    /// Runs one command line, writing what it shows to `out`.
+    pub async fn execute(&self, line: &str, out: &mut impl fmt::Write) -> fmt::Result {
+        let command = match line.split_whitespace().next() {
+            Some(command) => command,
+            None => return Ok(()),
+        };
+        match command {
+            "help" => {
+                for (name, description) in COMMANDS {
+                    writeln!(out, "{:<12}{}", name, description)?;
+                }
+                Ok(())
+            }
+            "interrupts" => irq::write_statistics(out),
+            "idle" => idle::write_statistics(out),
+            "tasks" => executor::write_tasks(&self.monitor.tasks().await, out),
+            "top" => executor::write_top(&self.monitor.tasks().await, out),
+            "workqueues" => workqueue::write_statistics(out),
+            "ps" => process::write_ps(out),
+            #[cfg(feature = "lock-debug")]
+            "lockdep" => lockdep::write_report(out),
+            _ => writeln!(out, "{}: unknown command, try help", command),
+        }
+    }
+
+    /// Reads command lines from the terminal of the calling task and runs
+    /// them, forever.
+    pub async fn run(self) {
+        let mut keys = InputStream::new(terminal::current());
+        let mut line = String::new();
+        print!("{}", PROMPT);
+        while let Some(key) = keys.next().await {
+            // keys without a character, like the arrows, do nothing
+            let c = match key {
+                DecodedKey::Unicode(c) => c,
+                DecodedKey::RawKey(_) => continue,
+            };
+            match c {
+                '\n' => {
+                    println!();
+                    let _ = self.execute(&line, &mut TerminalOutput).await;
+                    line.clear();
+                    print!("{}", PROMPT);
+                }
+                '\x08' => {
+                    if line.pop().is_some() {
+                        print!("\x08");
+                    }
+                }
+                c if !c.is_control() => {
+                    line.push(c);
+                    print!("{}", c);
+                }
+                _ => {}
+            }
+        }
+    }
+}
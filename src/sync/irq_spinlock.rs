@@ -0,0 +1,134 @@
+use core::{
+    fmt,
+    ops::{Deref, DerefMut},
+};
+use spin::{Mutex, MutexGuard};
+use x86_64::instructions::interrupts;
+
+#[cfg(feature = "lock-debug")]
+use super::lockdep;
+
+/// A spinlock that disables interrupts while it is held, so an interrupt
+/// handler taking the same lock cannot deadlock with the code it
+/// interrupted. The interrupt flag is restored when the guard is dropped.
+pub struct IrqSpinlock<T: ?Sized> {
+    name: &'static str,
+    #[cfg(feature = "lock-debug")]
+    class: lockdep::LockClass,
+    inner: Mutex<T>,
+}
+
+impl<T> IrqSpinlock<T> {
+    /// Creates a lock, `name` shows up in lock debugging reports.
+    pub const fn new(name: &'static str, value: T) -> IrqSpinlock<T> {
+        IrqSpinlock {
+            name,
+            #[cfg(feature = "lock-debug")]
+            class: lockdep::LockClass::new(),
+            inner: Mutex::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> IrqSpinlock<T> {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    #[track_caller]
+    pub fn lock(&self) -> IrqSpinlockGuard<'_, T> {
+        let enabled = interrupts::are_enabled();
+        interrupts::disable();
+        #[cfg(feature = "lock-debug")]
+        lockdep::acquire(&self.class, self.name, core::panic::Location::caller());
+        IrqSpinlockGuard {
+            lock: self,
+            guard: Some(self.inner.lock()),
+            enabled,
+        }
+    }
+
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<IrqSpinlockGuard<'_, T>> {
+        let enabled = interrupts::are_enabled();
+        interrupts::disable();
+        match self.inner.try_lock() {
+            Some(guard) => {
+                #[cfg(feature = "lock-debug")]
+                lockdep::acquire(&self.class, self.name, core::panic::Location::caller());
+                Some(IrqSpinlockGuard { lock: self, guard: Some(guard), enabled })
+            }
+            None => {
+                if enabled {
+                    interrupts::enable();
+                }
+                None
+            }
+        }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for IrqSpinlock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("IrqSpinlock").field("name", &self.name).field("value", &&*guard).finish(),
+            None => f.debug_struct("IrqSpinlock").field("name", &self.name).field("value", &"<locked>").finish(),
+        }
+    }
+}
+
+pub struct IrqSpinlockGuard<'a, T: ?Sized> {
+    #[cfg_attr(not(feature = "lock-debug"), allow(dead_code))]
+    lock: &'a IrqSpinlock<T>,
+    // taken on drop to unlock before interrupts are enabled again
+    guard: Option<MutexGuard<'a, T>>,
+    // the interrupt flag before locking
+    enabled: bool,
+}
+
+impl<T: ?Sized> Deref for IrqSpinlockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.as_ref().expect("unlocked guard")
+    }
+}
+
+impl<T: ?Sized> DerefMut for IrqSpinlockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.as_mut().expect("unlocked guard")
+    }
+}
+
+impl<T: ?Sized> Drop for IrqSpinlockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.guard = None;
+        #[cfg(feature = "lock-debug")]
+        lockdep::release(&self.lock.class);
+        if self.enabled {
+            interrupts::enable();
+        }
+    }
+}
+
+#[test_case]
+fn test_interrupt_flag_restored() {
+    static LOCK: IrqSpinlock<u32> = IrqSpinlock::new("test", 0);
+    assert!(interrupts::are_enabled());
+    {
+        let mut value = LOCK.lock();
+        assert!(!interrupts::are_enabled());
+        *value += 1;
+        // nested locking keeps them disabled until the outer guard goes
+        let inner = IrqSpinlock::new("inner", ());
+        drop(inner.lock());
+        assert!(!interrupts::are_enabled());
+        assert!(LOCK.try_lock().is_none());
+    }
+    assert!(interrupts::are_enabled());
+    assert_eq!(*LOCK.lock(), 1);
+}
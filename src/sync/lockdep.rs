@@ -0,0 +1,171 @@
+use core::fmt;
+use core::panic::Location;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+// locks tracked, later ones are not checked
+const MAX_CLASSES: usize = 64;
+// locks held at the same time
+const MAX_HELD: usize = 16;
+const MAX_INVERSIONS: usize = 16;
+
+/// Identifies a lock to the checker, assigned when it is first taken.
+pub struct LockClass(AtomicUsize);
+
+impl LockClass {
+    pub(super) const fn new() -> LockClass {
+        LockClass(AtomicUsize::new(0))
+    }
+
+    fn get(&self) -> Option<usize> {
+        match self.0.load(Ordering::Relaxed) {
+            0 => None,
+            class => Some(class - 1),
+        }
+    }
+}
+
+/// Two locks taken in opposite orders, which deadlocks once both orders
+/// run at the same time.
+#[derive(Debug, Clone, Copy)]
+pub struct Inversion {
+    /// Held while taking `second` at `location`.
+    pub first: &'static str,
+    pub second: &'static str,
+    pub location: &'static Location<'static>,
+    /// Where `first` was taken while holding `second` before.
+    pub earlier: &'static Location<'static>,
+}
+
+impl fmt::Display for Inversion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "lock order inversion: {} then {} at {}, but {} then {} at {}",
+            self.first, self.second, self.location, self.second, self.first, self.earlier
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Held {
+    class: usize,
+    location: &'static Location<'static>,
+}
+
+struct State {
+    names: [&'static str; MAX_CLASSES],
+    classes: usize,
+    held: [Option<Held>; MAX_HELD],
+    // where each class was first taken while holding another one
+    after: [[Option<&'static Location<'static>>; MAX_CLASSES]; MAX_CLASSES],
+    inversions: [Option<Inversion>; MAX_INVERSIONS],
+    // inversions beyond MAX_INVERSIONS and locks beyond MAX_CLASSES
+    missed: usize,
+}
+
+// only touched with interrupts disabled, by `IrqSpinlock`
+static STATE: Mutex<State> = Mutex::new(State {
+    names: [""; MAX_CLASSES],
+    classes: 0,
+    held: [None; MAX_HELD],
+    after: [[None; MAX_CLASSES]; MAX_CLASSES],
+    inversions: [None; MAX_INVERSIONS],
+    missed: 0,
+});
+
+impl State {
+    fn register(&mut self, class: &LockClass, name: &'static str) -> Option<usize> {
+        if let Some(class) = class.get() {
+            return Some(class);
+        }
+        if self.classes == MAX_CLASSES {
+            self.missed += 1;
+            return None;
+        }
+        let index = self.classes;
+        self.classes += 1;
+        self.names[index] = name;
+        class.0.store(index + 1, Ordering::Relaxed);
+        Some(index)
+    }
+
+    fn record(&mut self, inversion: Inversion) {
+        match self.inversions.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => *slot = Some(inversion),
+            None => self.missed += 1,
+        }
+    }
+}
+
+// called by `IrqSpinlock::lock` before spinning, so taking a lock twice
+// panics instead of hanging
+pub(super) fn acquire(class: &LockClass, name: &'static str, location: &'static Location<'static>) {
+    let mut state = STATE.lock();
+    let class = match state.register(class, name) {
+        Some(class) => class,
+        None => return,
+    };
+    let held: [Option<Held>; MAX_HELD] = state.held;
+    for holder in held.iter().flatten() {
+        if holder.class == class {
+            drop(state);
+            panic!("lock {} taken again at {}, it is held since {}", name, location, holder.location);
+        }
+        // `class` after `holder`, was it ever the other way round?
+        if let Some(earlier) = state.after[class][holder.class] {
+            if state.after[holder.class][class].is_none() {
+                let first = state.names[holder.class];
+                state.record(Inversion { first, second: name, location, earlier });
+            }
+        }
+        if state.after[holder.class][class].is_none() {
+            state.after[holder.class][class] = Some(location);
+        }
+    }
+    match state.held.iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => *slot = Some(Held { class, location }),
+        None => {
+            drop(state);
+            panic!("more than {} locks held at {}", MAX_HELD, location);
+        }
+    }
+}
+
+pub(super) fn release(class: &LockClass) {
+    let class = match class.get() {
+        Some(class) => class,
+        None => return,
+    };
+    let mut state = STATE.lock();
+    // locks are usually released in reverse order
+    if let Some(slot) = state.held.iter_mut().rev().find(|slot| matches!(slot, Some(held) if held.class == class)) {
+        *slot = None;
+    }
+}
+
+/// The lock order inversions seen since boot, each reported once.
+pub fn inversions() -> impl Iterator<Item = Inversion> {
+    let inversions = interrupts::without_interrupts(|| STATE.lock().inversions);
+    inversions.into_iter().flatten()
+}
+
+/// Writes the inversions and the locks held right now with where they
+/// were taken, for the `lockdep` shell command.
+pub fn write_report(out: &mut impl fmt::Write) -> fmt::Result {
+    let (held, names, missed) = interrupts::without_interrupts(|| {
+        let state = STATE.lock();
+        (state.held, state.names, state.missed)
+    });
+    for inversion in inversions() {
+        writeln!(out, "{}", inversion)?;
+    }
+    for holder in held.iter().flatten() {
+        writeln!(out, "held: {} since {}", names[holder.class], holder.location)?;
+    }
+    if missed != 0 {
+        writeln!(out, "{} locks or inversions were not tracked", missed)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    hint::spin_loop,
+    ops::{Deref, DerefMut},
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+};
+
+/// A queue entry of an `McsLock`, usually on the stack of the CPU waiting
+/// for the lock. Each waiter spins on its own node instead of on the lock,
+/// so a contended lock is not bounced between caches.
+pub struct McsNode {
+    next: AtomicPtr<McsNode>,
+    waiting: AtomicBool,
+}
+
+impl McsNode {
+    pub const fn new() -> McsNode {
+        McsNode {
+            next: AtomicPtr::new(ptr::null_mut()),
+            waiting: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Default for McsNode {
+    fn default() -> Self {
+        McsNode::new()
+    }
+}
+
+/// A fair queued spinlock, see `McsNode`. It does not touch the interrupt
+/// flag.
+pub struct McsLock<T: ?Sized> {
+    // the last node in the queue, null when unlocked
+    tail: AtomicPtr<McsNode>,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for McsLock<T> {}
+unsafe impl<T: ?Sized + Send> Sync for McsLock<T> {}
+
+impl<T> McsLock<T> {
+    pub const fn new(value: T) -> McsLock<T> {
+        McsLock {
+            tail: AtomicPtr::new(ptr::null_mut()),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> McsLock<T> {
+    /// Runs `f` with the lock held, queued on a node on this stack frame.
+    pub fn with_lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let mut node = McsNode::new();
+        // the guard is dropped before `node` goes out of scope
+        let mut guard = unsafe { self.lock(&mut node) };
+        f(&mut guard)
+    }
+
+    /// Runs `f` with the lock held if nobody holds or waits for it.
+    pub fn try_with_lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        let mut node = McsNode::new();
+        // the guard is dropped before `node` goes out of scope
+        let mut guard = unsafe { self.try_lock(&mut node) }?;
+        Some(f(&mut guard))
+    }
+
+    /// Queues `node` and waits for the lock. The node stays borrowed
+    /// until the guard is dropped.
+    ///
+    /// # Safety
+    ///
+    /// The guard must be dropped, not leaked with `mem::forget` or a
+    /// reference cycle: the lock keeps a pointer to `node` until then,
+    /// and the next waiter writes through it after `node` is gone.
+    pub unsafe fn lock<'a>(&'a self, node: &'a mut McsNode) -> McsLockGuard<'a, T> {
+        node.next.store(ptr::null_mut(), Ordering::Relaxed);
+        node.waiting.store(true, Ordering::Relaxed);
+        let node: &'a McsNode = node;
+        let raw = node as *const McsNode as *mut McsNode;
+        let previous = self.tail.swap(raw, Ordering::AcqRel);
+        if !previous.is_null() {
+            // the previous holder clears `waiting` when it unlocks
+            unsafe { (*previous).next.store(raw, Ordering::Release) };
+            while node.waiting.load(Ordering::Acquire) {
+                spin_loop();
+            }
+        }
+        McsLockGuard { lock: self, node }
+    }
+
+    /// Takes the lock if nobody holds or waits for it.
+    ///
+    /// # Safety
+    ///
+    /// As for `lock`, the guard must be dropped before `node` goes away.
+    pub unsafe fn try_lock<'a>(&'a self, node: &'a mut McsNode) -> Option<McsLockGuard<'a, T>> {
+        node.next.store(ptr::null_mut(), Ordering::Relaxed);
+        node.waiting.store(false, Ordering::Relaxed);
+        let node: &'a McsNode = node;
+        let raw = node as *const McsNode as *mut McsNode;
+        self.tail
+            .compare_exchange(ptr::null_mut(), raw, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| McsLockGuard { lock: self, node })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        !self.tail.load(Ordering::Relaxed).is_null()
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for McsLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.try_with_lock(|value| f.debug_struct("McsLock").field("value", &value).finish())
+            .unwrap_or_else(|| f.debug_struct("McsLock").field("value", &"<locked>").finish())
+    }
+}
+
+pub struct McsLockGuard<'a, T: ?Sized> {
+    lock: &'a McsLock<T>,
+    node: &'a McsNode,
+}
+
+impl<T: ?Sized> Deref for McsLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for McsLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for McsLockGuard<'_, T> {
+    fn drop(&mut self) {
+        let raw = self.node as *const McsNode as *mut McsNode;
+        let mut next = self.node.next.load(Ordering::Acquire);
+        if next.is_null() {
+            if self
+                .lock
+                .tail
+                .compare_exchange(raw, ptr::null_mut(), Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                return;
+            }
+            // a waiter swapped itself in but has not linked its node yet
+            loop {
+                next = self.node.next.load(Ordering::Acquire);
+                if !next.is_null() {
+                    break;
+                }
+                spin_loop();
+            }
+        }
+        unsafe { (*next).waiting.store(false, Ordering::Release) };
+    }
+}
+
+#[test_case]
+fn test_mcs_lock() {
+    let lock = McsLock::new(1);
+    lock.with_lock(|value| {
+        *value += 1;
+        assert!(lock.try_with_lock(|_| ()).is_none());
+    });
+    assert!(!lock.is_locked());
+    let mut node = McsNode::new();
+    assert_eq!(*unsafe { lock.lock(&mut node) }, 2);
+    assert!(!lock.is_locked());
+}
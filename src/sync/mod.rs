@@ -0,0 +1,13 @@
+pub mod irq_spinlock;
+#[cfg(feature = "lock-debug")]
+pub mod lockdep;
+pub mod mcs;
+pub mod rcu;
+pub mod rw_spinlock;
+pub mod ticket;
+
+pub use irq_spinlock::{IrqSpinlock, IrqSpinlockGuard};
+pub use mcs::{McsLock, McsLockGuard, McsNode};
+pub use rcu::{Rcu, RcuReadGuard};
+pub use rw_spinlock::{RwSpinlock, RwSpinlockReadGuard, RwSpinlockWriteGuard};
+pub use ticket::{TicketLock, TicketLockGuard};
@@ -0,0 +1,154 @@
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    hint::spin_loop,
+    ops::Deref,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+use x86_64::instructions::interrupts;
+use super::TicketLock;
+
+// readers register with the counter of the current epoch's parity, a grace
+// period flips the epoch and waits for the old counter to drain
+static EPOCH: AtomicUsize = AtomicUsize::new(0);
+static READERS: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+static GRACE_PERIODS: AtomicU64 = AtomicU64::new(0);
+// one grace period at a time
+static SYNCHRONIZE: TicketLock<()> = TicketLock::new(());
+
+/// A read-copy-update cell for read-mostly data. Readers never wait: they
+/// see either the old or the new value. Writers copy the current value,
+/// change the copy, publish it and wait for a grace period, after which
+/// no reader can still see the old one.
+///
+/// Both versions live inline, so it needs no heap and works before the
+/// allocator is up.
+pub struct Rcu<T> {
+    // the current version and the spare one the next update writes
+    slots: [UnsafeCell<Option<T>>; 2],
+    current: AtomicUsize,
+    writer: TicketLock<()>,
+}
+
+unsafe impl<T: Send> Send for Rcu<T> {}
+unsafe impl<T: Send + Sync> Sync for Rcu<T> {}
+
+impl<T> Rcu<T> {
+    pub const fn new(value: T) -> Rcu<T> {
+        Rcu {
+            slots: [UnsafeCell::new(Some(value)), UnsafeCell::new(None)],
+            current: AtomicUsize::new(0),
+            writer: TicketLock::new(()),
+        }
+    }
+
+    /// Enters a read section. Interrupts are disabled until the guard is
+    /// dropped, so do not hold it for long or across an `.await`.
+    pub fn read(&self) -> RcuReadGuard<'_, T> {
+        let enabled = interrupts::are_enabled();
+        interrupts::disable();
+        let parity = loop {
+            let epoch = EPOCH.load(Ordering::SeqCst);
+            READERS[epoch & 1].fetch_add(1, Ordering::SeqCst);
+            // a grace period started in between and may not wait for us
+            if EPOCH.load(Ordering::SeqCst) == epoch {
+                break epoch & 1;
+            }
+            READERS[epoch & 1].fetch_sub(1, Ordering::SeqCst);
+        };
+        let current = self.current.load(Ordering::Acquire);
+        let value = unsafe { (*self.slots[current].get()).as_ref().expect("empty rcu slot") };
+        RcuReadGuard { value, parity, enabled }
+    }
+
+    /// Copies the value, lets `update` change the copy and publishes it.
+    /// Returns once readers of the old value are gone. Must not be called
+    /// from a read section, which would wait for itself.
+    pub fn update<R>(&self, update: impl FnOnce(&mut T) -> R) -> R
+    where
+        T: Clone,
+    {
+        interrupts::without_interrupts(|| {
+            let _writer = self.writer.lock();
+            let current = self.current.load(Ordering::Relaxed);
+            let spare = current ^ 1;
+            let mut copy = unsafe { (*self.slots[current].get()).clone() };
+            let result = update(copy.as_mut().expect("empty rcu slot"));
+            // the previous update waited for the readers of the spare slot
+            unsafe { *self.slots[spare].get() = copy };
+            self.current.store(spare, Ordering::Release);
+            synchronize();
+            result
+        })
+    }
+
+    pub fn into_inner(self) -> T {
+        let [first, second] = self.slots;
+        let current = self.current.into_inner();
+        let value = if current == 0 { first } else { second };
+        value.into_inner().expect("empty rcu slot")
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Rcu<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Rcu").field("value", &&*self.read()).finish()
+    }
+}
+
+pub struct RcuReadGuard<'a, T> {
+    value: &'a T,
+    parity: usize,
+    // the interrupt flag before the read section
+    enabled: bool,
+}
+
+impl<T> Deref for RcuReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<T> Drop for RcuReadGuard<'_, T> {
+    fn drop(&mut self) {
+        READERS[self.parity].fetch_sub(1, Ordering::SeqCst);
+        if self.enabled {
+            interrupts::enable();
+        }
+    }
+}
+
+/// Waits until every read section that started before the call has
+/// ended. Must not be called from a read section.
+pub fn synchronize() {
+    interrupts::without_interrupts(|| {
+        let _serial = SYNCHRONIZE.lock();
+        let old = EPOCH.fetch_add(1, Ordering::SeqCst) & 1;
+        while READERS[old].load(Ordering::SeqCst) != 0 {
+            spin_loop();
+        }
+        GRACE_PERIODS.fetch_add(1, Ordering::Relaxed);
+    })
+}
+
+/// Grace periods completed since boot.
+pub fn grace_periods() -> u64 {
+    GRACE_PERIODS.load(Ordering::Relaxed)
+}
+
+#[test_case]
+fn test_rcu_update() {
+    static TABLE: Rcu<[u8; 4]> = Rcu::new([0; 4]);
+    let before = grace_periods();
+    let old = *TABLE.read();
+    assert!(interrupts::are_enabled());
+    let index = TABLE.update(|table| {
+        table[2] = 7;
+        2
+    });
+    assert_eq!(old, [0; 4]);
+    assert_eq!(TABLE.read()[index], 7);
+    assert_eq!(grace_periods(), before + 1);
+}
@@ -0,0 +1,156 @@
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    hint::spin_loop,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+const WRITER: usize = 1;
+// a writer is spinning, new readers wait so it is not starved
+const WRITER_WAITING: usize = 2;
+const READER: usize = 4;
+
+/// A spinlock for many readers or one writer. A waiting writer keeps new
+/// readers out. It does not touch the interrupt flag.
+pub struct RwSpinlock<T: ?Sized> {
+    // readers in units of `READER`, plus the two flags
+    state: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwSpinlock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwSpinlock<T> {}
+
+impl<T> RwSpinlock<T> {
+    pub const fn new(value: T) -> RwSpinlock<T> {
+        RwSpinlock {
+            state: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwSpinlock<T> {
+    pub fn read(&self) -> RwSpinlockReadGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            spin_loop();
+        }
+    }
+
+    pub fn try_read(&self) -> Option<RwSpinlockReadGuard<'_, T>> {
+        let state = self.state.load(Ordering::Relaxed);
+        if state & (WRITER | WRITER_WAITING) != 0 {
+            return None;
+        }
+        self.state
+            .compare_exchange_weak(state, state + READER, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| RwSpinlockReadGuard { lock: self })
+    }
+
+    pub fn write(&self) -> RwSpinlockWriteGuard<'_, T> {
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & !WRITER_WAITING == 0 {
+                if self
+                    .state
+                    .compare_exchange_weak(state, WRITER, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    return RwSpinlockWriteGuard { lock: self };
+                }
+            } else if state & WRITER_WAITING == 0 {
+                self.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            }
+            spin_loop();
+        }
+    }
+
+    pub fn try_write(&self) -> Option<RwSpinlockWriteGuard<'_, T>> {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| RwSpinlockWriteGuard { lock: self })
+    }
+
+    pub fn readers(&self) -> usize {
+        self.state.load(Ordering::Relaxed) / READER
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwSpinlock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_read() {
+            Some(guard) => f.debug_struct("RwSpinlock").field("value", &&*guard).finish(),
+            None => f.debug_struct("RwSpinlock").field("value", &"<locked>").finish(),
+        }
+    }
+}
+
+pub struct RwSpinlockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwSpinlock<T>,
+}
+
+impl<T: ?Sized> Deref for RwSpinlockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwSpinlockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.fetch_sub(READER, Ordering::Release);
+    }
+}
+
+pub struct RwSpinlockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwSpinlock<T>,
+}
+
+impl<T: ?Sized> Deref for RwSpinlockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwSpinlockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwSpinlockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        // keeps `WRITER_WAITING` set by other writers
+        self.lock.state.fetch_and(!WRITER, Ordering::Release);
+    }
+}
+
+#[test_case]
+fn test_rw_spinlock() {
+    let lock = RwSpinlock::new(0);
+    {
+        let first = lock.read();
+        let second = lock.try_read().unwrap();
+        assert_eq!(lock.readers(), 2);
+        assert!(lock.try_write().is_none());
+        assert_eq!(*first + *second, 0);
+    }
+    *lock.write() = 1;
+    let writer = lock.try_write().unwrap();
+    assert!(lock.try_read().is_none());
+    drop(writer);
+    assert_eq!(*lock.read(), 1);
+}
@@ -0,0 +1,105 @@
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    hint::spin_loop,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// A spinlock handing out tickets, so contending CPUs get the lock in
+/// the order they asked for it. It does not touch the interrupt flag.
+pub struct TicketLock<T: ?Sized> {
+    next: AtomicUsize,
+    serving: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for TicketLock<T> {}
+unsafe impl<T: ?Sized + Send> Sync for TicketLock<T> {}
+
+impl<T> TicketLock<T> {
+    pub const fn new(value: T) -> TicketLock<T> {
+        TicketLock {
+            next: AtomicUsize::new(0),
+            serving: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> TicketLock<T> {
+    pub fn lock(&self) -> TicketLockGuard<'_, T> {
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        while self.serving.load(Ordering::Acquire) != ticket {
+            spin_loop();
+        }
+        TicketLockGuard { lock: self }
+    }
+
+    /// Takes the lock if nobody holds or waits for it.
+    pub fn try_lock(&self) -> Option<TicketLockGuard<'_, T>> {
+        let serving = self.serving.load(Ordering::Relaxed);
+        self.next
+            .compare_exchange(serving, serving.wrapping_add(1), Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| TicketLockGuard { lock: self })
+    }
+
+    /// How many are waiting for the lock, including the holder.
+    pub fn queued(&self) -> usize {
+        let next = self.next.load(Ordering::Relaxed);
+        next.wrapping_sub(self.serving.load(Ordering::Relaxed))
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for TicketLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("TicketLock").field("value", &&*guard).finish(),
+            None => f.debug_struct("TicketLock").field("value", &"<locked>").finish(),
+        }
+    }
+}
+
+pub struct TicketLockGuard<'a, T: ?Sized> {
+    lock: &'a TicketLock<T>,
+}
+
+impl<T: ?Sized> Deref for TicketLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for TicketLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for TicketLockGuard<'_, T> {
+    fn drop(&mut self) {
+        // only the holder moves `serving`
+        let serving = self.lock.serving.load(Ordering::Relaxed);
+        self.lock.serving.store(serving.wrapping_add(1), Ordering::Release);
+    }
+}
+
+#[test_case]
+fn test_ticket_lock() {
+    let lock = TicketLock::new(1);
+    {
+        let mut value = lock.lock();
+        *value += 1;
+        assert!(lock.try_lock().is_none());
+        assert_eq!(lock.queued(), 1);
+    }
+    assert_eq!(lock.queued(), 0);
+    assert_eq!(*lock.try_lock().unwrap(), 2);
+}
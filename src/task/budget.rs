@@ -0,0 +1,43 @@
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU32, Ordering},
+    task::{Context, Poll},
+};
+
+/// How many `consume_budget` calls one poll of a task gets by default.
+pub const DEFAULT_BUDGET: u32 = 128;
+
+// what is left for the task being polled, unlimited outside of `Executor`
+static BUDGET: AtomicU32 = AtomicU32::new(u32::MAX);
+
+// called by the executor around polling a task
+pub(super) fn set(budget: u32) {
+    BUDGET.store(budget, Ordering::Relaxed);
+}
+
+/// Takes one unit of the budget of the running poll. Once it is used up
+/// the task yields to the others, so a task that is always ready cannot
+/// keep the CPU. Loops that never wait should await this every iteration.
+pub fn consume_budget() -> ConsumeBudget {
+    ConsumeBudget { yielded: false }
+}
+
+pub struct ConsumeBudget {
+    yielded: bool,
+}
+
+impl Future for ConsumeBudget {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        let left = BUDGET.load(Ordering::Relaxed);
+        if left == 0 && !self.yielded {
+            self.yielded = true;
+            context.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        BUDGET.store(left.saturating_sub(1), Ordering::Relaxed);
+        Poll::Ready(())
+    }
+}
@@ -1,60 +1,416 @@
-use super::{Task, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
+use super::{budget, JoinHandle, Priority, RawTask, Task, TaskId};
+use crate::interrupts::irq;
+use crate::time::{self, Instant};
+use alloc::{collections::BTreeMap, format, rc::Rc, sync::Arc, task::Wake, vec::Vec};
+use core::{cell::RefCell, fmt, future::Future, mem};
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, Ordering};
+use core::pin::Pin;
 use core::task::{Context, Poll, Waker};
+use core::time::Duration;
 use crossbeam_queue::ArrayQueue;
+use spin::RwLock;
+use x86_64::instructions::interrupts;
+
+/// How many tasks `Executor::new` takes before its policy applies.
+pub const DEFAULT_CAPACITY: usize = 100;
+
+/// How many rounds `QueuePolicy::Block` runs waiting for room before it
+/// drops the new task. A round without ready tasks waits for the next
+/// interrupt, so this is at least as many timer ticks when nothing
+/// completes.
+pub const BLOCK_ROUNDS: usize = 64;
+
+/// What `spawn` does when the executor already has as many tasks as its
+/// capacity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueuePolicy {
+    /// Double the capacity.
+    Grow,
+    /// Drop the new task with a warning, its handle completes with
+    /// `JoinError::Cancelled`.
+    DropWithWarning,
+    /// Run the ready tasks until one of them completes, for at most
+    /// `BLOCK_ROUNDS` rounds, then drop the new task like
+    /// `DropWithWarning`. Tasks spawned by those rounds start after the
+    /// wait.
+    Block,
+}
+
+/// What queued a task the last time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WakeReason {
+    Spawn,
+    /// The task woke itself, like a yield.
+    Yield,
+    /// Another task woke it.
+    Task,
+    /// A sleep ran out.
+    Timer,
+    /// An interrupt handler with this vector woke it.
+    Interrupt(u8),
+    Other,
+}
+
+impl WakeReason {
+    // why `task_id` is being woken right now
+    fn current(task_id: TaskId) -> WakeReason {
+        if let Some(vector) = irq::current_vector() {
+            return WakeReason::Interrupt(vector);
+        }
+        if time::timer::expiring() {
+            return WakeReason::Timer;
+        }
+        match super::current_id() {
+            Some(current) if current == task_id => WakeReason::Yield,
+            Some(_) => WakeReason::Task,
+            None => WakeReason::Other,
+        }
+    }
+
+    // packed into a u16 so interrupt handlers can store it atomically
+    fn encode(self) -> u16 {
+        match self {
+            WakeReason::Spawn => 0,
+            WakeReason::Yield => 1,
+            WakeReason::Task => 2,
+            WakeReason::Timer => 3,
+            WakeReason::Other => 4,
+            WakeReason::Interrupt(vector) => 0x100 | vector as u16,
+        }
+    }
+
+    fn decode(value: u16) -> WakeReason {
+        match value {
+            0 => WakeReason::Spawn,
+            1 => WakeReason::Yield,
+            2 => WakeReason::Task,
+            3 => WakeReason::Timer,
+            value if value & 0x100 != 0 => WakeReason::Interrupt(value as u8),
+            _ => WakeReason::Other,
+        }
+    }
+}
+
+impl fmt::Display for WakeReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WakeReason::Spawn => write!(f, "spawn"),
+            WakeReason::Yield => write!(f, "yield"),
+            WakeReason::Task => write!(f, "task"),
+            WakeReason::Timer => write!(f, "timer"),
+            WakeReason::Interrupt(vector) => write!(f, "irq {}", vector),
+            WakeReason::Other => write!(f, "other"),
+        }
+    }
+}
+
+/// Runtime accounting of one task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TaskStatistics {
+    pub polls: u64,
+    pub poll_time: Duration,
+    pub longest_poll: Duration,
+}
+
+/// A snapshot of a task for diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskInfo {
+    pub id: u64,
+    pub name: &'static str,
+    /// The virtual terminal the task prints to.
+    pub terminal: usize,
+    pub priority: Priority,
+    pub deadline: Option<Instant>,
+    pub last_wake: WakeReason,
+    pub statistics: TaskStatistics,
+}
+
+// ids of the tasks to poll; every task is in here at most once, so it
+// never holds more entries than the executor has tasks
+struct ReadyQueue {
+    // wakers only read lock, growing takes the write lock with interrupts
+    // disabled so interrupt handlers never wait for it
+    queue: RwLock<ArrayQueue<TaskId>>,
+    redundant_wakes: AtomicU64,
+}
+
+impl ReadyQueue {
+    fn new(capacity: usize) -> Self {
+        ReadyQueue {
+            queue: RwLock::new(ArrayQueue::new(capacity)),
+            redundant_wakes: AtomicU64::new(0),
+        }
+    }
+
+    fn push(&self, task_id: TaskId) {
+        self.queue.read().push(task_id).expect("task queued twice");
+    }
+
+    fn pop(&self) -> Option<TaskId> {
+        self.queue.read().pop().ok()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.read().is_empty()
+    }
+
+    fn grow(&self, capacity: usize) {
+        interrupts::without_interrupts(|| {
+            let mut queue = self.queue.write();
+            let grown = ArrayQueue::new(capacity);
+            while let Ok(task_id) = queue.pop() {
+                grown.push(task_id).expect("grown queue full");
+            }
+            *queue = grown;
+        });
+    }
+}
 
 pub struct Executor {
-    tasks: BTreeMap<TaskId, Task>,
-    task_queue: Arc<ArrayQueue<TaskId>>,
-    waker_cache: BTreeMap<TaskId, Waker>,
+    tasks: BTreeMap<TaskId, RawTask>,
+    ready_queue: Arc<ReadyQueue>,
+    waker_cache: BTreeMap<TaskId, Arc<TaskWaker>>,
+    statistics: BTreeMap<TaskId, TaskStatistics>,
+    // the tasks of the running round, kept to reuse the allocation
+    round: Vec<TaskId>,
+    // tasks handed to a `Spawner`, taken over around every round
+    spawned: Rc<RefCell<Vec<RawTask>>>,
+    // snapshots requested through a `Monitor`, taken after every round
+    monitor: Rc<RefCell<MonitorState>>,
+    capacity: usize,
+    policy: QueuePolicy,
+    budget: u32,
 }
 
 impl Executor {
     pub fn new() -> Self {
+        Executor::with_capacity(DEFAULT_CAPACITY, QueuePolicy::Grow)
+    }
+
+    /// Creates an executor that applies `policy` when spawning beyond
+    /// `capacity` tasks.
+    pub fn with_capacity(capacity: usize, policy: QueuePolicy) -> Self {
+        assert!(capacity > 0, "executor without capacity");
         Executor {
             tasks: BTreeMap::new(),
-            task_queue: Arc::new(ArrayQueue::new(100)),
+            ready_queue: Arc::new(ReadyQueue::new(capacity)),
             waker_cache: BTreeMap::new(),
+            statistics: BTreeMap::new(),
+            round: Vec::new(),
+            spawned: Rc::new(RefCell::new(Vec::new())),
+            monitor: Rc::new(RefCell::new(MonitorState::default())),
+            capacity,
+            policy,
+            budget: budget::DEFAULT_BUDGET,
         }
     }
 
-    pub fn spawn(&mut self, task: Task) {
+    /// Sets how many `consume_budget` calls a task may make per poll.
+    pub fn set_poll_budget(&mut self, budget: u32) {
+        self.budget = budget;
+    }
+
+    pub fn spawn<T: 'static>(&mut self, task: Task<T>) -> JoinHandle<T> {
+        let (task, handle) = task.into_parts();
+        self.spawn_raw(task);
+        handle
+    }
+
+    /// A handle tasks can spawn other tasks on this executor with.
+    pub fn spawner(&self) -> Spawner {
+        Spawner { spawned: self.spawned.clone() }
+    }
+
+    /// A handle tasks can look at the tasks of this executor with.
+    pub fn monitor(&self) -> Monitor {
+        Monitor { state: self.monitor.clone() }
+    }
+
+    // a dropped task completes its handle with `JoinError::Cancelled`
+    fn spawn_raw(&mut self, task: RawTask) {
+        if self.tasks.len() >= self.capacity {
+            match self.policy {
+                QueuePolicy::Grow => {
+                    self.capacity *= 2;
+                    self.ready_queue.grow(self.capacity);
+                }
+                QueuePolicy::DropWithWarning => return self.drop_task(task),
+                QueuePolicy::Block => {
+                    // tasks spawned by the rounds stay in `spawned` until
+                    // the next `spawn_pending`, spawning them here would
+                    // block again and nest another wait on the stack
+                    self.poll_round();
+                    self.answer_monitor();
+                    let mut rounds = 1;
+                    while self.tasks.len() >= self.capacity {
+                        // every task may be waiting for the one being spawned
+                        if rounds == BLOCK_ROUNDS {
+                            return self.drop_task(task);
+                        }
+                        time::timer::wake_expired();
+                        self.sleep_if_idle();
+                        self.poll_round();
+                        self.answer_monitor();
+                        rounds += 1;
+                    }
+                }
+            }
+        }
         let task_id = task.id;
         if self.tasks.insert(task.id, task).is_some() {
             panic!("task with same ID already in tasks");
         }
-        self.task_queue.push(task_id).expect("queue full");
+        let waker = TaskWaker::new(task_id, self.ready_queue.clone());
+        self.waker_cache.insert(task_id, waker.clone());
+        self.statistics.insert(task_id, TaskStatistics::default());
+        waker.queue(WakeReason::Spawn);
+    }
+
+    fn drop_task(&self, task: RawTask) {
+        crate::log!(
+            "WARNING: executor full with {} tasks, dropping task {}",
+            self.tasks.len(),
+            task.name
+        );
+    }
+
+    fn spawn_pending(&mut self) {
+        let spawned = mem::take(&mut *self.spawned.borrow_mut());
+        for task in spawned {
+            self.spawn_raw(task);
+        }
     }
 
     pub fn run(&mut self) -> ! {
         loop {
             self.run_ready_tasks();
+            time::timer::wake_expired();
             self.sleep_if_idle();
         }
     }
 
+    /// Polls tasks until none is ready, without waiting for interrupts.
+    pub fn run_until_idle(&mut self) {
+        self.spawn_pending();
+        while !self.ready_queue.is_empty() {
+            self.run_ready_tasks();
+            time::timer::wake_expired();
+        }
+    }
+
+    /// The number of tasks that have not completed yet.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// How many tasks fit before the queue policy applies.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Wakes that were dropped because their task was already queued or
+    /// had completed.
+    pub fn redundant_wakes(&self) -> u64 {
+        self.ready_queue.redundant_wakes.load(Ordering::Relaxed)
+    }
+
+    /// The tasks that have not completed, with their accounting.
+    pub fn task_info(&self) -> Vec<TaskInfo> {
+        self.tasks
+            .iter()
+            .map(|(id, task)| TaskInfo {
+                id: id.0,
+                name: task.name,
+                terminal: task.terminal,
+                priority: task.priority,
+                deadline: task.deadline,
+                last_wake: WakeReason::decode(self.waker_cache[id].last_wake.load(Ordering::Relaxed)),
+                statistics: self.statistics[id],
+            })
+            .collect()
+    }
+
+    /// Polls every task that is ready at the start of the round once, by
+    /// descending priority, then earliest deadline, then wake order. Tasks
+    /// woken meanwhile wait for the next round, so busy tasks cannot starve
+    /// the others.
     fn run_ready_tasks(&mut self) {
+        self.spawn_pending();
+        self.poll_round();
+        // tasks spawned by the round are ready for the next one
+        self.spawn_pending();
+        self.answer_monitor();
+    }
+
+    fn answer_monitor(&mut self) {
+        let mut state = self.monitor.borrow_mut();
+        if !state.requested {
+            return;
+        }
+        state.requested = false;
+        state.tasks = self.task_info();
+        state.generation += 1;
+        for waker in state.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+
+    fn poll_round(&mut self) {
         // destructure `self` to avoid borrow checker errors
         let Self {
             tasks,
-            task_queue,
+            ready_queue,
             waker_cache,
+            statistics,
+            round,
+            budget,
+            ..
         } = self;
 
-        while let Ok(task_id) = task_queue.pop() {
+        round.clear();
+        while let Some(task_id) = ready_queue.pop() {
+            if tasks.contains_key(&task_id) {
+                round.push(task_id);
+            }
+        }
+        // stable, so equal tasks keep their wake order
+        round.sort_by_key(|task_id| {
+            let task = &tasks[task_id];
+            (core::cmp::Reverse(task.priority), task.deadline.is_none(), task.deadline)
+        });
+
+        for &task_id in round.iter() {
             let task = match tasks.get_mut(&task_id) {
                 Some(task) => task,
                 None => continue, // task no longer exists
             };
-            let waker = waker_cache
-                .entry(task_id)
-                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
-            let mut context = Context::from_waker(waker);
-            match task.poll(&mut context) {
+            let task_waker = &waker_cache[&task_id];
+            // wakes from now on queue the task again
+            task_waker.queued.store(false, Ordering::Release);
+            let waker = Waker::from(task_waker.clone());
+            let mut context = Context::from_waker(&waker);
+            budget::set(*budget);
+            let start = Instant::now();
+            let result = task.poll(&mut context);
+            let elapsed = start.elapsed();
+            budget::set(u32::MAX);
+
+            let task_statistics = statistics.get_mut(&task_id).expect("task without statistics");
+            task_statistics.polls += 1;
+            task_statistics.poll_time += elapsed;
+            task_statistics.longest_poll = task_statistics.longest_poll.max(elapsed);
+            match result {
                 Poll::Ready(()) => {
-                    // task done -> remove it and its cached waker
+                    // task done -> remove it and its cached waker, wakes
+                    // through leftover clones of the waker are ignored
+                    task_waker.queued.store(true, Ordering::Release);
                     tasks.remove(&task_id);
                     waker_cache.remove(&task_id);
+                    statistics.remove(&task_id);
                 }
                 Poll::Pending => {}
             }
@@ -62,32 +418,137 @@ impl Executor {
     }
 
     fn sleep_if_idle(&self) {
-        use x86_64::instructions::interrupts::{self, enable_and_hlt};
+        time::idle::halt_unless(|| !self.ready_queue.is_empty());
+    }
+}
 
-        interrupts::disable();
-        if self.task_queue.is_empty() {
-            enable_and_hlt();
-        } else {
-            interrupts::enable();
+/// Spawns tasks on an executor from inside its tasks, which cannot borrow
+/// the executor. The tasks start in the executor's next round.
+#[derive(Clone)]
+pub struct Spawner {
+    spawned: Rc<RefCell<Vec<RawTask>>>,
+}
+
+impl Spawner {
+    pub fn spawn<T: 'static>(&self, task: Task<T>) -> JoinHandle<T> {
+        let (task, handle) = task.into_parts();
+        self.spawned.borrow_mut().push(task);
+        handle
+    }
+}
+
+#[derive(Default)]
+struct MonitorState {
+    requested: bool,
+    // counts the snapshots taken, a request is answered once it moves on
+    generation: u64,
+    tasks: Vec<TaskInfo>,
+    wakers: Vec<Waker>,
+}
+
+/// Lets tasks look at the executor running them, which they cannot
+/// borrow. The executor answers after the round the request was made in.
+#[derive(Clone)]
+pub struct Monitor {
+    state: Rc<RefCell<MonitorState>>,
+}
+
+impl Monitor {
+    /// The tasks of the executor that have not completed, the asking one
+    /// included.
+    pub fn tasks(&self) -> TaskSnapshot {
+        TaskSnapshot { state: self.state.clone(), generation: None }
+    }
+}
+
+/// Completes with a snapshot of the tasks, see `Monitor::tasks`.
+pub struct TaskSnapshot {
+    state: Rc<RefCell<MonitorState>>,
+    // the snapshot count when the request was made
+    generation: Option<u64>,
+}
+
+impl Future for TaskSnapshot {
+    type Output = Vec<TaskInfo>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Vec<TaskInfo>> {
+        let this = self.get_mut();
+        let mut state = this.state.borrow_mut();
+        match this.generation {
+            Some(generation) if state.generation > generation => return Poll::Ready(state.tasks.clone()),
+            Some(_) => {}
+            None => this.generation = Some(state.generation),
         }
+        state.requested = true;
+        state.wakers.push(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// Writes `tasks` by descending poll time with their priority, polls, poll
+/// time and last wake reason, for the `top` shell command.
+pub fn write_top(tasks: &[TaskInfo], out: &mut impl fmt::Write) -> fmt::Result {
+    let mut tasks = tasks.to_vec();
+    tasks.sort_by_key(|task| core::cmp::Reverse(task.statistics.poll_time));
+    let total = tasks.iter().map(|task| task.statistics.poll_time.as_nanos()).sum::<u128>().max(1);
+    writeln!(out, "    id  priority       polls     time   cpu%   longest  last wake  name")?;
+    for task in tasks {
+        let statistics = task.statistics;
+        writeln!(
+            out,
+            "{:>6}  {:>8}  {:>10}  {:>5}ms  {:>5}  {:>6}us  {:>9}  {}",
+            task.id,
+            format!("{:?}", task.priority),
+            statistics.polls,
+            statistics.poll_time.as_millis(),
+            statistics.poll_time.as_nanos() * 100 / total,
+            statistics.longest_poll.as_micros(),
+            format!("{}", task.last_wake),
+            task.name
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the id, terminal and name of `tasks`, for the `tasks` shell
+/// command.
+pub fn write_tasks(tasks: &[TaskInfo], out: &mut impl fmt::Write) -> fmt::Result {
+    writeln!(out, "    id  terminal  name")?;
+    for task in tasks {
+        writeln!(out, "{:>6}  {:>8}  {}", task.id, task.terminal, task.name)?;
     }
+    Ok(())
 }
 
 struct TaskWaker {
     task_id: TaskId,
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    // set while the task is in the ready queue, and once it completed
+    queued: AtomicBool,
+    last_wake: AtomicU16,
+    ready_queue: Arc<ReadyQueue>,
 }
 
 impl TaskWaker {
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
-        Waker::from(Arc::new(TaskWaker {
+    fn new(task_id: TaskId, ready_queue: Arc<ReadyQueue>) -> Arc<TaskWaker> {
+        Arc::new(TaskWaker {
             task_id,
-            task_queue,
-        }))
+            queued: AtomicBool::new(false),
+            last_wake: AtomicU16::new(WakeReason::Spawn.encode()),
+            ready_queue,
+        })
     }
 
     fn wake_task(&self) {
-        self.task_queue.push(self.task_id).expect("task_queue full");
+        self.queue(WakeReason::current(self.task_id));
+    }
+
+    fn queue(&self, reason: WakeReason) {
+        if self.queued.swap(true, Ordering::AcqRel) {
+            self.ready_queue.redundant_wakes.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.last_wake.store(reason.encode(), Ordering::Relaxed);
+            self.ready_queue.push(self.task_id);
+        }
     }
 }
 
@@ -99,4 +560,4 @@ impl Wake for TaskWaker {
     fn wake_by_ref(self: &Arc<Self>) {
         self.wake_task();
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,178 @@
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use spin::Mutex;
+
+/// Why a task did not produce its output.
+///
+/// Panics are not reported here: the kernel is built with
+/// `panic-strategy: abort`, so a panicking task stops the whole kernel and
+/// the panic message names the task instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// The task was aborted through its `JoinHandle` or dropped by the
+    /// executor before it completed.
+    Cancelled,
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JoinError::Cancelled => write!(f, "task was cancelled"),
+        }
+    }
+}
+
+// shared by a task and its handle
+struct JoinState<T> {
+    output: Option<Result<T, JoinError>>,
+    finished: bool,
+    aborted: bool,
+    // the task awaiting the handle
+    join_waker: Option<Waker>,
+    // the task itself, woken to notice an abort
+    task_waker: Option<Waker>,
+}
+
+type Shared<T> = Arc<Mutex<JoinState<T>>>;
+
+fn finish<T>(state: &Shared<T>, output: Result<T, JoinError>) {
+    let waker = {
+        let mut state = state.lock();
+        state.output = Some(output);
+        state.finished = true;
+        state.task_waker = None;
+        state.join_waker.take()
+    };
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+/// Wraps the future of a task, storing its output for the `JoinHandle` and
+/// dropping it when the task is aborted.
+pub(super) struct Joined<T> {
+    future: Option<Pin<Box<dyn Future<Output = T>>>>,
+    state: Shared<T>,
+}
+
+impl<T> Future for Joined<T> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        let aborted = {
+            let mut state = self.state.lock();
+            let stale = state.task_waker.as_ref().is_none_or(|waker| !waker.will_wake(context.waker()));
+            if stale && !state.aborted {
+                state.task_waker = Some(context.waker().clone());
+            }
+            state.aborted
+        };
+        if aborted {
+            // drop the future before the handle sees the task finished
+            self.future = None;
+            finish(&self.state, Err(JoinError::Cancelled));
+            return Poll::Ready(());
+        }
+        let future = self.future.as_mut().expect("task polled after completion");
+        match future.as_mut().poll(context) {
+            Poll::Ready(output) => {
+                self.future = None;
+                finish(&self.state, Ok(output));
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Drop for Joined<T> {
+    fn drop(&mut self) {
+        // dropped by an executor before it completed
+        if self.future.take().is_some() {
+            finish(&self.state, Err(JoinError::Cancelled));
+        }
+    }
+}
+
+/// Awaits the output of a spawned task. Dropping the handle detaches the
+/// task, it keeps running. A task that panics never completes its handle,
+/// the panic stops the kernel, see `JoinError`.
+pub struct JoinHandle<T> {
+    pub(super) name: &'static str,
+    state: Shared<T>,
+}
+
+/// Wraps `future` for a task and creates the handle to join it.
+pub(super) fn joined<T: 'static>(
+    future: impl Future<Output = T> + 'static,
+    name: &'static str,
+) -> (Joined<T>, JoinHandle<T>) {
+    let state = Arc::new(Mutex::new(JoinState {
+        output: None,
+        finished: false,
+        aborted: false,
+        join_waker: None,
+        task_waker: None,
+    }));
+    let joined = Joined {
+        future: Some(Box::pin(future)),
+        state: state.clone(),
+    };
+    (joined, JoinHandle { name, state })
+}
+
+impl<T> JoinHandle<T> {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Whether the task has completed or was aborted.
+    pub fn is_finished(&self) -> bool {
+        self.state.lock().finished
+    }
+
+    /// Cancels the task. Its future is dropped the next time the executor
+    /// gets to it and the handle completes with `JoinError::Cancelled`. Does
+    /// nothing once the task has finished.
+    pub fn abort(&self) {
+        let waker = {
+            let mut state = self.state.lock();
+            if state.finished {
+                return;
+            }
+            state.aborted = true;
+            state.task_waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        let mut state = self.state.lock();
+        if let Some(output) = state.output.take() {
+            return Poll::Ready(output);
+        }
+        assert!(!state.finished, "JoinHandle polled after completion");
+        state.join_waker = Some(context.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> fmt::Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("JoinHandle")
+            .field("name", &self.name)
+            .field("finished", &self.is_finished())
+            .finish()
+    }
+}
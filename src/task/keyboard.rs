@@ -1,11 +1,17 @@
 use conquer_once::spin::OnceCell;
 use crossbeam_queue::ArrayQueue;
-use crate::{print,println};
-use core::{pin::Pin, task::{Poll, Context}};
+use crate::{log, print};
+use crate::ps2;
+use crate::vga_buffer::{terminal::{self, InputStream}, Writer};
+use super::layout::{self, Modifiers};
+use super::workqueue::{self, Work};
+use alloc::{sync::{Arc, Weak}, vec::Vec};
+use core::{pin::Pin, sync::atomic::{AtomicUsize, Ordering}, task::{Poll, Context}};
 use futures_util::stream::{Stream,StreamExt};
 use futures_util::task::AtomicWaker;
+use spin::Mutex;
 
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, KeyState, Keyboard, ScancodeSet1};
 
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 static WAKER: AtomicWaker = AtomicWaker::new();
@@ -44,32 +50,214 @@ impl Stream for ScancodeStream {
 }
 
 
+// scancodes dropped since the last warning, which is printed by a work
+// item instead of in the interrupt handler
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
 pub(crate) fn add_scancode(scancode: u8) {
-    if let Ok(queue) = SCANCODE_QUEUE.try_get() {
-        if let Err(_) = queue.push(scancode) {
-            println!("WARNING: scancode queue full; dropping keyboard input");
-        } else {
-            WAKER.wake(); // new
-        }
+    let uninitialized = match SCANCODE_QUEUE.try_get() {
+        Ok(queue) => match queue.push(scancode) {
+            Ok(()) => {
+                WAKER.wake(); // new
+                return;
+            }
+            Err(_) => false,
+        },
+        Err(_) => true,
+    };
+    let work = Work::new("keyboard warning", warn_dropped, uninitialized as usize);
+    if DROPPED.fetch_add(1, Ordering::Relaxed) == 0 && workqueue::queue(work).is_err() {
+        // the next scancode dropped tries again
+        DROPPED.store(0, Ordering::Relaxed);
+    }
+}
+
+fn warn_dropped(uninitialized: usize) {
+    let dropped = DROPPED.swap(0, Ordering::Relaxed);
+    if uninitialized != 0 {
+        log!("WARNING: scancode queue uninitialized; dropped {} scancodes", dropped);
     } else {
-        println!("WARNING: scancode queue uninitialized");
+        log!("WARNING: scancode queue full; dropped {} scancodes", dropped);
     }
 }
 
 
-pub async fn print_keypresses() {
+/// A key going down or up, with the modifiers at the time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub state: KeyState,
+    pub modifiers: Modifiers,
+    /// What the key types on the current layout, only set for key presses.
+    pub key: Option<DecodedKey>,
+}
+
+// key events queued for one subscriber
+struct Subscriber {
+    queue: ArrayQueue<KeyEvent>,
+    waker: AtomicWaker,
+}
+
+const SUBSCRIBER_CAPACITY: usize = 64;
+
+static SUBSCRIBERS: Mutex<Vec<Weak<Subscriber>>> = Mutex::new(Vec::new());
+
+/// Every key event from the moment it was created, unsubscribes when dropped.
+pub struct KeyEventStream {
+    subscriber: Arc<Subscriber>,
+}
+
+/// Subscribes to the key events of the keyboard service. A subscriber
+/// that falls behind loses its oldest events.
+pub fn subscribe() -> KeyEventStream {
+    let subscriber = Arc::new(Subscriber {
+        queue: ArrayQueue::new(SUBSCRIBER_CAPACITY),
+        waker: AtomicWaker::new(),
+    });
+    SUBSCRIBERS.lock().push(Arc::downgrade(&subscriber));
+    KeyEventStream { subscriber }
+}
+
+impl Stream for KeyEventStream {
+    type Item = KeyEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<KeyEvent>> {
+        let subscriber = &self.subscriber;
+        if let Ok(event) = subscriber.queue.pop() {
+            return Poll::Ready(Some(event));
+        }
+
+        subscriber.waker.register(cx.waker());
+
+        match subscriber.queue.pop() {
+            Ok(event) => {
+                subscriber.waker.take();
+                Poll::Ready(Some(event))
+            }
+            Err(crossbeam_queue::PopError) => Poll::Pending,
+        }
+    }
+}
+
+/// Hands `event` to every subscriber as if it came from the keyboard,
+/// forgetting the subscribers that were dropped.
+pub fn publish(event: KeyEvent) {
+    SUBSCRIBERS.lock().retain(|subscriber| match subscriber.upgrade() {
+        Some(subscriber) => {
+            if subscriber.queue.push(event).is_err() {
+                let _ = subscriber.queue.pop();
+                let _ = subscriber.queue.push(event);
+            }
+            subscriber.waker.wake();
+            true
+        }
+        None => false,
+    });
+}
+
+const COMMAND_SET_LEDS: u8 = 0xed;
+// replies of the keyboard to commands, they arrive like scancodes
+const REPLY_ACK: u8 = 0xfa;
+const REPLY_RESEND: u8 = 0xfe;
+const SCANCODE_EXTENDED: u8 = 0xe0;
+// the extra key of ISO keyboards, which pc-keyboard does not decode
+const SCANCODE_ISO_KEY: u8 = 0x56;
+const SCANCODE_RELEASED: u8 = 0x80;
+
+/// Lights the keyboard LEDs to match the lock keys.
+pub fn set_leds(modifiers: &Modifiers) {
+    let leds = (modifiers.scroll_lock as u8) | (modifiers.num_lock as u8) << 1 | (modifiers.caps_lock as u8) << 2;
+    // the LEDs are cosmetic, a keyboard that does not listen is left alone
+    let _ = ps2::send_keyboard(COMMAND_SET_LEDS).and_then(|_| ps2::send_keyboard(leds));
+}
+
+/// The keyboard service: decodes scancodes into key events for the
+/// subscribers, handles the console shortcuts and hands the typed keys
+/// to the virtual terminal on the screen.
+pub async fn dispatch_keypresses() {
     let mut scancodes = ScancodeStream::new();
+    // only decodes scancodes to key codes, translating them is up to the layout
     let mut keyboard = Keyboard::new(layouts::Us104Key, ScancodeSet1,
         HandleControl::Ignore);
+    let mut modifiers = Modifiers::default();
+    let mut extended = false;
+    set_leds(&modifiers);
 
     while let Some(scancode) = scancodes.next().await {
-        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-            if let Some(key) = keyboard.process_keyevent(key_event) {
-                match key {
-                    DecodedKey::Unicode(character) => print!("{}", character),
-                    DecodedKey::RawKey(key) => print!("{:?}", key),
-                }
-            }
+        if scancode == REPLY_ACK || scancode == REPLY_RESEND {
+            continue;
+        }
+        let key_event = if !extended && scancode & !SCANCODE_RELEASED == SCANCODE_ISO_KEY {
+            let state = if scancode & SCANCODE_RELEASED == 0 { KeyState::Down } else { KeyState::Up };
+            Some((KeyCode::HashTilde, state))
+        } else {
+            keyboard.add_byte(scancode).ok().flatten().map(|event| (event.code, event.state))
+        };
+        extended = scancode == SCANCODE_EXTENDED;
+        let (code, state) = match key_event {
+            Some(key_event) => key_event,
+            None => continue,
+        };
+
+        let down = state == KeyState::Down;
+        let locks = (modifiers.caps_lock, modifiers.num_lock, modifiers.scroll_lock);
+        modifiers.update(code, down);
+        if locks != (modifiers.caps_lock, modifiers.num_lock, modifiers.scroll_lock) {
+            set_leds(&modifiers);
+        }
+        let key = if down { layout::layout().map(code, &modifiers) } else { None };
+        let event = KeyEvent { code, state, modifiers, key };
+
+        if down && handle_hotkey(&event) {
+            continue;
+        }
+        publish(event);
+        if let Some(key) = key {
+            terminal::push_input(key);
         }
     }
 }
+
+// the console shortcuts, returns whether the key was one
+fn handle_hotkey(event: &KeyEvent) -> bool {
+    let modifiers = &event.modifiers;
+    match event.code {
+        // Shift+PageUp/PageDown page through the scrollback by half a screen
+        KeyCode::PageUp if modifiers.shift() => scroll_view(|writer| writer.scroll_back(SCROLL_STEP)),
+        KeyCode::PageDown if modifiers.shift() => scroll_view(|writer| writer.scroll_forward(SCROLL_STEP)),
+        // Alt+F1..F6 switch virtual terminals
+        code if modifiers.alt => {
+            let index = match code {
+                KeyCode::F1 => 0,
+                KeyCode::F2 => 1,
+                KeyCode::F3 => 2,
+                KeyCode::F4 => 3,
+                KeyCode::F5 => 4,
+                KeyCode::F6 => 5,
+                _ => return false,
+            };
+            terminal::switch_to(index);
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Echoes the keys typed on the terminal of the calling task.
+pub async fn print_keypresses() {
+    let mut keys = InputStream::new(terminal::current());
+
+    while let Some(key) = keys.next().await {
+        // keys without a character, like the arrows, are not echoed
+        if let DecodedKey::Unicode(character) = key {
+            print!("{}", character);
+        }
+    }
+}
+
+const SCROLL_STEP: usize = 12;
+
+// scrolls the terminal on the screen
+fn scroll_view(f: impl FnOnce(&mut Writer)) {
+    f(&mut terminal::writer(terminal::active()).lock());
+}
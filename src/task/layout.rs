@@ -0,0 +1,367 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+use pc_keyboard::{DecodedKey, KeyCode};
+
+/// The state of the modifier and lock keys when a key event happened.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+    pub left_shift: bool,
+    pub right_shift: bool,
+    pub left_ctrl: bool,
+    pub right_ctrl: bool,
+    pub alt: bool,
+    /// The right Alt key, which types the third symbol of a key on the
+    /// European layouts.
+    pub alt_gr: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool,
+    // lock keys that are down, so typematic repeat does not toggle again
+    held_locks: u8,
+}
+
+impl Modifiers {
+    pub fn shift(&self) -> bool {
+        self.left_shift || self.right_shift
+    }
+
+    pub fn ctrl(&self) -> bool {
+        self.left_ctrl || self.right_ctrl
+    }
+
+    /// Updates the modifiers for a key going down or up, returns whether
+    /// the key was a modifier or lock key.
+    pub fn update(&mut self, code: KeyCode, down: bool) -> bool {
+        match code {
+            KeyCode::ShiftLeft => self.left_shift = down,
+            KeyCode::ShiftRight => self.right_shift = down,
+            KeyCode::ControlLeft => self.left_ctrl = down,
+            KeyCode::ControlRight => self.right_ctrl = down,
+            KeyCode::AltLeft => self.alt = down,
+            KeyCode::AltRight => self.alt_gr = down,
+            // lock keys toggle when pressed
+            KeyCode::CapsLock => self.caps_lock ^= self.lock_pressed(0, down),
+            KeyCode::NumpadLock => self.num_lock ^= self.lock_pressed(1, down),
+            KeyCode::ScrollLock => self.scroll_lock ^= self.lock_pressed(2, down),
+            _ => return false,
+        }
+        true
+    }
+
+    // whether the lock key went down just now rather than repeating
+    fn lock_pressed(&mut self, lock: u8, down: bool) -> bool {
+        let held = self.held_locks & 1 << lock != 0;
+        if down {
+            self.held_locks |= 1 << lock;
+        } else {
+            self.held_locks &= !(1 << lock);
+        }
+        down && !held
+    }
+}
+
+/// Keyboard layouts the keyboard service can switch between at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Us104,
+    Uk105,
+    De105,
+    Es105,
+}
+
+static LAYOUT: AtomicU8 = AtomicU8::new(Layout::Us104 as u8);
+
+/// The layout key presses are currently translated with.
+pub fn layout() -> Layout {
+    match LAYOUT.load(Ordering::Relaxed) {
+        1 => Layout::Uk105,
+        2 => Layout::De105,
+        3 => Layout::Es105,
+        _ => Layout::Us104,
+    }
+}
+
+pub fn set_layout(layout: Layout) {
+    LAYOUT.store(layout as u8, Ordering::Relaxed);
+}
+
+impl Layout {
+    pub const ALL: [Layout; 4] = [Layout::Us104, Layout::Uk105, Layout::De105, Layout::Es105];
+
+    /// The short name used to pick the layout, like "us" or "de".
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Us104 => "us",
+            Layout::Uk105 => "uk",
+            Layout::De105 => "de",
+            Layout::Es105 => "es",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Layout> {
+        Layout::ALL.iter().copied().find(|layout| layout.name() == name)
+    }
+
+    /// Translates a pressed key. Keys without a character, like the
+    /// arrows or function keys, come back as `DecodedKey::RawKey`, the
+    /// modifier keys themselves as `None`. Accent keys type the accent
+    /// right away, there are no dead keys.
+    pub fn map(self, code: KeyCode, modifiers: &Modifiers) -> Option<DecodedKey> {
+        if let Some(c) = control_key(code) {
+            return Some(DecodedKey::Unicode(c));
+        }
+        if let Some(key) = numpad_key(code, modifiers.num_lock) {
+            return Some(key);
+        }
+        let symbols = match self.symbols(code) {
+            Some(symbols) => symbols,
+            None if is_modifier(code) => return None,
+            None => return Some(DecodedKey::RawKey(code)),
+        };
+        let (normal, shifted, alt_gr) = symbols;
+        if modifiers.alt_gr {
+            return alt_gr.map(DecodedKey::Unicode);
+        }
+        // caps lock only affects letters, Shift undoes it
+        let letter = normal.is_alphabetic() && shifted.is_alphabetic();
+        let c = if modifiers.shift() ^ (letter && modifiers.caps_lock) { shifted } else { normal };
+        if modifiers.ctrl() && c.is_ascii_alphabetic() {
+            // Ctrl+A..Ctrl+Z are the ASCII control characters 0x01..0x1a
+            return Some(DecodedKey::Unicode((c as u8 & 0x1f) as char));
+        }
+        Some(DecodedKey::Unicode(c))
+    }
+
+    // the unshifted, shifted and AltGr symbols of a key
+    fn symbols(self, code: KeyCode) -> Option<(char, char, Option<char>)> {
+        let symbols = match self {
+            Layout::Us104 => us_symbols(code),
+            Layout::Uk105 => uk_symbols(code),
+            Layout::De105 => de_symbols(code),
+            Layout::Es105 => es_symbols(code),
+        };
+        symbols.or_else(|| {
+            let letter = letter(code)?;
+            // German keyboards swap Y and Z
+            let letter = match (self, letter) {
+                (Layout::De105, 'y') => 'z',
+                (Layout::De105, 'z') => 'y',
+                _ => letter,
+            };
+            Some((letter, letter.to_ascii_uppercase(), None))
+        })
+    }
+}
+
+fn is_modifier(code: KeyCode) -> bool {
+    Modifiers::default().update(code, true)
+}
+
+fn control_key(code: KeyCode) -> Option<char> {
+    Some(match code {
+        KeyCode::Enter | KeyCode::NumpadEnter => '\n',
+        KeyCode::Tab => '\t',
+        KeyCode::Backspace => '\x08',
+        KeyCode::Escape => '\x1b',
+        KeyCode::Delete => '\x7f',
+        KeyCode::Spacebar => ' ',
+        _ => return None,
+    })
+}
+
+// the numpad types digits with Num Lock on and moves the cursor without
+fn numpad_key(code: KeyCode, num_lock: bool) -> Option<DecodedKey> {
+    let (digit, navigation) = match code {
+        KeyCode::NumpadSlash => return Some(DecodedKey::Unicode('/')),
+        KeyCode::NumpadStar => return Some(DecodedKey::Unicode('*')),
+        KeyCode::NumpadMinus => return Some(DecodedKey::Unicode('-')),
+        KeyCode::NumpadPlus => return Some(DecodedKey::Unicode('+')),
+        KeyCode::Numpad0 => ('0', KeyCode::Insert),
+        KeyCode::Numpad1 => ('1', KeyCode::End),
+        KeyCode::Numpad2 => ('2', KeyCode::ArrowDown),
+        KeyCode::Numpad3 => ('3', KeyCode::PageDown),
+        KeyCode::Numpad4 => ('4', KeyCode::ArrowLeft),
+        KeyCode::Numpad5 => ('5', KeyCode::Numpad5),
+        KeyCode::Numpad6 => ('6', KeyCode::ArrowRight),
+        KeyCode::Numpad7 => ('7', KeyCode::Home),
+        KeyCode::Numpad8 => ('8', KeyCode::ArrowUp),
+        KeyCode::Numpad9 => ('9', KeyCode::PageUp),
+        KeyCode::NumpadPeriod => ('.', KeyCode::Delete),
+        _ => return None,
+    };
+    Some(if num_lock {
+        DecodedKey::Unicode(digit)
+    } else if navigation == KeyCode::Delete {
+        DecodedKey::Unicode('\x7f')
+    } else {
+        DecodedKey::RawKey(navigation)
+    })
+}
+
+fn letter(code: KeyCode) -> Option<char> {
+    const LETTERS: [KeyCode; 26] = [
+        KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E, KeyCode::F, KeyCode::G,
+        KeyCode::H, KeyCode::I, KeyCode::J, KeyCode::K, KeyCode::L, KeyCode::M, KeyCode::N,
+        KeyCode::O, KeyCode::P, KeyCode::Q, KeyCode::R, KeyCode::S, KeyCode::T, KeyCode::U,
+        KeyCode::V, KeyCode::W, KeyCode::X, KeyCode::Y, KeyCode::Z,
+    ];
+    let index = LETTERS.iter().position(|&letter| letter == code)?;
+    Some((b'a' + index as u8) as char)
+}
+
+// the key names of pc-keyboard follow the US layout, the key above Enter
+// is BackSlash and the extra key of ISO keyboards next to the left Shift
+// is HashTilde
+
+fn us_symbols(code: KeyCode) -> Option<(char, char, Option<char>)> {
+    Some(match code {
+        KeyCode::BackTick => ('`', '~', None),
+        KeyCode::Key1 => ('1', '!', None),
+        KeyCode::Key2 => ('2', '@', None),
+        KeyCode::Key3 => ('3', '#', None),
+        KeyCode::Key4 => ('4', '$', None),
+        KeyCode::Key5 => ('5', '%', None),
+        KeyCode::Key6 => ('6', '^', None),
+        KeyCode::Key7 => ('7', '&', None),
+        KeyCode::Key8 => ('8', '*', None),
+        KeyCode::Key9 => ('9', '(', None),
+        KeyCode::Key0 => ('0', ')', None),
+        KeyCode::Minus => ('-', '_', None),
+        KeyCode::Equals => ('=', '+', None),
+        KeyCode::BracketSquareLeft => ('[', '{', None),
+        KeyCode::BracketSquareRight => (']', '}', None),
+        KeyCode::BackSlash | KeyCode::HashTilde => ('\\', '|', None),
+        KeyCode::SemiColon => (';', ':', None),
+        KeyCode::Quote => ('\'', '"', None),
+        KeyCode::Comma => (',', '<', None),
+        KeyCode::Fullstop => ('.', '>', None),
+        KeyCode::Slash => ('/', '?', None),
+        _ => return None,
+    })
+}
+
+fn uk_symbols(code: KeyCode) -> Option<(char, char, Option<char>)> {
+    Some(match code {
+        KeyCode::BackTick => ('`', '¬', Some('¦')),
+        KeyCode::Key2 => ('2', '"', None),
+        KeyCode::Key3 => ('3', '£', None),
+        KeyCode::Key4 => ('4', '$', Some('€')),
+        KeyCode::BackSlash => ('#', '~', None),
+        KeyCode::Quote => ('\'', '@', None),
+        KeyCode::HashTilde => ('\\', '|', None),
+        KeyCode::A => ('a', 'A', Some('á')),
+        KeyCode::E => ('e', 'E', Some('é')),
+        KeyCode::I => ('i', 'I', Some('í')),
+        KeyCode::O => ('o', 'O', Some('ó')),
+        KeyCode::U => ('u', 'U', Some('ú')),
+        _ => return us_symbols(code),
+    })
+}
+
+fn de_symbols(code: KeyCode) -> Option<(char, char, Option<char>)> {
+    Some(match code {
+        KeyCode::BackTick => ('^', '°', None),
+        KeyCode::Key1 => ('1', '!', None),
+        KeyCode::Key2 => ('2', '"', Some('²')),
+        KeyCode::Key3 => ('3', '§', Some('³')),
+        KeyCode::Key4 => ('4', '$', None),
+        KeyCode::Key5 => ('5', '%', None),
+        KeyCode::Key6 => ('6', '&', None),
+        KeyCode::Key7 => ('7', '/', Some('{')),
+        KeyCode::Key8 => ('8', '(', Some('[')),
+        KeyCode::Key9 => ('9', ')', Some(']')),
+        KeyCode::Key0 => ('0', '=', Some('}')),
+        KeyCode::Minus => ('ß', '?', Some('\\')),
+        KeyCode::Equals => ('´', '`', None),
+        KeyCode::BracketSquareLeft => ('ü', 'Ü', None),
+        KeyCode::BracketSquareRight => ('+', '*', Some('~')),
+        KeyCode::BackSlash => ('#', '\'', None),
+        KeyCode::SemiColon => ('ö', 'Ö', None),
+        KeyCode::Quote => ('ä', 'Ä', None),
+        KeyCode::Comma => (',', ';', None),
+        KeyCode::Fullstop => ('.', ':', None),
+        KeyCode::Slash => ('-', '_', None),
+        KeyCode::HashTilde => ('<', '>', Some('|')),
+        KeyCode::Q => ('q', 'Q', Some('@')),
+        KeyCode::E => ('e', 'E', Some('€')),
+        KeyCode::M => ('m', 'M', Some('µ')),
+        _ => return None,
+    })
+}
+
+fn es_symbols(code: KeyCode) -> Option<(char, char, Option<char>)> {
+    Some(match code {
+        KeyCode::BackTick => ('º', 'ª', Some('\\')),
+        KeyCode::Key1 => ('1', '!', Some('|')),
+        KeyCode::Key2 => ('2', '"', Some('@')),
+        KeyCode::Key3 => ('3', '·', Some('#')),
+        KeyCode::Key4 => ('4', '$', Some('~')),
+        KeyCode::Key5 => ('5', '%', None),
+        KeyCode::Key6 => ('6', '&', Some('¬')),
+        KeyCode::Key7 => ('7', '/', None),
+        KeyCode::Key8 => ('8', '(', None),
+        KeyCode::Key9 => ('9', ')', None),
+        KeyCode::Key0 => ('0', '=', None),
+        KeyCode::Minus => ('\'', '?', None),
+        KeyCode::Equals => ('¡', '¿', None),
+        KeyCode::BracketSquareLeft => ('`', '^', Some('[')),
+        KeyCode::BracketSquareRight => ('+', '*', Some(']')),
+        KeyCode::BackSlash => ('ç', 'Ç', Some('}')),
+        KeyCode::SemiColon => ('ñ', 'Ñ', None),
+        KeyCode::Quote => ('´', '¨', Some('{')),
+        KeyCode::Comma => (',', ';', None),
+        KeyCode::Fullstop => ('.', ':', None),
+        KeyCode::Slash => ('-', '_', None),
+        KeyCode::HashTilde => ('<', '>', None),
+        KeyCode::E => ('e', 'E', Some('€')),
+        _ => return None,
+    })
+}
+
+#[test_case]
+fn test_us_layout() {
+    let mut modifiers = Modifiers::default();
+    let layout = Layout::Us104;
+    assert_eq!(layout.map(KeyCode::Key2, &modifiers), Some(DecodedKey::Unicode('2')));
+    assert_eq!(layout.map(KeyCode::ShiftLeft, &modifiers), None);
+    assert_eq!(layout.map(KeyCode::ArrowUp, &modifiers), Some(DecodedKey::RawKey(KeyCode::ArrowUp)));
+    modifiers.update(KeyCode::ShiftLeft, true);
+    assert_eq!(layout.map(KeyCode::Key2, &modifiers), Some(DecodedKey::Unicode('@')));
+    assert_eq!(layout.map(KeyCode::A, &modifiers), Some(DecodedKey::Unicode('A')));
+    modifiers.update(KeyCode::ShiftLeft, false);
+    modifiers.update(KeyCode::ControlRight, true);
+    assert_eq!(layout.map(KeyCode::C, &modifiers), Some(DecodedKey::Unicode('\u{3}')));
+}
+
+#[test_case]
+fn test_lock_keys() {
+    let mut modifiers = Modifiers::default();
+    let layout = Layout::Es105;
+    // holding the key down repeats the press, which must not toggle again
+    modifiers.update(KeyCode::CapsLock, true);
+    modifiers.update(KeyCode::CapsLock, true);
+    modifiers.update(KeyCode::CapsLock, false);
+    assert!(modifiers.caps_lock);
+    assert_eq!(layout.map(KeyCode::SemiColon, &modifiers), Some(DecodedKey::Unicode('Ñ')));
+    assert_eq!(layout.map(KeyCode::Key1, &modifiers), Some(DecodedKey::Unicode('1')));
+    modifiers.update(KeyCode::ShiftRight, true);
+    assert_eq!(layout.map(KeyCode::Z, &modifiers), Some(DecodedKey::Unicode('z')));
+
+    assert_eq!(layout.map(KeyCode::Numpad8, &modifiers), Some(DecodedKey::RawKey(KeyCode::ArrowUp)));
+    modifiers.update(KeyCode::NumpadLock, true);
+    assert_eq!(layout.map(KeyCode::Numpad8, &modifiers), Some(DecodedKey::Unicode('8')));
+}
+
+#[test_case]
+fn test_european_layouts() {
+    let mut modifiers = Modifiers::default();
+    assert_eq!(Layout::De105.map(KeyCode::Y, &modifiers), Some(DecodedKey::Unicode('z')));
+    assert_eq!(Layout::De105.map(KeyCode::Minus, &modifiers), Some(DecodedKey::Unicode('ß')));
+    assert_eq!(Layout::Uk105.map(KeyCode::BackSlash, &modifiers), Some(DecodedKey::Unicode('#')));
+    modifiers.update(KeyCode::AltRight, true);
+    assert_eq!(Layout::De105.map(KeyCode::Q, &modifiers), Some(DecodedKey::Unicode('@')));
+    assert_eq!(Layout::Es105.map(KeyCode::Key2, &modifiers), Some(DecodedKey::Unicode('@')));
+    assert_eq!(Layout::Uk105.map(KeyCode::Key4, &modifiers), Some(DecodedKey::Unicode('€')));
+    assert_eq!(Layout::Us104.map(KeyCode::Key4, &modifiers), None);
+    assert_eq!(Layout::from_name("de"), Some(Layout::De105));
+    assert_eq!(Layout::from_name("fr"), None);
+}
@@ -1,4 +1,5 @@
 
+use crate::{time::Instant, vga_buffer::terminal};
 use alloc::boxed::Box;
 use core::{
     future::Future,
@@ -6,26 +7,128 @@ use core::{
     sync::atomic::{AtomicU64, Ordering},
     task::{Context, Poll},
 };
+use spin::Mutex;
 
+pub mod budget;
 pub mod executor;
+pub mod join;
 pub mod keyboard;
+pub mod layout;
+pub mod mouse;
 pub mod simple_executor;
+pub mod sync;
+pub mod workqueue;
 
-pub struct Task {
+pub use budget::consume_budget;
+pub use join::{JoinError, JoinHandle};
+
+/// Tasks with a higher priority are polled first in every round. It only
+/// orders the tasks ready when a round starts: a high priority task woken
+/// during a round waits for the next one, behind the rest of the round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Priority {
+    Idle,
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// A future to spawn on an executor, which hands back a `JoinHandle` for
+/// its output.
+pub struct Task<T = ()> {
+    raw: RawTask,
+    handle: JoinHandle<T>,
+}
+
+// what the executors keep of a task once its output type is erased
+struct RawTask {
     id: TaskId,
+    name: &'static str,
+    priority: Priority,
+    deadline: Option<Instant>,
     future: Pin<Box<dyn Future<Output = ()>>>,
+    // virtual terminal print! writes to while the task runs
+    terminal: usize,
 }
 
-impl Task {
-    pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+impl<T: 'static> Task<T> {
+    /// Creates a task on the terminal of the code spawning it.
+    pub fn new(future: impl Future<Output = T> + 'static) -> Task<T> {
+        Task::with_terminal(future, terminal::current())
+    }
+
+    /// Creates a task whose output goes to virtual terminal `terminal`.
+    pub fn with_terminal(future: impl Future<Output = T> + 'static, terminal: usize) -> Task<T> {
+        assert!(terminal < terminal::COUNT, "there is no terminal {}", terminal);
+        let name = "task";
+        let (future, handle) = join::joined(future, name);
         Task {
-            id: TaskId::new(),
-            future: Box::pin(future),
+            raw: RawTask {
+                id: TaskId::new(),
+                name,
+                priority: Priority::default(),
+                deadline: None,
+                future: Box::pin(future),
+                terminal,
+            },
+            handle,
         }
     }
 
+    /// Names the task in diagnostics and panic messages.
+    pub fn named(mut self, name: &'static str) -> Task<T> {
+        self.raw.name = name;
+        self.handle.name = name;
+        self
+    }
+
+    pub fn priority(mut self, priority: Priority) -> Task<T> {
+        self.raw.priority = priority;
+        self
+    }
+
+    /// Polls the task before others of the same priority whose deadline is
+    /// later or missing, earliest deadline first.
+    pub fn deadline(mut self, deadline: Instant) -> Task<T> {
+        self.raw.deadline = Some(deadline);
+        self
+    }
+
+    // split into what the executor keeps and what the spawner gets
+    fn into_parts(self) -> (RawTask, JoinHandle<T>) {
+        (self.raw, self.handle)
+    }
+}
+
+impl RawTask {
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
-        self.future.as_mut().poll(context)
+        terminal::set_current(self.terminal);
+        *CURRENT.lock() = Some(self.name);
+        CURRENT_ID.store(self.id.0 + 1, Ordering::Relaxed);
+        let result = self.future.as_mut().poll(context);
+        CURRENT_ID.store(0, Ordering::Relaxed);
+        *CURRENT.lock() = None;
+        terminal::set_current(0);
+        result
+    }
+}
+
+// name of the task being polled
+static CURRENT: Mutex<Option<&'static str>> = Mutex::new(None);
+// id plus one of the task being polled, 0 outside of tasks
+static CURRENT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// The name of the task being polled, `None` outside of tasks. Does not
+/// block, so panic handlers can call it.
+pub fn current_name() -> Option<&'static str> {
+    CURRENT.try_lock().and_then(|current| *current)
+}
+
+fn current_id() -> Option<TaskId> {
+    match CURRENT_ID.load(Ordering::Relaxed) {
+        0 => None,
+        id => Some(TaskId(id - 1)),
     }
 }
 
@@ -37,4 +140,4 @@ impl TaskId {
         static NEXT_ID: AtomicU64 = AtomicU64::new(0);
         TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,153 @@
+use conquer_once::spin::OnceCell;
+use crossbeam_queue::ArrayQueue;
+use crate::ps2;
+use core::{pin::Pin, task::{Poll, Context}};
+use futures_util::stream::Stream;
+use futures_util::task::AtomicWaker;
+
+static BYTE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Which mouse buttons are held down.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MouseButtons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+/// Movement since the last event, x grows to the right and y downwards
+/// like screen coordinates, a positive wheel scrolls down.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub dx: i16,
+    pub dy: i16,
+    pub wheel: i8,
+    pub buttons: MouseButtons,
+}
+
+// the first byte of a packet holds the buttons, the signs and overflow
+// flags of the movement and an always set bit
+const PACKET_LEFT: u8 = 0x01;
+const PACKET_RIGHT: u8 = 0x02;
+const PACKET_MIDDLE: u8 = 0x04;
+const PACKET_ALWAYS_SET: u8 = 0x08;
+const PACKET_X_SIGN: u8 = 0x10;
+const PACKET_Y_SIGN: u8 = 0x20;
+const PACKET_X_OVERFLOW: u8 = 0x40;
+const PACKET_Y_OVERFLOW: u8 = 0x80;
+
+/// Decodes a 3 byte mouse packet, or a 4 byte one with wheel movement.
+pub fn decode_packet(packet: &[u8]) -> MouseEvent {
+    let flags = packet[0];
+    // the movement is 9 bit two's complement, the sign is in the flags
+    let delta = |byte: u8, sign: u8, overflow: u8| {
+        if flags & overflow != 0 {
+            0
+        } else if flags & sign != 0 {
+            byte as i16 - 0x100
+        } else {
+            byte as i16
+        }
+    };
+    // the wheel is a 4 bit two's complement number
+    let wheel = match packet.get(3) {
+        Some(&byte) => ((byte << 4) as i8) >> 4,
+        None => 0,
+    };
+    MouseEvent {
+        dx: delta(packet[1], PACKET_X_SIGN, PACKET_X_OVERFLOW),
+        dy: -delta(packet[2], PACKET_Y_SIGN, PACKET_Y_OVERFLOW),
+        wheel,
+        buttons: MouseButtons {
+            left: flags & PACKET_LEFT != 0,
+            right: flags & PACKET_RIGHT != 0,
+            middle: flags & PACKET_MIDDLE != 0,
+        },
+    }
+}
+
+/// Mouse events assembled from the bytes IRQ 12 receives.
+pub struct MouseStream {
+    packet: [u8; 4],
+    len: usize,
+}
+
+impl MouseStream {
+    pub fn new() -> Self {
+        BYTE_QUEUE.try_init_once(|| ArrayQueue::new(256))
+            .expect("MouseStream::new should only be called once");
+        MouseStream { packet: [0; 4], len: 0 }
+    }
+
+    // adds a byte to the packet, returns the event once it is complete
+    fn add_byte(&mut self, byte: u8) -> Option<MouseEvent> {
+        // a first byte without the always set bit means the stream lost a
+        // byte, skip ahead to the next packet start
+        if self.len == 0 && byte & PACKET_ALWAYS_SET == 0 {
+            return None;
+        }
+        self.packet[self.len] = byte;
+        self.len += 1;
+        let size = if ps2::mouse_has_wheel() { 4 } else { 3 };
+        if self.len < size {
+            return None;
+        }
+        self.len = 0;
+        Some(decode_packet(&self.packet[..size]))
+    }
+}
+
+impl Stream for MouseStream {
+    type Item = MouseEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<MouseEvent>> {
+        let queue = BYTE_QUEUE.try_get().expect("not initialized");
+
+        loop {
+            while let Ok(byte) = queue.pop() {
+                if let Some(event) = self.add_byte(byte) {
+                    return Poll::Ready(Some(event));
+                }
+            }
+
+            WAKER.register(cx.waker());
+
+            match queue.pop() {
+                Ok(byte) => {
+                    WAKER.take();
+                    if let Some(event) = self.add_byte(byte) {
+                        return Poll::Ready(Some(event));
+                    }
+                }
+                Err(crossbeam_queue::PopError) => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Called by the mouse interrupt handler, bytes are dropped while no
+/// `MouseStream` exists.
+pub(crate) fn add_byte(byte: u8) {
+    if let Ok(queue) = BYTE_QUEUE.try_get() {
+        // a full queue means nobody reads the mouse, losing bytes only
+        // costs the stream a resync
+        if queue.push(byte).is_ok() {
+            WAKER.wake();
+        }
+    }
+}
+
+#[test_case]
+fn test_decode_packet() {
+    let event = decode_packet(&[PACKET_ALWAYS_SET | PACKET_LEFT, 5, 3]);
+    assert_eq!((event.dx, event.dy, event.wheel), (5, -3, 0));
+    assert!(event.buttons.left && !event.buttons.right);
+
+    let event = decode_packet(&[PACKET_ALWAYS_SET | PACKET_X_SIGN | PACKET_Y_SIGN | PACKET_MIDDLE, 0xfe, 0xff, 0x0f]);
+    assert_eq!((event.dx, event.dy, event.wheel), (-2, 1, -1));
+    assert!(event.buttons.middle);
+
+    let event = decode_packet(&[PACKET_ALWAYS_SET | PACKET_X_OVERFLOW, 0xff, 7]);
+    assert_eq!((event.dx, event.dy), (0, -7));
+}
@@ -0,0 +1,199 @@
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use spin::Mutex;
+
+/// Nobody is subscribed, the value comes back.
+#[derive(PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SendError(..)")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    /// Every sender is gone and the receiver has seen every value.
+    Closed,
+    /// The receiver fell behind and missed this many values, the next
+    /// receive returns the oldest one still kept.
+    Lagged(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Closed,
+    Lagged(u64),
+}
+
+struct State<T> {
+    // the last `capacity` values, the front one numbered `first`
+    values: VecDeque<T>,
+    first: u64,
+    capacity: usize,
+    senders: usize,
+    receivers: usize,
+    wakers: Vec<Waker>,
+}
+
+impl<T> State<T> {
+    fn end(&self) -> u64 {
+        self.first + self.values.len() as u64
+    }
+}
+
+/// Creates a channel where every receiver sees every value sent after it
+/// subscribed. Only the last `capacity` values are kept for receivers that
+/// fall behind.
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel without capacity");
+    let state = Arc::new(Mutex::new(State {
+        values: VecDeque::with_capacity(capacity),
+        first: 0,
+        capacity,
+        senders: 1,
+        receivers: 1,
+        wakers: Vec::new(),
+    }));
+    (Sender { state: state.clone() }, Receiver { state, next: 0 })
+}
+
+pub struct Sender<T> {
+    state: Arc<Mutex<State<T>>>,
+}
+
+impl<T: Clone> Sender<T> {
+    /// Sends `value` to every receiver, returning how many there are.
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        let (receivers, wakers) = {
+            let mut state = self.state.lock();
+            if state.receivers == 0 {
+                return Err(SendError(value));
+            }
+            if state.values.len() == state.capacity {
+                state.values.pop_front();
+                state.first += 1;
+            }
+            state.values.push_back(value);
+            (state.receivers, core::mem::take(&mut state.wakers))
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+        Ok(receivers)
+    }
+
+    /// A new receiver seeing the values sent from now on.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut state = self.state.lock();
+        state.receivers += 1;
+        Receiver {
+            state: self.state.clone(),
+            next: state.end(),
+        }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.state.lock().receivers
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.state.lock().senders += 1;
+        Sender { state: self.state.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let wakers = {
+            let mut state = self.state.lock();
+            state.senders -= 1;
+            match state.senders {
+                0 => core::mem::take(&mut state.wakers),
+                _ => Vec::new(),
+            }
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    state: Arc<Mutex<State<T>>>,
+    // number of the next value to receive
+    next: u64,
+}
+
+impl<T: Clone> Receiver<T> {
+    /// Waits for the next value.
+    pub fn recv(&mut self) -> Recv<'_, T> {
+        Recv { receiver: self }
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let state = self.state.clone();
+        let mut state = state.lock();
+        match self.take(&mut state) {
+            Some(Ok(value)) => Ok(value),
+            Some(Err(RecvError::Lagged(missed))) => Err(TryRecvError::Lagged(missed)),
+            Some(Err(RecvError::Closed)) => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    // the next value or error, `None` when there is nothing yet
+    fn take(&mut self, state: &mut State<T>) -> Option<Result<T, RecvError>> {
+        if self.next < state.first {
+            let missed = state.first - self.next;
+            self.next = state.first;
+            return Some(Err(RecvError::Lagged(missed)));
+        }
+        if self.next < state.end() {
+            let value = state.values[(self.next - state.first) as usize].clone();
+            self.next += 1;
+            return Some(Ok(value));
+        }
+        match state.senders {
+            0 => Some(Err(RecvError::Closed)),
+            _ => None,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.state.lock().receivers -= 1;
+    }
+}
+
+pub struct Recv<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T: Clone> Future for Recv<'_, T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        let state = self.receiver.state.clone();
+        let mut state = state.lock();
+        match self.receiver.take(&mut state) {
+            Some(result) => Poll::Ready(result),
+            None => {
+                if !state.wakers.iter().any(|waker| waker.will_wake(context.waker())) {
+                    state.wakers.push(context.waker().clone());
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -0,0 +1,61 @@
+// Primitives for tasks to wait on each other without spinning. Waiting
+// futures register their `Waker` and the executor polls them again once
+// they can make progress. They are for tasks only, interrupt handlers
+// must not use them since they allocate.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Waker};
+use futures_util::task::AtomicWaker;
+
+pub mod broadcast;
+pub mod mpsc;
+mod mutex;
+mod notify;
+pub mod oneshot;
+mod rwlock;
+mod semaphore;
+
+pub use mutex::{Mutex, MutexGuard};
+pub use notify::{Notified, Notify};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::{Acquire, AcquireError, Semaphore, SemaphorePermit, TryAcquireError};
+
+// a task parked in the queue of a primitive, granted when it may go on
+struct Waiter {
+    granted: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl Waiter {
+    fn new(context: &Context) -> Arc<Waiter> {
+        let waiter = Arc::new(Waiter {
+            granted: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        });
+        waiter.waker.register(context.waker());
+        waiter
+    }
+
+    fn is_granted(&self) -> bool {
+        self.granted.load(Ordering::Acquire)
+    }
+
+    // marks the waiter granted, its waker is woken once the lock of the
+    // primitive is released
+    fn grant(&self, wake: &mut Wakeups) {
+        self.granted.store(true, Ordering::Release);
+        if let Some(waker) = self.waker.take() {
+            wake.push(waker);
+        }
+    }
+}
+
+// wakers collected under a lock and woken after releasing it
+type Wakeups = Vec<Waker>;
+
+fn wake_all(wakeups: Wakeups) {
+    for waker in wakeups {
+        waker.wake();
+    }
+}
@@ -0,0 +1,268 @@
+use super::Semaphore;
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use futures_util::stream::Stream;
+use spin::Mutex;
+
+/// The receiver is gone, the value comes back.
+#[derive(PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SendError(..)")
+    }
+}
+
+#[derive(PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// A bounded channel has no room.
+    Full(T),
+    Closed(T),
+}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "Full(..)"),
+            TrySendError::Closed(_) => write!(f, "Closed(..)"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    /// Every sender is gone and the queue is empty.
+    Closed,
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+    receiver: bool,
+    waker: Option<Waker>,
+}
+
+struct Channel<T> {
+    state: Mutex<State<T>>,
+    // free slots of a bounded channel
+    slots: Option<Semaphore>,
+}
+
+impl<T> Channel<T> {
+    fn new(capacity: Option<usize>) -> Arc<Channel<T>> {
+        Arc::new(Channel {
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                senders: 1,
+                receiver: true,
+                waker: None,
+            }),
+            slots: capacity.map(Semaphore::new),
+        })
+    }
+
+    fn push(&self, value: T) -> Result<(), T> {
+        let waker = {
+            let mut state = self.state.lock();
+            if !state.receiver {
+                return Err(value);
+            }
+            state.queue.push_back(value);
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    fn add_sender(&self) {
+        self.state.lock().senders += 1;
+    }
+
+    fn drop_sender(&self) {
+        let waker = {
+            let mut state = self.state.lock();
+            state.senders -= 1;
+            match state.senders {
+                0 => state.waker.take(),
+                _ => None,
+            }
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Creates a channel holding at most `capacity` values, senders wait for
+/// room.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel without capacity");
+    let channel = Channel::new(Some(capacity));
+    (Sender { channel: channel.clone() }, Receiver { channel })
+}
+
+/// Creates a channel whose queue grows as needed, sending never waits.
+pub fn unbounded_channel<T>() -> (UnboundedSender<T>, Receiver<T>) {
+    let channel = Channel::new(None);
+    (UnboundedSender { channel: channel.clone() }, Receiver { channel })
+}
+
+/// Sends into a bounded channel.
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> Sender<T> {
+    fn slots(&self) -> &Semaphore {
+        self.channel.slots.as_ref().expect("bounded channel")
+    }
+
+    /// Waits for room and sends `value`.
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        match self.slots().acquire().await {
+            Ok(permit) => permit.forget(),
+            Err(_) => return Err(SendError(value)),
+        }
+        self.channel.push(value).map_err(SendError)
+    }
+
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        match self.slots().try_acquire() {
+            Ok(permit) => permit.forget(),
+            Err(super::TryAcquireError::NoPermits) => return Err(TrySendError::Full(value)),
+            Err(super::TryAcquireError::Closed) => return Err(TrySendError::Closed(value)),
+        }
+        self.channel.push(value).map_err(TrySendError::Closed)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        !self.channel.state.lock().receiver
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.channel.add_sender();
+        Sender { channel: self.channel.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.channel.drop_sender();
+    }
+}
+
+/// Sends into an unbounded channel.
+pub struct UnboundedSender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> UnboundedSender<T> {
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.channel.push(value).map_err(SendError)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        !self.channel.state.lock().receiver
+    }
+}
+
+impl<T> Clone for UnboundedSender<T> {
+    fn clone(&self) -> Self {
+        self.channel.add_sender();
+        UnboundedSender { channel: self.channel.clone() }
+    }
+}
+
+impl<T> Drop for UnboundedSender<T> {
+    fn drop(&mut self) {
+        self.channel.drop_sender();
+    }
+}
+
+/// Receives from a bounded or unbounded channel, in the order the values
+/// were sent.
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Waits for the next value, `None` once every sender is gone and the
+    /// queue is empty.
+    pub fn recv(&mut self) -> Recv<'_, T> {
+        Recv { receiver: self }
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut state = self.channel.state.lock();
+        match state.queue.pop_front() {
+            Some(value) => {
+                drop(state);
+                self.free_slot();
+                Ok(value)
+            }
+            None if state.senders == 0 => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    fn free_slot(&self) {
+        if let Some(slots) = &self.channel.slots {
+            slots.add_permits(1);
+        }
+    }
+
+    fn poll_recv(&mut self, context: &mut Context) -> Poll<Option<T>> {
+        let mut state = self.channel.state.lock();
+        if let Some(value) = state.queue.pop_front() {
+            drop(state);
+            self.free_slot();
+            return Poll::Ready(Some(value));
+        }
+        if state.senders == 0 {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(context.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<T>> {
+        self.get_mut().poll_recv(context)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.channel.state.lock().receiver = false;
+        // wake the senders waiting for room
+        if let Some(slots) = &self.channel.slots {
+            slots.close();
+        }
+    }
+}
+
+pub struct Recv<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T> Future for Recv<'_, T> {
+    type Output = Option<T>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<T>> {
+        self.receiver.poll_recv(context)
+    }
+}
@@ -0,0 +1,130 @@
+use super::{wake_all, Waiter, Wakeups};
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use spin::Mutex;
+
+struct State {
+    // a `notify_one` that found nobody waiting, kept for the next waiter
+    permit: bool,
+    waiters: VecDeque<Arc<Waiter>>,
+    // woken by `notify_one` but not polled yet; dropping one of them passes
+    // the wakeup on to the next waiter
+    forwarded: Vec<Arc<Waiter>>,
+}
+
+/// Wakes waiting tasks without passing any data.
+pub struct Notify {
+    state: Mutex<State>,
+}
+
+impl Notify {
+    pub const fn new() -> Notify {
+        Notify {
+            state: Mutex::new(State {
+                permit: false,
+                waiters: VecDeque::new(),
+                forwarded: Vec::new(),
+            }),
+        }
+    }
+
+    /// Wakes the task that waits longest. With nobody waiting, the next
+    /// call to `notified` completes right away.
+    pub fn notify_one(&self) {
+        let mut wakeups = Vec::new();
+        {
+            let mut state = self.state.lock();
+            Self::notify_one_locked(&mut state, &mut wakeups);
+        }
+        wake_all(wakeups);
+    }
+
+    fn notify_one_locked(state: &mut State, wakeups: &mut Wakeups) {
+        match state.waiters.pop_front() {
+            Some(waiter) => {
+                waiter.grant(wakeups);
+                state.forwarded.push(waiter);
+            }
+            None => state.permit = true,
+        }
+    }
+
+    /// Wakes every task waiting right now, without storing a permit.
+    pub fn notify_waiters(&self) {
+        let mut wakeups = Vec::new();
+        {
+            let mut state = self.state.lock();
+            for waiter in state.waiters.drain(..) {
+                waiter.grant(&mut wakeups);
+            }
+        }
+        wake_all(wakeups);
+    }
+
+    /// Waits for a notification.
+    pub fn notified(&self) -> Notified<'_> {
+        Notified { notify: self, waiter: None }
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Notify::new()
+    }
+}
+
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    waiter: Option<Arc<Waiter>>,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        let mut state = self.notify.state.lock();
+        if let Some(waiter) = &self.waiter {
+            if !waiter.is_granted() {
+                waiter.waker.register(context.waker());
+                return Poll::Pending;
+            }
+            let waiter = self.waiter.take().expect("granted waiter");
+            state.forwarded.retain(|forwarded| !Arc::ptr_eq(forwarded, &waiter));
+            return Poll::Ready(());
+        }
+        if state.permit {
+            state.permit = false;
+            return Poll::Ready(());
+        }
+        let waiter = Waiter::new(context);
+        state.waiters.push_back(waiter.clone());
+        drop(state);
+        self.waiter = Some(waiter);
+        Poll::Pending
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let waiter = match self.waiter.take() {
+            Some(waiter) => waiter,
+            None => return,
+        };
+        let mut wakeups = Vec::new();
+        {
+            let mut state = self.notify.state.lock();
+            state.waiters.retain(|queued| !Arc::ptr_eq(queued, &waiter));
+            let forwarded = state.forwarded.len();
+            state.forwarded.retain(|forwarded| !Arc::ptr_eq(forwarded, &waiter));
+            // a `notify_one` meant for this waiter goes to the next one
+            if state.forwarded.len() != forwarded {
+                Notify::notify_one_locked(&mut state, &mut wakeups);
+            }
+        }
+        wake_all(wakeups);
+    }
+}
@@ -0,0 +1,218 @@
+use super::{wake_all, Waiter, Wakeups};
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use spin::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcquireError {
+    /// The semaphore was closed.
+    Closed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryAcquireError {
+    Closed,
+    /// Not enough permits, or other tasks are waiting for them first.
+    NoPermits,
+}
+
+struct State {
+    permits: usize,
+    // first come, first served; a big request holds up smaller ones behind it
+    waiters: VecDeque<(usize, Arc<Waiter>)>,
+    closed: bool,
+}
+
+impl State {
+    // hands out permits to the waiters at the front
+    fn grant(&mut self, wakeups: &mut Wakeups) {
+        while let Some((needed, _)) = self.waiters.front() {
+            if *needed > self.permits {
+                break;
+            }
+            self.permits -= needed;
+            let (_, waiter) = self.waiters.pop_front().expect("front waiter");
+            waiter.grant(wakeups);
+        }
+    }
+}
+
+/// Counts permits that tasks wait for in the order they asked.
+pub struct Semaphore {
+    state: Mutex<State>,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            state: Mutex::new(State {
+                permits,
+                waiters: VecDeque::new(),
+                closed: false,
+            }),
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().permits
+    }
+
+    pub fn add_permits(&self, permits: usize) {
+        let mut wakeups = Vec::new();
+        {
+            let mut state = self.state.lock();
+            state.permits += permits;
+            state.grant(&mut wakeups);
+        }
+        wake_all(wakeups);
+    }
+
+    /// Waits for one permit.
+    pub fn acquire(&self) -> Acquire<'_> {
+        self.acquire_many(1)
+    }
+
+    /// Waits for `permits` permits at once.
+    pub fn acquire_many(&self, permits: usize) -> Acquire<'_> {
+        Acquire {
+            semaphore: self,
+            permits,
+            waiter: None,
+        }
+    }
+
+    pub fn try_acquire(&self) -> Result<SemaphorePermit<'_>, TryAcquireError> {
+        self.try_acquire_many(1)
+    }
+
+    pub fn try_acquire_many(&self, permits: usize) -> Result<SemaphorePermit<'_>, TryAcquireError> {
+        let mut state = self.state.lock();
+        if state.closed {
+            return Err(TryAcquireError::Closed);
+        }
+        if !state.waiters.is_empty() || state.permits < permits {
+            return Err(TryAcquireError::NoPermits);
+        }
+        state.permits -= permits;
+        Ok(SemaphorePermit { semaphore: self, permits })
+    }
+
+    /// Fails all waiting and future acquires. Permits already handed out
+    /// stay valid.
+    pub fn close(&self) {
+        let mut wakeups = Vec::new();
+        {
+            let mut state = self.state.lock();
+            state.closed = true;
+            for (_, waiter) in state.waiters.drain(..) {
+                if let Some(waker) = waiter.waker.take() {
+                    wakeups.push(waker);
+                }
+            }
+        }
+        wake_all(wakeups);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().closed
+    }
+
+    // returns permits without a `SemaphorePermit`, for the other primitives
+    pub(super) fn release(&self, permits: usize) {
+        self.add_permits(permits);
+    }
+}
+
+/// Permits that go back to the semaphore when dropped.
+#[must_use]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+impl SemaphorePermit<'_> {
+    pub fn permits(&self) -> usize {
+        self.permits
+    }
+
+    /// Keeps the permits out of the semaphore for good.
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        if self.permits != 0 {
+            self.semaphore.release(self.permits);
+        }
+    }
+}
+
+/// Waits in line for permits. Dropping it gives up the place in the queue,
+/// or the permits if they were granted in the meantime.
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+    waiter: Option<Arc<Waiter>>,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = Result<SemaphorePermit<'a>, AcquireError>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        let semaphore = self.semaphore;
+        let permits = self.permits;
+        if let Some(waiter) = &self.waiter {
+            waiter.waker.register(context.waker());
+            if waiter.is_granted() {
+                self.waiter = None;
+                return Poll::Ready(Ok(SemaphorePermit { semaphore, permits }));
+            }
+            if semaphore.is_closed() {
+                self.waiter = None;
+                return Poll::Ready(Err(AcquireError::Closed));
+            }
+            return Poll::Pending;
+        }
+
+        let mut state = semaphore.state.lock();
+        if state.closed {
+            return Poll::Ready(Err(AcquireError::Closed));
+        }
+        if state.waiters.is_empty() && state.permits >= permits {
+            state.permits -= permits;
+            return Poll::Ready(Ok(SemaphorePermit { semaphore, permits }));
+        }
+        let waiter = Waiter::new(context);
+        state.waiters.push_back((permits, waiter.clone()));
+        drop(state);
+        self.waiter = Some(waiter);
+        Poll::Pending
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let waiter = match self.waiter.take() {
+            Some(waiter) => waiter,
+            None => return,
+        };
+        let mut wakeups = Vec::new();
+        {
+            let mut state = self.semaphore.state.lock();
+            if waiter.is_granted() {
+                state.permits += self.permits;
+            } else {
+                state.waiters.retain(|(_, queued)| !Arc::ptr_eq(queued, &waiter));
+            }
+            // whoever was behind may fit now
+            state.grant(&mut wakeups);
+        }
+        wake_all(wakeups);
+    }
+}
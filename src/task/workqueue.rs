@@ -0,0 +1,216 @@
+use super::consume_budget;
+use crate::sync::IrqSpinlock;
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::task::AtomicWaker;
+
+/// CPUs with a work queue of their own.
+pub const MAX_CPUS: usize = 8;
+/// Work items a CPU can have waiting.
+pub const CAPACITY: usize = 64;
+
+/// A function to run later in task context, with a word of data for it.
+/// Queueing one does not allocate, so interrupt handlers can hand their
+/// slow work to it.
+#[derive(Clone, Copy)]
+pub struct Work {
+    name: &'static str,
+    function: fn(usize),
+    data: usize,
+}
+
+impl Work {
+    pub const fn new(name: &'static str, function: fn(usize), data: usize) -> Work {
+        Work { name, function, data }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn run(self) {
+        (self.function)(self.data)
+    }
+}
+
+impl fmt::Debug for Work {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Work").field("name", &self.name).field("data", &self.data).finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueError {
+    /// `CAPACITY` items are waiting already, the work was dropped.
+    Full,
+    InvalidCpu(usize),
+}
+
+// a ring buffer, work runs in the order it was queued
+struct Ring {
+    items: [Option<Work>; CAPACITY],
+    head: usize,
+    len: usize,
+}
+
+impl Ring {
+    const fn new() -> Ring {
+        Ring { items: [None; CAPACITY], head: 0, len: 0 }
+    }
+
+    fn push(&mut self, work: Work) -> Result<(), QueueError> {
+        if self.len == CAPACITY {
+            return Err(QueueError::Full);
+        }
+        self.items[(self.head + self.len) % CAPACITY] = Some(work);
+        self.len += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<Work> {
+        if self.len == 0 {
+            return None;
+        }
+        let work = self.items[self.head].take();
+        self.head = (self.head + 1) % CAPACITY;
+        self.len -= 1;
+        work
+    }
+}
+
+struct WorkQueue {
+    ring: IrqSpinlock<Ring>,
+    // the worker task of the CPU
+    waker: AtomicWaker,
+    queued: AtomicU64,
+    ran: AtomicU64,
+    dropped: AtomicU64,
+}
+
+static QUEUES: [WorkQueue; MAX_CPUS] = [const {
+    WorkQueue {
+        ring: IrqSpinlock::new("work queue", Ring::new()),
+        waker: AtomicWaker::new(),
+        queued: AtomicU64::new(0),
+        ran: AtomicU64::new(0),
+        dropped: AtomicU64::new(0),
+    }
+}; MAX_CPUS];
+
+// only the boot CPU runs so far
+fn current_cpu() -> usize {
+    0
+}
+
+/// Queues `work` for the worker of the CPU this runs on.
+pub fn queue(work: Work) -> Result<(), QueueError> {
+    queue_on(current_cpu(), work)
+}
+
+/// Queues `work` for the worker of `cpu`.
+pub fn queue_on(cpu: usize, work: Work) -> Result<(), QueueError> {
+    let queue = QUEUES.get(cpu).ok_or(QueueError::InvalidCpu(cpu))?;
+    if let Err(error) = queue.ring.lock().push(work) {
+        queue.dropped.fetch_add(1, Ordering::Relaxed);
+        return Err(error);
+    }
+    queue.queued.fetch_add(1, Ordering::Relaxed);
+    queue.waker.wake();
+    Ok(())
+}
+
+/// Work items waiting for the worker of `cpu`.
+pub fn pending(cpu: usize) -> usize {
+    QUEUES.get(cpu).map_or(0, |queue| queue.ring.lock().len)
+}
+
+/// Runs the work queued for `cpu`, forever. Spawn one task per CPU with
+/// it; work items share the task's budget, so a flood of them still lets
+/// other tasks run. Fails with `InvalidCpu` past `MAX_CPUS`.
+pub fn run_worker(cpu: usize) -> Result<impl Future<Output = ()>, QueueError> {
+    let queue = QUEUES.get(cpu).ok_or(QueueError::InvalidCpu(cpu))?;
+    Ok(work_loop(queue))
+}
+
+async fn work_loop(queue: &'static WorkQueue) {
+    loop {
+        Queued { queue }.await;
+        // a `while let` would keep the ring locked while the work runs
+        loop {
+            let work = queue.ring.lock().pop();
+            match work {
+                Some(work) => {
+                    work.run();
+                    queue.ran.fetch_add(1, Ordering::Relaxed);
+                    consume_budget().await;
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+// ready once the queue has work
+struct Queued {
+    queue: &'static WorkQueue,
+}
+
+impl Future for Queued {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.queue.ring.lock().len > 0 {
+            return Poll::Ready(());
+        }
+        self.queue.waker.register(cx.waker());
+        if self.queue.ring.lock().len > 0 {
+            self.queue.waker.take();
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Writes how much work each CPU queued, ran and dropped, for the
+/// `workqueues` shell command.
+pub fn write_statistics(out: &mut impl fmt::Write) -> fmt::Result {
+    writeln!(out, "cpu      queued         ran  dropped  pending")?;
+    for (cpu, queue) in QUEUES.iter().enumerate() {
+        let queued = queue.queued.load(Ordering::Relaxed);
+        let dropped = queue.dropped.load(Ordering::Relaxed);
+        if queued == 0 && dropped == 0 {
+            continue;
+        }
+        writeln!(
+            out,
+            "{:>3}  {:>10}  {:>10}  {:>7}  {:>7}",
+            cpu,
+            queued,
+            queue.ran.load(Ordering::Relaxed),
+            dropped,
+            pending(cpu)
+        )?;
+    }
+    Ok(())
+}
+
+#[test_case]
+fn test_ring_order() {
+    fn nothing(_: usize) {}
+    let mut ring = Ring::new();
+    for data in 0..CAPACITY {
+        ring.push(Work::new("test", nothing, data)).unwrap();
+    }
+    assert_eq!(ring.push(Work::new("test", nothing, CAPACITY)).unwrap_err(), QueueError::Full);
+    assert_eq!(ring.pop().map(|work| work.data), Some(0));
+    ring.push(Work::new("test", nothing, CAPACITY)).unwrap();
+    let order: [usize; CAPACITY] = core::array::from_fn(|_| ring.pop().unwrap().data);
+    assert_eq!(order, core::array::from_fn(|index| index + 1));
+    assert!(ring.pop().is_none());
+}
@@ -0,0 +1,321 @@
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use x86_64::instructions::{interrupts, port::Port};
+use x86_64::structures::paging::{
+    mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+use crate::{acpi, log};
+use super::{PIT_COMMAND, PIT_FREQUENCY, TICK_HZ};
+
+/// Where the HPET registers are mapped.
+pub const HPET_START: usize = 0x_6666_6666_0000;
+
+// HPET registers
+pub(super) const HPET_CAPABILITIES: usize = 0x000;
+pub(super) const HPET_CONFIGURATION: usize = 0x010;
+const HPET_MAIN_COUNTER: usize = 0x0f0;
+const HPET_COUNTER_64_BIT: u64 = 1 << 13;
+const HPET_ENABLE: u64 = 1 << 0;
+// the base address in the ACPI table is a generic address structure
+const HPET_TABLE_ADDRESS_SPACE: usize = 40;
+const HPET_TABLE_ADDRESS: usize = 44;
+const ADDRESS_SPACE_MEMORY: u8 = 0;
+
+// PIT channel 2 can be gated and its output read back through port 0x61
+const PIT_CHANNEL_2: u16 = 0x42;
+const PIT_GATE: u16 = 0x61;
+const PIT_GATE_ENABLE: u8 = 0x01;
+const PIT_SPEAKER: u8 = 0x02;
+const PIT_OUTPUT_2: u8 = 0x20;
+// channel 2, low then high byte, interrupt on terminal count
+const PIT_MODE_ONE_SHOT: u8 = 0xb0;
+// the TSC is counted against the reference for 1 / CALIBRATION_HZ seconds
+const CALIBRATION_HZ: u64 = 100;
+// polls before giving up on the reference clock
+const CALIBRATION_TIMEOUT: usize = 10_000_000;
+
+// CPUID leaves telling about the invariant TSC
+const CPUID_MAX_EXTENDED: u32 = 0x8000_0000;
+const CPUID_POWER_MANAGEMENT: u32 = 0x8000_0007;
+const CPUID_INVARIANT_TSC: u32 = 1 << 8;
+
+/// A free running counter time is measured with.
+pub trait ClockSource: Sync {
+    fn name(&self) -> &'static str;
+    /// The counter, it never goes back.
+    fn read(&self) -> u64;
+    /// Counter increments per second.
+    fn frequency(&self) -> u64;
+}
+
+#[derive(Debug)]
+pub enum ClockError {
+    /// ACPI has no HPET table.
+    NoHpet,
+    /// The HPET is not memory mapped on a page boundary or has a 32 bit
+    /// counter.
+    UnsupportedHpet,
+    Map(MapToError<Size4KiB>),
+    /// The TSC changes its rate with the CPU frequency.
+    NoInvariantTsc,
+    /// The reference clock did not advance during calibration.
+    CalibrationFailed,
+}
+
+impl From<MapToError<Size4KiB>> for ClockError {
+    fn from(error: MapToError<Size4KiB>) -> Self {
+        ClockError::Map(error)
+    }
+}
+
+/// The timer interrupt count, always there but only as fine as `TICK_HZ`.
+pub struct Pit;
+
+impl ClockSource for Pit {
+    fn name(&self) -> &'static str {
+        "pit"
+    }
+
+    fn read(&self) -> u64 {
+        super::ticks()
+    }
+
+    fn frequency(&self) -> u64 {
+        TICK_HZ
+    }
+}
+
+/// The main counter of the high precision event timer.
+pub struct Hpet {
+    frequency: AtomicU64,
+}
+
+impl Hpet {
+    pub(super) fn register(offset: usize) -> *mut u64 {
+        (HPET_START + offset) as *mut u64
+    }
+}
+
+impl ClockSource for Hpet {
+    fn name(&self) -> &'static str {
+        "hpet"
+    }
+
+    fn read(&self) -> u64 {
+        unsafe { Hpet::register(HPET_MAIN_COUNTER).read_volatile() }
+    }
+
+    fn frequency(&self) -> u64 {
+        self.frequency.load(Ordering::Relaxed)
+    }
+}
+
+/// The time stamp counter, the cheapest to read; only used when its rate
+/// does not change with power states.
+pub struct Tsc {
+    frequency: AtomicU64,
+}
+
+pub(super) fn rdtsc() -> u64 {
+    let (low, high): (u32, u32);
+    unsafe { core::arch::asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack)) };
+    (high as u64) << 32 | low as u64
+}
+
+impl ClockSource for Tsc {
+    fn name(&self) -> &'static str {
+        "tsc"
+    }
+
+    fn read(&self) -> u64 {
+        rdtsc()
+    }
+
+    fn frequency(&self) -> u64 {
+        self.frequency.load(Ordering::Relaxed)
+    }
+}
+
+pub static PIT: Pit = Pit;
+pub static HPET: Hpet = Hpet { frequency: AtomicU64::new(0) };
+pub static TSC: Tsc = Tsc { frequency: AtomicU64::new(0) };
+// indexes into SOURCES
+#[derive(Clone, Copy)]
+enum Source {
+    Pit,
+    Hpet,
+    Tsc,
+}
+
+static SOURCES: [&dyn ClockSource; 3] = [&PIT, &HPET, &TSC];
+
+static CURRENT: AtomicUsize = AtomicUsize::new(Source::Pit as usize);
+// nanoseconds at the moment the current source was selected and its
+// counter then, so time goes on smoothly when switching
+static BASE_NANOS: AtomicU64 = AtomicU64::new(0);
+static BASE_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// The source `Instant::now()` reads.
+pub fn current() -> &'static dyn ClockSource {
+    SOURCES[CURRENT.load(Ordering::Relaxed)]
+}
+
+/// Whether the current source is the timer interrupt count.
+pub fn counts_ticks() -> bool {
+    CURRENT.load(Ordering::Relaxed) == Source::Pit as usize
+}
+
+fn to_nanos(count: u64, frequency: u64) -> u64 {
+    (count as u128 * 1_000_000_000 / frequency as u128) as u64
+}
+
+/// Nanoseconds since the timer was started.
+pub fn nanos() -> u64 {
+    let source = current();
+    let elapsed = source.read().wrapping_sub(BASE_COUNT.load(Ordering::Relaxed));
+    BASE_NANOS.load(Ordering::Relaxed) + to_nanos(elapsed, source.frequency())
+}
+
+// switching happens at boot, before anything else could read halfway
+fn select(source: Source) {
+    interrupts::without_interrupts(|| {
+        let now = nanos();
+        BASE_COUNT.store(SOURCES[source as usize].read(), Ordering::Relaxed);
+        BASE_NANOS.store(now, Ordering::Relaxed);
+        CURRENT.store(source as usize, Ordering::Relaxed);
+    });
+}
+
+/// Maps and starts the HPET that ACPI describes.
+pub fn init_hpet(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), ClockError> {
+    let table = acpi::find_table(b"HPET").ok_or(ClockError::NoHpet)?;
+    if table.len() < HPET_TABLE_ADDRESS + 8 || table[HPET_TABLE_ADDRESS_SPACE] != ADDRESS_SPACE_MEMORY {
+        return Err(ClockError::UnsupportedHpet);
+    }
+    let address = u64::from_le_bytes(table[HPET_TABLE_ADDRESS..HPET_TABLE_ADDRESS + 8].try_into().unwrap());
+    if address % 4096 != 0 {
+        return Err(ClockError::UnsupportedHpet);
+    }
+
+    // device memory must not be cached
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+    let page = Page::containing_address(VirtAddr::new(HPET_START as u64));
+    let frame = PhysFrame::containing_address(PhysAddr::new(address));
+    unsafe { mapper.map_to(page, frame, flags, frame_allocator)? }.flush();
+
+    let capabilities = unsafe { Hpet::register(HPET_CAPABILITIES).read_volatile() };
+    // the period is given in femtoseconds
+    let period = capabilities >> 32;
+    if capabilities & HPET_COUNTER_64_BIT == 0 || period == 0 {
+        return Err(ClockError::UnsupportedHpet);
+    }
+    HPET.frequency.store(1_000_000_000_000_000 / period, Ordering::Relaxed);
+    unsafe {
+        let configuration = Hpet::register(HPET_CONFIGURATION);
+        configuration.write_volatile(configuration.read_volatile() | HPET_ENABLE);
+    }
+    Ok(())
+}
+
+// returns eax, ecx and edx of a CPUID leaf
+pub(crate) fn cpuid(leaf: u32) -> (u32, u32, u32) {
+    let (eax, ecx, edx): (u32, u32, u32);
+    // rbx is reserved by LLVM but written by cpuid
+    unsafe {
+        core::arch::asm!(
+            "push rbx", "cpuid", "pop rbx",
+            inout("eax") leaf => eax, inout("ecx") 0u32 => ecx, out("edx") edx,
+        );
+    }
+    (eax, ecx, edx)
+}
+
+/// Whether the TSC ticks at a constant rate in every power state.
+pub fn has_invariant_tsc() -> bool {
+    cpuid(CPUID_MAX_EXTENDED).0 >= CPUID_POWER_MANAGEMENT
+        && cpuid(CPUID_POWER_MANAGEMENT).2 & CPUID_INVARIANT_TSC != 0
+}
+
+// TSC increments during a one shot countdown of PIT channel 2
+fn calibrate_with_pit() -> Option<u64> {
+    let mut gate = Port::<u8>::new(PIT_GATE);
+    let mut command = Port::<u8>::new(PIT_COMMAND);
+    let mut channel = Port::<u8>::new(PIT_CHANNEL_2);
+    let count = (PIT_FREQUENCY / CALIBRATION_HZ) as u16;
+    interrupts::without_interrupts(|| unsafe {
+        let saved = gate.read();
+        gate.write(saved & !PIT_SPEAKER | PIT_GATE_ENABLE);
+        command.write(PIT_MODE_ONE_SHOT);
+        channel.write(count as u8);
+        channel.write((count >> 8) as u8);
+        let start = rdtsc();
+        let finished = (0..CALIBRATION_TIMEOUT).any(|_| gate.read() & PIT_OUTPUT_2 != 0);
+        let end = rdtsc();
+        gate.write(saved);
+        if finished {
+            Some((end - start) * CALIBRATION_HZ)
+        } else {
+            None
+        }
+    })
+}
+
+fn calibrate_with_hpet() -> Option<u64> {
+    let frequency = HPET.frequency();
+    let wait = frequency / CALIBRATION_HZ;
+    interrupts::without_interrupts(|| {
+        let hpet_start = HPET.read();
+        let start = rdtsc();
+        let finished = (0..CALIBRATION_TIMEOUT).any(|_| HPET.read() - hpet_start >= wait);
+        let hpet_elapsed = HPET.read() - hpet_start;
+        let end = rdtsc();
+        if finished {
+            Some(((end - start) as u128 * frequency as u128 / hpet_elapsed as u128) as u64)
+        } else {
+            None
+        }
+    })
+}
+
+/// Measures the TSC frequency against the HPET, or the PIT without one.
+pub fn calibrate_tsc() -> Result<u64, ClockError> {
+    if !has_invariant_tsc() {
+        return Err(ClockError::NoInvariantTsc);
+    }
+    let frequency = if HPET.frequency() != 0 { calibrate_with_hpet() } else { calibrate_with_pit() };
+    let frequency = frequency.filter(|frequency| *frequency > 0).ok_or(ClockError::CalibrationFailed)?;
+    TSC.frequency.store(frequency, Ordering::Relaxed);
+    Ok(frequency)
+}
+
+/// Sets up the HPET and TSC and switches to the best of them: the TSC,
+/// then the HPET, then the PIT. Call once the ACPI tables are found.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> &'static dyn ClockSource {
+    if let Err(error) = init_hpet(mapper, frame_allocator) {
+        log!("WARNING: no HPET: {:?}", error);
+    }
+    match calibrate_tsc() {
+        Ok(_) => select(Source::Tsc),
+        Err(_) if HPET.frequency() != 0 => select(Source::Hpet),
+        Err(_) => {}
+    }
+    let source = current();
+    log!("clocksource: {} at {} Hz", source.name(), source.frequency());
+    source
+}
+
+#[test_case]
+fn test_nanos() {
+    assert_eq!(to_nanos(14_318_180, 14_318_180), 1_000_000_000);
+    assert_eq!(to_nanos(3, TICK_HZ), 30_000_000);
+    // large counts of fast clocks must not overflow
+    assert_eq!(to_nanos(u64::MAX / 2, 4_000_000_000), u64::MAX / 2 / 4);
+    let before = nanos();
+    assert!(nanos() >= before);
+}
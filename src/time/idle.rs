@@ -0,0 +1,137 @@
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use x86_64::instructions::interrupts;
+use crate::interrupts::{irq, PIC_1_OFFSET};
+use super::{tickless, timer, Instant};
+
+/// What ended a halt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WakeupReason {
+    /// The periodic tick or the one-shot timer.
+    Timer,
+    /// A device interrupt with this vector.
+    Device(u8),
+    /// Something without a registered handler, like an NMI.
+    Other,
+}
+
+/// Time spent halted and what woke the CPU up, since boot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdleStatistics {
+    pub halts: u64,
+    pub idle: Duration,
+    pub uptime: Duration,
+    pub timer_wakeups: u64,
+    pub device_wakeups: u64,
+    pub other_wakeups: u64,
+}
+
+static HALTS: AtomicU64 = AtomicU64::new(0);
+static IDLE_NANOS: AtomicU64 = AtomicU64::new(0);
+static TIMER_WAKEUPS: AtomicU64 = AtomicU64::new(0);
+static DEVICE_WAKEUPS: AtomicU64 = AtomicU64::new(0);
+static OTHER_WAKEUPS: AtomicU64 = AtomicU64::new(0);
+static WAKEUPS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
+fn classify(vector: Option<u8>) -> WakeupReason {
+    let event_timer = tickless::event_timer().map(|timer| timer.vector());
+    match vector {
+        Some(vector) if vector == PIC_1_OFFSET || Some(vector) == event_timer => WakeupReason::Timer,
+        Some(vector) => WakeupReason::Device(vector),
+        None => WakeupReason::Other,
+    }
+}
+
+/// Halts until the next interrupt unless `has_work`, which runs with
+/// interrupts disabled so no wakeup gets lost in between. In tickless mode
+/// the one-shot timer is armed for the next sleeping task first. Returns
+/// what ended the halt, `None` if there was no halt.
+pub fn halt_unless(has_work: impl FnOnce() -> bool) -> Option<WakeupReason> {
+    interrupts::disable();
+    if has_work() {
+        interrupts::enable();
+        return None;
+    }
+    if let Some(event_timer) = tickless::event_timer() {
+        let armed = match timer::next_deadline() {
+            Some(deadline) => event_timer.arm(deadline),
+            None => {
+                event_timer.disarm();
+                true
+            }
+        };
+        if !armed {
+            interrupts::enable();
+            return None;
+        }
+    }
+
+    irq::take_last_vector();
+    let start = Instant::now();
+    // the interrupt handler has run by the time hlt returns
+    interrupts::enable_and_hlt();
+    let idle = start.elapsed();
+    let vector = irq::take_last_vector();
+    let reason = classify(vector);
+
+    HALTS.fetch_add(1, Ordering::Relaxed);
+    IDLE_NANOS.fetch_add(idle.as_nanos() as u64, Ordering::Relaxed);
+    if let Some(vector) = vector {
+        WAKEUPS[vector as usize].fetch_add(1, Ordering::Relaxed);
+    }
+    match reason {
+        WakeupReason::Timer => &TIMER_WAKEUPS,
+        WakeupReason::Device(_) => &DEVICE_WAKEUPS,
+        WakeupReason::Other => &OTHER_WAKEUPS,
+    }
+    .fetch_add(1, Ordering::Relaxed);
+    Some(reason)
+}
+
+pub fn statistics() -> IdleStatistics {
+    IdleStatistics {
+        halts: HALTS.load(Ordering::Relaxed),
+        idle: Duration::from_nanos(IDLE_NANOS.load(Ordering::Relaxed)),
+        uptime: super::uptime(),
+        timer_wakeups: TIMER_WAKEUPS.load(Ordering::Relaxed),
+        device_wakeups: DEVICE_WAKEUPS.load(Ordering::Relaxed),
+        other_wakeups: OTHER_WAKEUPS.load(Ordering::Relaxed),
+    }
+}
+
+/// Writes the idle time, the wakeup reasons and the wakeups per vector,
+/// for the `idle` shell command.
+pub fn write_statistics(out: &mut impl fmt::Write) -> fmt::Result {
+    let statistics = statistics();
+    let percent = statistics.idle.as_nanos() * 100 / statistics.uptime.as_nanos().max(1);
+    writeln!(
+        out,
+        "idle {:?} of {:?} ({}%) in {} halts, timer: {}",
+        statistics.idle,
+        statistics.uptime,
+        percent,
+        statistics.halts,
+        tickless::event_timer().map_or("periodic tick", |timer| timer.name())
+    )?;
+    writeln!(
+        out,
+        "wakeups: timer {}  device {}  other {}",
+        statistics.timer_wakeups, statistics.device_wakeups, statistics.other_wakeups
+    )?;
+    writeln!(out, "vector     wakeups")?;
+    for (vector, wakeups) in WAKEUPS.iter().enumerate() {
+        let wakeups = wakeups.load(Ordering::Relaxed);
+        if wakeups != 0 {
+            writeln!(out, "{:>6}  {:>10}", vector, wakeups)?;
+        }
+    }
+    Ok(())
+}
+
+#[test_case]
+fn test_classify() {
+    assert_eq!(classify(Some(PIC_1_OFFSET)), WakeupReason::Timer);
+    assert_eq!(classify(Some(PIC_1_OFFSET + 1)), WakeupReason::Device(PIC_1_OFFSET + 1));
+    assert_eq!(classify(None), WakeupReason::Other);
+}
@@ -0,0 +1,384 @@
+use core::fmt;
+use core::ops::{Add, AddAssign, Sub};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use x86_64::instructions::port::Port;
+
+pub mod clocksource;
+pub mod idle;
+pub mod rtc;
+pub mod tickless;
+pub mod timer;
+
+/// How often the timer interrupt fires per second.
+pub const TICK_HZ: u64 = 100;
+// input clock of the programmable interval timer
+const PIT_FREQUENCY: u64 = 1_193_182;
+const PIT_CHANNEL_0: u16 = 0x40;
+const PIT_COMMAND: u16 = 0x43;
+// channel 0, low then high byte of the divisor, square wave
+const PIT_MODE_SQUARE_WAVE: u8 = 0x36;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+// nanoseconds since the epoch when `clocksource::nanos()` was 0, wrapping
+// arithmetic lets it stand for a negative value too
+static WALL_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Programs the timer to `TICK_HZ` and sets the wall clock from the RTC.
+/// Until then `now()` counts from the epoch.
+pub fn init() {
+    let divisor = (PIT_FREQUENCY / TICK_HZ) as u16;
+    let mut command = Port::<u8>::new(PIT_COMMAND);
+    let mut channel = Port::<u8>::new(PIT_CHANNEL_0);
+    unsafe {
+        command.write(PIT_MODE_SQUARE_WAVE);
+        channel.write(divisor as u8);
+        channel.write((divisor >> 8) as u8);
+    }
+    match rtc::read() {
+        Ok(time) => synchronize(&time, true),
+        Err(error) => crate::println!("WARNING: no wall clock time, the RTC failed: {:?}", error),
+    }
+    rtc::init();
+}
+
+/// Called by the timer interrupt handler.
+pub(crate) fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Timer interrupts since boot. They come every `1 / TICK_HZ` seconds
+/// unless `tickless` mode is on.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Time since the timer was started.
+pub fn uptime() -> Duration {
+    Duration::from_nanos(clocksource::nanos())
+}
+
+/// The current UTC time, kept by the clocksource and corrected by the RTC
+/// once a second.
+pub fn now() -> Timestamp {
+    let wall = WALL_OFFSET.load(Ordering::Relaxed).wrapping_add(clocksource::nanos());
+    Timestamp::from_unix(wall / 1_000_000_000, (wall % 1_000_000_000) as u32)
+}
+
+/// Sets the wall clock and the RTC, which only goes up to 2999.
+pub fn set_time(time: &DateTime) -> Result<(), rtc::RtcError> {
+    rtc::write(time)?;
+    synchronize(time, true);
+    Ok(())
+}
+
+// makes `now()` return `time`; unless `force`d the clock is not moved back
+// by less than a second, the clocksource keeps time between RTC updates
+// better than the whole seconds the RTC reads
+pub(crate) fn synchronize(time: &DateTime, force: bool) {
+    // nanoseconds since 1970 run out in 2554
+    let offset = time.to_unix().saturating_mul(1_000_000_000).wrapping_sub(clocksource::nanos());
+    let ahead = WALL_OFFSET.load(Ordering::Relaxed).wrapping_sub(offset) as i64;
+    if force || ahead <= 0 || ahead >= 1_000_000_000 {
+        WALL_OFFSET.store(offset, Ordering::Relaxed);
+    }
+}
+
+/// A point on the monotonic clock with nanosecond resolution, for
+/// measuring how long something took or setting deadlines. Its resolution
+/// is the one of `clocksource::current()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    nanos: u64,
+}
+
+impl Instant {
+    /// Later than any deadline that will pass, for waits without an end.
+    pub const FAR_FUTURE: Instant = Instant { nanos: u64::MAX };
+
+    pub fn now() -> Instant {
+        Instant { nanos: clocksource::nanos() }
+    }
+
+    /// Nanoseconds since the timer was started.
+    pub fn as_nanos(&self) -> u64 {
+        self.nanos
+    }
+
+    /// Zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.nanos.saturating_sub(earlier.nanos))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        Some(Instant { nanos: self.nanos.checked_add(nanos)? })
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        Some(Instant { nanos: self.nanos.checked_sub(nanos)? })
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration).expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, duration: Duration) -> Instant {
+        self.checked_sub(duration).expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// Prints a line prefixed with the current UTC time.
+#[macro_export]
+macro_rules! log {
+    ($($arg:tt)*) => ($crate::println!("[{}] {}", $crate::time::now(), format_args!($($arg)*)));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+/// A calendar date and time of day in UTC, from 1970 to the end of 9999.
+/// `DateTime::new` checks the fields, so every value is a real date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    year: u16,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+}
+
+const MAX_YEAR: u16 = 9999;
+// 9999-12-31T23:59:59Z
+const MAX_UNIX_SECONDS: u64 = 253_402_300_799;
+
+fn is_leap_year(year: u16) -> bool {
+    year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// days since 1970-01-01 of a date, counting years from March so the leap
+// day comes last
+fn days_from_civil(year: u16, month: u8, day: u8) -> u64 {
+    let year = year as u64 - if month <= 2 { 1 } else { 0 };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let month = month as u64;
+    let day_of_year = (153 * if month > 2 { month - 3 } else { month + 9 } + 2) / 5 + day as u64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    // 719468 days lie between 0000-03-01 and 1970-01-01
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: u64) -> (u16, u8, u8) {
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u8;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u8;
+    let year = (era * 400 + year_of_era) as u16 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+impl DateTime {
+    /// Returns `None` for dates that do not exist or lie outside 1970 to
+    /// 9999.
+    pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Option<DateTime> {
+        let valid = (1970..=MAX_YEAR).contains(&year)
+            && (1..=12).contains(&month)
+            && day >= 1
+            && day <= days_in_month(year, month)
+            && hour < 24
+            && minute < 60
+            && second < 60;
+        if valid {
+            Some(DateTime { year, month, day, hour, minute, second })
+        } else {
+            None
+        }
+    }
+
+    /// Times after the end of 9999 become its last second.
+    pub fn from_unix(seconds: u64) -> DateTime {
+        let seconds = seconds.min(MAX_UNIX_SECONDS);
+        let (year, month, day) = civil_from_days(seconds / 86400);
+        let second_of_day = seconds % 86400;
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (second_of_day / 3600) as u8,
+            minute: (second_of_day / 60 % 60) as u8,
+            second: (second_of_day % 60) as u8,
+        }
+    }
+
+    pub fn year(&self) -> u16 {
+        self.year
+    }
+
+    /// 1 to 12.
+    pub fn month(&self) -> u8 {
+        self.month
+    }
+
+    /// 1 to 31.
+    pub fn day(&self) -> u8 {
+        self.day
+    }
+
+    pub fn hour(&self) -> u8 {
+        self.hour
+    }
+
+    pub fn minute(&self) -> u8 {
+        self.minute
+    }
+
+    pub fn second(&self) -> u8 {
+        self.second
+    }
+
+    /// Seconds since 1970-01-01 00:00:00 UTC.
+    pub fn to_unix(&self) -> u64 {
+        days_from_civil(self.year, self.month, self.day) * 86400
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64
+    }
+
+    pub fn weekday(&self) -> Weekday {
+        // 1970-01-01 was a Thursday
+        match (days_from_civil(self.year, self.month, self.day) + 3) % 7 {
+            0 => Weekday::Monday,
+            1 => Weekday::Tuesday,
+            2 => Weekday::Wednesday,
+            3 => Weekday::Thursday,
+            4 => Weekday::Friday,
+            5 => Weekday::Saturday,
+            _ => Weekday::Sunday,
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    /// Formats as ISO 8601, like "2024-02-29T13:05:09Z".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// A point in time as seconds and nanoseconds since the Unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp {
+    seconds: u64,
+    nanos: u32,
+}
+
+impl Timestamp {
+    pub const UNIX_EPOCH: Timestamp = Timestamp { seconds: 0, nanos: 0 };
+
+    pub fn from_unix(seconds: u64, nanos: u32) -> Timestamp {
+        Timestamp {
+            seconds: seconds + (nanos / 1_000_000_000) as u64,
+            nanos: nanos % 1_000_000_000,
+        }
+    }
+
+    pub fn unix_seconds(&self) -> u64 {
+        self.seconds
+    }
+
+    pub fn subsec_nanos(&self) -> u32 {
+        self.nanos
+    }
+
+    pub fn date_time(&self) -> DateTime {
+        DateTime::from_unix(self.seconds)
+    }
+}
+
+impl fmt::Display for Timestamp {
+    /// Formats as ISO 8601 with milliseconds, like "2024-02-29T13:05:09.250Z".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let time = self.date_time();
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            time.year,
+            time.month,
+            time.day,
+            time.hour,
+            time.minute,
+            time.second,
+            self.nanos / 1_000_000
+        )
+    }
+}
+
+#[test_case]
+fn test_date_time_conversion() {
+    let leap_day = DateTime::new(2024, 2, 29, 13, 5, 9).unwrap();
+    assert_eq!(leap_day.to_unix(), 1_709_211_909);
+    assert_eq!(DateTime::from_unix(1_709_211_909), leap_day);
+    assert_eq!(leap_day.weekday(), Weekday::Thursday);
+    assert_eq!(DateTime::from_unix(0), DateTime::new(1970, 1, 1, 0, 0, 0).unwrap());
+    assert_eq!(DateTime::new(2023, 2, 29, 0, 0, 0), None);
+    assert_eq!(DateTime::new(2100, 2, 29, 0, 0, 0), None);
+    assert_eq!(DateTime::new(1969, 12, 31, 0, 0, 0), None);
+    assert_eq!(DateTime::new(2024, 0, 1, 0, 0, 0), None);
+    let last = DateTime::from_unix(u64::MAX);
+    assert_eq!(Some(last), DateTime::new(9999, 12, 31, 23, 59, 59));
+    assert_eq!(last.to_unix(), MAX_UNIX_SECONDS);
+}
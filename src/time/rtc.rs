@@ -0,0 +1,339 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+use x86_64::instructions::{interrupts, port::Port};
+use crate::interrupts::irq::{self, IrqReturn};
+use super::DateTime;
+
+/// The PIC line of the RTC.
+pub const RTC_IRQ: u8 = 8;
+
+// CMOS registers of the clock; the alarm registers follow the ones they match
+const SECONDS: u8 = 0x00;
+const SECONDS_ALARM: u8 = 0x01;
+const MINUTES: u8 = 0x02;
+const MINUTES_ALARM: u8 = 0x03;
+const HOURS: u8 = 0x04;
+const HOURS_ALARM: u8 = 0x05;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0a;
+const STATUS_B: u8 = 0x0b;
+const STATUS_C: u8 = 0x0c;
+// not standardized, but where QEMU and most PCs keep it
+const CENTURY: u8 = 0x32;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 0x80;
+const STATUS_A_RATE: u8 = 0x0f;
+const STATUS_B_SET: u8 = 0x80;
+const STATUS_B_PERIODIC: u8 = 0x40;
+const STATUS_B_ALARM: u8 = 0x20;
+const STATUS_B_UPDATE_ENDED: u8 = 0x10;
+const STATUS_B_BINARY: u8 = 0x04;
+const STATUS_B_24_HOUR: u8 = 0x02;
+const HOUR_PM: u8 = 0x80;
+// an alarm register set to this matches any value
+const ALARM_ANY: u8 = 0xc0;
+
+// status polls before giving up on an update to end, one takes about a
+// microsecond and an update at most 2 ms
+const UPDATE_TIMEOUT: usize = 10_000;
+// reads until two in a row agree
+const READ_ATTEMPTS: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcError {
+    /// The update in progress flag never cleared.
+    Timeout,
+    /// The clock holds a date that does not exist, it was likely never set.
+    InvalidTime,
+    /// The clock changed during every read.
+    Unstable,
+    /// Periodic rates go from 3 (8192 Hz) to 15 (2 Hz).
+    InvalidRate(u8),
+    /// The clock only holds years up to 2999.
+    OutOfRange,
+}
+
+// the last year the century register can tell apart
+const MAX_YEAR: u16 = 2999;
+
+struct Cmos {
+    address: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Cmos {
+    fn read(&mut self, register: u8) -> u8 {
+        // bit 7 of the address disables NMIs, it stays clear
+        unsafe {
+            self.address.write(register);
+            self.data.read()
+        }
+    }
+
+    fn write(&mut self, register: u8, value: u8) {
+        unsafe {
+            self.address.write(register);
+            self.data.write(value);
+        }
+    }
+
+    fn wait_for_update(&mut self) -> Result<(), RtcError> {
+        for _ in 0..UPDATE_TIMEOUT {
+            if self.read(STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS == 0 {
+                return Ok(());
+            }
+        }
+        Err(RtcError::Timeout)
+    }
+
+    fn read_raw(&mut self) -> RawTime {
+        RawTime {
+            second: self.read(SECONDS),
+            minute: self.read(MINUTES),
+            hour: self.read(HOURS),
+            day: self.read(DAY),
+            month: self.read(MONTH),
+            year: self.read(YEAR),
+            century: self.read(CENTURY),
+        }
+    }
+}
+
+static CMOS: Mutex<Cmos> = Mutex::new(Cmos { address: Port::new(0x70), data: Port::new(0x71) });
+static PERIODIC: AtomicU64 = AtomicU64::new(0);
+static ALARMS: AtomicU64 = AtomicU64::new(0);
+static ALARM_WAKER: AtomicWaker = AtomicWaker::new();
+
+// the interrupt handler uses the CMOS too
+fn with_cmos<R>(f: impl FnOnce(&mut Cmos) -> R) -> R {
+    interrupts::without_interrupts(|| f(&mut CMOS.lock()))
+}
+
+/// The clock registers as stored, in BCD or binary and 12 or 24 hour
+/// format depending on status register B.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RawTime {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+// registers hold BCD unless status register B selects binary
+fn from_register(raw: u8, status_b: u8) -> u8 {
+    if status_b & STATUS_B_BINARY != 0 { raw } else { (raw >> 4) * 10 + (raw & 0x0f) }
+}
+
+fn to_register(value: u8, status_b: u8) -> u8 {
+    if status_b & STATUS_B_BINARY != 0 { value } else { ((value / 10) << 4) | (value % 10) }
+}
+
+impl RawTime {
+    fn decode(&self, status_b: u8) -> Option<DateTime> {
+        let value = |raw: u8| from_register(raw, status_b);
+        let mut hour = value(self.hour & !HOUR_PM);
+        if status_b & STATUS_B_24_HOUR == 0 {
+            // 12 AM is midnight and 12 PM noon
+            hour = hour % 12 + if self.hour & HOUR_PM != 0 { 12 } else { 0 };
+        }
+        let year = value(self.year) as u16;
+        let century = match value(self.century) {
+            century @ 19..=29 => century as u16,
+            // no century register, the two digits cover 1970 to 2069
+            _ if year >= 70 => 19,
+            _ => 20,
+        };
+        DateTime::new(century * 100 + year, value(self.month), value(self.day), hour, value(self.minute), value(self.second))
+    }
+
+    fn encode(time: &DateTime, status_b: u8) -> RawTime {
+        let value = |decimal: u8| to_register(decimal, status_b);
+        RawTime {
+            second: value(time.second),
+            minute: value(time.minute),
+            hour: encode_hour(time.hour, status_b),
+            day: value(time.day),
+            month: value(time.month),
+            year: value((time.year % 100) as u8),
+            century: value((time.year / 100) as u8),
+        }
+    }
+}
+
+fn encode_hour(hour: u8, status_b: u8) -> u8 {
+    if status_b & STATUS_B_24_HOUR != 0 {
+        to_register(hour, status_b)
+    } else {
+        let twelve_hour = if hour.is_multiple_of(12) { 12 } else { hour % 12 };
+        to_register(twelve_hour, status_b) | if hour >= 12 { HOUR_PM } else { 0 }
+    }
+}
+
+/// Reads the time of the clock, avoiding the moment it updates.
+pub fn read() -> Result<DateTime, RtcError> {
+    let (raw, status_b) = with_cmos(|cmos| -> Result<(RawTime, u8), RtcError> {
+        cmos.wait_for_update()?;
+        let mut last = cmos.read_raw();
+        for _ in 0..READ_ATTEMPTS {
+            // an update may have started right after the flag was checked
+            cmos.wait_for_update()?;
+            let raw = cmos.read_raw();
+            if raw == last {
+                return Ok((raw, cmos.read(STATUS_B)));
+            }
+            last = raw;
+        }
+        Err(RtcError::Unstable)
+    })?;
+    raw.decode(status_b).ok_or(RtcError::InvalidTime)
+}
+
+/// Sets the time of the clock, keeping its format.
+pub fn write(time: &DateTime) -> Result<(), RtcError> {
+    if time.year() > MAX_YEAR {
+        return Err(RtcError::OutOfRange);
+    }
+    with_cmos(|cmos| {
+        let status_b = cmos.read(STATUS_B);
+        let raw = RawTime::encode(time, status_b);
+        // updates are halted while the registers are inconsistent
+        cmos.write(STATUS_B, status_b | STATUS_B_SET);
+        cmos.write(SECONDS, raw.second);
+        cmos.write(MINUTES, raw.minute);
+        cmos.write(HOURS, raw.hour);
+        cmos.write(DAY, raw.day);
+        cmos.write(MONTH, raw.month);
+        cmos.write(YEAR, raw.year);
+        cmos.write(CENTURY, raw.century);
+        cmos.write(STATUS_B, status_b & !STATUS_B_SET);
+    });
+    Ok(())
+}
+
+fn set_status_b(flags: u8, enabled: bool) {
+    with_cmos(|cmos| {
+        let status_b = cmos.read(STATUS_B);
+        cmos.write(STATUS_B, if enabled { status_b | flags } else { status_b & !flags });
+    });
+}
+
+/// Registers the IRQ 8 handler, which resynchronizes the wall clock every
+/// time the RTC finishes an update.
+pub fn init() {
+    // a pending flag in register C would keep the line from firing
+    with_cmos(|cmos| cmos.read(STATUS_C));
+    set_status_b(STATUS_B_UPDATE_ENDED, true);
+    irq::register_irq(RTC_IRQ, "rtc", 0, interrupt_handler).expect("rtc interrupt handler");
+}
+
+/// Starts the periodic interrupt at 32768 >> (`rate` - 1) Hz, or stops it
+/// for `None`.
+pub fn set_periodic(rate: Option<u8>) -> Result<(), RtcError> {
+    match rate {
+        Some(rate) if !(3..=15).contains(&rate) => Err(RtcError::InvalidRate(rate)),
+        Some(rate) => {
+            with_cmos(|cmos| {
+                let status_a = cmos.read(STATUS_A);
+                cmos.write(STATUS_A, status_a & !STATUS_A_RATE | rate);
+            });
+            set_status_b(STATUS_B_PERIODIC, true);
+            Ok(())
+        }
+        None => {
+            set_status_b(STATUS_B_PERIODIC, false);
+            Ok(())
+        }
+    }
+}
+
+/// Periodic interrupts since boot.
+pub fn periodic_count() -> u64 {
+    PERIODIC.load(Ordering::Relaxed)
+}
+
+/// Raises the alarm interrupt every day at the given time, a `None` hour
+/// or minute matches any.
+pub fn set_alarm(hour: Option<u8>, minute: Option<u8>, second: u8) {
+    with_cmos(|cmos| {
+        let status_b = cmos.read(STATUS_B);
+        cmos.write(SECONDS_ALARM, to_register(second, status_b));
+        cmos.write(MINUTES_ALARM, minute.map_or(ALARM_ANY, |minute| to_register(minute, status_b)));
+        cmos.write(HOURS_ALARM, hour.map_or(ALARM_ANY, |hour| encode_hour(hour, status_b)));
+        cmos.write(STATUS_B, status_b | STATUS_B_ALARM);
+    });
+}
+
+pub fn cancel_alarm() {
+    set_status_b(STATUS_B_ALARM, false);
+}
+
+/// Completes at the next alarm interrupt.
+pub fn alarm() -> Alarm {
+    Alarm { seen: ALARMS.load(Ordering::Relaxed) }
+}
+
+pub struct Alarm {
+    seen: u64,
+}
+
+impl Future for Alarm {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if ALARMS.load(Ordering::Relaxed) != self.seen {
+            return Poll::Ready(());
+        }
+        ALARM_WAKER.register(cx.waker());
+        if ALARMS.load(Ordering::Relaxed) != self.seen {
+            ALARM_WAKER.take();
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+fn interrupt_handler(_vector: u8) -> IrqReturn {
+    let mut cmos = CMOS.lock();
+    // reading register C acknowledges the interrupt
+    let flags = cmos.read(STATUS_C);
+    if flags & (STATUS_B_PERIODIC | STATUS_B_ALARM | STATUS_B_UPDATE_ENDED) == 0 {
+        return IrqReturn::None;
+    }
+    if flags & STATUS_B_PERIODIC != 0 {
+        PERIODIC.fetch_add(1, Ordering::Relaxed);
+    }
+    if flags & STATUS_B_ALARM != 0 {
+        ALARMS.fetch_add(1, Ordering::Relaxed);
+        ALARM_WAKER.wake();
+    }
+    if flags & STATUS_B_UPDATE_ENDED != 0 {
+        // the registers stay put for almost a second after an update
+        let status_b = cmos.read(STATUS_B);
+        if let Some(time) = cmos.read_raw().decode(status_b) {
+            super::synchronize(&time, false);
+        }
+    }
+    IrqReturn::Handled
+}
+
+#[test_case]
+fn test_decode() {
+    let expected = DateTime::new(2024, 2, 29, 0, 5, 9);
+    let bcd_12_hour = RawTime { second: 0x09, minute: 0x05, hour: 0x12, day: 0x29, month: 0x02, year: 0x24, century: 0x20 };
+    assert_eq!(bcd_12_hour.decode(0), expected);
+    let binary_24_hour = RawTime { second: 9, minute: 5, hour: 0, day: 29, month: 2, year: 24, century: 0 };
+    assert_eq!(binary_24_hour.decode(STATUS_B_BINARY | STATUS_B_24_HOUR), expected);
+    let afternoon = DateTime::new(2024, 2, 29, 13, 5, 9).unwrap();
+    assert_eq!(RawTime::encode(&afternoon, 0).hour, HOUR_PM | 0x01);
+    assert_eq!(RawTime::encode(&afternoon, 0).decode(0), Some(afternoon));
+}
@@ -0,0 +1,226 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::paging::{
+    mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+use crate::interrupts::{self, irq::{self, IrqReturn}, PIC_1_OFFSET};
+use super::clocksource::{self, ClockSource, Hpet, HPET, HPET_CAPABILITIES, HPET_CONFIGURATION, TSC};
+use super::Instant;
+
+/// Where the local APIC registers are mapped.
+pub const LAPIC_START: usize = 0x_6666_6667_0000;
+/// The vector the local APIC timer raises.
+pub const LAPIC_TIMER_VECTOR: u8 = 0xef;
+const SPURIOUS_VECTOR: u8 = 0xff;
+const TIMER_IRQ: u8 = 0;
+
+// local APIC registers and bits
+const IA32_APIC_BASE: u32 = 0x1b;
+const IA32_TSC_DEADLINE: u32 = 0x6e0;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_ADDRESS: u64 = 0x000f_ffff_ffff_f000;
+const LAPIC_EOI: usize = 0x0b0;
+const LAPIC_SPURIOUS: usize = 0x0f0;
+const LAPIC_LVT_TIMER: usize = 0x320;
+const LAPIC_LVT_LINT0: usize = 0x350;
+const LAPIC_LVT_LINT1: usize = 0x360;
+const LAPIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+const LVT_TSC_DEADLINE: u32 = 0b10 << 17;
+const LVT_EXTINT: u32 = 0b111 << 8;
+const LVT_NMI: u32 = 0b100 << 8;
+const CPUID_FEATURES: u32 = 1;
+const CPUID_TSC_DEADLINE: u32 = 1 << 24;
+
+// HPET timer 0 and the legacy replacement route sending it to IRQ 0
+const HPET_LEGACY_ROUTE_CAPABLE: u64 = 1 << 15;
+const HPET_LEGACY_ROUTE: u64 = 1 << 1;
+const HPET_TIMER0_CONFIGURATION: usize = 0x100;
+const HPET_TIMER0_COMPARATOR: usize = 0x108;
+const HPET_TIMER_INTERRUPT: u64 = 1 << 2;
+const HPET_TIMER_PERIODIC: u64 = 1 << 3;
+const HPET_TIMER_32_BIT: u64 = 1 << 8;
+
+/// A timer raising one interrupt at a programmed deadline.
+pub trait EventTimer: Sync {
+    fn name(&self) -> &'static str;
+    /// The interrupt vector of the timer.
+    fn vector(&self) -> u8;
+    /// Programs the interrupt for `deadline`. Returns false when the
+    /// deadline passed before the timer could be armed, no interrupt comes
+    /// then.
+    fn arm(&self, deadline: Instant) -> bool;
+    fn disarm(&self);
+}
+
+#[derive(Debug)]
+pub enum TicklessError {
+    /// Neither a TSC-deadline local APIC nor an HPET with legacy routing.
+    NoEventTimer,
+    /// The clocksource counts timer ticks, it would stop without them.
+    PeriodicClocksource,
+    Map(MapToError<Size4KiB>),
+}
+
+impl From<MapToError<Size4KiB>> for TicklessError {
+    fn from(error: MapToError<Size4KiB>) -> Self {
+        TicklessError::Map(error)
+    }
+}
+
+// the value `source` will have at `deadline`
+fn counter_at(source: &dyn ClockSource, deadline: Instant) -> u64 {
+    let wait = deadline.duration_since(Instant::now()).as_nanos();
+    // far away deadlines become one the counter never reaches
+    let counts = u64::try_from(wait * source.frequency() as u128 / 1_000_000_000).unwrap_or(u64::MAX);
+    source.read().saturating_add(counts)
+}
+
+/// The local APIC timer in TSC-deadline mode.
+pub struct LapicDeadline;
+
+fn lapic_register(offset: usize) -> *mut u32 {
+    (LAPIC_START + offset) as *mut u32
+}
+
+impl EventTimer for LapicDeadline {
+    fn name(&self) -> &'static str {
+        "lapic-tsc-deadline"
+    }
+
+    fn vector(&self) -> u8 {
+        LAPIC_TIMER_VECTOR
+    }
+
+    fn arm(&self, deadline: Instant) -> bool {
+        // a deadline in the past fires right away, 0 would disarm
+        let tsc = counter_at(&TSC, deadline).max(1);
+        unsafe { Msr::new(IA32_TSC_DEADLINE).write(tsc) };
+        true
+    }
+
+    fn disarm(&self) {
+        unsafe { Msr::new(IA32_TSC_DEADLINE).write(0) };
+    }
+}
+
+/// Comparator 0 of the HPET in one-shot mode.
+pub struct HpetComparator;
+
+impl HpetComparator {
+    fn set_interrupt(&self, enabled: bool) {
+        unsafe {
+            let configuration = Hpet::register(HPET_TIMER0_CONFIGURATION);
+            let value = configuration.read_volatile();
+            let value = if enabled { value | HPET_TIMER_INTERRUPT } else { value & !HPET_TIMER_INTERRUPT };
+            configuration.write_volatile(value);
+        }
+    }
+}
+
+impl EventTimer for HpetComparator {
+    fn name(&self) -> &'static str {
+        "hpet-comparator"
+    }
+
+    fn vector(&self) -> u8 {
+        PIC_1_OFFSET + TIMER_IRQ
+    }
+
+    fn arm(&self, deadline: Instant) -> bool {
+        let comparator = counter_at(&HPET, deadline);
+        unsafe { Hpet::register(HPET_TIMER0_COMPARATOR).write_volatile(comparator) };
+        self.set_interrupt(true);
+        // the comparator only matches while the counter passes it
+        HPET.read() < comparator
+    }
+
+    fn disarm(&self) {
+        self.set_interrupt(false);
+    }
+}
+
+pub static LAPIC_DEADLINE: LapicDeadline = LapicDeadline;
+pub static HPET_COMPARATOR: HpetComparator = HpetComparator;
+static EVENT_TIMERS: [&dyn EventTimer; 2] = [&LAPIC_DEADLINE, &HPET_COMPARATOR];
+// index into EVENT_TIMERS plus one, 0 while ticking periodically
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// The timer the idle loop programs, `None` with the periodic tick.
+pub fn event_timer() -> Option<&'static dyn EventTimer> {
+    match ACTIVE.load(Ordering::Relaxed) {
+        0 => None,
+        active => Some(EVENT_TIMERS[active - 1]),
+    }
+}
+
+fn lapic_timer_handler(_vector: u8) -> IrqReturn {
+    super::tick();
+    unsafe { lapic_register(LAPIC_EOI).write_volatile(0) };
+    IrqReturn::Handled
+}
+
+fn init_lapic(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), TicklessError> {
+    let mut apic_base = Msr::new(IA32_APIC_BASE);
+    let base = unsafe { apic_base.read() };
+    if base & APIC_BASE_ENABLE == 0 {
+        unsafe { apic_base.write(base | APIC_BASE_ENABLE) };
+    }
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+    let page = Page::containing_address(VirtAddr::new(LAPIC_START as u64));
+    let frame = PhysFrame::containing_address(PhysAddr::new(base & APIC_BASE_ADDRESS));
+    unsafe { mapper.map_to(page, frame, flags, frame_allocator)? }.flush();
+
+    irq::register(LAPIC_TIMER_VECTOR, "lapic timer", 0, lapic_timer_handler).expect("lapic timer handler");
+    unsafe {
+        lapic_register(LAPIC_SPURIOUS).write_volatile(LAPIC_SOFTWARE_ENABLE | SPURIOUS_VECTOR as u32);
+        // the PICs keep delivering through LINT0 in virtual wire mode
+        lapic_register(LAPIC_LVT_LINT0).write_volatile(LVT_EXTINT);
+        lapic_register(LAPIC_LVT_LINT1).write_volatile(LVT_NMI);
+        lapic_register(LAPIC_LVT_TIMER).write_volatile(LVT_TSC_DEADLINE | LAPIC_TIMER_VECTOR as u32);
+    }
+    // the PIT is not needed anymore
+    interrupts::disable_irq(TIMER_IRQ);
+    Ok(())
+}
+
+// with the legacy route comparator 0 replaces the PIT on IRQ 0 and
+// comparator 1 the RTC on IRQ 8, so RTC interrupts stop
+fn init_hpet_comparator() {
+    unsafe {
+        let timer = Hpet::register(HPET_TIMER0_CONFIGURATION);
+        let value = timer.read_volatile();
+        timer.write_volatile(value & !(HPET_TIMER_INTERRUPT | HPET_TIMER_PERIODIC | HPET_TIMER_32_BIT));
+        let configuration = Hpet::register(HPET_CONFIGURATION);
+        configuration.write_volatile(configuration.read_volatile() | HPET_LEGACY_ROUTE);
+    }
+}
+
+/// Stops the periodic tick and lets the idle loop program a one-shot
+/// timer for the next deadline instead. Prefers the local APIC in
+/// TSC-deadline mode over the HPET. Call after `clocksource::init`.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<&'static dyn EventTimer, TicklessError> {
+    if clocksource::counts_ticks() {
+        return Err(TicklessError::PeriodicClocksource);
+    }
+    let tsc_deadline = clocksource::cpuid(CPUID_FEATURES).1 & CPUID_TSC_DEADLINE != 0;
+    let legacy_route = HPET.frequency() != 0
+        && unsafe { Hpet::register(HPET_CAPABILITIES).read_volatile() } & HPET_LEGACY_ROUTE_CAPABLE != 0;
+    let active = if tsc_deadline && TSC.frequency() != 0 {
+        init_lapic(mapper, frame_allocator)?;
+        1
+    } else if legacy_route {
+        init_hpet_comparator();
+        2
+    } else {
+        return Err(TicklessError::NoEventTimer);
+    };
+    ACTIVE.store(active, Ordering::Relaxed);
+    Ok(EVENT_TIMERS[active - 1])
+}
@@ -0,0 +1,99 @@
+use alloc::collections::BTreeMap;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use super::Instant;
+
+// pending sleeps by deadline, the id tells apart equal deadlines
+static TIMERS: Mutex<BTreeMap<(Instant, u64), Waker>> = Mutex::new(BTreeMap::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static EXPIRING: AtomicBool = AtomicBool::new(false);
+
+/// Completes once `duration` has passed. Durations too long for the
+/// clock never complete.
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(Instant::now().checked_add(duration).unwrap_or(Instant::FAR_FUTURE))
+}
+
+/// Completes once `deadline` has passed.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep {
+        deadline,
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        registered: false,
+    }
+}
+
+pub struct Sleep {
+    deadline: Instant,
+    id: u64,
+    registered: bool,
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        let key = (self.deadline, self.id);
+        // the idle loop reads the timers with interrupts disabled
+        interrupts::without_interrupts(|| TIMERS.lock().insert(key, cx.waker().clone()));
+        self.registered = true;
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if self.registered {
+            let key = (self.deadline, self.id);
+            interrupts::without_interrupts(|| TIMERS.lock().remove(&key));
+        }
+    }
+}
+
+/// The earliest deadline of a pending sleep.
+pub fn next_deadline() -> Option<Instant> {
+    interrupts::without_interrupts(|| TIMERS.lock().keys().next().map(|(deadline, _)| *deadline))
+}
+
+/// Wakes the tasks whose sleeps are over, called by the executor between
+/// polls. Not from interrupt handlers, dropping wakers may free memory.
+pub fn wake_expired() {
+    let now = Instant::now();
+    loop {
+        let expired = interrupts::without_interrupts(|| {
+            let mut timers = TIMERS.lock();
+            match timers.first_key_value() {
+                Some((&(deadline, _), _)) if deadline <= now => timers.pop_first(),
+                _ => None,
+            }
+        });
+        match expired {
+            Some((_, waker)) => {
+                EXPIRING.store(true, Ordering::Relaxed);
+                waker.wake();
+                EXPIRING.store(false, Ordering::Relaxed);
+            }
+            None => break,
+        }
+    }
+}
+
+/// Whether `wake_expired` is waking a task right now, so wakers can tell
+/// timers from other wakeups.
+pub fn expiring() -> bool {
+    EXPIRING.load(Ordering::Relaxed)
+}
@@ -0,0 +1,22 @@
+// little endian helpers for on-disk and firmware structures, `offset` is
+// in bytes and must leave room for the whole value
+
+pub(crate) fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+pub(crate) fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+pub(crate) fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from(read_u32(bytes, offset)) | u64::from(read_u32(bytes, offset + 4)) << 32
+}
+
+#[test_case]
+fn test_read_le() {
+    let bytes = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09];
+    assert_eq!(read_u16(&bytes, 1), 0x0302);
+    assert_eq!(read_u32(&bytes, 0), 0x0403_0201);
+    assert_eq!(read_u64(&bytes, 1), 0x0908_0706_0504_0302);
+}
@@ -0,0 +1,68 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::time::Duration;
+use os::time::clocksource::{self, ClockSource, HPET};
+use os::time::{self, Instant};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+    os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    os::acpi::init(phys_mem_offset).expect("ACPI initialization failed");
+    clocksource::init(&mut mapper, &mut frame_allocator);
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+#[test_case]
+fn qemu_has_an_hpet() {
+    assert!(os::acpi::find_table(b"HPET").is_some());
+    // QEMU's HPET runs at 100 MHz
+    assert_eq!(HPET.frequency(), 100_000_000);
+    let before = HPET.read();
+    assert!(HPET.read() > before);
+}
+
+#[test_case]
+fn instant_agrees_with_timer_ticks() {
+    assert_ne!(clocksource::current().name(), "pit");
+    // wait for a tick edge so the measurement spans whole ticks
+    let first = time::ticks();
+    while time::ticks() == first {
+        x86_64::instructions::hlt();
+    }
+    let start = Instant::now();
+    let start_tick = time::ticks();
+    while time::ticks() < start_tick + 5 {
+        x86_64::instructions::hlt();
+    }
+    let elapsed = start.elapsed();
+    // five ticks of 10 ms, give or take one
+    assert!(elapsed > Duration::from_millis(40) && elapsed < Duration::from_millis(60), "{:?}", elapsed);
+}
+
+#[test_case]
+fn instant_arithmetic() {
+    let now = Instant::now();
+    let later = now + Duration::from_micros(1500);
+    assert_eq!(later - now, Duration::from_micros(1500));
+    assert_eq!(now - later, Duration::ZERO);
+    assert_eq!(later - Duration::from_micros(1500), now);
+    assert!(Instant::now() >= now);
+}
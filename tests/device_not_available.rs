@@ -0,0 +1,30 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use os::interrupts::exceptions::{Exception};
+use os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use x86_64::registers::control::{Cr0, Cr0Flags};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("device_not_available::device_not_available...\t");
+    os::gdt::init();
+    os::interrupts::init_idt();
+    // x87 instructions fault while the task switched flag is set
+    unsafe {
+        Cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR);
+            flags.insert(Cr0Flags::TASK_SWITCHED);
+        });
+        core::arch::asm!("fnop");
+    }
+    serial_println!("[no exception]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_exception_panic_handler(Exception::DeviceNotAvailable, info)
+}
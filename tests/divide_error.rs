@@ -0,0 +1,25 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use os::interrupts::exceptions::{Exception};
+use os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("divide_error::divide_error...\t");
+    os::gdt::init();
+    os::interrupts::init_idt();
+    // dividing by a zero register
+    unsafe {
+        core::arch::asm!("div {0:e}", in(reg) 0u32, inout("eax") 1u32 => _, inout("edx") 0u32 => _);
+    }
+    serial_println!("[no exception]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_exception_panic_handler(Exception::DivideError, info)
+}
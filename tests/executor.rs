@@ -0,0 +1,226 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{rc::Rc, string::String, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::cell::{Cell, RefCell};
+use core::future::{self, Future};
+use core::panic::PanicInfo;
+use core::pin::{pin, Pin};
+use core::task::{Context, Poll};
+use core::time::Duration;
+use futures_util::task::noop_waker_ref;
+use os::task::executor::{self, Executor, QueuePolicy, Spawner, WakeReason};
+use os::task::{consume_budget, JoinError, JoinHandle, Priority, Task};
+use os::time::Instant;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use os::allocator;
+    use os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+    os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("Heap Initialization Failed!");
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+// wakes itself `wakes` times in a row before yielding once
+struct YieldNow {
+    yielded: bool,
+    wakes: usize,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+        self.yielded = true;
+        for _ in 0..self.wakes {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
+
+fn yield_now(wakes: usize) -> YieldNow {
+    YieldNow { yielded: false, wakes }
+}
+
+fn output<T>(handle: JoinHandle<T>) -> Result<T, JoinError> {
+    let mut cx = Context::from_waker(noop_waker_ref());
+    match pin!(handle).poll(&mut cx) {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("task did not complete"),
+    }
+}
+
+#[test_case]
+fn thousands_of_tasks_grow_the_queue() {
+    const TASKS: usize = 5000;
+    let mut executor = Executor::new();
+    let handles: Vec<_> = (0..TASKS)
+        .map(|index| {
+            executor.spawn(Task::new(async move {
+                yield_now(3).await;
+                yield_now(1).await;
+                index
+            }))
+        })
+        .collect();
+    assert!(executor.capacity() >= TASKS);
+    executor.run_until_idle();
+
+    assert!(executor.is_empty());
+    // every task woke itself twice more than needed on its first yield
+    assert_eq!(executor.redundant_wakes(), 2 * TASKS as u64);
+    for (index, handle) in handles.into_iter().enumerate() {
+        assert_eq!(output(handle), Ok(index));
+    }
+}
+
+#[test_case]
+fn drop_policy_cancels_new_tasks() {
+    let mut executor = Executor::with_capacity(2, QueuePolicy::DropWithWarning);
+    let first = executor.spawn(Task::new(future::pending::<()>()));
+    let second = executor.spawn(Task::new(async { 2 }));
+    let dropped = executor.spawn(Task::new(async { 3 }).named("dropped"));
+    assert_eq!(executor.len(), 2);
+    assert_eq!(executor.capacity(), 2);
+    executor.run_until_idle();
+
+    assert_eq!(output(dropped), Err(JoinError::Cancelled));
+    assert_eq!(output(second), Ok(2));
+    assert!(!first.is_finished());
+}
+
+#[test_case]
+fn block_policy_waits_for_room() {
+    let mut executor = Executor::with_capacity(2, QueuePolicy::Block);
+    let first = executor.spawn(Task::new(future::pending::<()>()));
+    let second = executor.spawn(Task::new(async { yield_now(1).await; 2 }));
+    // runs the others until the second task completes
+    let third = executor.spawn(Task::new(async { 3 }));
+    assert!(second.is_finished());
+    executor.run_until_idle();
+
+    assert_eq!(output(second), Ok(2));
+    assert_eq!(output(third), Ok(3));
+    assert!(!first.is_finished());
+    assert_eq!(executor.capacity(), 2);
+}
+
+#[test_case]
+fn block_policy_gives_up_without_room() {
+    let mut executor = Executor::with_capacity(1, QueuePolicy::Block);
+    let first = executor.spawn(Task::new(future::pending::<()>()));
+    // the first task never completes, so the second one is dropped
+    let second = executor.spawn(Task::new(async { 2 }).named("blocked"));
+    assert_eq!(output(second), Err(JoinError::Cancelled));
+    assert_eq!(executor.len(), 1);
+    assert!(!first.is_finished());
+}
+
+// a task that spawns a task that does nothing, yields once, then
+// spawns the next of `left` more tasks like itself
+fn chain(spawner: Spawner, done: Rc<Cell<usize>>, left: usize) -> Task<()> {
+    Task::new(async move {
+        spawner.spawn(Task::new(async {}));
+        yield_now(1).await;
+        if left > 0 {
+            spawner.spawn(chain(spawner.clone(), done.clone(), left - 1));
+        }
+        done.set(done.get() + 1);
+    })
+}
+
+#[test_case]
+fn block_policy_does_not_nest_waits() {
+    // with room for one task every spawn blocks, and the task spawning
+    // the next link runs while the one before it waits
+    let mut executor = Executor::with_capacity(1, QueuePolicy::Block);
+    let done = Rc::new(Cell::new(0));
+    executor.spawn(chain(executor.spawner(), done.clone(), 500));
+    executor.run_until_idle();
+    assert_eq!(done.get(), 501);
+    assert!(executor.is_empty());
+}
+
+#[test_case]
+fn rounds_follow_priority_then_deadline() {
+    let order = Rc::new(RefCell::new(Vec::new()));
+    let mut executor = Executor::new();
+    let now = Instant::now();
+    let tasks = [
+        ("normal", Priority::Normal, None),
+        ("late", Priority::Normal, Some(now + Duration::from_secs(2))),
+        ("idle", Priority::Idle, None),
+        ("early", Priority::Normal, Some(now + Duration::from_secs(1))),
+        ("high", Priority::High, None),
+    ];
+    for (name, priority, deadline) in tasks {
+        let order = order.clone();
+        let mut task = Task::new(async move { order.borrow_mut().push(name) }).named(name).priority(priority);
+        if let Some(deadline) = deadline {
+            task = task.deadline(deadline);
+        }
+        executor.spawn(task);
+    }
+    executor.run_until_idle();
+    assert_eq!(*order.borrow(), ["high", "early", "late", "normal", "idle"]);
+}
+
+#[test_case]
+fn budget_makes_busy_tasks_yield() {
+    let consumed = Rc::new(Cell::new(0));
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let mut executor = Executor::new();
+    executor.set_poll_budget(10);
+    let counter = consumed.clone();
+    let busy = executor.spawn(Task::new(async move {
+        for _ in 0..100 {
+            consume_budget().await;
+            counter.set(counter.get() + 1);
+        }
+    }).named("busy"));
+    let (counter, log) = (consumed.clone(), seen.clone());
+    executor.spawn(Task::new(async move {
+        while log.borrow().len() < 5 {
+            log.borrow_mut().push(counter.get());
+            yield_now(1).await;
+        }
+    }).named("watcher"));
+
+    executor.run_until_idle();
+    assert_eq!(output(busy), Ok(()));
+    // the busy task got 10 units of budget per round
+    assert_eq!(*seen.borrow(), [10, 20, 30, 40, 50]);
+
+    let _spinning = executor.spawn(Task::new(future::pending::<()>()).named("spinning"));
+    executor.run_until_idle();
+    let info = executor.task_info();
+    assert_eq!(info.len(), 1);
+    assert_eq!(info[0].name, "spinning");
+    assert_eq!(info[0].statistics.polls, 1);
+    assert_eq!(info[0].last_wake, WakeReason::Spawn);
+    let mut top = String::new();
+    executor::write_top(&info, &mut top).unwrap();
+    assert!(top.contains("spinning"));
+}
@@ -0,0 +1,243 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use os::block::{ramdisk::RamDisk, BlockDevice};
+use os::fs::ext2::{Ext2, Inconsistency};
+use os::fs::{FileSystem, FileType, FsError};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use os::allocator;
+    use os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+    os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("Heap Initialization Failed!");
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+// a single group volume with 1 KiB blocks: superblock in block 1, group
+// descriptors in 2, bitmaps in 3 and 4, inode table in 5..=8 and the
+// root directory in 9
+const BLOCK_SIZE: usize = 1024;
+const BLOCKS: u32 = 32;
+const INODES: u32 = 32;
+const FREE_BLOCKS: u32 = 22;
+const FREE_INODES: u32 = 22;
+
+fn put_u16(image: &mut [u8], offset: usize, value: u16) {
+    image[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(image: &mut [u8], offset: usize, value: u32) {
+    image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+// builds the structures `mke2fs -t ext2 -b 1024 -N 32` would write
+fn build_image() -> Arc<RamDisk> {
+    let mut image = vec![0u8; BLOCKS as usize * BLOCK_SIZE];
+    let sb = BLOCK_SIZE;
+    put_u32(&mut image, sb, INODES);
+    put_u32(&mut image, sb + 4, BLOCKS);
+    put_u32(&mut image, sb + 12, FREE_BLOCKS);
+    put_u32(&mut image, sb + 16, FREE_INODES);
+    put_u32(&mut image, sb + 20, 1);
+    put_u32(&mut image, sb + 32, 8192);
+    put_u32(&mut image, sb + 36, 8192);
+    put_u32(&mut image, sb + 40, INODES);
+    put_u16(&mut image, sb + 56, 0xef53);
+    put_u16(&mut image, sb + 58, 1);
+    put_u16(&mut image, sb + 60, 1);
+    put_u32(&mut image, sb + 76, 1);
+    put_u32(&mut image, sb + 84, 11);
+    put_u16(&mut image, sb + 88, 128);
+    put_u32(&mut image, sb + 96, 0x0002);
+
+    let gd = 2 * BLOCK_SIZE;
+    put_u32(&mut image, gd, 3);
+    put_u32(&mut image, gd + 4, 4);
+    put_u32(&mut image, gd + 8, 5);
+    put_u16(&mut image, gd + 12, FREE_BLOCKS as u16);
+    put_u16(&mut image, gd + 14, FREE_INODES as u16);
+    put_u16(&mut image, gd + 16, 1);
+
+    // blocks 1..=9 and inodes 1..=10 are in use, bits past the end of
+    // the group are padding and stay set
+    image[3 * BLOCK_SIZE..5 * BLOCK_SIZE].fill(0xff);
+    for bit in 9..BLOCKS as usize - 1 {
+        image[3 * BLOCK_SIZE + bit / 8] &= !(1 << (bit % 8));
+    }
+    for bit in 10..INODES as usize {
+        image[4 * BLOCK_SIZE + bit / 8] &= !(1 << (bit % 8));
+    }
+
+    let root = 5 * BLOCK_SIZE + 128;
+    put_u16(&mut image, root, 0x41ed);
+    put_u32(&mut image, root + 4, BLOCK_SIZE as u32);
+    put_u16(&mut image, root + 26, 2);
+    put_u32(&mut image, root + 28, 2);
+    put_u32(&mut image, root + 40, 9);
+
+    let dir = 9 * BLOCK_SIZE;
+    put_u32(&mut image, dir, 2);
+    put_u16(&mut image, dir + 4, 12);
+    image[dir + 6] = 1;
+    image[dir + 7] = 2;
+    image[dir + 8] = b'.';
+    put_u32(&mut image, dir + 12, 2);
+    put_u16(&mut image, dir + 16, (BLOCK_SIZE - 12) as u16);
+    image[dir + 18] = 2;
+    image[dir + 19] = 2;
+    image[dir + 20..dir + 22].copy_from_slice(b"..");
+    Arc::new(RamDisk::from_vec(image))
+}
+
+fn mount(disk: &Arc<RamDisk>) -> Ext2<Arc<RamDisk>> {
+    Ext2::mount(disk.clone()).expect("mount failed")
+}
+
+#[test_case]
+fn mount_clean_image() {
+    let fs = mount(&build_image());
+    assert_eq!(fs.superblock().block_size(), BLOCK_SIZE);
+    assert_eq!(fs.superblock().group_count(), 1);
+    assert!(fs.check_report().is_empty());
+    assert!(fs.read_dir("/").unwrap().is_empty());
+}
+
+#[test_case]
+fn write_and_read_file() {
+    let mut fs = mount(&build_image());
+    fs.write_file("/notes.txt", b"ext2 on ThesisOS").unwrap();
+    assert_eq!(fs.read_file("/notes.txt").unwrap(), b"ext2 on ThesisOS");
+    assert_eq!(fs.superblock().free_inodes_count, FREE_INODES - 1);
+    assert_eq!(fs.superblock().free_blocks_count, FREE_BLOCKS - 1);
+
+    fs.write_file("/notes.txt", b"rewritten").unwrap();
+    assert_eq!(fs.read_file("/notes.txt").unwrap(), b"rewritten");
+    assert_eq!(fs.superblock().free_blocks_count, FREE_BLOCKS - 1);
+}
+
+#[test_case]
+fn indirect_blocks() {
+    let mut fs = mount(&build_image());
+    // 13 data blocks need the single indirect block as well
+    let data: Vec<u8> = (0..13 * BLOCK_SIZE as u32).map(|i| (i % 251) as u8).collect();
+    fs.write_file("/big.bin", &data).unwrap();
+    assert_eq!(fs.superblock().free_blocks_count, FREE_BLOCKS - 14);
+    assert_eq!(fs.read_file("/big.bin").unwrap(), data);
+
+    fs.remove("/big.bin").unwrap();
+    assert_eq!(fs.superblock().free_blocks_count, FREE_BLOCKS);
+    assert_eq!(fs.superblock().free_inodes_count, FREE_INODES);
+}
+
+#[test_case]
+fn failed_rewrite_keeps_old_contents() {
+    let mut fs = mount(&build_image());
+    let old: Vec<u8> = (0..4 * BLOCK_SIZE as u32).map(|i| (i % 251) as u8).collect();
+    fs.write_file("/old.bin", &old).unwrap();
+    // 17 data blocks and their indirect block take the rest of the volume
+    fs.write_file("/fill.bin", &vec![0xaa; 17 * BLOCK_SIZE]).unwrap();
+    assert_eq!(fs.superblock().free_blocks_count, 0);
+
+    assert_eq!(fs.write_file("/old.bin", &vec![0x55; 5 * BLOCK_SIZE]), Err(FsError::NoSpace));
+    assert_eq!(fs.read_file("/old.bin").unwrap(), old);
+    assert_eq!(fs.superblock().free_blocks_count, 0);
+    assert!(fs.check_report().is_empty());
+}
+
+#[test_case]
+fn directories() {
+    let mut fs = mount(&build_image());
+    fs.create_dir("/home").unwrap();
+    fs.create_dir("/home/student").unwrap();
+    fs.write_file("/home/student/thesis.tex", b"\\chapter{Intro}").unwrap();
+
+    let home = fs.read_dir("/home").unwrap();
+    assert_eq!(home.len(), 1);
+    assert_eq!(home[0].file_type, FileType::Directory);
+    assert_eq!(fs.read_file("/home/student/../student/thesis.tex").unwrap(), b"\\chapter{Intro}");
+    assert_eq!(fs.create_dir("/home"), Err(FsError::AlreadyExists));
+    assert_eq!(fs.remove("/home"), Err(FsError::DirectoryNotEmpty));
+    assert_eq!(fs.read_file("/home"), Err(FsError::IsADirectory));
+
+    fs.remove("/home/student/thesis.tex").unwrap();
+    fs.remove("/home/student").unwrap();
+    fs.remove("/home").unwrap();
+    assert_eq!(fs.superblock().free_blocks_count, FREE_BLOCKS);
+    assert_eq!(fs.superblock().free_inodes_count, FREE_INODES);
+}
+
+#[test_case]
+fn symlinks() {
+    let mut fs = mount(&build_image());
+    fs.create_dir("/etc").unwrap();
+    fs.write_file("/etc/motd", b"welcome").unwrap();
+    fs.symlink("/motd", "etc/motd").unwrap();
+    fs.symlink("/etc/self", "/etc").unwrap();
+
+    // a target of 60 bytes or more does not fit into the inode
+    let long_target = "/etc/./././././././././././././././././././././././././././motd";
+    fs.symlink("/long", long_target).unwrap();
+
+    assert_eq!(fs.read_link("/motd").unwrap(), "etc/motd");
+    assert_eq!(fs.read_link("/long").unwrap(), long_target);
+    assert_eq!(fs.read_file("/motd").unwrap(), b"welcome");
+    assert_eq!(fs.read_file("/long").unwrap(), b"welcome");
+    assert_eq!(fs.read_file("/etc/self/self/motd").unwrap(), b"welcome");
+    assert_eq!(fs.read_dir("/").unwrap().iter().filter(|e| e.file_type == FileType::Symlink).count(), 2);
+
+    fs.symlink("/loop", "/loop").unwrap();
+    assert_eq!(fs.read_file("/loop"), Err(FsError::SymlinkLoop));
+}
+
+#[test_case]
+fn mount_repairs_free_counts() {
+    let disk = build_image();
+    // claim more free blocks than the bitmap has
+    disk.write_bytes(BLOCK_SIZE as u64 + 12, &30u32.to_le_bytes()).unwrap();
+    disk.write_bytes(2 * BLOCK_SIZE as u64 + 12, &30u16.to_le_bytes()).unwrap();
+
+    let fs = mount(&disk);
+    assert_eq!(
+        fs.check_report(),
+        [
+            Inconsistency::GroupFreeBlocks { group: 0, recorded: 30, counted: FREE_BLOCKS },
+            Inconsistency::FreeBlocks { recorded: 30, counted: FREE_BLOCKS },
+        ]
+    );
+    assert!(mount(&disk).check_report().is_empty());
+}
+
+#[test_case]
+fn rejects_bad_magic() {
+    let disk = build_image();
+    disk.write_bytes(BLOCK_SIZE as u64 + 56, &[0, 0]).unwrap();
+    assert!(matches!(Ext2::mount(disk), Err(FsError::Corrupted(_))));
+}
+
+#[test_case]
+fn rejects_inode_count_short_of_the_groups() {
+    let disk = build_image();
+    // the only group claims 32 inodes but the volume has one
+    disk.write_bytes(BLOCK_SIZE as u64, &1u32.to_le_bytes()).unwrap();
+    assert!(matches!(Ext2::mount(disk), Err(FsError::Corrupted(_))));
+}
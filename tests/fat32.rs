@@ -0,0 +1,255 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use os::block::{ramdisk::RamDisk, BlockDevice};
+use os::fs::fat32::Fat32;
+use os::fs::mount as mounts;
+use os::fs::{FileSystem, FileType, FsError};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use os::allocator;
+    use os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+    os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("Heap Initialization Failed!");
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+// layout of the test image: 512 byte sectors and clusters, 8 reserved
+// sectors, two one-sector FATs and 40 data clusters
+const TOTAL_SECTORS: usize = 50;
+const RESERVED_SECTORS: usize = 8;
+const DATA_CLUSTERS: u32 = 40;
+
+// builds the same structures `mkfs.vfat -F 32 -s 1` would write
+fn build_image() -> Arc<RamDisk> {
+    let mut image = vec![0u8; TOTAL_SECTORS * 512];
+    {
+        let boot = &mut image[..512];
+        boot[0..3].copy_from_slice(&[0xeb, 0x58, 0x90]);
+        boot[3..11].copy_from_slice(b"THESISOS");
+        boot[11..13].copy_from_slice(&512u16.to_le_bytes());
+        boot[13] = 1;
+        boot[14..16].copy_from_slice(&(RESERVED_SECTORS as u16).to_le_bytes());
+        boot[16] = 2;
+        boot[21] = 0xf8;
+        boot[32..36].copy_from_slice(&(TOTAL_SECTORS as u32).to_le_bytes());
+        boot[36..40].copy_from_slice(&1u32.to_le_bytes());
+        boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+        boot[48..50].copy_from_slice(&1u16.to_le_bytes());
+        boot[50..52].copy_from_slice(&6u16.to_le_bytes());
+        boot[66] = 0x29;
+        boot[71..82].copy_from_slice(b"THESISOS   ");
+        boot[82..90].copy_from_slice(b"FAT32   ");
+        boot[510..512].copy_from_slice(&[0x55, 0xaa]);
+    }
+    {
+        let info = &mut image[512..1024];
+        info[0..4].copy_from_slice(&0x4161_5252u32.to_le_bytes());
+        info[484..488].copy_from_slice(&0x6141_7272u32.to_le_bytes());
+        // every cluster but the root directory is free
+        info[488..492].copy_from_slice(&(DATA_CLUSTERS - 1).to_le_bytes());
+        info[492..496].copy_from_slice(&3u32.to_le_bytes());
+        info[508..512].copy_from_slice(&0xaa55_0000u32.to_le_bytes());
+    }
+    for fat in 0..2 {
+        let start = (RESERVED_SECTORS + fat) * 512;
+        let entries = [0x0fff_fff8u32, 0x0fff_ffff, 0x0fff_ffff];
+        for (i, entry) in entries.iter().enumerate() {
+            image[start + i * 4..start + i * 4 + 4].copy_from_slice(&entry.to_le_bytes());
+        }
+    }
+    Arc::new(RamDisk::from_vec(image))
+}
+
+fn mount(disk: &Arc<RamDisk>) -> Fat32<Arc<RamDisk>> {
+    Fat32::mount(disk.clone()).expect("mount failed")
+}
+
+#[test_case]
+fn mount_reads_bpb() {
+    let fs = mount(&build_image());
+    assert_eq!(fs.bpb().root_cluster, 2);
+    assert_eq!(fs.cluster_size(), 512);
+    assert_eq!(fs.free_clusters(), DATA_CLUSTERS - 1);
+    assert!(fs.read_dir("/").unwrap().is_empty());
+}
+
+#[test_case]
+fn write_and_read_file() {
+    let mut fs = mount(&build_image());
+    fs.write_file("/hello.txt", b"hello from ThesisOS").unwrap();
+    assert_eq!(fs.read_file("/hello.txt").unwrap(), b"hello from ThesisOS");
+    assert_eq!(fs.read_file("/HELLO.TXT").unwrap(), b"hello from ThesisOS");
+
+    let entries = fs.read_dir("/").unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].name, "hello.txt");
+    assert_eq!(entries[0].file_type, FileType::File);
+    assert_eq!(entries[0].size, 19);
+    // FAT keeps two second steps
+    let modified = entries[0].modified.unwrap().to_unix();
+    assert!(modified.abs_diff(os::time::now().unix_seconds()) <= 2);
+}
+
+#[test_case]
+fn multi_cluster_file_and_overwrite() {
+    let mut fs = mount(&build_image());
+    let data: Vec<u8> = (0..2000u32).map(|i| i as u8).collect();
+    fs.write_file("/data.bin", &data).unwrap();
+    assert_eq!(fs.free_clusters(), DATA_CLUSTERS - 1 - 4);
+    assert_eq!(fs.read_file("/data.bin").unwrap(), data);
+
+    // shrinking the file gives clusters back
+    fs.write_file("/data.bin", b"short").unwrap();
+    assert_eq!(fs.free_clusters(), DATA_CLUSTERS - 1 - 1);
+    assert_eq!(fs.read_file("/data.bin").unwrap(), b"short");
+}
+
+#[test_case]
+fn long_file_names() {
+    let mut fs = mount(&build_image());
+    let name = "A rather long file name with spaces.markdown";
+    fs.write_file("/A rather long file name with spaces.markdown", b"lfn").unwrap();
+    fs.write_file("/A rather long file name, second.markdown", b"lfn2").unwrap();
+
+    let entries = fs.read_dir("/").unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].name, name);
+    assert_eq!(fs.read_file("/a rather long file name with spaces.markdown").unwrap(), b"lfn");
+    // the generated 8.3 aliases stay reachable
+    assert_eq!(fs.read_file("/ARATHE~1.MAR").unwrap(), b"lfn");
+    assert_eq!(fs.read_file("/ARATHE~2.MAR").unwrap(), b"lfn2");
+}
+
+#[test_case]
+fn nested_directories() {
+    let mut fs = mount(&build_image());
+    fs.create_dir("/docs").unwrap();
+    fs.create_dir("/docs/thesis").unwrap();
+    fs.write_file("/docs/thesis/chapter1.txt", b"Introduction").unwrap();
+
+    assert_eq!(fs.create_dir("/docs"), Err(FsError::AlreadyExists));
+    assert_eq!(fs.read_dir("/docs").unwrap()[0].file_type, FileType::Directory);
+    assert_eq!(fs.read_file("/docs/thesis/chapter1.txt").unwrap(), b"Introduction");
+    assert_eq!(fs.read_file("/docs/thesis/../thesis/chapter1.txt").unwrap(), b"Introduction");
+    assert_eq!(fs.read_file("/docs/missing.txt"), Err(FsError::NotFound));
+    assert_eq!(fs.read_dir("/docs/thesis/chapter1.txt"), Err(FsError::NotADirectory));
+}
+
+#[test_case]
+fn directory_grows_past_one_cluster() {
+    let mut fs = mount(&build_image());
+    fs.create_dir("/many").unwrap();
+    // 20 entries do not fit next to "." and ".." in a 512 byte cluster
+    for i in 0..20 {
+        let mut name = String::from("/many/file");
+        name.push((b'a' + i) as char);
+        fs.write_file(&name, &[i]).unwrap();
+    }
+    assert_eq!(fs.read_dir("/many").unwrap().len(), 20);
+    assert_eq!(fs.read_file("/many/filet").unwrap(), [19]);
+}
+
+#[test_case]
+fn remove_frees_clusters() {
+    let mut fs = mount(&build_image());
+    fs.create_dir("/tmp").unwrap();
+    fs.write_file("/tmp/scratch", &[0xaa; 1500]).unwrap();
+    assert_eq!(fs.remove("/tmp"), Err(FsError::DirectoryNotEmpty));
+
+    fs.remove("/tmp/scratch").unwrap();
+    assert_eq!(fs.remove("/tmp/."), Err(FsError::InvalidName));
+    assert_eq!(fs.remove("/tmp/.."), Err(FsError::InvalidName));
+    assert!(fs.read_dir("/tmp").unwrap().is_empty());
+    fs.remove("/tmp").unwrap();
+    assert_eq!(fs.free_clusters(), DATA_CLUSTERS - 1);
+    assert!(fs.read_dir("/").unwrap().is_empty());
+}
+
+#[test_case]
+fn out_of_space() {
+    let mut fs = mount(&build_image());
+    let too_big = vec![0u8; DATA_CLUSTERS as usize * 512];
+    assert_eq!(fs.write_file("/big", &too_big), Err(FsError::NoSpace));
+    // the partial allocation was rolled back
+    assert_eq!(fs.free_clusters(), DATA_CLUSTERS - 1);
+}
+
+#[test_case]
+fn failed_overwrite_keeps_old_contents() {
+    let mut fs = mount(&build_image());
+    let old = vec![5u8; 20 * 512];
+    fs.write_file("/keep", &old).unwrap();
+    // the old chain is still in use while the new one is allocated
+    assert_eq!(fs.write_file("/keep", &[6; 20 * 512]), Err(FsError::NoSpace));
+    assert_eq!(fs.read_file("/keep").unwrap(), old);
+    assert_eq!(fs.free_clusters(), DATA_CLUSTERS - 1 - 20);
+}
+
+#[test_case]
+fn long_name_sequence_zero_is_ignored() {
+    let disk = build_image();
+    mount(&disk).write_file("/Long name.txt", b"lfn").unwrap();
+    // the root directory starts with the single long name entry, copy it
+    // over the short entry with a sequence number of 0
+    let root = ((RESERVED_SECTORS + 2) * 512) as u64;
+    let mut entry = [0u8; 32];
+    disk.read_bytes(root, &mut entry).unwrap();
+    entry[0] = 0x40;
+    disk.write_bytes(root + 32, &entry).unwrap();
+    assert!(mount(&disk).read_dir("/").unwrap().is_empty());
+}
+
+#[test_case]
+fn mount_table_resolves_longest_prefix() {
+    let mut data = mount(&build_image());
+    data.write_file("/x", b"on data").unwrap();
+    mounts::mount("/", mount(&build_image())).unwrap();
+    mounts::mount("/data/", data).unwrap();
+    assert_eq!(mounts::mount("/data", mount(&build_image())), Err(FsError::AlreadyExists));
+    assert_eq!(mounts::mount_points(), ["/", "/data"]);
+
+    let (fs, path) = mounts::resolve("/data//x").unwrap();
+    assert_eq!(path, "/x");
+    assert_eq!(fs.lock().read_file(&path).unwrap(), b"on data");
+    let (fs, path) = mounts::resolve("/database").unwrap();
+    assert_eq!(path, "/database");
+    assert_eq!(fs.lock().read_file(&path), Err(FsError::NotFound));
+
+    mounts::unmount("/data").unwrap();
+    mounts::unmount("/").unwrap();
+    assert_eq!(mounts::unmount("/").err(), Some(FsError::NotFound));
+    assert_eq!(mounts::resolve("/data/x").err(), Some(FsError::NotFound));
+}
+
+#[test_case]
+fn fs_info_survives_remount() {
+    let disk = build_image();
+    {
+        let mut fs = mount(&disk);
+        fs.write_file("/keep.txt", &[1; 700]).unwrap();
+    }
+    let fs = mount(&disk);
+    assert_eq!(fs.free_clusters(), DATA_CLUSTERS - 1 - 2);
+    assert_eq!(fs.read_file("/keep.txt").unwrap(), [1; 700]);
+}
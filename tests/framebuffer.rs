@@ -0,0 +1,156 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use os::framebuffer::{console::Console, font::Font, Framebuffer, FramebufferError, Rgb};
+use os::memory::BootInfoFrameAllocator;
+use spin::Mutex;
+use x86_64::structures::paging::OffsetPageTable;
+
+entry_point!(main);
+
+static MEMORY: Mutex<Option<(OffsetPageTable<'static>, BootInfoFrameAllocator)>> = Mutex::new(None);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use os::allocator;
+    use os::memory;
+    use x86_64::VirtAddr;
+    os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("Heap Initialization Failed!");
+    // the last test maps the real framebuffer
+    *MEMORY.lock() = Some((mapper, frame_allocator));
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+const BLACK: Rgb = Rgb::new(0, 0, 0);
+const WHITE: Rgb = Rgb::new(0xff, 0xff, 0xff);
+
+// a framebuffer on the heap, leaked since it has to outlive the test
+fn memory_framebuffer(width: usize, height: usize) -> Framebuffer {
+    let pixels: &'static mut [u32] = Vec::leak(vec![0u32; width * height]);
+    unsafe { Framebuffer::new(pixels.as_mut_ptr(), width, height, width) }
+}
+
+#[test_case]
+fn test_builtin_font() {
+    let font = Font::builtin();
+    assert_eq!((font.width(), font.height()), (8, 16));
+    let a = font.glyph('A').expect("no glyph for A");
+    assert_eq!(a.len(), 16);
+    assert!(a.iter().any(|&row| row != 0));
+    assert!(font.glyph(' ').unwrap().iter().all(|&row| row == 0));
+    // code page 437 and Latin-1 characters are there too
+    assert!(font.glyph('─').is_some());
+    assert!(font.glyph('é').is_some());
+    assert!(font.glyph('\u{10ffff}').is_none());
+    assert_eq!(font.glyph_or_replacement('\u{10ffff}').len(), 16);
+}
+
+#[test_case]
+fn test_drawing() {
+    let mut framebuffer = memory_framebuffer(32, 16);
+    framebuffer.fill_rect(2, 2, 4, 3, WHITE);
+    assert_eq!(framebuffer.pixel(2, 2), Some(WHITE));
+    assert_eq!(framebuffer.pixel(5, 4), Some(WHITE));
+    assert_eq!(framebuffer.pixel(6, 4), Some(BLACK));
+    assert_eq!(framebuffer.pixel(32, 0), None);
+
+    // lines are clipped to the framebuffer
+    framebuffer.clear(BLACK);
+    framebuffer.draw_line((-4, -4), (40, 40), WHITE);
+    for i in 0..16 {
+        assert_eq!(framebuffer.pixel(i, i), Some(WHITE));
+    }
+    assert_eq!(framebuffer.pixel(1, 0), Some(BLACK));
+
+    framebuffer.clear(BLACK);
+    framebuffer.draw_rect(0, 0, 4, 4, WHITE);
+    assert_eq!(framebuffer.pixel(3, 3), Some(WHITE));
+    assert_eq!(framebuffer.pixel(1, 1), Some(BLACK));
+
+    framebuffer.blit(30, 0, 4, &[WHITE; 8]);
+    assert_eq!(framebuffer.pixel(31, 1), Some(WHITE));
+}
+
+#[test_case]
+fn test_console_output() {
+    let font = Font::builtin();
+    let glyph = font.glyph('H').unwrap();
+    let mut console = Console::new(memory_framebuffer(80, 48), Font::builtin()).unwrap();
+    assert_eq!(console.size(), (10, 3));
+
+    console.write_string("\x1b[31;44mH\x1b[m");
+    let red = Rgb::new(0xaa, 0, 0);
+    let blue = Rgb::new(0, 0, 0xaa);
+    for (row, bits) in glyph.iter().enumerate() {
+        for col in 0..8 {
+            let expected = if bits & (0x80 >> col) != 0 { red } else { blue };
+            assert_eq!(console.framebuffer().pixel(col, row), Some(expected));
+        }
+    }
+    assert_eq!(console.position(), (0, 1));
+
+    // the cursor moves and the screen scrolls like in text mode
+    console.write_string("\x1b[3;1Hbottom\n");
+    assert_eq!(console.position(), (2, 0));
+    let background = console.framebuffer().pixel(0, 32);
+    assert_eq!(background, Some(BLACK));
+    let second_row: Vec<Option<Rgb>> = (0..8).map(|x| console.framebuffer().pixel(x, 16 + 8)).collect();
+    assert!(second_row.contains(&Some(Rgb::new(0, 0xaa, 0))));
+}
+
+#[test_case]
+fn test_console_escapes() {
+    let mut console = Console::new(memory_framebuffer(80, 48), Font::builtin()).unwrap();
+    // the cursor is an underline in the last two pixel rows of its cell
+    let green = Rgb::new(0, 0xaa, 0);
+    assert_eq!(console.framebuffer().pixel(0, 15), Some(green));
+    console.write_string("\x1b[?25l");
+    assert_eq!(console.framebuffer().pixel(0, 15), Some(BLACK));
+    console.write_string("\x1b[?25h");
+    assert_eq!(console.framebuffer().pixel(0, 15), Some(green));
+
+    console.write_string("\x1b[2;5H\x1b[s\x1b[H\x1b[u");
+    assert_eq!(console.position(), (1, 4));
+    // only the top two rows scroll
+    console.write_string("\x1b[1;2ra\nb\nc");
+    assert_eq!(console.position(), (1, 1));
+    console.write_string("\x1bc");
+    assert_eq!(console.position(), (0, 0));
+    console.write_string("\n\n\n");
+    assert_eq!(console.position(), (2, 0));
+}
+
+#[test_case]
+fn test_console_needs_one_glyph() {
+    let framebuffer = memory_framebuffer(80, 8);
+    assert!(matches!(Console::new(framebuffer, Font::builtin()), Err(FramebufferError::InvalidMode)));
+}
+
+#[test_case]
+fn test_qemu_framebuffer() {
+    let mut memory = MEMORY.lock();
+    let (mapper, frame_allocator) = memory.as_mut().unwrap();
+    os::framebuffer::init(mapper, frame_allocator, 640, 480).expect("no framebuffer");
+    os::println!("test_qemu_framebuffer output");
+    let console = os::framebuffer::console::disable().unwrap();
+    assert_eq!(console.size(), (80, 30));
+    assert_eq!(console.position(), (1, 0));
+    os::framebuffer::bga::disable();
+}
@@ -0,0 +1,27 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use os::interrupts::exceptions::{self, Exception};
+use os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("general_protection_fault::general_protection_fault...\t");
+    os::gdt::init();
+    os::interrupts::init_idt();
+    // selector 0x68 is entry 13 of the GDT, far past its end
+    unsafe { core::arch::asm!("mov ds, {0:x}", in(reg) 0x68u16) };
+    serial_println!("[no exception]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let selector = exceptions::last_fault().and_then(|report| report.selector_error());
+    if selector.map(|selector| selector.index) != Some(13) {
+        os::test_panic_handler(info);
+    }
+    os::test_exception_panic_handler(Exception::GeneralProtectionFault, info)
+}
@@ -0,0 +1,88 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures_util::stream::Stream;
+use futures_util::task::noop_waker_ref;
+use os::task::keyboard::{self, KeyEvent, KeyEventStream};
+use os::task::layout::Modifiers;
+use pc_keyboard::{DecodedKey, KeyCode, KeyState};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use os::allocator;
+    use os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+    os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("Heap Initialization Failed!");
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+fn key(c: char) -> KeyEvent {
+    KeyEvent {
+        code: KeyCode::A,
+        state: KeyState::Down,
+        modifiers: Modifiers::default(),
+        key: Some(DecodedKey::Unicode(c)),
+    }
+}
+
+fn next(stream: &mut KeyEventStream) -> Option<KeyEvent> {
+    let mut cx = Context::from_waker(noop_waker_ref());
+    match Pin::new(stream).poll_next(&mut cx) {
+        Poll::Ready(event) => event,
+        Poll::Pending => None,
+    }
+}
+
+#[test_case]
+fn every_subscriber_gets_the_events() {
+    let mut first = keyboard::subscribe();
+    keyboard::publish(key('a'));
+    let mut second = keyboard::subscribe();
+    keyboard::publish(key('b'));
+
+    assert_eq!(next(&mut first), Some(key('a')));
+    assert_eq!(next(&mut first), Some(key('b')));
+    assert_eq!(next(&mut first), None);
+    // events from before subscribing are not replayed
+    assert_eq!(next(&mut second), Some(key('b')));
+    assert_eq!(next(&mut second), None);
+
+    drop(first);
+    keyboard::publish(key('c'));
+    assert_eq!(next(&mut second), Some(key('c')));
+}
+
+#[test_case]
+fn slow_subscriber_loses_oldest_events() {
+    let mut stream = keyboard::subscribe();
+    // one more than a subscriber queues
+    for offset in 0..65 {
+        keyboard::publish(key(char::from_u32('a' as u32 + offset).unwrap()));
+    }
+    assert_eq!(next(&mut stream), Some(key('b')));
+    let mut rest = 1;
+    while next(&mut stream).is_some() {
+        rest += 1;
+    }
+    assert_eq!(rest, 64);
+}
@@ -0,0 +1,79 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use os::sync::{lockdep, IrqSpinlock};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    os::init();
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+static FIRST: IrqSpinlock<u32> = IrqSpinlock::new("first", 0);
+static SECOND: IrqSpinlock<u32> = IrqSpinlock::new("second", 0);
+
+#[test_case]
+fn consistent_order_is_fine() {
+    for _ in 0..2 {
+        let _first = FIRST.lock();
+        let _second = SECOND.lock();
+    }
+    assert_eq!(lockdep::inversions().count(), 0);
+}
+
+#[test_case]
+fn inversion_is_reported_once() {
+    for _ in 0..2 {
+        let _second = SECOND.lock();
+        let _first = FIRST.lock();
+    }
+    let inversion = lockdep::inversions().next().unwrap();
+    assert_eq!(lockdep::inversions().count(), 1);
+    assert_eq!((inversion.first, inversion.second), ("second", "first"));
+    assert_eq!(inversion.location.file(), file!());
+
+    let mut report = Report { bytes: [0; 512], len: 0 };
+    {
+        let _first = FIRST.lock();
+        lockdep::write_report(&mut report).unwrap();
+    }
+    assert!(report.contains("lock order inversion: second then first"));
+    assert!(report.contains("held: first since"));
+}
+
+// a fixed buffer, the test runs without a heap
+struct Report {
+    bytes: [u8; 512],
+    len: usize,
+}
+
+impl Report {
+    fn contains(&self, text: &str) -> bool {
+        let report = core::str::from_utf8(&self.bytes[..self.len]).unwrap();
+        report.contains(text)
+    }
+}
+
+impl core::fmt::Write for Report {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let end = self.len + s.len();
+        if end > self.bytes.len() {
+            return Err(core::fmt::Error);
+        }
+        self.bytes[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
@@ -0,0 +1,27 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use os::interrupts::exceptions::{self, Exception};
+use os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("page_fault::page_fault...\t");
+    os::gdt::init();
+    os::interrupts::init_idt();
+    // nothing is mapped there
+    unsafe { core::ptr::write_volatile(0xdead_beef_0000 as *mut u64, 42) };
+    serial_println!("[no exception]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let address = exceptions::last_fault().and_then(|report| report.fault_address);
+    if address.map(|address| address.as_u64()) != Some(0xdead_beef_0000) {
+        os::test_panic_handler(info);
+    }
+    os::test_exception_panic_handler(Exception::PageFault, info)
+}
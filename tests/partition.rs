@@ -0,0 +1,218 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{format, string::String, sync::Arc, vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use os::block::partition::{
+    crc32, open_partitions, Guid, Partition, PartitionError, PartitionKind, PartitionTable, TableKind,
+};
+use os::block::{ramdisk::RamDisk, BlockDevice, BlockError};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use os::allocator;
+    use os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+    os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("Heap Initialization Failed!");
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+const SECTORS: usize = 64;
+// 0FC63DAF-8483-4772-8E79-3D69D8477DE4, the GPT type of Linux filesystems
+const LINUX_FS: [u8; 16] = [
+    0xaf, 0x3d, 0xc6, 0x0f, 0x83, 0x84, 0x72, 0x47, 0x8e, 0x79, 0x3d, 0x69, 0xd8, 0x47, 0x7d, 0xe4,
+];
+
+fn put_u32(image: &mut [u8], offset: usize, value: u32) {
+    image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(image: &mut [u8], offset: usize, value: u64) {
+    image[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+fn mbr_entry(sector: &mut [u8], slot: usize, system_id: u8, start: u32, sectors: u32) {
+    let entry = 446 + slot * 16;
+    sector[entry + 4] = system_id;
+    put_u32(sector, entry + 8, start);
+    put_u32(sector, entry + 12, sectors);
+    sector[510] = 0x55;
+    sector[511] = 0xaa;
+}
+
+// two primaries (one of them bootable) and an extended partition at
+// sector 20 holding logical partitions at 22..30 and 32..40
+fn build_mbr_disk() -> Arc<RamDisk> {
+    let mut image = vec![0u8; SECTORS * 512];
+    mbr_entry(&mut image[..512], 0, 0x83, 2, 8);
+    image[446] = 0x80;
+    mbr_entry(&mut image[..512], 1, 0x0c, 10, 10);
+    mbr_entry(&mut image[..512], 2, 0x0f, 20, 40);
+
+    let ebr = 20 * 512;
+    mbr_entry(&mut image[ebr..ebr + 512], 0, 0x83, 2, 8);
+    mbr_entry(&mut image[ebr..ebr + 512], 1, 0x05, 10, 10);
+    let ebr = 30 * 512;
+    mbr_entry(&mut image[ebr..ebr + 512], 0, 0x82, 2, 8);
+    Arc::new(RamDisk::from_vec(image))
+}
+
+fn gpt_header(image: &mut [u8], lba: u64, alternate: u64, entries_lba: u64, entries_crc: u32) {
+    let offset = lba as usize * 512;
+    let header = &mut image[offset..offset + 512];
+    header[0..8].copy_from_slice(b"EFI PART");
+    put_u32(header, 8, 0x0001_0000);
+    put_u32(header, 12, 92);
+    put_u64(header, 24, lba);
+    put_u64(header, 32, alternate);
+    put_u64(header, 40, 3);
+    put_u64(header, 48, SECTORS as u64 - 3);
+    header[56..72].copy_from_slice(&[0x42; 16]);
+    put_u64(header, 72, entries_lba);
+    put_u32(header, 80, 4);
+    put_u32(header, 84, 128);
+    put_u32(header, 88, entries_crc);
+    let crc = crc32(&header[..92]);
+    put_u32(header, 16, crc);
+}
+
+// a protective MBR, and "boot" at 3..=10 and "data" at 11..=60 in a four
+// entry array mirrored at the end of the disk
+fn build_gpt_disk() -> Arc<RamDisk> {
+    let mut image = vec![0u8; SECTORS * 512];
+    mbr_entry(&mut image[..512], 0, 0xee, 1, SECTORS as u32 - 1);
+
+    let mut entries = [0u8; 512];
+    for (slot, (first, last, name)) in [(3u64, 10u64, "boot"), (11, 60, "data")].iter().enumerate() {
+        let entry = &mut entries[slot * 128..(slot + 1) * 128];
+        entry[0..16].copy_from_slice(&LINUX_FS);
+        entry[16..32].copy_from_slice(&[slot as u8 + 1; 16]);
+        put_u64(entry, 32, *first);
+        put_u64(entry, 40, *last);
+        for (i, unit) in name.encode_utf16().enumerate() {
+            entry[56 + i * 2..58 + i * 2].copy_from_slice(&unit.to_le_bytes());
+        }
+    }
+    let entries_crc = crc32(&entries);
+    image[2 * 512..3 * 512].copy_from_slice(&entries);
+    image[(SECTORS - 2) * 512..(SECTORS - 1) * 512].copy_from_slice(&entries);
+    gpt_header(&mut image, 1, SECTORS as u64 - 1, 2, entries_crc);
+    gpt_header(&mut image, SECTORS as u64 - 1, 1, SECTORS as u64 - 2, entries_crc);
+    Arc::new(RamDisk::from_vec(image))
+}
+
+#[test_case]
+fn crc32_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+}
+
+#[test_case]
+fn mbr_with_logical_partitions() {
+    let table = PartitionTable::read(&build_mbr_disk()).unwrap();
+    assert_eq!(table.kind, TableKind::Mbr);
+    let layout: vec::Vec<(usize, u64, u64)> =
+        table.partitions.iter().map(|p| (p.index, p.start_lba, p.sector_count)).collect();
+    assert_eq!(layout, [(1, 2, 8), (2, 10, 10), (5, 22, 8), (6, 32, 8)]);
+    assert_eq!(table.partitions[0].kind, PartitionKind::Mbr { system_id: 0x83, bootable: true });
+    assert_eq!(table.partitions[3].kind, PartitionKind::Mbr { system_id: 0x82, bootable: false });
+}
+
+#[test_case]
+fn mbr_extended_chain_loop() {
+    let disk = build_mbr_disk();
+    // point the second EBR back at the first one
+    disk.write_bytes(30 * 512 + 446 + 16 + 4, &[0x05, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+    assert!(matches!(PartitionTable::read(&disk), Err(PartitionError::InvalidMbr(_))));
+}
+
+#[test_case]
+fn gpt_partitions() {
+    let table = PartitionTable::read(&build_gpt_disk()).unwrap();
+    assert_eq!(table.kind, TableKind::Gpt { disk_guid: Guid([0x42; 16]), used_backup_header: false });
+    assert_eq!(table.partitions.len(), 2);
+    assert_eq!((table.partitions[1].start_lba, table.partitions[1].sector_count), (11, 50));
+    match &table.partitions[0].kind {
+        PartitionKind::Gpt { type_guid, name, .. } => {
+            assert_eq!(format!("{}", type_guid), "0FC63DAF-8483-4772-8E79-3D69D8477DE4");
+            assert_eq!(name, &String::from("boot"));
+        }
+        kind => panic!("unexpected partition kind {:?}", kind),
+    }
+}
+
+#[test_case]
+fn gpt_falls_back_to_backup_header() {
+    let disk = build_gpt_disk();
+    // corrupt the primary entry array so its checksum no longer matches
+    disk.write_bytes(2 * 512 + 40, &[0xff]).unwrap();
+    let table = PartitionTable::read(&disk).unwrap();
+    assert_eq!(table.kind, TableKind::Gpt { disk_guid: Guid([0x42; 16]), used_backup_header: true });
+    assert_eq!(table.partitions.len(), 2);
+
+    // and the backup header checksum as well
+    disk.write_bytes((SECTORS as u64 - 1) * 512 + 60, &[0xff]).unwrap();
+    assert!(matches!(PartitionTable::read(&disk), Err(PartitionError::InvalidGpt(_))));
+}
+
+// rewrites the entry count and size of the header at `lba`, keeping its
+// checksum valid
+fn set_entry_layout(disk: &RamDisk, lba: u64, count: u32, size: u32) {
+    let mut header = [0u8; 512];
+    disk.read_sectors(lba, &mut header).unwrap();
+    put_u32(&mut header, 80, count);
+    put_u32(&mut header, 84, size);
+    put_u32(&mut header, 16, 0);
+    let crc = crc32(&header[..92]);
+    put_u32(&mut header, 16, crc);
+    disk.write_sectors(lba, &header).unwrap();
+}
+
+#[test_case]
+fn gpt_rejects_huge_entry_arrays() {
+    let disk = build_gpt_disk();
+    for (count, size) in [(4, 1 << 28), (1 << 20, 128), (4, 96)] {
+        set_entry_layout(&disk, 1, count, size);
+        set_entry_layout(&disk, SECTORS as u64 - 1, count, size);
+        assert!(matches!(PartitionTable::read(&disk), Err(PartitionError::InvalidGpt(_))));
+    }
+}
+
+#[test_case]
+fn partition_is_bounds_checked() {
+    let disk = build_gpt_disk();
+    let partitions = open_partitions(&disk).unwrap();
+    let boot = &partitions[0];
+    assert_eq!(boot.sector_count(), 8);
+
+    boot.write_sectors(7, &[0xab; 512]).unwrap();
+    let mut sector = [0u8; 512];
+    disk.read_sectors(10, &mut sector).unwrap();
+    assert_eq!(sector, [0xab; 512]);
+
+    assert_eq!(boot.write_sectors(8, &[0; 512]), Err(BlockError::OutOfRange));
+    assert_eq!(boot.read_sectors(7, &mut [0; 1024]), Err(BlockError::OutOfRange));
+    assert!(Partition::new(disk.clone(), 60, 8).is_err());
+}
+
+#[test_case]
+fn no_table() {
+    let disk = RamDisk::new(4);
+    assert_eq!(PartitionTable::read(&disk), Err(PartitionError::NoTable));
+}
@@ -0,0 +1,190 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{rc::Rc, string::String, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::cell::Cell;
+use core::future::{self, Future};
+use core::panic::PanicInfo;
+use core::pin::pin;
+use core::task::{Context, Poll};
+use futures_util::task::noop_waker_ref;
+use os::fs::{mount, DirEntry, FileSystem, FileType, FsError};
+use os::process::{self, ExitStatus, Pid, ProcessError, State};
+use os::task::executor::Executor;
+use os::task::{JoinHandle, Task};
+use x86_64::{registers::control::Cr3, PhysAddr};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use os::allocator;
+    use os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+    os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("Heap Initialization Failed!");
+    unsafe { process::address_space::init(phys_mem_offset, frame_allocator) };
+    mount::mount("/", Files(&["log"])).unwrap();
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+// a read-only filesystem with empty files in its root directory
+struct Files(&'static [&'static str]);
+
+impl FileSystem for Files {
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, FsError> {
+        if path != "/" {
+            return Err(FsError::NotFound);
+        }
+        let entry = |name: &&str| DirEntry {
+            name: String::from(*name),
+            file_type: FileType::File,
+            size: 0,
+            modified: None,
+        };
+        Ok(self.0.iter().map(entry).collect())
+    }
+
+    fn read_file(&self, _path: &str) -> Result<Vec<u8>, FsError> {
+        Ok(Vec::new())
+    }
+
+    fn write_file(&mut self, _path: &str, _data: &[u8]) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn create_dir(&mut self, _path: &str) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn remove(&mut self, _path: &str) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+}
+
+fn output<T>(handle: JoinHandle<T>) -> T {
+    let mut cx = Context::from_waker(noop_waker_ref());
+    match pin!(handle).poll(&mut cx) {
+        Poll::Ready(output) => output.unwrap(),
+        Poll::Pending => panic!("task did not finish"),
+    }
+}
+
+fn state(pid: Pid) -> Option<State> {
+    process::processes().into_iter().find(|process| process.pid == pid).map(|process| process.state)
+}
+
+fn page_table(pid: Pid) -> Option<PhysAddr> {
+    process::processes().into_iter().find(|process| process.pid == pid).and_then(|process| process.page_table)
+}
+
+#[test_case]
+fn wait_collects_exit_status() {
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    let waited = Rc::new(Cell::new(None));
+    let result = waited.clone();
+    // stands in for init, the kernel reaps its children without a status
+    let (parents, children) = (spawner.clone(), spawner.clone());
+    let init = process::spawn(&spawner, "init", async move {
+        let parent = process::spawn(&parents, "parent", async move {
+            assert_eq!(process::open("/missing", false), Err(ProcessError::Fs(FsError::NotFound)));
+            let fd = process::open("/log", true).unwrap();
+            let first = process::spawn(&children, "returns", async {
+                // the child got a copy of the file table
+                assert!(process::with_files(|files| files.get(process::Fd(0)).is_some()));
+                3
+            })
+            .unwrap();
+            let second = process::spawn(&children, "exits", async { match process::exit(4).unwrap().await {} }).unwrap();
+            process::close(fd).unwrap();
+            let mut sum = 0;
+            while let Ok((pid, status)) = process::wait(None).await {
+                assert!(pid == first || pid == second);
+                if let ExitStatus::Exited(code) = status {
+                    sum += code;
+                }
+            }
+            sum
+        })
+        .unwrap();
+        result.set(Some((parent, process::wait(Some(parent)).await)));
+        0
+    })
+    .unwrap();
+    executor.run_until_idle();
+    let (parent, status) = waited.get().unwrap();
+    assert_eq!(status, Ok((parent, ExitStatus::Exited(7))));
+    assert_eq!(state(parent), None);
+    assert_eq!(state(init), None);
+    assert_eq!(process::kill(parent), Err(ProcessError::NoSuchProcess(parent)));
+    assert!(matches!(process::exit(0), Err(ProcessError::NotInProcess)));
+}
+
+#[test_case]
+fn kill_ends_a_process() {
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    let children = spawner.clone();
+    let parent = process::spawn(&spawner, "parent", async move {
+        process::spawn(&children, "orphan", future::pending()).unwrap();
+        match process::exit(0).unwrap().await {}
+    })
+    .unwrap();
+    executor.run_until_idle();
+    // the kernel reaped the parent, its child now belongs to the kernel
+    assert_eq!(state(parent), None);
+    let orphan = process::processes()
+        .into_iter()
+        .find(|process| process.name == "orphan" && process.parent == Pid::KERNEL)
+        .map(|process| process.pid)
+        .unwrap();
+    assert_eq!(state(orphan), Some(State::Running));
+
+    assert_eq!(process::kill(Pid::KERNEL), Err(ProcessError::NotKillable));
+    process::kill(orphan).unwrap();
+    executor.run_until_idle();
+    assert_eq!(state(orphan), None);
+    let waited = executor.spawn(Task::new(process::wait(None)));
+    executor.run_until_idle();
+    assert_eq!(output(waited), Err(ProcessError::NoChildren));
+}
+
+#[test_case]
+fn processes_run_on_their_own_page_table() {
+    let mut executor = Executor::new();
+    let loaded = Rc::new(Cell::new(None));
+    let inside = loaded.clone();
+    let pid = process::spawn(&executor.spawner(), "paged", async move {
+        inside.set(Some(Cr3::read().0.start_address()));
+        future::pending().await
+    })
+    .unwrap();
+    executor.run_until_idle();
+    let table = page_table(pid);
+    assert!(table.is_some());
+    assert_eq!(loaded.get(), table);
+    assert_ne!(Some(Cr3::read().0.start_address()), table);
+
+    // the table of an ended process goes to the next one
+    process::kill(pid).unwrap();
+    executor.run_until_idle();
+    let next = process::spawn(&executor.spawner(), "next", future::pending()).unwrap();
+    assert_eq!(page_table(next), table);
+    process::kill(next).unwrap();
+    executor.run_until_idle();
+}
@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::future::Future;
+use core::panic::PanicInfo;
+use core::pin::{pin, Pin};
+use core::task::{Context, Poll};
+use futures_util::stream::Stream;
+use futures_util::task::noop_waker_ref;
+use os::serial::{self, ComPort, SerialStream};
+use os::vga_buffer::terminal::{self, InputStream};
+use pc_keyboard::DecodedKey;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use os::allocator;
+    use os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+    os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("Heap Initialization Failed!");
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+// sends `bytes` on COM1 and lets the UART hand them back to its receiver
+fn loop_back(bytes: &[u8]) {
+    // the name of the test is still on its way out
+    serial::flush();
+    let mut port = serial::port(ComPort::Com1).lock();
+    port.set_loopback(true);
+    for &byte in bytes {
+        port.send(byte);
+    }
+    port.flush();
+    port.set_loopback(false);
+}
+
+// polls until ready, the receive interrupt may take a few character times
+fn wait_for<T>(mut poll: impl FnMut(&mut Context) -> Poll<T>) -> T {
+    let mut cx = Context::from_waker(noop_waker_ref());
+    for _ in 0..100 {
+        if let Poll::Ready(value) = poll(&mut cx) {
+            return value;
+        }
+        x86_64::instructions::hlt();
+    }
+    panic!("nothing arrived");
+}
+
+#[test_case]
+fn stream_receives_bytes() {
+    let mut bytes = SerialStream::new(ComPort::Com1);
+    loop_back(b"ok");
+    assert_eq!(wait_for(|cx| Pin::new(&mut bytes).poll_next(cx)), Some(b'o'));
+    assert_eq!(wait_for(|cx| Pin::new(&mut bytes).poll_next(cx)), Some(b'k'));
+}
+
+#[test_case]
+fn forward_input_types_on_the_terminal() {
+    let mut forward = pin!(serial::forward_input());
+    let mut keys = InputStream::new(terminal::active());
+    // a two byte character and the carriage return terminals send for Enter
+    loop_back("é\r".as_bytes());
+    let mut next_key = || {
+        wait_for(|cx| {
+            let _ = forward.as_mut().poll(cx);
+            Pin::new(&mut keys).poll_next(cx)
+        })
+    };
+    assert_eq!(next_key(), Some(DecodedKey::Unicode('é')));
+    assert_eq!(next_key(), Some(DecodedKey::Unicode('\n')));
+}
@@ -0,0 +1,123 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::string::String;
+use bootloader::{entry_point, BootInfo};
+use core::future::Future;
+use core::panic::PanicInfo;
+use core::pin::pin;
+use core::task::{Context, Poll};
+use futures_util::task::noop_waker_ref;
+use os::shell::Shell;
+use os::task::workqueue::{self, Work};
+use os::task::{executor::Executor, Task};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use os::allocator;
+    use os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+    os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("Heap Initialization Failed!");
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+// runs a command in a task of its own executor, next to an idle task
+fn run(line: &'static str) -> String {
+    let mut executor = Executor::new();
+    let shell = Shell::new(executor.monitor());
+    executor.spawn(Task::new(core::future::pending::<()>()).named("idle"));
+    let handle = executor.spawn(Task::new(async move {
+        let mut out = String::new();
+        shell.execute(line, &mut out).await.unwrap();
+        out
+    }).named("shell"));
+    executor.run_until_idle();
+    let mut cx = Context::from_waker(noop_waker_ref());
+    match pin!(handle).poll(&mut cx) {
+        Poll::Ready(output) => output.unwrap(),
+        Poll::Pending => panic!("{} did not finish", line),
+    }
+}
+
+#[test_case]
+fn help_and_unknown_commands() {
+    let help = run("help");
+    assert!(help.lines().any(|line| line.starts_with("interrupts")));
+    assert_eq!(run("  "), "");
+    assert_eq!(run("reboot now"), "reboot: unknown command, try help\n");
+}
+
+#[test_case]
+fn interrupts_lists_handlers() {
+    let out = run("interrupts");
+    assert!(out.starts_with("vector  irq"));
+    let timer = out.lines().find(|line| line.ends_with(" timer")).unwrap();
+    assert!(timer.trim_start().starts_with("32"));
+}
+
+#[test_case]
+fn idle_shows_halts_and_wakeups() {
+    let out = run("idle");
+    assert!(out.starts_with("idle "));
+    assert!(out.lines().any(|line| line.starts_with("wakeups: timer")));
+}
+
+#[test_case]
+fn tasks_lists_the_executor() {
+    let out = run("tasks");
+    assert!(out.starts_with("    id  terminal  name"));
+    assert!(out.lines().any(|line| line.ends_with(" idle")));
+    assert!(out.lines().any(|line| line.ends_with(" shell")));
+}
+
+#[test_case]
+fn top_shows_poll_time() {
+    let out = run("top");
+    assert!(out.starts_with("    id  priority       polls"));
+    let idle = out.lines().find(|line| line.ends_with(" idle")).unwrap();
+    assert!(idle.contains("spawn"));
+}
+
+#[test_case]
+fn workqueues_counts_queued_work() {
+    fn nothing(_: usize) {}
+    workqueue::queue(Work::new("shell test", nothing, 0)).unwrap();
+    let out = run("workqueues");
+    assert!(out.starts_with("cpu      queued"));
+    let cpu = out.lines().nth(1).unwrap();
+    assert!(cpu.trim_start().starts_with("0 "));
+}
+
+#[test_case]
+fn ps_lists_processes() {
+    let out = run("ps");
+    assert!(out.starts_with("  pid   ppid  state      files  page table  name"));
+    let kernel = out.lines().nth(1).unwrap();
+    assert!(kernel.trim_start().starts_with("0 "));
+    assert!(kernel.ends_with(" kernel"));
+}
+
+#[cfg(feature = "lock-debug")]
+#[test_case]
+fn lockdep_shows_held_locks() {
+    static HELD: os::sync::IrqSpinlock<()> = os::sync::IrqSpinlock::new("shell test", ());
+    let _held = HELD.lock();
+    let out = run("lockdep");
+    assert!(out.lines().any(|line| line.starts_with("held: shell test since ")));
+}
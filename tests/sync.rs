@@ -0,0 +1,188 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{rc::Rc, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::cell::{Cell, RefCell};
+use core::future::Future;
+use core::panic::PanicInfo;
+use core::pin::{pin, Pin};
+use core::task::{Context, Poll};
+use futures_util::task::noop_waker_ref;
+use os::task::executor::Executor;
+use os::task::sync::{broadcast, mpsc, oneshot, Mutex, Notify, RwLock, Semaphore};
+use os::task::Task;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use os::allocator;
+    use os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+    os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("Heap Initialization Failed!");
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+fn yield_now() -> YieldNow {
+    YieldNow(false)
+}
+
+#[test_case]
+fn mutex_is_held_across_await() {
+    static COUNTER: Mutex<u32> = Mutex::new(0);
+    let mut executor = Executor::new();
+    for _ in 0..2 {
+        executor.spawn(Task::new(async {
+            for _ in 0..10 {
+                let mut counter = COUNTER.lock().await;
+                let value = *counter;
+                yield_now().await;
+                *counter = value + 1;
+            }
+        }));
+    }
+    executor.run_until_idle();
+    assert_eq!(*COUNTER.try_lock().unwrap(), 20);
+}
+
+#[test_case]
+fn waiting_writer_keeps_new_readers_out() {
+    let lock = Rc::new(RwLock::new(0));
+    let mut executor = Executor::new();
+    let reader = lock.try_read().unwrap();
+    let writer = lock.clone();
+    executor.spawn(Task::new(async move { *writer.write().await = 1 }));
+    executor.run_until_idle();
+
+    assert!(lock.try_read().is_none());
+    assert_eq!(*reader, 0);
+    drop(reader);
+    executor.run_until_idle();
+    assert_eq!(*lock.try_read().unwrap(), 1);
+}
+
+#[test_case]
+fn semaphore_serves_in_order() {
+    let semaphore = Rc::new(Semaphore::new(1));
+    let order = Rc::new(RefCell::new(Vec::new()));
+    let held = semaphore.try_acquire().unwrap();
+    let mut executor = Executor::new();
+    for (name, permits) in [("two", 2), ("one", 1)] {
+        let (semaphore, order) = (semaphore.clone(), order.clone());
+        executor.spawn(Task::new(async move {
+            let _permit = semaphore.acquire_many(permits).await.unwrap();
+            order.borrow_mut().push(name);
+        }));
+    }
+    executor.run_until_idle();
+    // "one" fits but waits behind "two"
+    assert!(order.borrow().is_empty());
+    semaphore.add_permits(1);
+    drop(held);
+    executor.run_until_idle();
+    assert_eq!(*order.borrow(), ["two", "one"]);
+    assert_eq!(semaphore.available_permits(), 2);
+}
+
+#[test_case]
+fn notify_keeps_one_permit() {
+    let notify = Rc::new(Notify::new());
+    let woken = Rc::new(Cell::new(0));
+    notify.notify_one();
+    notify.notify_one();
+    let mut executor = Executor::new();
+    for _ in 0..3 {
+        let (notify, woken) = (notify.clone(), woken.clone());
+        executor.spawn(Task::new(async move {
+            notify.notified().await;
+            woken.set(woken.get() + 1);
+        }));
+    }
+    executor.run_until_idle();
+    assert_eq!(woken.get(), 1);
+    notify.notify_waiters();
+    executor.run_until_idle();
+    assert_eq!(woken.get(), 3);
+}
+
+#[test_case]
+fn bounded_channel_applies_backpressure() {
+    let (sender, mut receiver) = mpsc::channel(2);
+    let (done, mut finished) = oneshot::channel();
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async move {
+        for value in 0..10 {
+            sender.send(value).await.unwrap();
+        }
+        done.send(()).unwrap();
+    }));
+    executor.run_until_idle();
+    // the sender waits for room after two values
+    assert_eq!(finished.try_recv(), Err(oneshot::TryRecvError::Empty));
+
+    let received = executor.spawn(Task::new(async move {
+        let mut values = Vec::new();
+        while let Some(value) = receiver.recv().await {
+            values.push(value);
+        }
+        values
+    }));
+    executor.run_until_idle();
+    let mut cx = Context::from_waker(noop_waker_ref());
+    assert_eq!(pin!(finished).poll(&mut cx), Poll::Ready(Ok(())));
+    let values = match pin!(received).poll(&mut cx) {
+        Poll::Ready(values) => values.unwrap(),
+        Poll::Pending => panic!("receiver did not finish"),
+    };
+    assert_eq!(values, (0..10).collect::<Vec<_>>());
+
+    let (sender, receiver) = mpsc::unbounded_channel();
+    sender.send(1).unwrap();
+    drop(receiver);
+    assert_eq!(sender.send(2), Err(mpsc::SendError(2)));
+}
+
+#[test_case]
+fn broadcast_reports_lag() {
+    let (sender, mut slow) = broadcast::channel(2);
+    let mut late = sender.subscribe();
+    for value in 0..4 {
+        assert_eq!(sender.send(value), Ok(2));
+    }
+    assert_eq!(slow.try_recv(), Err(broadcast::TryRecvError::Lagged(2)));
+    assert_eq!(slow.try_recv(), Ok(2));
+    assert_eq!(late.try_recv(), Err(broadcast::TryRecvError::Lagged(2)));
+    drop(sender);
+    assert_eq!(slow.try_recv(), Ok(3));
+    assert_eq!(slow.try_recv(), Err(broadcast::TryRecvError::Closed));
+}
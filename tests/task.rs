@@ -0,0 +1,75 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::rc::Rc;
+use bootloader::{entry_point, BootInfo};
+use core::cell::Cell;
+use core::future::{self, Future};
+use core::panic::PanicInfo;
+use core::pin::pin;
+use core::task::{Context, Poll};
+use futures_util::task::noop_waker_ref;
+use os::task::{simple_executor::SimpleExecutor, JoinError, Task};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use os::allocator;
+    use os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+    os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("Heap Initialization Failed!");
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+#[test_case]
+fn join_handle_yields_output() {
+    let mut executor = SimpleExecutor::new();
+    let answer = executor.spawn(Task::new(async { 6 * 7 }).named("answer"));
+    let joined = executor.spawn(Task::new(async move { answer.await.map(|answer| answer + 1) }));
+    assert!(!joined.is_finished());
+    executor.run();
+
+    assert!(joined.is_finished());
+    let mut cx = Context::from_waker(noop_waker_ref());
+    assert_eq!(pin!(joined).poll(&mut cx), Poll::Ready(Ok(Ok(43))));
+}
+
+#[test_case]
+fn abort_drops_the_future() {
+    struct Guard(Rc<Cell<bool>>);
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            self.0.set(true);
+        }
+    }
+
+    let dropped = Rc::new(Cell::new(false));
+    let guard = Guard(dropped.clone());
+    let mut executor = SimpleExecutor::new();
+    let handle = executor.spawn(Task::new(async move {
+        let _guard = guard;
+        future::pending::<()>().await
+    }).named("pending"));
+    assert_eq!(handle.name(), "pending");
+    handle.abort();
+    executor.run();
+
+    assert!(dropped.get());
+    let mut cx = Context::from_waker(noop_waker_ref());
+    assert_eq!(pin!(handle).poll(&mut cx), Poll::Ready(Err(JoinError::Cancelled)));
+}
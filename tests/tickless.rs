@@ -0,0 +1,84 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::future::Future;
+use core::panic::PanicInfo;
+use core::pin::pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+use futures_util::task::noop_waker_ref;
+use os::time::{self, idle, timer, Instant};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use os::allocator;
+    use os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+    os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("Heap Initialization Failed!");
+    os::acpi::init(phys_mem_offset).expect("ACPI initialization failed");
+    time::clocksource::init(&mut mapper, &mut frame_allocator);
+    time::tickless::init(&mut mapper, &mut frame_allocator).expect("no tickless mode");
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+#[test_case]
+fn sleep_wakes_once_at_its_deadline() {
+    let mut cx = Context::from_waker(noop_waker_ref());
+    let start = Instant::now();
+    let mut sleep = pin!(timer::sleep(Duration::from_millis(50)));
+    assert_eq!(sleep.as_mut().poll(&mut cx), Poll::Pending);
+    assert_eq!(timer::next_deadline(), Some(sleep.deadline()));
+
+    let ticks = time::ticks();
+    let halts = idle::statistics().halts;
+    while sleep.as_mut().poll(&mut cx).is_pending() {
+        timer::wake_expired();
+        idle::halt_unless(|| false);
+    }
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    // a periodic tick would have fired five times
+    assert!(time::ticks() - ticks <= 2, "{} timer interrupts", time::ticks() - ticks);
+    assert!(idle::statistics().halts - halts <= 2);
+    assert!(idle::statistics().timer_wakeups >= 1);
+}
+
+#[test_case]
+fn dropped_sleep_is_forgotten() {
+    let mut cx = Context::from_waker(noop_waker_ref());
+    {
+        let mut sleep = pin!(timer::sleep(Duration::from_secs(60)));
+        assert_eq!(sleep.as_mut().poll(&mut cx), Poll::Pending);
+        assert!(timer::next_deadline().is_some());
+    }
+    assert_eq!(timer::next_deadline(), None);
+    assert!(timer::sleep(Duration::ZERO).deadline() <= Instant::now());
+}
+
+#[test_case]
+fn endless_sleep_is_never_due() {
+    let mut cx = Context::from_waker(noop_waker_ref());
+    let mut sleep = pin!(timer::sleep(Duration::MAX));
+    assert_eq!(sleep.deadline(), Instant::FAR_FUTURE);
+    assert_eq!(sleep.as_mut().poll(&mut cx), Poll::Pending);
+    // armed for a counter value the clock never reaches
+    let event_timer = time::tickless::event_timer().unwrap();
+    assert!(event_timer.arm(Instant::FAR_FUTURE));
+    event_timer.disarm();
+}
@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use os::vga_buffer::{self, terminal};
+use x86_64::instructions::interrupts;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use os::allocator;
+    use os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+    os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("Heap Initialization Failed!");
+    vga_buffer::enable_scrollback();
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+// reads the characters of a screen row straight from VGA memory
+fn screen_row(row: usize) -> [u8; 8] {
+    let mut text = [0u8; 8];
+    for (col, byte) in text.iter_mut().enumerate() {
+        let cell = unsafe { core::ptr::read_volatile((0xb8000 as *const u16).add(row * 80 + col)) };
+        *byte = cell as u8;
+    }
+    text
+}
+
+#[test_case]
+fn scroll_back_and_forward() {
+    interrupts::without_interrupts(|| {
+        let mut writer = terminal::writer(0).lock();
+        writer.clear_screen();
+        // 40 lines on a 25 row screen push lines 0..=15 into the history
+        for i in 0..40 {
+            writeln!(writer, "line {:02}", i).unwrap();
+        }
+        assert_eq!(&screen_row(0), b"line 16 ");
+
+        writer.scroll_back(1);
+        assert_eq!(writer.view_offset(), 1);
+        assert_eq!(&screen_row(0), b"line 15 ");
+        assert_eq!(&screen_row(1), b"line 16 ");
+
+        writer.scroll_back(15);
+        assert_eq!(&screen_row(0), b"line 00 ");
+        writer.scroll_forward(6);
+        assert_eq!(&screen_row(0), b"line 06 ");
+
+        // new output returns to the live screen
+        write!(writer, "x").unwrap();
+        assert_eq!(writer.view_offset(), 0);
+        assert_eq!(&screen_row(0), b"line 16 ");
+        assert_eq!(writer.position(), (24, 1));
+    });
+}
+
+#[test_case]
+fn history_is_bounded() {
+    interrupts::without_interrupts(|| {
+        let mut writer = terminal::writer(0).lock();
+        for i in 0..vga_buffer::SCROLLBACK_ROWS + 50 {
+            writeln!(writer, "row {}", i).unwrap();
+        }
+        writer.scroll_back(usize::MAX / 2);
+        assert_eq!(writer.view_offset(), vga_buffer::SCROLLBACK_ROWS);
+        writer.scroll_forward(usize::MAX);
+        assert_eq!(writer.view_offset(), 0);
+    });
+}
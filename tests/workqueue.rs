@@ -0,0 +1,94 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use os::interrupts::irq::{self, IrqReturn};
+use os::task::executor::Executor;
+use os::task::workqueue::{self, QueueError, Work, CAPACITY, MAX_CPUS};
+use os::task::Task;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use os::allocator;
+    use os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+    os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("Heap Initialization Failed!");
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    os::test_panic_handler(info)
+}
+
+static INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+static CHECKSUMS: AtomicUsize = AtomicUsize::new(0);
+static CHECKSUM: AtomicU64 = AtomicU64::new(0);
+static IN_INTERRUPT: AtomicBool = AtomicBool::new(false);
+
+// the handler only queues the slow part
+fn handler(_vector: u8) -> IrqReturn {
+    INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+    workqueue::queue(Work::new("checksum", checksum, 100_000)).unwrap();
+    IrqReturn::Handled
+}
+
+fn checksum(len: usize) {
+    let mut sum = 0u64;
+    for byte in 0..len as u64 {
+        sum = sum.rotate_left(5) ^ (byte & 0xff);
+    }
+    CHECKSUM.store(sum, Ordering::Relaxed);
+    let in_interrupt = irq::current_vector().is_some() || !x86_64::instructions::interrupts::are_enabled();
+    IN_INTERRUPT.fetch_or(in_interrupt, Ordering::Relaxed);
+    CHECKSUMS.fetch_add(1, Ordering::Relaxed);
+}
+
+#[test_case]
+fn heavy_work_runs_after_the_handler() {
+    let id = irq::register(201, "checksum", 0, handler).unwrap();
+    unsafe { core::arch::asm!("int 201") };
+    unsafe { core::arch::asm!("int 201") };
+    assert_eq!(INTERRUPTS.load(Ordering::Relaxed), 2);
+    assert_eq!(CHECKSUMS.load(Ordering::Relaxed), 0);
+    assert_eq!(workqueue::pending(0), 2);
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(workqueue::run_worker(0).unwrap()).named("work"));
+    executor.run_until_idle();
+    assert_eq!(CHECKSUMS.load(Ordering::Relaxed), 2);
+    assert_ne!(CHECKSUM.load(Ordering::Relaxed), 0);
+    assert!(!IN_INTERRUPT.load(Ordering::Relaxed));
+    assert_eq!(workqueue::pending(0), 0);
+    irq::unregister(id);
+}
+
+#[test_case]
+fn full_queue_drops_work() {
+    static RAN: AtomicUsize = AtomicUsize::new(0);
+    fn count(_: usize) {
+        RAN.fetch_add(1, Ordering::Relaxed);
+    }
+    for data in 0..CAPACITY {
+        workqueue::queue(Work::new("count", count, data)).unwrap();
+    }
+    assert_eq!(workqueue::queue(Work::new("count", count, CAPACITY)), Err(QueueError::Full));
+    assert_eq!(workqueue::queue_on(MAX_CPUS, Work::new("count", count, 0)), Err(QueueError::InvalidCpu(MAX_CPUS)));
+    assert_eq!(workqueue::run_worker(MAX_CPUS).err(), Some(QueueError::InvalidCpu(MAX_CPUS)));
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(workqueue::run_worker(0).unwrap()).named("work"));
+    executor.run_until_idle();
+    assert_eq!(RAN.load(Ordering::Relaxed), CAPACITY);
+}